@@ -0,0 +1,66 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use chesslib::{ai::eval, bitboard::{cast_cardinals, cast_diagonals}, game::Position, state::Square};
+
+// openings, middlegames and endgames, with castling, en passant and promotions available
+const CORPUS: &[&str] = &[
+	Position::FEN_INITIAL,
+	"r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+	"r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+	"r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+	"rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+	"n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+	"8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+	"8/5pk1/6p1/7p/7P/6P1/5PK1/8 w - - 0 1",
+];
+
+fn corpus() -> Vec<Position> {
+	CORPUS.iter().map(|fen| Position::from_fen(fen).unwrap()).collect()
+}
+
+fn bench_movegen(c: &mut Criterion) {
+	let positions = corpus();
+	c.bench_function("gen_pseudolegal", |b| b.iter(|| {
+		for pos in &positions {
+			black_box(pos.gen_pseudolegal());
+		}
+	}));
+	c.bench_function("gen_legal", |b| b.iter(|| {
+		for pos in &positions {
+			black_box(pos.gen_legal());
+		}
+	}));
+	let moves: Vec<_> = positions.iter().map(|pos| (pos, pos.gen_legal())).collect();
+	c.bench_function("apply_move", |b| b.iter(|| {
+		for (pos, moves) in &moves {
+			for mov in moves {
+				let mut pos2 = (*pos).clone();
+				pos2.apply_move(mov);
+				black_box(pos2);
+			}
+		}
+	}));
+}
+
+fn bench_eval(c: &mut Criterion) {
+	let positions = corpus();
+	c.bench_function("eval", |b| b.iter(|| {
+		for pos in &positions {
+			black_box(eval(pos.get_board(), pos.side_to_move()));
+		}
+	}));
+}
+
+fn bench_cast_ray(c: &mut Criterion) {
+	let occupancies: Vec<_> = corpus().iter().map(|pos| pos.get_board().all_pieces()).collect();
+	c.bench_function("cast_ray", |b| b.iter(|| {
+		for &pieces in &occupancies {
+			for squ in Square::iter_all() {
+				black_box(cast_diagonals(squ, pieces) | cast_cardinals(squ, pieces));
+			}
+		}
+	}));
+}
+
+criterion_group!(benches, bench_movegen, bench_eval, bench_cast_ray);
+criterion_main!(benches);
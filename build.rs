@@ -0,0 +1,59 @@
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+	let out = Command::new("git").args(args).output().ok()?;
+	if !out.status.success() {
+		return None;
+	}
+	Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+}
+
+fn main() {
+	let mut hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+	if git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|s| !s.is_empty()) {
+		hash.push_str("-dirty");
+	}
+	println!("cargo:rustc-env=CHESS_GIT_HASH={}", hash);
+	// the hash changes with HEAD, or with the branch it points to on a commit
+	// (a loose ref, or packed-refs once packed), and the dirty flag with the index
+	// and the tracked sources
+	for path in ["HEAD", "index", "packed-refs"] {
+		rerun_if_git_path_changed(path);
+	}
+	if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+		rerun_if_git_path_changed(&branch);
+	}
+	for path in ["src", "benches", "tests", "Cargo.toml", "build.rs"] {
+		println!("cargo:rerun-if-changed={}", path);
+	}
+	#[cfg(feature = "capi")]
+	generate_header();
+}
+
+// a missing file would make Cargo re-run the script on every build
+fn rerun_if_git_path_changed(path: &str) {
+	if let Some(path) = git(&["rev-parse", "--git-path", path]).filter(|path| std::path::Path::new(path).exists()) {
+		println!("cargo:rerun-if-changed={}", path);
+	}
+}
+
+// the header of the C API, kept in the repository for hosts that do not build the library
+#[cfg(feature = "capi")]
+fn generate_header() {
+	println!("cargo:rerun-if-changed=src/capi.rs");
+	let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+	let mut config = cbindgen::Config::default();
+	config.language = cbindgen::Language::C;
+	config.include_guard = Some("CHESSLIB_H".to_string());
+	config.header = Some("/* Generated from src/capi.rs by build.rs, do not edit. */".to_string());
+	config.usize_is_size_t = true;
+	// ChessResult::WhiteWins becomes CHESS_RESULT_WHITE_WINS
+	config.enumeration.rename_variants = cbindgen::RenameRule::ScreamingSnakeCase;
+	config.enumeration.prefix_with_name = true;
+	cbindgen::Builder::new()
+		.with_config(config)
+		.with_src(format!("{}/src/capi.rs", dir))
+		.generate()
+		.expect("cannot generate the C header")
+		.write_to_file(format!("{}/include/chesslib.h", dir));
+}
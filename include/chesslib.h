@@ -0,0 +1,118 @@
+/* Generated from src/capi.rs by build.rs, do not edit. */
+
+#ifndef CHESSLIB_H
+#define CHESSLIB_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The largest number of legal moves in any position, as a buffer size.
+ */
+#define CHESS_MAX_MOVES 256
+
+typedef enum ChessResult {
+  CHESS_RESULT_ONGOING,
+  CHESS_RESULT_WHITE_WINS,
+  CHESS_RESULT_BLACK_WINS,
+  CHESS_RESULT_DRAW,
+} ChessResult;
+
+/**
+ * A SimpleAI instance, whose transposition table persists between searches.
+ */
+typedef struct ChessEngine ChessEngine;
+
+/**
+ * A position with its legal moves.
+ */
+typedef struct ChessPosition ChessPosition;
+
+/**
+ * A move in UCI notation ("e2e4", "e7e8q"), NUL-terminated.
+ */
+typedef struct ChessMove {
+  char uci[6];
+} ChessMove;
+
+typedef struct ChessSearchResult {
+  struct ChessMove best_move;
+  /**
+   * In centipawns, from the perspective of the side to move.
+   */
+  int16_t score;
+  /**
+   * Moves to a forced mate, negative if the side to move gets mated, 0 if none was found.
+   */
+  int16_t mate;
+  /**
+   * The last completed iteration.
+   */
+  uint32_t depth;
+  uint64_t nodes;
+} ChessSearchResult;
+
+/**
+ * Returns a new position, the initial one, to free with chess_position_free.
+ */
+struct ChessPosition *chess_position_new(void);
+
+/**
+ * Frees a position; does nothing if it is NULL.
+ */
+void chess_position_free(struct ChessPosition *pos);
+
+/**
+ * Replaces the position by that of a FEN string; fails if the FEN is invalid
+ * or describes an impossible position.
+ */
+bool chess_position_set_fen(struct ChessPosition *pos, const char *fen);
+
+/**
+ * Writes the FEN of the position to `buf`, truncated to `len` bytes with the NUL
+ * terminator, and returns its full length without the terminator, as snprintf.
+ */
+size_t chess_position_get_fen(const struct ChessPosition *pos, char *buf, size_t len);
+
+/**
+ * Writes up to `len` legal moves to `moves`, and returns the number of legal
+ * moves, which never exceeds CHESS_MAX_MOVES.
+ */
+size_t chess_position_legal_moves(const struct ChessPosition *pos,
+                                  struct ChessMove *moves,
+                                  size_t len);
+
+/**
+ * Plays a move given in UCI notation; fails if it is invalid or illegal.
+ */
+bool chess_position_apply_uci(struct ChessPosition *pos, const char *uci);
+
+/**
+ * The result of the game if it is over, claimable draws aside.
+ */
+enum ChessResult chess_position_result(const struct ChessPosition *pos);
+
+/**
+ * Returns a new engine searching up to `max_depth`, to free with chess_engine_free.
+ */
+struct ChessEngine *chess_engine_new(uint32_t max_depth);
+
+/**
+ * Frees an engine; does nothing if it is NULL.
+ */
+void chess_engine_free(struct ChessEngine *engine);
+
+/**
+ * Searches the position up to the maximum depth of the engine, or for about
+ * `time_ms` milliseconds if it is not 0, on the calling thread; fails if the
+ * game is over.
+ */
+bool chess_engine_search(const struct ChessEngine *engine,
+                         const struct ChessPosition *pos,
+                         uint32_t time_ms,
+                         struct ChessSearchResult *result);
+
+#endif /* CHESSLIB_H */
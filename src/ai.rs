@@ -1,15 +1,46 @@
-use std::{cell::RefCell, sync::{Arc, Mutex}, thread::JoinHandle, time::Instant};
+use std::{cell::RefCell, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex}, thread::JoinHandle, time::Duration};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
-use crate::{game::Position, state::{Board, Color, Move, Piece, PieceType, Square}};
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{bitboard::{cast_cardinals, cast_diagonals, Bb, KING_PATTERNS, KNIGHT_PATTERNS}, game::Position, state::{Board, Color, Move, MoveList, Piece, PieceType, Square}, tt::{Bound, TranspositionTable}};
 
 pub trait ChessAi: Send {
 	fn name(&self) -> String;
-	fn pick_move(&self, pos: &Position, legal_moves: &[Move]) -> Move;
+	// searches until one of the limits is reached, and returns the best move found so far
+	fn search(&self, pos: &Position, legal_moves: &[Move], limits: SearchLimits) -> SearchOutcome;
+	// thinks on the opponent's time, after the AI played in `pos`, until `stop` is set
+	fn ponder(&self, _pos: &Position, _stop: &AtomicBool) {}
+	// a search bounded only by the settings of the AI
+	fn pick_move(&self, pos: &Position, legal_moves: &[Move]) -> Move {
+		self.search(pos, legal_moves, SearchLimits::default()).best_move
+	}
+}
+
+// bounds of a search, which ends at the first one reached; AIs whose search is
+// short or not iterative may ignore them
+#[derive(Clone, Copy, Default)]
+pub struct SearchLimits<'a> {
+	pub depth: Option<u32>, // capped by the maximum depth of the AI
+	pub time: Option<Duration>,
+	pub nodes: Option<u64>, // of the main thread
+	pub stop: Option<&'a AtomicBool>, // set from another thread to end the search
+}
+impl SearchLimits<'_> {
+	pub fn within(budget: Duration) -> Self {
+		SearchLimits { time: Some(budget), ..SearchLimits::default() }
+	}
 }
 
+// runs an AI in background threads; dropping it stops them
 pub struct ParallelAi {
 	ai: Arc<Mutex<Box<dyn ChessAi>>>,
-	thinker: Option<JoinHandle<Move>>,
+	thinker: Option<JoinHandle<(u64, SearchOutcome)>>, // with the key of the position searched
+	stop: Arc<AtomicBool>, // of the current thinker or ponderer
 	name: RefCell<String>,
 }
 impl ParallelAi {
@@ -18,6 +49,7 @@ impl ParallelAi {
 			name: RefCell::new(ai.name()),
 			ai: Arc::new(Mutex::new(Box::new(ai))),
 			thinker: None,
+			stop: Arc::new(AtomicBool::new(false)),
 		}
 	}
 	pub fn name(&self) -> String {
@@ -26,19 +58,48 @@ impl ParallelAi {
 		}
 		self.name.borrow().clone()
 	}
-	pub fn pick_move_async(&mut self, pos: &Position, legal_moves: &[Move]) {
+	// searches in a thread within `limits`, whose stop flag is replaced by that of move_now;
+	// a search already running is cancelled
+	pub fn search_async(&mut self, pos: &Position, legal_moves: &[Move], limits: SearchLimits<'static>) {
+		self.cancel();
 		let pos = pos.clone();
 		let legal_moves = legal_moves.to_owned();
 		let ai = self.ai.clone();
+		let stop = self.renew_stop();
+		// waits for the previous thinker or ponderer, if any, to notice its stop flag
 		self.thinker = Some(std::thread::spawn(move || {
 			let ai = ai.lock().unwrap();
-			return ai.pick_move(&pos, &legal_moves);
+			(pos.hash_key(), ai.search(&pos, &legal_moves, SearchLimits { stop: Some(&stop), ..limits }))
 		}));
 	}
+	// stops the thinker or ponderer, e.g. when the position changed; the result of
+	// the thinker is discarded
+	pub fn cancel(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		self.thinker = None;
+	}
+	// makes the thinker play its best move so far
+	pub fn move_now(&self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+	// thinks in the background until the next move is requested, as for ChessAi::ponder
+	pub fn ponder(&mut self, pos: &Position) {
+		let pos = pos.clone();
+		let ai = self.ai.clone();
+		let stop = self.renew_stop();
+		std::thread::spawn(move || ai.lock().unwrap().ponder(&pos, &stop));
+	}
+	// stops the previous thinker or ponderer, and returns the flag of the next one
+	fn renew_stop(&mut self) -> Arc<AtomicBool> {
+		self.stop.store(true, Ordering::Relaxed);
+		self.stop = Arc::new(AtomicBool::new(false));
+		self.stop.clone()
+	}
 	pub fn is_thinking(&self) -> bool {
 		self.thinker.is_some()
 	}
-	pub fn try_get_result(&mut self) -> Option<Move> {
+	// the outcome of the search, with the key of the position it was computed for
+	pub fn try_get_result(&mut self) -> Option<(u64, SearchOutcome)> {
 		if self.thinker.as_ref().expect("no active thinker thread").is_finished() {
 			Some(self.thinker.take().unwrap().join().unwrap())
 		} else {
@@ -46,14 +107,82 @@ impl ParallelAi {
 		}
 	}
 }
+impl Drop for ParallelAi {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+}
+
+// continuous analysis in a background thread, restarted on every new position
+pub struct Analyzer {
+	ai: Arc<Mutex<SimpleAi>>,
+	stop: Arc<AtomicBool>,
+	updates: Option<mpsc::Receiver<SearchOutcome>>,
+	latest: Option<SearchOutcome>,
+}
+impl Analyzer {
+	pub fn new(max_depth: u32) -> Self {
+		Analyzer {
+			ai: Arc::new(Mutex::new(SimpleAi::new(max_depth))),
+			stop: Arc::new(AtomicBool::new(false)),
+			updates: None,
+			latest: None,
+		}
+	}
+	pub fn analyze(&mut self, pos: &Position) {
+		self.stop();
+		let legal_moves = pos.gen_legal();
+		if legal_moves.is_empty() {
+			return;
+		}
+		let pos = pos.clone();
+		let ai = self.ai.clone();
+		let stop = self.stop.clone();
+		let (sender, receiver) = mpsc::channel();
+		self.updates = Some(receiver);
+		// waits for the previous search to notice its stop flag
+		std::thread::spawn(move || {
+			let ai = ai.lock().unwrap();
+			ai.analyze(&pos, &legal_moves, &stop, |outcome| { let _ = sender.send(outcome.clone()); });
+		});
+	}
+	pub fn stop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		self.stop = Arc::new(AtomicBool::new(false));
+		self.updates = None;
+		self.latest = None;
+	}
+	// deepest completed iteration so far
+	pub fn latest(&mut self) -> Option<&SearchOutcome> {
+		if let Some(updates) = &self.updates {
+			while let Ok(outcome) = updates.try_recv() {
+				self.latest = Some(outcome);
+			}
+		}
+		self.latest.as_ref()
+	}
+}
+impl Drop for Analyzer {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+}
 
-pub struct RandomAi();
+// plays uniformly random moves, drawn from a seeded generator so that games can be replayed
+pub struct RandomAi {
+	rng: Mutex<StdRng>,
+}
+impl RandomAi {
+	pub fn new(seed: u64) -> RandomAi {
+		RandomAi { rng: Mutex::new(StdRng::seed_from_u64(seed)) }
+	}
+}
 impl ChessAi for RandomAi {
 	fn name(&self) -> String {
 		return "RandomAI".to_string();
 	}
-	fn pick_move(&self, _position: &Position, legal_moves: &[Move]) -> Move {
-		legal_moves[rand::random::<usize>() % legal_moves.len()]
+	fn search(&self, _position: &Position, legal_moves: &[Move], _limits: SearchLimits) -> SearchOutcome {
+		SearchOutcome::unsearched(legal_moves[self.rng.lock().unwrap().gen_range(0..legal_moves.len())])
 	}
 }
 
@@ -161,6 +290,124 @@ fn eval_side(board: &Board, color: Color, is_endgame: bool) -> i16 {
 	val
 }
 
+// king safety terms, in centipawns
+const SHIELD_CLOSE: i16 = 12; // own pawn right in front of the king, per file
+const SHIELD_FAR: i16 = 6; // own pawn two ranks ahead
+const SHIELD_MISSING: i16 = -14;
+const SEMI_OPEN_FILE: i16 = -10; // no own pawn on a file next to the king
+const OPEN_FILE: i16 = -20; // no pawn at all
+// attack units per square of the king zone attacked, by attacker type
+const ATTACK_UNITS: [(PieceType, i16); 4] = [
+	(PieceType::Knight, 2), (PieceType::Bishop, 2), (PieceType::Rook, 3), (PieceType::Queen, 5),
+];
+// percentage of the attack units counted, by number of attackers: a lone
+// attacker is rarely dangerous
+const ATTACKER_WEIGHT: [i16; 8] = [0, 50, 75, 88, 94, 97, 99, 100];
+const CP_PER_ATTACK_UNIT: i16 = 4;
+
+// pawn shield and open files around the king of `color`, and enemy pieces
+// attacking the squares around it; only relevant while there is material to attack with
+fn eval_king_safety(board: &Board, color: Color) -> i16 {
+	let Some(king) = board.find_piece(Piece::new(color, PieceType::King)).iter().next() else {
+		return 0;
+	};
+	let own_pawns = board.find_piece(Piece::new(color, PieceType::Pawn));
+	let all_pawns = own_pawns | board.find_piece(Piece::new(color.opponent(), PieceType::Pawn));
+	let mut val = 0;
+
+	let rel_rank = color.rel_rank(king.rank());
+	let files = king.file().saturating_sub(1)..=(king.file() + 1).min(7);
+	// the shield only matters for a king that stayed on the back ranks, away from the center
+	if rel_rank <= 1 && !(3..=4).contains(&king.file()) {
+		for file in files.clone() {
+			let ahead = |ranks: u8| {
+				let rank = color.rel_rank(rel_rank + ranks);
+				own_pawns.at(Square::at(file, rank))
+			};
+			val += if ahead(1) { SHIELD_CLOSE } else if ahead(2) { SHIELD_FAR } else { SHIELD_MISSING };
+		}
+	}
+	for file in files {
+		if (all_pawns & Bb::file(file)).none() {
+			val += OPEN_FILE;
+		} else if (own_pawns & Bb::file(file)).none() {
+			val += SEMI_OPEN_FILE;
+		}
+	}
+
+	let zone = KING_PATTERNS[king] | Bb::one(king);
+	let pieces = board.all_pieces();
+	let (mut units, mut attackers) = (0, 0);
+	for (ptype, weight) in ATTACK_UNITS {
+		for from in board.find_piece(Piece::new(color.opponent(), ptype)).iter() {
+			let attacks = match ptype {
+				PieceType::Knight => KNIGHT_PATTERNS[from],
+				PieceType::Bishop => cast_diagonals(from, pieces),
+				PieceType::Rook => cast_cardinals(from, pieces),
+				_ => cast_cardinals(from, pieces) | cast_diagonals(from, pieces),
+			};
+			let hits = (attacks & zone).count() as i16;
+			if hits > 0 {
+				units += weight * hits;
+				attackers += 1;
+			}
+		}
+	}
+	val -= units * CP_PER_ATTACK_UNIT * ATTACKER_WEIGHT[attackers.min(7)] / 100;
+	val
+}
+
+const PINNED_PIECE: i16 = -20;
+
+// pieces of `color` other than pawns pinned to their king
+fn eval_pins(board: &Board, color: Color) -> i16 {
+	let pawns = board.find_piece(Piece::new(color, PieceType::Pawn));
+	(board.pinned_pieces(color) - pawns).count() as i16 * PINNED_PIECE
+}
+
+// centipawns per safe square a piece can move to
+const MOBILITY: [(PieceType, i16); 4] = [
+	(PieceType::Knight, 4), (PieceType::Bishop, 5), (PieceType::Rook, 2), (PieceType::Queen, 1),
+];
+const ROOK_OPEN_FILE: i16 = 20;
+const ROOK_SEMI_OPEN_FILE: i16 = 10; // no own pawn on the file
+const ROOK_SEVENTH_RANK: i16 = 20;
+
+// mobility of the pieces of `color`, counting the squares not occupied by their
+// own pieces nor attacked by enemy pawns, and rooks on open files or the 7th rank
+fn eval_activity(board: &Board, color: Color) -> i16 {
+	let own = board.find_color(color);
+	let pieces = board.all_pieces();
+	let own_pawns = board.find_piece(Piece::new(color, PieceType::Pawn));
+	let enemy_pawns = board.find_piece(Piece::new(color.opponent(), PieceType::Pawn));
+	let pawn_forward = enemy_pawns.shift_ver(color.down());
+	let safe = !own & !(pawn_forward.shift_left(1) | pawn_forward.shift_right(1));
+	let mut val = 0;
+	for (ptype, weight) in MOBILITY {
+		for from in board.find_piece(Piece::new(color, ptype)).iter() {
+			let attacks = match ptype {
+				PieceType::Knight => KNIGHT_PATTERNS[from],
+				PieceType::Bishop => cast_diagonals(from, pieces),
+				PieceType::Rook => cast_cardinals(from, pieces),
+				_ => cast_cardinals(from, pieces) | cast_diagonals(from, pieces),
+			};
+			val += weight * (attacks & safe).count() as i16;
+		}
+	}
+	for rook in board.find_piece(Piece::new(color, PieceType::Rook)).iter() {
+		let file = Bb::file(rook.file());
+		if ((own_pawns | enemy_pawns) & file).none() {
+			val += ROOK_OPEN_FILE;
+		} else if (own_pawns & file).none() {
+			val += ROOK_SEMI_OPEN_FILE;
+		}
+		if color.rel_rank(rook.rank()) == 6 {
+			val += ROOK_SEVENTH_RANK;
+		}
+	}
+	val
+}
+
 fn is_endgame(board: &Board, color: Color) -> bool {
 	let queens = board.count_pieces(color, PieceType::Queen);
 	let minor = board.count_pieces(color, PieceType::Knight)
@@ -170,79 +417,1098 @@ fn is_endgame(board: &Board, color: Color) -> bool {
 	return queens == 0 || (minor <= 1 && other  == 0);
 }
 
-fn eval(board: &Board, color: Color) -> i16 {
+// static evaluation from the point of view of `color`
+pub fn eval(board: &Board, color: Color) -> i16 {
 	let is_endgame = is_endgame(board, color);
-	eval_side(board, color, is_endgame) - eval_side(board, color.opponent(), is_endgame)
+	let mut val = eval_side(board, color, is_endgame) - eval_side(board, color.opponent(), is_endgame);
+	val += eval_activity(board, color) - eval_activity(board, color.opponent());
+	val += eval_pins(board, color) - eval_pins(board, color.opponent());
+	if !is_endgame {
+		val += eval_king_safety(board, color) - eval_king_safety(board, color.opponent());
+	}
+	val
 }
 
-fn negamax(pos: &Position, depth: u32, min: i16, max: i16) -> i16 {
-	let color = pos.side_to_move();
-	if depth == 0 {
-		return eval(pos.get_board(), color);
+// fingerprint of the evaluation function, computed on a fixed set of positions,
+// so that results can be attributed to a specific version of the evaluation
+pub fn eval_signature() -> u32 {
+	const SIGNATURE_FENS: [&str; 4] = [
+		Position::FEN_INITIAL,
+		"r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+		"r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+		"8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+	];
+	// FNV-1a
+	let mut hash: u32 = 0x811c9dc5;
+	for fen in SIGNATURE_FENS {
+		let pos = Position::from_fen(fen).unwrap();
+		for color in [Color::White, Color::Black] {
+			for byte in eval(pos.get_board(), color).to_le_bytes() {
+				hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+			}
+		}
 	}
-	let mut moves = pos.gen_pseudolegal();
-	if moves.len() == 0 {
-		if pos.is_in_check(color) {
-			return -std::i16::MAX; // checkmate
-		} else {
-			return 0; // stalemate
+	hash
+}
+
+// default transposition table size, in MB
+const TT_SIZE: usize = 16;
+
+// score of a mate at the root; a mate `n` plies away scores MATE - n, so that
+// faster mates are preferred and slower ones are resisted
+pub const MATE: i16 = 30000;
+const MAX_MATE_PLIES: i16 = 1000;
+// returned by nodes whose last move left its own king in check
+const ILLEGAL: i16 = i16::MAX;
+
+pub fn is_mate_score(score: i16) -> bool {
+	score.saturating_abs() >= MATE - MAX_MATE_PLIES
+}
+// moves until the mate a score announces, negative if it is the side the score
+// is relative to that gets mated
+pub fn mate_in(score: i16) -> Option<i16> {
+	if !is_mate_score(score) {
+		None
+	} else if score > 0 {
+		Some((MATE.saturating_sub(score).max(0) + 1) / 2)
+	} else {
+		Some(-(MATE.saturating_add(score).max(0) / 2))
+	}
+}
+
+// the transposition table stores mate scores relative to the node rather than
+// the root, as the same position can be reached at different plies
+fn score_to_tt(score: i16, ply: usize) -> i16 {
+	if !is_mate_score(score) { score } else if score > 0 { score + ply as i16 } else { score - ply as i16 }
+}
+fn score_from_tt(score: i16, ply: usize) -> i16 {
+	if !is_mate_score(score) { score } else if score > 0 { score - ply as i16 } else { score + ply as i16 }
+}
+
+// evaluation function of the search
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EvalBackend {
+	Classical, // see eval
+	#[cfg(feature = "nnue")]
+	Nnue, // the network set with nnue::set_network
+}
+impl EvalBackend {
+	pub fn parse(s: &str) -> Result<EvalBackend, String> {
+		match s {
+			"classical" => Ok(EvalBackend::Classical),
+			#[cfg(feature = "nnue")]
+			"nnue" => Ok(EvalBackend::Nnue),
+			#[cfg(not(feature = "nnue"))]
+			"nnue" => Err(format!("the NNUE evaluation needs a build with --features nnue")),
+			_ => Err(format!("unknown evaluation: {} (expected classical or nnue)", s)),
+		}
+	}
+}
+impl std::fmt::Display for EvalBackend {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str(match self {
+			EvalBackend::Classical => "classical",
+			#[cfg(feature = "nnue")]
+			EvalBackend::Nnue => "nnue",
+		})
+	}
+}
+
+// settings of the search, mostly pruning ones which trade accuracy for depth
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SearchParams {
+	// late move reductions: quiet moves after the first few are searched
+	// `lmr_reduction` plies shallower, and again at full depth if they look good
+	pub lmr_min_depth: u32,
+	pub lmr_min_moves: usize,
+	pub lmr_reduction: u32, // 0 disables LMR
+	// futility pruning: near the leaves, quiet moves are skipped when the static
+	// evaluation is more than `futility_margin` per remaining ply below alpha
+	pub futility_max_depth: u32, // 0 disables futility pruning
+	pub futility_margin: i16,
+	pub eval: EvalBackend,
+	// draws (by repetition, the 50-move rule or stalemate) score `-contempt` for the
+	// side to move at the root: positive to avoid them, negative to seek them
+	pub contempt: i16,
+}
+impl Default for SearchParams {
+	fn default() -> Self {
+		SearchParams {
+			lmr_min_depth: 3, lmr_min_moves: 4, lmr_reduction: 1, futility_max_depth: 2, futility_margin: 200, eval: EvalBackend::Classical,
+			contempt: 0,
+		}
+	}
+}
+impl SearchParams {
+	// changes to the default parameters, as "lmr_reduction=2,futility_margin=150"
+	pub fn parse(s: &str) -> Result<SearchParams, String> {
+		let mut params = SearchParams::default();
+		for assignment in s.split(',').filter(|s| !s.is_empty()) {
+			let (name, value) = assignment.split_once('=')
+				.ok_or_else(|| format!("invalid search parameter: {} (expected name=value)", assignment))?;
+			let invalid = || format!("invalid value for {}: {}", name, value);
+			match name {
+				"lmr_min_depth" => params.lmr_min_depth = value.parse().map_err(|_| invalid())?,
+				"lmr_min_moves" => params.lmr_min_moves = value.parse().map_err(|_| invalid())?,
+				"lmr_reduction" => params.lmr_reduction = value.parse().map_err(|_| invalid())?,
+				"futility_max_depth" => params.futility_max_depth = value.parse().map_err(|_| invalid())?,
+				"futility_margin" => params.futility_margin = value.parse().map_err(|_| invalid())?,
+				"eval" => params.eval = EvalBackend::parse(value)?,
+				"contempt" => params.contempt = value.parse().map_err(|_| invalid())?,
+				_ => return Err(format!("unknown search parameter: {}", name)),
+			}
+		}
+		Ok(params)
+	}
+}
+impl std::fmt::Display for SearchParams {
+	// the parameters that differ from the defaults, in the format parse accepts
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let default = SearchParams::default();
+		let fields = [
+			("lmr_min_depth", self.lmr_min_depth.to_string(), default.lmr_min_depth.to_string()),
+			("lmr_min_moves", self.lmr_min_moves.to_string(), default.lmr_min_moves.to_string()),
+			("lmr_reduction", self.lmr_reduction.to_string(), default.lmr_reduction.to_string()),
+			("futility_max_depth", self.futility_max_depth.to_string(), default.futility_max_depth.to_string()),
+			("futility_margin", self.futility_margin.to_string(), default.futility_margin.to_string()),
+			("eval", self.eval.to_string(), default.eval.to_string()),
+			("contempt", self.contempt.to_string(), default.contempt.to_string()),
+		];
+		let changed: Vec<String> = fields.into_iter().filter(|(_, value, default)| value != default)
+			.map(|(name, value, _)| format!("{}={}", name, value)).collect();
+		write!(f, "{}", changed.join(","))
+	}
+}
+
+// state shared by all nodes of a search
+struct Search<'a> {
+	tt: &'a TranspositionTable, // shared with the other threads of the search
+	deadline: Option<Instant>,
+	stop: Option<&'a AtomicBool>, // set from another thread to abort the search
+	max_nodes: Option<u64>,
+	aborted: bool,
+	nodes: u64,
+	ply: usize, // distance from the root of the current node
+	killers: Vec<[Option<Move>; 2]>, // quiet moves that caused cutoffs, per ply
+	params: SearchParams,
+	root_depth: u32, // depth of the current iteration, which bounds the extensions
+	rng: StdRng, // breaks ties between root moves
+	path: Vec<u64>, // keys of the positions of the game, then of the current line, for repetitions
+	root_color: Color, // draws are scored from its perspective
+}
+impl<'a> Search<'a> {
+	fn new(tt: &'a TranspositionTable, deadline: Option<Instant>) -> Search<'a> {
+		Search {
+			tt, deadline, stop: None, max_nodes: None, aborted: false, nodes: 0, ply: 0, killers: vec![], params: SearchParams::default(), root_depth: 0,
+			rng: StdRng::seed_from_u64(0), path: vec![], root_color: Color::White,
+		}
+	}
+
+	// score of a child node, from the perspective of the parent
+	fn search_child(&mut self, pos: &Position, depth: u32, min: i16, max: i16) -> i16 {
+		self.ply += 1;
+		self.path.push(pos.hash_key());
+		let score = -self.negamax(pos, depth, -max, -min);
+		self.path.pop();
+		self.ply -= 1;
+		score
+	}
+
+	fn draw_score(&self, color: Color) -> i16 {
+		if color == self.root_color { -self.params.contempt } else { self.params.contempt }
+	}
+
+	// the 50-move rule, or a position seen since the last capture or pawn move;
+	// a single repetition is enough, as the line could be repeated again
+	fn is_draw(&self, pos: &Position, key: u64) -> bool {
+		let clock = pos.halfmove_clock() as usize;
+		clock >= 100 || self.path.iter().rev().skip(1).take(clock).any(|prev| *prev == key)
+	}
+
+	// static evaluation from the perspective of the side to move
+	fn evaluate(&self, pos: &Position) -> i16 {
+		match self.params.eval {
+			EvalBackend::Classical => eval(pos.get_board(), pos.side_to_move()),
+			// positions outside of search_root have no accumulator
+			#[cfg(feature = "nnue")]
+			EvalBackend::Nnue => pos.nnue_eval().unwrap_or_else(|| eval(pos.get_board(), pos.side_to_move())),
+		}
+	}
+
+	fn store_killer(&mut self, mov: Move) {
+		if self.killers.len() <= self.ply {
+			self.killers.resize(self.ply + 1, [None; 2]);
+		}
+		let killers = &mut self.killers[self.ply];
+		if killers[0] != Some(mov) {
+			killers[1] = killers[0];
+			killers[0] = Some(mov);
+		}
+	}
+
+	fn negamax(&mut self, pos: &Position, depth: u32, min: i16, max: i16) -> i16 {
+		self.nodes += 1;
+		if self.nodes & 1023 == 0 && (self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+				|| self.stop.is_some_and(|stop| stop.load(Ordering::Relaxed))) {
+			self.aborted = true;
+		}
+		if self.max_nodes.is_some_and(|max_nodes| self.nodes > max_nodes) {
+			self.aborted = true;
+		}
+		if self.aborted {
+			return 0; // the result will be discarded anyway
+		}
+		let color = pos.side_to_move();
+		// moves are pseudolegal, so the king can be left in check
+		if pos.is_in_check(color.opponent()) {
+			return ILLEGAL;
+		}
+		let key = *self.path.last().unwrap(); // pushed by search_child
+		if self.is_draw(pos, key) {
+			return self.draw_score(color);
+		}
+		// forcing positions are searched one ply deeper, up to twice the nominal depth:
+		// checks, and positions with a single legal move (only looked for away from
+		// the leaves, as it takes generating the legal moves)
+		let in_check = pos.is_in_check(color);
+		let extend = self.ply < 2 * self.root_depth as usize
+			&& (in_check || (depth >= 2 && pos.gen_legal().len() == 1));
+		let depth = depth + extend as u32;
+		if depth == 0 {
+			return self.evaluate(pos);
+		}
+		let entry = self.tt.probe(key);
+		if let Some(entry) = entry {
+			if entry.depth as u32 >= depth {
+				let score = score_from_tt(entry.score, self.ply);
+				match entry.bound {
+					Bound::Exact => return score.clamp(min, max),
+					Bound::Lower if score >= max => return max,
+					Bound::Upper if score <= min => return min,
+					_ => {},
+				}
+			}
+		}
+		let hash_move = entry.and_then(|entry| entry.best_move);
+		let killers = self.killers.get(self.ply).copied().unwrap_or_default();
+		let params = self.params;
+		// quiet moves cannot raise the score enough; mates are never pruned
+		let futile = depth <= params.futility_max_depth && !in_check && !is_mate_score(min)
+			&& self.evaluate(pos) as i32 + params.futility_margin as i32 * depth as i32 <= min as i32;
+		let mut cur_max = min;
+		let mut best_move = None;
+		let mut has_moves = false;
+		let mut move_count = 0;
+		for mov in MoveGen::new(pos, hash_move, killers) {
+			let mut pos2 = pos.clone();
+			pos2.apply_move(&mov);
+			// checks and captures are always searched, and so is a first legal move,
+			// so that the absence of legal moves is still detected
+			let quiet = pos.is_quiet(&mov) && !pos2.is_in_check(pos2.side_to_move());
+			if futile && quiet && has_moves {
+				continue;
+			}
+			let reduce = params.lmr_reduction > 0 && depth >= params.lmr_min_depth
+				&& move_count >= params.lmr_min_moves && quiet && !in_check;
+			let mut score = if reduce {
+				self.search_child(&pos2, (depth - 1).saturating_sub(params.lmr_reduction), cur_max, max)
+			} else {
+				self.search_child(&pos2, depth - 1, cur_max, max)
+			};
+			// a reduced move that looks better than expected is searched again at full depth
+			if reduce && score > cur_max && !self.aborted {
+				score = self.search_child(&pos2, depth - 1, cur_max, max);
+			}
+			if self.aborted {
+				return 0;
+			}
+			if score == -ILLEGAL {
+				continue;
+			}
+			has_moves = true;
+			move_count += 1;
+			if score > cur_max {
+				cur_max = score;
+				best_move = Some(mov);
+				if cur_max >= max {
+					if pos.is_quiet(&mov) {
+						self.store_killer(mov);
+					}
+					self.tt.store(key, depth as u8, score_to_tt(max, self.ply), Bound::Lower, best_move);
+					return max;
+				}
+			}
+		}
+		if !has_moves {
+			if pos.is_in_check(color) {
+				return -(MATE - self.ply as i16); // checkmate
+			} else {
+				return self.draw_score(color); // stalemate
+			}
+		}
+		let bound = if best_move.is_some() { Bound::Exact } else { Bound::Upper };
+		self.tt.store(key, depth as u8, score_to_tt(cur_max, self.ply), bound, best_move);
+		return cur_max;
+	}
+
+	fn hashfull(&self) -> u32 {
+		self.tt.hashfull()
+	}
+
+	// follows the best moves stored in the transposition table after the root move
+	fn principal_variation(&self, pos: &Position, first: Move, max_len: u32) -> Vec<Move> {
+		let mut pv = vec![first];
+		let mut pos = pos.clone();
+		pos.apply_move(&first);
+		while pv.len() < max_len as usize {
+			let mov = match self.tt.probe(pos.hash_key()).and_then(|entry| entry.best_move) {
+				Some(mov) if pos.gen_legal().contains(&mov) => mov,
+				_ => break,
+			};
+			pv.push(mov);
+			pos.apply_move(&mov);
+		}
+		pv
+	}
+
+	// searches every root move with a full window, breaking ties randomly;
+	// returns None if the search was aborted before completion
+	fn search_root(&mut self, pos: &Position, legal_moves: &[Move], depth: u32) -> Option<(Move, i16)> {
+		self.root_depth = depth;
+		self.root_color = pos.side_to_move();
+		#[cfg(feature = "nnue")]
+		let pos = &{
+			let mut pos = pos.clone();
+			if self.params.eval == EvalBackend::Nnue {
+				pos.enable_nnue();
+			}
+			pos
+		};
+		let mut max = std::i16::MIN;
+		let mut best_move = None;
+		self.path.push(pos.hash_key());
+		for mov in legal_moves {
+			let mut pos2 = pos.clone();
+			pos2.apply_move(mov);
+			let score = self.search_child(&pos2, depth - 1, -std::i16::MAX, std::i16::MAX);
+			if self.aborted {
+				break;
+			}
+			if score > max || (score == max && self.rng.gen_bool(0.5)) {
+				max = score;
+				best_move = Some(*mov);
+			}
+		}
+		self.path.pop();
+		if self.aborted {
+			return None;
+		}
+		best_move.map(|mov| (mov, max))
+	}
+}
+
+// moves of a position in the order the search tries them, generated in stages so
+// that a cutoff saves generating the later ones: the hash move, captures and
+// promotions, the killer moves, then the other quiet moves; within a stage,
+// moves are ordered by the static evaluation of the resulting position
+pub struct MoveGen<'a> {
+	pos: &'a Position,
+	next_stage: Stage,
+	hash_move: Option<Move>,
+	killers: [Option<Move>; 2],
+	moves: MoveList, // rest of the current stage, best move last
+}
+#[derive(Clone, Copy)]
+enum Stage {
+	HashMove,
+	Noisy,
+	Killers,
+	Quiet,
+	Done,
+}
+impl<'a> MoveGen<'a> {
+	// the hash move and killers may come from other positions, and are only
+	// tried if they are pseudolegal here
+	pub fn new(pos: &'a Position, hash_move: Option<Move>, killers: [Option<Move>; 2]) -> MoveGen<'a> {
+		let hash_move = hash_move.filter(|mov| pos.is_pseudolegal(mov));
+		let killers = killers.map(|killer| killer.filter(|mov|
+			Some(*mov) != hash_move && pos.is_quiet(mov) && pos.is_pseudolegal(mov)));
+		MoveGen { pos, next_stage: Stage::HashMove, hash_move, killers, moves: MoveList::new() }
+	}
+
+	// moves of a stage not tried in an earlier one, best move last
+	fn sorted(&self, mut moves: MoveList) -> MoveList {
+		let color = self.pos.side_to_move();
+		moves.retain(|mov| Some(*mov) != self.hash_move && !self.killers.contains(&Some(*mov)));
+		moves.sort_by_cached_key(|mov| {
+			let mut pos2 = self.pos.clone();
+			pos2.apply_move(mov);
+			eval(pos2.get_board(), color)
+		});
+		moves
+	}
+}
+impl Iterator for MoveGen<'_> {
+	type Item = Move;
+	fn next(&mut self) -> Option<Move> {
+		loop {
+			if let Some(mov) = self.moves.pop() {
+				return Some(mov);
+			}
+			self.next_stage = match self.next_stage {
+				Stage::HashMove => {
+					self.moves.extend(self.hash_move);
+					Stage::Noisy
+				},
+				Stage::Noisy => {
+					self.moves = self.sorted(self.pos.gen_noisy());
+					Stage::Killers
+				},
+				Stage::Killers => {
+					self.moves = self.killers.iter().rev().flatten().copied().collect();
+					Stage::Quiet
+				},
+				Stage::Quiet => {
+					self.moves = self.sorted(self.pos.gen_quiet());
+					Stage::Done
+				},
+				Stage::Done => return None,
+			};
 		}
 	}
-	moves.sort_by_cached_key(|mov| {
+}
+
+fn sort_by_static_eval(pos: &Position, legal_moves: &[Move]) -> Vec<Move> {
+	let color = pos.side_to_move();
+	let mut legal_moves = legal_moves.to_owned();
+	legal_moves.sort_by_cached_key(|mov| {
 		let mut pos2 = pos.clone();
 		pos2.apply_move(mov);
 		-eval(pos2.get_board(), color)
 	});
-	let mut cur_max = min;
-	for mov in moves {
+	legal_moves
+}
+
+// scores every legal move with a full-window search, best move first
+pub fn score_moves(pos: &Position, legal_moves: &[Move], depth: u32) -> Vec<(Move, i16)> {
+	score_moves_until(pos, legal_moves, depth, &AtomicBool::new(false)).unwrap()
+}
+// the same, or None if `stop` is set from another thread before the end
+pub fn score_moves_until(pos: &Position, legal_moves: &[Move], depth: u32, stop: &AtomicBool) -> Option<Vec<(Move, i16)>> {
+	debug_assert!(depth >= 1);
+	let tt = TranspositionTable::new(1);
+	let mut search = Search::new(&tt, None);
+	search.stop = Some(stop);
+	search.root_depth = depth;
+	search.root_color = pos.side_to_move();
+	search.path.push(pos.hash_key());
+	let mut scored = vec![];
+	for mov in legal_moves {
 		let mut pos2 = pos.clone();
-		pos2.apply_move(&mov);
-		let score = -negamax(&pos2, depth - 1, -max, -cur_max);
-		if score > cur_max {
-			cur_max = score;
-			if cur_max >= max {
-				return max;
+		pos2.apply_move(mov);
+		let score = search.search_child(&pos2, depth - 1, -std::i16::MAX, std::i16::MAX);
+		if search.aborted {
+			return None;
+		}
+		scored.push((*mov, score));
+	}
+	scored.sort_by_key(|(_, score)| -score);
+	Some(scored)
+}
+
+// nodes searched to a fixed depth from an empty transposition table; there is
+// no iterative deepening, whose move ordering depends on random tie-breaks,
+// so the count only changes with the search itself (see the bench binary)
+pub fn count_nodes(pos: &Position, depth: u32) -> u64 {
+	debug_assert!(depth >= 1);
+	let tt = TranspositionTable::new(TT_SIZE);
+	let mut search = Search::new(&tt, None);
+	search.search_root(pos, &sort_by_static_eval(pos, &pos.gen_legal()), depth);
+	search.nodes
+}
+
+// the shortest forced mate for the side to move in at most `max_plies` plies (so
+// a mate in 2 takes 3), as the line of moves to play, with the longest defense;
+// a brute-force search of every move to the given depth, so meant for short mates
+pub fn search_mate(pos: &Position, max_plies: u32) -> Option<Vec<Move>> {
+	(1..=max_plies).step_by(2).find_map(|plies| mate_attack(pos, plies))
+}
+
+// a mating line of exactly `plies` plies or fewer, for the side to move
+fn mate_attack(pos: &Position, plies: u32) -> Option<Vec<Move>> {
+	let mut children: Vec<(Move, Position, bool)> = pos.gen_legal().into_iter().map(|mov| {
+		let mut child = pos.clone();
+		child.apply_move(&mov);
+		let check = child.is_in_check(child.side_to_move());
+		(mov, child, check)
+	}).collect();
+	// checks first, as they lead to most mates; only they can mate right away
+	children.sort_by_key(|(_, _, check)| !check);
+	for (mov, child, check) in &children {
+		if !check && plies == 1 {
+			break;
+		}
+		let replies = child.gen_legal();
+		if replies.is_empty() {
+			if *check {
+				return Some(vec![*mov]);
+			}
+			continue; // stalemate
+		}
+		if plies >= 3 {
+			if let Some(line) = mate_defense(child, &replies, plies - 1) {
+				return Some([vec![*mov], line].concat());
 			}
 		}
 	}
-	return cur_max;
+	None
 }
 
+// the longest line in which every reply gets mated within `plies` plies, if they
+// all do, with the shortest mate after each
+fn mate_defense(pos: &Position, replies: &[Move], plies: u32) -> Option<Vec<Move>> {
+	let mut longest: Option<Vec<Move>> = None;
+	for reply in replies {
+		let mut child = pos.clone();
+		child.apply_move(reply);
+		let line = search_mate(&child, plies - 1)?;
+		if longest.as_ref().is_none_or(|longest| line.len() + 1 > longest.len()) {
+			longest = Some([vec![*reply], line].concat());
+		}
+	}
+	longest
+}
+
+// time to spend on the next move given the remaining clock time
+pub fn time_budget(remaining: Duration, increment: Duration) -> Duration {
+	const MOVE_OVERHEAD: Duration = Duration::from_millis(100); // network lag, etc.
+	let budget = remaining / 30 + increment;
+	let max = remaining.saturating_sub(MOVE_OVERHEAD) / 2;
+	budget.min(max)
+}
+
+// result of a timed search
+#[derive(Clone)]
+pub struct SearchOutcome {
+	pub best_move: Move,
+	pub score: i16, // from the perspective of the side to move
+	pub mate: Option<i16>, // moves to a forced mate, negative if the side to move gets mated
+	pub depth: u32, // last completed iteration
+	pub pv: Vec<Move>, // expected line, starting with the best move
+	pub nodes: u64, // of the helper threads too, in the final outcome
+	pub elapsed: Duration,
+	pub hashfull: u32, // permill
+}
+impl SearchOutcome {
+	// outcome of an AI that does not search
+	fn unsearched(best_move: Move) -> SearchOutcome {
+		SearchOutcome { best_move, score: 0, mate: None, depth: 0, pv: vec![best_move], nodes: 0, elapsed: Duration::ZERO, hashfull: 0 }
+	}
+}
+
+// With several threads, the search is a "lazy SMP" one: helper threads search
+// the same position in parallel, and only share their results with the main
+// thread through the transposition table. They start with different root moves
+// and depths, so that they fill the table ahead of the main thread.
 pub struct SimpleAi {
 	depth: u32,
+	params: SearchParams,
+	threads: usize,
+	tt: TranspositionTable,
+	rng: Mutex<StdRng>, // seeds the generator of each search
+	history: Mutex<Vec<u64>>, // see set_history
 }
 impl SimpleAi {
 	pub fn new(depth: u32) -> SimpleAi {
-		SimpleAi { depth }
+		SimpleAi {
+			depth, params: SearchParams::default(), threads: 1, tt: TranspositionTable::new(TT_SIZE),
+			rng: Mutex::new(StdRng::from_entropy()), history: Mutex::new(vec![]),
+		}
+	}
+	// makes the choices between equal moves reproducible, with a single thread
+	pub fn with_seed(mut self, seed: u64) -> SimpleAi {
+		self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+		self
+	}
+	pub fn with_params(mut self, params: SearchParams) -> SimpleAi {
+		self.params = params;
+		self
+	}
+	pub fn with_threads(mut self, threads: usize) -> SimpleAi {
+		assert!(threads >= 1);
+		// browsers have no threads without extra setup, so helpers are never spawned there
+		self.threads = if cfg!(target_arch = "wasm32") { 1 } else { threads };
+		self
+	}
+
+	// keys of the positions of the game before the next one searched, oldest first,
+	// so that the search sees repetitions of them as draws
+	pub fn set_history(&self, keys: Vec<u64>) {
+		*self.history.lock().unwrap() = keys;
+	}
+
+	fn new_search(&self, deadline: Option<Instant>) -> Search<'_> {
+		let mut search = Search::new(&self.tt, deadline);
+		search.params = self.params;
+		search.rng = StdRng::seed_from_u64(self.rng.lock().unwrap().gen());
+		search.path = self.history.lock().unwrap().clone();
+		search
+	}
+
+	// runs `search` on the calling thread while the helper threads search the same
+	// position, until it returns; also returns the number of nodes the helpers searched
+	fn with_helpers<T>(&self, pos: &Position, legal_moves: &[Move], search: impl FnOnce() -> T) -> (T, u64) {
+		self.tt.new_search();
+		let stop = AtomicBool::new(false);
+		std::thread::scope(|scope| {
+			let helpers: Vec<_> = (1..self.threads)
+				.map(|id| scope.spawn({ let stop = &stop; move || self.help(pos, legal_moves, id, stop) }))
+				.collect();
+			let res = search();
+			stop.store(true, Ordering::Relaxed);
+			(res, helpers.into_iter().map(|helper| helper.join().unwrap()).sum())
+		})
+	}
+
+	// iterative deepening of a helper thread, until `stop` is set
+	fn help(&self, pos: &Position, legal_moves: &[Move], id: usize, stop: &AtomicBool) -> u64 {
+		let mut search = self.new_search(None);
+		search.stop = Some(stop);
+		let mut legal_moves = sort_by_static_eval(pos, legal_moves);
+		let first = id % legal_moves.len();
+		legal_moves[..=first].rotate_right(1);
+		for depth in 1 + id as u32 % 2..=self.depth {
+			match search.search_root(pos, &legal_moves, depth) {
+				Some((best_move, _)) => {
+					let idx = legal_moves.iter().position(|mov| *mov == best_move).unwrap();
+					legal_moves[..=idx].rotate_right(1);
+				},
+				None => break,
+			}
+		}
+		search.nodes
+	}
+
+	// iterative deepening up to the maximum depth, stopping when the time budget runs out
+	pub fn pick_move_timed(&self, pos: &Position, legal_moves: &[Move], budget: Duration) -> SearchOutcome {
+		self.search_reporting(pos, legal_moves, SearchLimits::within(budget), |_| {})
+	}
+
+	// iterative deepening up to the maximum depth, reporting every completed iteration,
+	// until `stop` is set
+	pub fn analyze(&self, pos: &Position, legal_moves: &[Move], stop: &AtomicBool, report: impl FnMut(&SearchOutcome)) {
+		self.search_reporting(pos, legal_moves, SearchLimits { stop: Some(stop), ..SearchLimits::default() }, report);
+	}
+
+	// searches the position after the reply the last search expected, until `stop`
+	// is set; if the opponent plays it, the next search finds the results in the
+	// transposition table, and quickly gets back to the depth pondering reached
+	pub fn ponder(&self, pos: &Position, stop: &AtomicBool) {
+		let Some(reply) = self.tt.probe(pos.hash_key()).and_then(|entry| entry.best_move)
+			.filter(|mov| pos.gen_legal().contains(mov)) else { return };
+		let mut pos2 = pos.clone();
+		pos2.apply_move(&reply);
+		let legal_moves = pos2.gen_legal();
+		if !legal_moves.is_empty() {
+			// the position before the reply is part of the game by then
+			let history = self.history.lock().unwrap().clone();
+			self.history.lock().unwrap().push(pos.hash_key());
+			self.search_reporting(&pos2, &legal_moves, SearchLimits { stop: Some(stop), ..SearchLimits::default() }, |_| {});
+			self.set_history(history);
+		}
+	}
+
+	// iterative deepening up to the maximum depth, until a limit is reached; `report`
+	// is called after every completed iteration (e.g. to print UCI info lines or log
+	// the progress), with the nodes of the main thread only
+	pub fn search_reporting(&self, pos: &Position, legal_moves: &[Move], limits: SearchLimits,
+			report: impl FnMut(&SearchOutcome)) -> SearchOutcome {
+		let (mut outcome, helper_nodes) = self.with_helpers(pos, legal_moves,
+			|| self.deepen_main(pos, legal_moves, limits, report));
+		outcome.nodes += helper_nodes;
+		outcome
+	}
+
+	fn deepen_main(&self, pos: &Position, legal_moves: &[Move], limits: SearchLimits,
+			mut report: impl FnMut(&SearchOutcome)) -> SearchOutcome {
+		let t0 = Instant::now();
+		let budget = limits.time;
+		let mut search = self.new_search(budget.map(|budget| t0 + budget));
+		search.stop = limits.stop;
+		search.max_nodes = limits.nodes;
+		let max_depth = limits.depth.map_or(self.depth, |depth| depth.min(self.depth));
+		let mut legal_moves = sort_by_static_eval(pos, legal_moves);
+		let mut completed_depth = 0;
+		let mut best_score = 0;
+		let outcome = |search: &Search, legal_moves: &[Move], score, depth| SearchOutcome {
+			best_move: legal_moves[0],
+			score,
+			mate: mate_in(score),
+			depth,
+			pv: search.principal_variation(pos, legal_moves[0], depth.max(1)),
+			nodes: search.nodes,
+			elapsed: Instant::now() - t0,
+			hashfull: search.hashfull(),
+		};
+		for depth in 1..=max_depth {
+			let iter_t0 = Instant::now();
+			if let Some((best_move, score)) = search.search_root(pos, &legal_moves, depth) {
+				best_score = score;
+				// search the best move first in the next iteration
+				let idx = legal_moves.iter().position(|mov| *mov == best_move).unwrap();
+				legal_moves[..=idx].rotate_right(1);
+				completed_depth = depth;
+				report(&outcome(&search, &legal_moves, score, depth));
+			} else {
+				break;
+			}
+			// the next iteration is unlikely to complete in the remaining time
+			let elapsed = Instant::now() - t0;
+			if budget.is_some_and(|budget| elapsed + (Instant::now() - iter_t0) * 4 > budget) {
+				break;
+			}
+		}
+		outcome(&search, &legal_moves, best_score, completed_depth)
 	}
 }
 impl ChessAi for SimpleAi {
 	fn name(&self) -> String {
 		return format!("SimpleAI {}", self.depth);
 	}
-	fn pick_move(&self, pos: &Position, legal_moves: &[Move]) -> Move {
+	fn search(&self, pos: &Position, legal_moves: &[Move], limits: SearchLimits) -> SearchOutcome {
+		let res = self.search_reporting(pos, legal_moves, limits, |_| {});
+		let stopped = limits.stop.is_some_and(|stop| stop.load(Ordering::Relaxed));
+		debug!("SimpleAi ({}): depth {} in {} ms (hashfull {}){}",
+			pos.side_to_move(), res.depth, res.elapsed.as_millis(), res.hashfull, if stopped { " (stopped)" } else { "" });
+		res
+	}
+	fn ponder(&self, pos: &Position, stop: &AtomicBool) {
+		SimpleAi::ponder(self, pos, stop);
+	}
+}
+
+// A weaker AI for human opponents, at a skill level from 1 to MAX_SKILL: it
+// searches shallower than SimpleAI, misjudges every move by a random amount,
+// and sometimes plays any move that looks nearly as good as the best one.
+// At the maximum skill, it plays the best move of a depth 5 search.
+pub struct LimitedAi {
+	skill: u32,
+	rng: Mutex<StdRng>,
+}
+impl LimitedAi {
+	pub const MAX_SKILL: u32 = 10;
+
+	pub fn new(skill: u32) -> LimitedAi {
+		assert!((1..=LimitedAi::MAX_SKILL).contains(&skill), "invalid skill level");
+		LimitedAi { skill, rng: Mutex::new(StdRng::from_entropy()) }
+	}
+	pub fn with_seed(mut self, seed: u64) -> LimitedAi {
+		self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+		self
+	}
+	fn depth(&self) -> u32 {
+		1 + self.skill * 2 / 5
+	}
+	// up to this many centipawns are added to or taken from each score
+	fn noise(&self) -> i16 {
+		(LimitedAi::MAX_SKILL - self.skill) as i16 * 25
+	}
+	// chance of playing any move within the margin of the best one
+	fn mistake_chance(&self) -> f64 {
+		(LimitedAi::MAX_SKILL - self.skill) as f64 * 0.04
+	}
+	fn mistake_margin(&self) -> i16 {
+		(LimitedAi::MAX_SKILL - self.skill) as i16 * 30
+	}
+}
+impl ChessAi for LimitedAi {
+	fn name(&self) -> String {
+		format!("LimitedAI {}", self.skill)
+	}
+	// the search is short, and not iterative, so the limits are ignored
+	fn search(&self, pos: &Position, legal_moves: &[Move], _limits: SearchLimits) -> SearchOutcome {
 		let t0 = Instant::now();
-		let color = pos.side_to_move();
-		let mut legal_moves = legal_moves.to_owned();
-		legal_moves.sort_by_cached_key(|mov| {
-			let mut pos2 = pos.clone();
-			pos2.apply_move(mov);
-			-eval(pos2.get_board(), color)
-		});
-		let mut max = std::i16::MIN;
-		let mut best_move = None;
-		for mov in legal_moves {
-			let mut pos2 = pos.clone();
-			pos2.apply_move(&mov);
-			let score = -negamax(&pos2, self.depth - 1, -std::i16::MAX, std::i16::MAX);
-			if score > max || (score == max && rand::random::<u8>() < 128) {
-				max = score;
-				best_move = Some(mov);
-			}
+		let mut rng = self.rng.lock().unwrap();
+		let noise = self.noise();
+		// mates are never misjudged
+		let mut scored: Vec<(Move, i16)> = score_moves(pos, legal_moves, self.depth()).into_iter()
+			.map(|(mov, score)| (mov, if is_mate_score(score) { score } else { score + rng.gen_range(-noise..=noise) }))
+			.collect();
+		scored.sort_by_key(|(_, score)| -score);
+		let best = scored[0].1;
+		let (best_move, score) = if rng.gen_bool(self.mistake_chance()) {
+			let candidates: Vec<(Move, i16)> = scored.into_iter()
+				.take_while(|(_, score)| *score >= best.saturating_sub(self.mistake_margin())).collect();
+			candidates[rng.gen_range(0..candidates.len())]
+		} else {
+			scored[0]
+		};
+		SearchOutcome { score, mate: mate_in(score), depth: self.depth(), elapsed: t0.elapsed(), ..SearchOutcome::unsearched(best_move) }
+	}
+}
+#[cfg(test)]
+mod test_eval {
+	use crate::{game::Position, state::Color};
+	use super::{eval, eval_activity, eval_king_safety};
+
+	fn king_safety(fen: &str, color: Color) -> i16 {
+		eval_king_safety(Position::from_fen(fen).unwrap().get_board(), color)
+	}
+
+	#[test]
+	fn test_king_safety() {
+		let initial = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		assert_eq!(eval(initial.get_board(), Color::White), 0);
+		// castled behind intact pawns, then with the shield pushed and broken
+		let castled = king_safety("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 6 6", Color::White);
+		let pushed = king_safety("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2NPP/PPPP1P2/R1BQ1RK1 w - - 0 7", Color::White);
+		let broken = king_safety("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1P2/R1BQ1RK1 w - - 0 7", Color::White);
+		assert!(castled > pushed && pushed > broken, "{} {} {}", castled, pushed, broken);
+		// pieces aimed at the king
+		let attacked = king_safety("r1b2rk1/pppp1ppp/2n5/2b1p3/2B1P1nq/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 6 6", Color::White);
+		assert!(attacked < castled, "{} {}", attacked, castled);
+	}
+
+	#[test]
+	fn test_activity() {
+		let activity = |fen: &str, color| eval_activity(Position::from_fen(fen).unwrap().get_board(), color);
+		// a developed knight moves more freely than one on its home square
+		assert!(activity("4k3/8/8/8/8/5N2/8/4K3 w - - 0 1", Color::White) > activity("4k3/8/8/8/8/8/8/4K1N1 w - - 0 1", Color::White));
+		// squares attacked by enemy pawns do not count
+		assert!(activity("4k3/8/8/8/8/5N2/8/4K3 w - - 0 1", Color::White) > activity("4k3/8/5p2/8/8/5N2/8/4K3 w - - 0 1", Color::White));
+		// rooks like open files and the 7th rank
+		let closed = activity("4k3/p7/8/8/8/8/P7/R3K3 w - - 0 1", Color::White);
+		let open = activity("4k3/p7/8/8/8/8/1P6/R3K3 w - - 0 1", Color::White);
+		let seventh = activity("4k3/R7/8/8/8/8/1P6/4K3 w - - 0 1", Color::White);
+		assert!(open > closed && seventh > open, "{} {} {}", closed, open, seventh);
+	}
+}
+
+#[cfg(test)]
+mod test_search {
+	use std::{sync::atomic::AtomicBool, time::{Duration, Instant}};
+	use crate::game::Position;
+	use crate::state::Move;
+	use crate::game::GameResult;
+	use super::{mate_in, score_moves_until, search_mate, ChessAi, LimitedAi, ParallelAi, RandomAi, SearchLimits, SearchParams, SimpleAi, MATE};
+
+	#[test]
+	fn test_mate_in() {
+		assert_eq!(mate_in(150), None);
+		assert_eq!(mate_in(MATE - 1), Some(1));
+		assert_eq!(mate_in(MATE - 3), Some(2));
+		assert_eq!(mate_in(-(MATE - 2)), Some(-1));
+		assert_eq!(mate_in(-(MATE - 4)), Some(-2));
+	}
+
+	#[test]
+	fn test_search_params() {
+		assert_eq!(SearchParams::parse("").unwrap(), SearchParams::default());
+		let params = SearchParams::parse("lmr_reduction=2,futility_margin=150").unwrap();
+		assert!(params.lmr_reduction == 2 && params.futility_margin == 150);
+		assert_eq!(params.lmr_min_depth, SearchParams::default().lmr_min_depth);
+		assert_eq!(params.to_string(), "lmr_reduction=2,futility_margin=150");
+		assert_eq!(SearchParams::parse(&params.to_string()).unwrap(), params);
+		assert!(SearchParams::parse("lmr=2").is_err());
+		assert!(SearchParams::parse("futility_margin=x").is_err());
+		assert_eq!(SearchParams::parse("eval=classical").unwrap(), SearchParams::default());
+		assert!(SearchParams::parse("eval=best").is_err());
+		assert_eq!(SearchParams::parse("contempt=-20").unwrap().contempt, -20);
+	}
+
+	#[test]
+	fn test_contempt() {
+		// Rh1h2 repeats the position after White's last move
+		let mut pos = Position::from_fen("4k3/r7/8/8/8/8/7R/4K3 b - - 0 1").unwrap();
+		let mut history = vec![];
+		for uci in ["a7a8", "h2h1", "a8a7"] {
+			history.push(pos.hash_key());
+			pos.apply_move(Move::parse_uci(uci, &pos.gen_legal()).ok().unwrap());
+		}
+		let best_move = |contempt, history: &[u64]| {
+			let ai = SimpleAi::new(1).with_params(SearchParams { contempt, ..SearchParams::default() });
+			ai.set_history(history.to_vec());
+			ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60)).best_move.uci_notation()
+		};
+		assert_eq!(best_move(-500, &history), "h1h2");
+		assert_ne!(best_move(500, &history), "h1h2");
+		// three rooks down, White has a perpetual check with Qe8+ and Qh5+, found as a
+		// repetition within the search, scored as a draw for any contempt below the loss
+		let pos = Position::from_fen("6k1/6p1/8/8/8/rrr5/4Q1PP/7K w - - 0 1").unwrap();
+		for contempt in [0, 100] {
+			let ai = SimpleAi::new(5).with_params(SearchParams { contempt, ..SearchParams::default() });
+			let outcome = ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60));
+			assert_eq!((outcome.best_move.uci_notation().as_str(), outcome.score), ("e2e8", -contempt));
 		}
-		println!("SimpleAi ({}): search completed in {} ms",
-			pos.side_to_move(),
-			(Instant::now() - t0).as_millis());
-		best_move.unwrap().clone()
+		// the 50-move rule
+		let pos = Position::from_fen("4k3/r7/8/8/8/8/7R/4K3 w - - 99 80").unwrap();
+		let ai = SimpleAi::new(2).with_params(SearchParams { contempt: 300, ..SearchParams::default() });
+		assert_eq!(ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60)).score, -300);
+	}
+
+	#[test]
+	fn test_mate_distance() {
+		let outcome = |fen: &str, depth| {
+			let pos = Position::from_fen(fen).unwrap();
+			SimpleAi::new(depth).pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60))
+		};
+		let mate1 = outcome("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+		assert_eq!(mate1.mate, Some(1));
+		assert_eq!(mate1.best_move.uci_notation(), "a1a8");
+		let mate2 = outcome("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1", 4);
+		assert_eq!(mate2.mate, Some(2));
+		assert_eq!(mate2.best_move.uci_notation(), "d5f6");
+		// the side getting mated knows it
+		let mated = outcome("6k1/5ppp/8/8/8/8/8/R5K1 b - - 0 1", 4);
+		assert_eq!(mated.mate, None);
+		let mated = outcome("k7/8/1K6/8/8/8/8/7R b - - 0 1", 4);
+		assert_eq!(mated.mate, Some(-1));
+	}
+
+	#[test]
+	fn test_search_mate() {
+		let line = |fen: &str, max_plies| {
+			let pos = Position::from_fen(fen).unwrap();
+			let line = search_mate(&pos, max_plies)?;
+			let mut end = pos.clone();
+			for mov in &line {
+				end.apply_move(mov);
+			}
+			assert_eq!(end.game_result(), Some(GameResult::Checkmate(pos.side_to_move())));
+			Some(line.iter().map(|mov| mov.uci_notation()).collect::<Vec<_>>())
+		};
+		assert_eq!(line("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 5).unwrap(), ["a1a8"]);
+		// Nf6+ gxf6 Bxf6#, or Kf8 Nd7#
+		let mate2 = "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1";
+		assert_eq!(line(mate2, 2), None);
+		assert_eq!(line(mate2, 3).unwrap()[0], "d5f6");
+		// the only way to force mate takes the opposition first
+		assert_eq!(line("k7/8/2K5/8/8/8/8/7R w - - 0 1", 3).unwrap(), ["c6b6", "a8b8", "h1h8"]);
+		// Qc7 would stalemate
+		assert_eq!(line("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1", 1).unwrap(), ["c1c8"]);
+		assert_eq!(line("k7/8/1K6/8/8/8/8/2Q5 b - - 0 1", 5), None);
+		assert_eq!(line(Position::FEN_INITIAL, 3), None);
+	}
+
+	#[test]
+	fn test_threads() {
+		let pos = Position::from_fen("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1").unwrap();
+		let ai = SimpleAi::new(4).with_threads(4);
+		let outcome = ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60));
+		assert_eq!((outcome.best_move.uci_notation(), outcome.mate), ("d5f6".to_owned(), Some(2)));
+		assert_eq!(ai.pick_move(&pos, &pos.gen_legal()).uci_notation(), "d5f6");
+	}
+
+	#[test]
+	fn test_report() {
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut reports = vec![];
+		let outcome = SimpleAi::new(4).search_reporting(&pos, &pos.gen_legal(), SearchLimits::default(), |outcome| reports.push(outcome.clone()));
+		assert_eq!(reports.iter().map(|report| report.depth).collect::<Vec<_>>(), [1, 2, 3, 4]);
+		assert!(reports.windows(2).all(|w| w[0].nodes < w[1].nodes && w[0].elapsed <= w[1].elapsed));
+		let last = reports.last().unwrap();
+		assert!(last.best_move == outcome.best_move && last.pv == outcome.pv && last.score == outcome.score);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_limits() {
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let moves = pos.gen_legal();
+		let ai = SimpleAi::new(6);
+		assert_eq!(ai.search(&pos, &moves, SearchLimits { depth: Some(2), ..SearchLimits::default() }).depth, 2);
+		let outcome = ai.search(&pos, &moves, SearchLimits { nodes: Some(2000), ..SearchLimits::default() });
+		assert!(outcome.nodes <= 2001 && outcome.depth < 6, "{} nodes, depth {}", outcome.nodes, outcome.depth);
+		// AIs that do not search still tell which move they play
+		let outcome = RandomAi::new(0).search(&pos, &moves, SearchLimits::default());
+		assert!(moves.contains(&outcome.best_move) && outcome.pv == [outcome.best_move] && outcome.depth == 0);
+		let outcome = LimitedAi::new(LimitedAi::MAX_SKILL).search(&pos, &moves, SearchLimits::default());
+		assert_eq!(outcome.depth, 5);
+	}
+
+	#[test]
+	fn test_score_moves_until() {
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let moves = pos.gen_legal();
+		assert_eq!(score_moves_until(&pos, &moves, 2, &AtomicBool::new(false)).unwrap().len(), 20);
+		assert!(score_moves_until(&pos, &moves, 5, &AtomicBool::new(true)).is_none());
+	}
+
+	#[test]
+	fn test_parallel_ai() {
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pos2 = pos.clone();
+		pos2.apply_move(&pos.gen_legal()[0]);
+		let mut bot = ParallelAi::new(SimpleAi::new(30));
+		// a search without limits is superseded, and stops
+		bot.search_async(&pos, &pos.gen_legal(), SearchLimits::default());
+		bot.search_async(&pos2, &pos2.gen_legal(), SearchLimits { depth: Some(2), ..SearchLimits::default() });
+		let t0 = Instant::now();
+		let (key, outcome) = loop {
+			if let Some(res) = bot.try_get_result() {
+				break res;
+			}
+			assert!(t0.elapsed() < Duration::from_secs(10), "the first search did not stop");
+			std::thread::sleep(Duration::from_millis(10));
+		};
+		assert_eq!((key, outcome.depth), (pos2.hash_key(), 2));
+		bot.search_async(&pos, &pos.gen_legal(), SearchLimits::default());
+		bot.cancel();
+		assert!(!bot.is_thinking());
+	}
+
+	#[test]
+	fn test_ponder() {
+		let pos = Position::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+		let ai = SimpleAi::new(4);
+		let outcome = ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60));
+		let mut pos = pos.clone();
+		pos.apply_move(&outcome.best_move);
+		ai.ponder(&pos, &AtomicBool::new(false));
+		// on a ponder hit, the search mostly finds its results in the table
+		pos.apply_move(&outcome.pv[1]);
+		let nodes = ai.pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60)).nodes;
+		let fresh_nodes = SimpleAi::new(4).pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60)).nodes;
+		assert!(nodes * 4 < fresh_nodes, "{} nodes after pondering, {} without", nodes, fresh_nodes);
+		// a stopped search still plays a legal move
+		let mov = ai.search(&pos, &pos.gen_legal(), SearchLimits { stop: Some(&AtomicBool::new(true)), ..SearchLimits::default() }).best_move;
+		assert!(pos.gen_legal().contains(&mov));
+	}
+
+	#[test]
+	fn test_limited_ai() {
+		let pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+		let mov = LimitedAi::new(LimitedAi::MAX_SKILL).pick_move(&pos, &pos.gen_legal());
+		assert_eq!(mov.uci_notation(), "a1a8");
+		// the weakest level plays legal moves, and not always the same
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let moves: Vec<Move> = (0..20).map(|_| LimitedAi::new(1).pick_move(&pos, &pos.gen_legal())).collect();
+		assert!(moves.iter().all(|mov| pos.gen_legal().contains(mov)));
+		assert!(moves.iter().any(|mov| *mov != moves[0]));
+	}
+
+	#[test]
+	fn test_extensions() {
+		// Nf6+ gxf6 Bxf7#: both checks are extended, so two plies are enough
+		let pos = Position::from_fen("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1").unwrap();
+		let outcome = SimpleAi::new(2).pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60));
+		assert_eq!(outcome.mate, Some(2));
+		assert_eq!(outcome.best_move.uci_notation(), "d5f6");
+	}
+
+	#[test]
+	fn test_seeds() {
+		// the same seed replays the same choices, between random moves and between equal moves
+		let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let moves = pos.gen_legal();
+		let random = |seed| { let ai = RandomAi::new(seed); (0..10).map(|_| ai.pick_move(&pos, &moves)).collect::<Vec<Move>>() };
+		assert_eq!(random(7), random(7));
+		assert_ne!(random(7), random(8));
+		let limited = |seed| { let ai = LimitedAi::new(1).with_seed(seed); (0..10).map(|_| ai.pick_move(&pos, &moves)).collect::<Vec<Move>>() };
+		assert_eq!(limited(7), limited(7));
+		// with bare kings, every move is a draw
+		let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+		let simple = |seed| SimpleAi::new(1).with_seed(seed).pick_move(&pos, &pos.gen_legal());
+		assert_eq!(simple(3), simple(3));
+		assert!((0..20).any(|seed| simple(seed) != simple(0)));
+	}
+}
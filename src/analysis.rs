@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::{
+	ai::{is_mate_score, score_moves, SearchLimits, SimpleAi, MATE},
+	game::{Game, Position},
+	pgn::Pgn,
+	state::Move,
+	wdl::WdlModel,
+};
+
+// minimum score gap (in centipawns) between the best and second best move
+// for the best move to be considered the only good move
+pub const ONLY_MOVE_MARGIN: i32 = 200;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Criticality {
+	Forced,   // there is a single legal move
+	OnlyMove, // the best move is much better than all alternatives
+	Normal,
+}
+
+pub struct PositionAnalysis {
+	scored_moves: Vec<(Move, i16)>, // best move first
+}
+impl PositionAnalysis {
+	pub fn new(pos: &Position, depth: u32) -> Option<PositionAnalysis> {
+		let legal_moves = pos.gen_legal();
+		if legal_moves.is_empty() {
+			return None;
+		}
+		Some(PositionAnalysis {
+			scored_moves: score_moves(pos, &legal_moves, depth),
+		})
+	}
+
+	pub fn scored_moves(&self) -> &[(Move, i16)] {
+		&self.scored_moves
+	}
+	pub fn best_move(&self) -> Move {
+		self.scored_moves[0].0
+	}
+	pub fn best_score(&self) -> i16 {
+		self.scored_moves[0].1
+	}
+	pub fn score_of(&self, mov: &Move) -> Option<i16> {
+		self.scored_moves.iter().find(|(m, _)| m == mov).map(|(_, score)| *score)
+	}
+
+	// score gap between the best and second best move, if there are several
+	pub fn margin(&self) -> Option<i32> {
+		let second = self.scored_moves.get(1)?;
+		Some(self.best_score() as i32 - second.1 as i32)
+	}
+
+	pub fn criticality(&self, margin: i32) -> Criticality {
+		match self.margin() {
+			None => Criticality::Forced,
+			Some(gap) if gap >= margin => Criticality::OnlyMove,
+			_ => Criticality::Normal,
+		}
+	}
+	pub fn is_only_move(&self) -> bool {
+		self.criticality(ONLY_MOVE_MARGIN) != Criticality::Normal
+	}
+}
+
+// how much a move gave away, as a loss of expected score
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoveJudgment {
+	Inaccuracy,
+	Mistake,
+	Blunder,
+}
+impl MoveJudgment {
+	// ?!, ? and ??
+	pub fn nag(self) -> u8 {
+		match self {
+			MoveJudgment::Inaccuracy => 6,
+			MoveJudgment::Mistake => 2,
+			MoveJudgment::Blunder => 4,
+		}
+	}
+}
+impl fmt::Display for MoveJudgment {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", match self {
+			MoveJudgment::Inaccuracy => "Inaccuracy",
+			MoveJudgment::Mistake => "Mistake",
+			MoveJudgment::Blunder => "Blunder",
+		})
+	}
+}
+
+// the smallest losses of expected score (from 0 to 1) of each judgment; the
+// defaults are those of lichess, which counts in winning chances from -1 to 1
+#[derive(Clone, Copy, Debug)]
+pub struct JudgmentThresholds {
+	pub inaccuracy: f64,
+	pub mistake: f64,
+	pub blunder: f64,
+}
+impl Default for JudgmentThresholds {
+	fn default() -> Self {
+		JudgmentThresholds { inaccuracy: 0.05, mistake: 0.1, blunder: 0.15 }
+	}
+}
+impl JudgmentThresholds {
+	pub fn judge(&self, loss: f64) -> Option<MoveJudgment> {
+		if loss >= self.blunder {
+			Some(MoveJudgment::Blunder)
+		} else if loss >= self.mistake {
+			Some(MoveJudgment::Mistake)
+		} else if loss >= self.inaccuracy {
+			Some(MoveJudgment::Inaccuracy)
+		} else {
+			None
+		}
+	}
+}
+
+// of a score from the side to move, mates being certain
+pub fn expected_score(score: i16) -> f64 {
+	if is_mate_score(score) {
+		if score > 0 { 1.0 } else { 0.0 }
+	} else {
+		WdlModel::default().expected_score(score as i32)
+	}
+}
+
+// a move of a game against the engine's choice, with scores from the side of the mover
+#[derive(Clone, Debug)]
+pub struct MoveReview {
+	pub played: Move,
+	pub score: i16, // after the move played
+	pub best: Move,
+	pub best_score: i16,
+	pub best_line: Vec<Move>, // from the position of the move
+	pub loss: f64, // of expected score, 0 for the best move
+	pub judgment: Option<MoveJudgment>,
+}
+
+// searches every position of a game once, the score of a move being that of the
+// position it leads to; `progress` is told of each search, with the number of positions
+pub fn review_game(pgn: &Pgn, ai: &SimpleAi, limits: SearchLimits, thresholds: JudgmentThresholds,
+		mut progress: impl FnMut(usize, usize)) -> Vec<MoveReview> {
+	let moves = pgn.moves();
+	let mut game = Game::new(pgn.start_position());
+	let mut searches = vec![];
+	for i in 0..=moves.len() {
+		let pos = game.position();
+		let legal_moves = pos.gen_legal();
+		progress(i, moves.len() + 1);
+		searches.push(if legal_moves.is_empty() {
+			// checkmated or stalemated
+			(None, if pos.is_in_check(pos.side_to_move()) { -MATE } else { 0 })
+		} else if i == moves.len() && pgn.draw_reason().is_some() {
+			(None, 0)
+		} else {
+			ai.set_history(game.previous_keys().to_vec());
+			let outcome = ai.search_reporting(pos, &legal_moves, limits, |_| {});
+			(Some((outcome.best_move, outcome.pv)), outcome.score)
+		});
+		if let Some(mov) = moves.get(i) {
+			game.push(*mov);
+		}
+	}
+	moves.iter().enumerate().map(|(i, &played)| {
+		let (best, best_score) = (searches[i].0.clone().expect("a move was played"), searches[i].1);
+		// mates are one ply further from the mover
+		let next = searches[i + 1].1;
+		let score = if !is_mate_score(next) { -next } else if next > 0 { -(next - 1) } else { -(next + 1) };
+		let loss = if played == best.0 { 0.0 } else { (expected_score(best_score) - expected_score(score)).max(0.0) };
+		MoveReview { played, score, best: best.0, best_score, best_line: best.1, loss, judgment: thresholds.judge(loss) }
+	}).collect()
+}
+
+#[cfg(test)]
+mod test_analysis {
+	use crate::{ai::{mate_in, SearchLimits, SimpleAi}, game::Position, pgn::Pgn, state::Move};
+
+	use super::{review_game, Criticality, JudgmentThresholds, MoveJudgment, PositionAnalysis, ONLY_MOVE_MARGIN};
+
+	fn analyze(fen: &str, depth: u32) -> (Position, PositionAnalysis) {
+		let pos = Position::from_fen(fen).unwrap();
+		let analysis = PositionAnalysis::new(&pos, depth).unwrap();
+		(pos, analysis)
+	}
+
+	#[test]
+	fn test_only_move() {
+		// back-rank mate
+		let (pos, analysis) = analyze("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+		let legal_moves = pos.gen_legal();
+		assert!(analysis.best_move() == *Move::parse_algebraic("Ra8", &legal_moves).ok().unwrap());
+		assert_eq!(analysis.criticality(ONLY_MOVE_MARGIN), Criticality::OnlyMove);
+	}
+
+	#[test]
+	fn test_forced() {
+		let (_, analysis) = analyze("R6k/6p1/8/8/8/8/8/6K1 b - - 0 1", 2);
+		assert_eq!(analysis.criticality(ONLY_MOVE_MARGIN), Criticality::Forced);
+		assert!(analysis.is_only_move());
+	}
+
+	#[test]
+	fn test_normal() {
+		let (_, analysis) = analyze(Position::FEN_INITIAL, 2);
+		assert_eq!(analysis.criticality(ONLY_MOVE_MARGIN), Criticality::Normal);
+	}
+
+	#[test]
+	fn test_review_game() {
+		let thresholds = JudgmentThresholds::default();
+		assert_eq!(thresholds.judge(0.02), None);
+		assert_eq!(thresholds.judge(0.12), Some(MoveJudgment::Mistake));
+		assert_eq!(thresholds.judge(0.5), Some(MoveJudgment::Blunder));
+
+		// 4...Nf6 allows the scholar's mate
+		let pgn = Pgn::parse("1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0").unwrap();
+		let limits = SearchLimits { depth: Some(3), ..Default::default() };
+		let mut searched = 0;
+		let reviews = review_game(&pgn, &SimpleAi::new(3), limits, thresholds, |i, total| {
+			assert_eq!((i, total), (searched, 8));
+			searched += 1;
+		});
+		assert_eq!(searched, 8);
+		assert_eq!(reviews.len(), 7);
+		let blunder = &reviews[5];
+		assert_eq!(blunder.judgment, Some(MoveJudgment::Blunder));
+		assert_eq!(mate_in(blunder.score), Some(-1));
+		assert_ne!(blunder.best, blunder.played);
+		let mate = &reviews[6];
+		assert_eq!((mate.best, mate.loss, mate.judgment), (mate.played, 0.0, None));
+		assert_eq!(mate_in(mate.best_score), Some(1));
+	}
+}
@@ -0,0 +1,144 @@
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use chesslib::{
+	ai::{mate_in, SearchLimits, SimpleAi},
+	analysis::{review_game, JudgmentThresholds, MoveJudgment, MoveReview},
+	game::Position,
+	pgn::Pgn,
+	state::{Color, Move},
+};
+
+const USAGE: &str = "usage: analyze [--time MS] [--depth N] [--inaccuracy PCT] [--mistake PCT] [--blunder PCT] [-o OUTPUT] PGN
+Runs SimpleAI on every position of the games of a PGN file, for --time ms (default: 500) up to
+--depth (default: 32) each, and writes the games annotated with the evaluation after each move
+([%eval] comments, from White's side), and for the moves that lost enough of the expected score
+(in percent, by default 5 for an inaccuracy, 10 for a mistake and 15 for a blunder), the NAG of
+their judgment and the line the engine preferred. The output goes to OUTPUT, or to the standard
+output, and a summary of each game to the standard error.";
+
+const MAX_DEPTH: u32 = 32;
+
+// a score from White's side in the [%eval] format: pawns, or #N for a mate in N
+fn format_eval(white_score: i16) -> String {
+	match mate_in(white_score) {
+		Some(moves) => format!("#{}", moves),
+		None => format!("{:.2}", white_score as f64 / 100.0),
+	}
+}
+
+fn san_line(pos: &Position, moves: &[Move]) -> String {
+	let mut pos = pos.clone();
+	let mut line = vec![];
+	for mov in moves {
+		line.push(pos.algebraic_notation(mov));
+		pos.apply_move(mov);
+	}
+	line.join(" ")
+}
+
+// fills in the annotations of the moves, keeping the comments already there
+fn annotate(pgn: &mut Pgn, reviews: &[MoveReview]) {
+	let positions = pgn.positions();
+	for (i, review) in reviews.iter().enumerate() {
+		let pos = &positions[i];
+		let white = |score: i16| if pos.side_to_move() == Color::White { score } else { -score };
+		let mut comment = format!("[%eval {}]", format_eval(white(review.score)));
+		if let Some(judgment) = review.judgment {
+			comment += &format!(" ({} \u{2192} {}) {}. {} was best ({}).", format_eval(white(review.best_score)),
+				format_eval(white(review.score)), judgment, pos.algebraic_notation(&review.best), san_line(pos, &review.best_line));
+		}
+		let annotation = pgn.annotation_mut(i);
+		if let Some(judgment) = review.judgment {
+			// replaces the move quality glyphs, !, ?, !!, ??, !? and ?!
+			annotation.nags.retain(|nag| !(1..=6).contains(nag));
+			annotation.nags.push(judgment.nag());
+		}
+		annotation.comment = Some(match annotation.comment.take() {
+			Some(prev) => format!("{} {}", prev, comment),
+			None => comment,
+		});
+	}
+}
+
+// judgments and average loss of each side
+fn summary(pgn: &Pgn, reviews: &[MoveReview]) -> String {
+	let first = pgn.start_position().side_to_move();
+	[Color::White, Color::Black].iter().map(|&color| {
+		let own: Vec<&MoveReview> = reviews.iter().enumerate()
+			.filter(|(i, _)| (i % 2 == 0) == (color == first))
+			.map(|(_, review)| review).collect();
+		let count = |judgment| own.iter().filter(|review| review.judgment == Some(judgment)).count();
+		let average = own.iter().map(|review| review.loss).sum::<f64>() * 100.0 / own.len().max(1) as f64;
+		format!("{}: inaccuracies {}, mistakes {}, blunders {}, {:.1}% of expected score lost per move on average",
+			color, count(MoveJudgment::Inaccuracy), count(MoveJudgment::Mistake), count(MoveJudgment::Blunder), average)
+	}).collect::<Vec<_>>().join("\n")
+}
+
+fn main() {
+	match (|| -> Result<(), String> {
+		let mut time = Duration::from_millis(500);
+		let mut depth = MAX_DEPTH;
+		let mut thresholds = JudgmentThresholds::default();
+		let mut output = None;
+		let mut input = None;
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			let percent = |name: &str, value: String| value.parse::<f64>().ok().filter(|pct| (0.0..=100.0).contains(pct))
+				.map(|pct| pct / 100.0).ok_or_else(|| format!("{} is not a percentage", name));
+			match arg.as_str() {
+				"--time" => time = Duration::from_millis(value("--time")?.parse()
+					.map_err(|_| format!("--time is not a number of milliseconds"))?),
+				"--depth" => depth = value("--depth")?.parse().ok().filter(|depth| *depth > 0)
+					.ok_or_else(|| format!("--depth is not a positive integer"))?,
+				"--inaccuracy" => thresholds.inaccuracy = percent("--inaccuracy", value("--inaccuracy")?)?,
+				"--mistake" => thresholds.mistake = percent("--mistake", value("--mistake")?)?,
+				"--blunder" => thresholds.blunder = percent("--blunder", value("--blunder")?)?,
+				"-o" => output = Some(PathBuf::from(value("-o")?)),
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ if input.is_none() => input = Some(PathBuf::from(arg)),
+				_ => return Err(format!("more than one PGN file given\n{}", USAGE)),
+			}
+		}
+		let Some(input) = input else {
+			return Err(format!("no PGN file given\n{}", USAGE));
+		};
+		if !(thresholds.inaccuracy <= thresholds.mistake && thresholds.mistake <= thresholds.blunder) {
+			return Err(format!("thresholds must satisfy inaccuracy <= mistake <= blunder"));
+		}
+
+		let games = Pgn::load(&input).map_err(|err| format!("could not load {}: {}", input.display(), err))?;
+		let limits = SearchLimits { depth: Some(depth), time: Some(time), ..Default::default() };
+		let mut annotated = String::new();
+		for (i, mut pgn) in games.into_iter().enumerate() {
+			let name = format!("game {} ({} - {})", i + 1,
+				pgn.get_tag("White").unwrap_or("?"), pgn.get_tag("Black").unwrap_or("?"));
+			// a fresh transposition table for each game
+			let ai = SimpleAi::new(MAX_DEPTH);
+			let reviews = review_game(&pgn, &ai, limits, thresholds, |ply, total| {
+				eprint!("\r{}: position {}/{}", name, ply + 1, total);
+			});
+			eprintln!("\n{}", summary(&pgn, &reviews));
+			annotate(&mut pgn, &reviews);
+			pgn.set_tag("Annotator", format!("SimpleAI, depth {} or {} ms per position", depth, time.as_millis()));
+			annotated += &format!("{}\n", pgn);
+		}
+
+		match output {
+			Some(path) => std::fs::write(&path, annotated)
+				.map_err(|err| format!("could not write {}: {}", path.display(), err))?,
+			None => std::io::stdout().write_all(annotated.as_bytes()).map_err(|err| format!("could not write the games: {}", err))?,
+		}
+		Ok(())
+	})() {
+		Ok(()) => {},
+		Err(err) => {
+			eprintln!("error: {}", err);
+			std::process::exit(2);
+		},
+	}
+}
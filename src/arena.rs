@@ -0,0 +1,222 @@
+use std::{fmt, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+	ai::{ChessAi, SearchParams, SimpleAi},
+	game::{Game, Position},
+	pgn::{Pgn, PgnResult},
+	state::{Color, Move},
+};
+
+// Games between engine settings, as played by the match and calibrate binaries:
+// pairs of games with swapped colors from the same opening, played in parallel,
+// with every random choice drawn from a seed so that a run can be replayed.
+
+pub const MAX_PLIES: usize = 300; // longer games are adjudicated as draws
+const MAX_TIMED_DEPTH: u32 = 32;
+
+#[derive(Clone, Copy)]
+pub enum Setting {
+	Random,
+	Depth(u32, SearchParams),
+	MoveTime(Duration, SearchParams),
+}
+impl Setting {
+	// dN, tN or random, with search parameters after a colon
+	pub fn parse(s: &str) -> Result<Setting, String> {
+		let value = |s: &str| s.parse::<u32>().ok().filter(|n| *n > 0);
+		let (s, params) = match s.split_once(':') {
+			Some((s, params)) => (s, SearchParams::parse(params)?),
+			None => (s, SearchParams::default()),
+		};
+		if s == "random" {
+			Ok(Setting::Random)
+		} else if let Some(depth) = s.strip_prefix('d').and_then(value) {
+			Ok(Setting::Depth(depth, params))
+		} else if let Some(ms) = s.strip_prefix('t').and_then(value) {
+			Ok(Setting::MoveTime(Duration::from_millis(ms as u64), params))
+		} else {
+			Err(format!("invalid engine: {} (expected dN, tN or random)", s))
+		}
+	}
+	pub fn params(&self) -> Option<&SearchParams> {
+		match self {
+			Setting::Random => None,
+			Setting::Depth(_, params) | Setting::MoveTime(_, params) => Some(params),
+		}
+	}
+}
+impl fmt::Display for Setting {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let params = |params: &SearchParams| if *params == SearchParams::default() { String::new() } else { format!(":{}", params) };
+		match self {
+			Setting::Random => write!(f, "random"),
+			Setting::Depth(depth, p) => write!(f, "d{}{}", depth, params(p)),
+			Setting::MoveTime(time, p) => write!(f, "t{}{}", time.as_millis(), params(p)),
+		}
+	}
+}
+
+// an engine playing with a given setting, keeping its state between moves;
+// random moves and ties are drawn from seeded generators so that games can be replayed
+pub struct Player {
+	setting: Setting,
+	ai: Option<SimpleAi>,
+	rng: StdRng,
+}
+impl Player {
+	pub fn new(setting: Setting, seed: u64) -> Player {
+		let ai = match setting {
+			Setting::Random => None,
+			Setting::Depth(depth, params) => Some(SimpleAi::new(depth).with_params(params).with_seed(seed)),
+			Setting::MoveTime(_, params) => Some(SimpleAi::new(MAX_TIMED_DEPTH).with_params(params).with_seed(seed)),
+		};
+		Player { setting, ai, rng: StdRng::seed_from_u64(seed) }
+	}
+	pub fn name(&self) -> String {
+		match &self.ai {
+			Some(ai) => format!("{} ({})", ai.name(), self.setting),
+			None => self.setting.to_string(),
+		}
+	}
+	pub fn pick_move(&mut self, pos: &Position, moves: &[Move], history: &[u64]) -> Move {
+		if let Some(ai) = &self.ai {
+			ai.set_history(history.to_vec());
+		}
+		match (self.setting, &self.ai) {
+			// the timed search is used for fixed depths too, as it does not log every move
+			(Setting::Depth(..), Some(ai)) => ai.pick_move_timed(pos, moves, Duration::from_secs(3600)).best_move,
+			(Setting::MoveTime(time, _), Some(ai)) => ai.pick_move_timed(pos, moves, time).best_move,
+			_ => moves[self.rng.gen_range(0..moves.len())],
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct Opening {
+	pub start: Position,
+	pub moves: Vec<Move>,
+}
+impl Opening {
+	// random moves from the initial position, so that games between deterministic
+	// engines differ; openings that end the game are avoided
+	pub fn random(plies: usize, rng: &mut StdRng) -> Opening {
+		let start = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		'retry: loop {
+			let mut pos = start.clone();
+			let mut moves = vec![];
+			for _ in 0..plies {
+				let legal = pos.gen_legal();
+				if legal.is_empty() {
+					continue 'retry;
+				}
+				let mov = legal[rng.gen_range(0..legal.len())];
+				pos.apply_move(&mov);
+				moves.push(mov);
+			}
+			if pos.game_result().is_none() {
+				return Opening { start, moves };
+			}
+		}
+	}
+	// the moves of a game, from its starting position
+	pub fn from_pgn(pgn: &Pgn) -> Opening {
+		Opening { start: pgn.start_position().clone(), moves: pgn.moves().to_vec() }
+	}
+}
+
+// plays a game from the given opening, drawn by the usual rules or adjudicated
+// after MAX_PLIES, with the players and the termination in the PGN tags
+pub fn play_game(opening: &Opening, white: Setting, black: Setting, seed: u64) -> Pgn {
+	let mut players = [Player::new(white, seed), Player::new(black, seed.wrapping_add(1))];
+	let mut game = Game::from_moves(&opening.start, &opening.moves);
+	let (result, termination) = loop {
+		if let Some(result) = game.result() {
+			break (result.into(), "normal");
+		}
+		if game.moves().len() >= MAX_PLIES {
+			break (PgnResult::Draw, "adjudication");
+		}
+		let pos = game.position();
+		let moves = pos.gen_legal();
+		let player = &mut players[if pos.side_to_move() == Color::White { 0 } else { 1 }];
+		let mov = player.pick_move(pos, &moves, game.previous_keys());
+		game.push(mov);
+	};
+	let mut pgn = game.to_pgn()
+		.tag("White", players[0].name())
+		.tag("Black", players[1].name());
+	pgn.set_result(result);
+	pgn.set_tag("Termination", termination);
+	pgn
+}
+
+// the score of a game for one side
+pub fn score(result: PgnResult, color: Color) -> f64 {
+	match result {
+		PgnResult::Win(winner) if winner == color => 1.0,
+		PgnResult::Win(_) => 0.0,
+		_ => 0.5,
+	}
+}
+
+// plays `games` games between two engines in parallel, engine1 having White in
+// even games and Black in odd ones, game i starting from opening i / 2 with the
+// seed `seed + 2 * i`; batches hold whole pairs so that colors stay balanced, and
+// after each batch `on_batch` gets the new games and returns whether to go on
+pub fn play_pairs(openings: &[Opening], engine1: Setting, engine2: Setting, games: usize, seed: u64,
+		mut on_batch: impl FnMut(&[Pgn]) -> bool) -> Vec<Pgn> {
+	debug_assert!(openings.len() >= games.div_ceil(2));
+	let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).next_multiple_of(2);
+	let mut jobs: Vec<usize> = (0..games).collect();
+	let mut pgns = vec![];
+	while !jobs.is_empty() {
+		let batch: Vec<usize> = jobs.drain(..threads.min(jobs.len())).collect();
+		pgns.extend(std::thread::scope(|scope| {
+			let handles: Vec<_> = batch.iter().map(|&game| {
+				let opening = &openings[game / 2];
+				let (white, black) = if game % 2 == 0 { (engine1, engine2) } else { (engine2, engine1) };
+				let game_seed = seed.wrapping_add(2 * game as u64);
+				scope.spawn(move || play_game(opening, white, black, game_seed).tag("Round", game + 1))
+			}).collect();
+			handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+		}));
+		if !on_batch(&pgns[pgns.len() - batch.len()..]) {
+			break;
+		}
+	}
+	pgns
+}
+
+#[cfg(test)]
+mod test_arena {
+	use rand::{rngs::StdRng, SeedableRng};
+
+	use super::{play_pairs, score, Opening, Setting};
+	use crate::state::Color;
+
+	#[test]
+	fn test_play_pairs() {
+		let mut rng = StdRng::seed_from_u64(1);
+		let openings: Vec<Opening> = (0..2).map(|_| Opening::random(4, &mut rng)).collect();
+		let (random, depth) = (Setting::parse("random").unwrap(), Setting::parse("d1").unwrap());
+		let play = || play_pairs(&openings, depth, random, 4, 7, |_| true);
+		let pgns = play();
+		assert_eq!(pgns.len(), 4);
+		// the same seed replays the same games
+		assert_eq!(pgns.iter().map(|pgn| pgn.to_string()).collect::<Vec<_>>(),
+			play().iter().map(|pgn| pgn.to_string()).collect::<Vec<_>>());
+		// both games of a pair start from the same opening, with colors swapped
+		assert_eq!(pgns[0].moves()[..4], pgns[1].moves()[..4]);
+		assert!(pgns[0].get_tag("White").unwrap().contains("d1"));
+		assert_eq!(pgns[1].get_tag("White"), Some("random"));
+		assert!(pgns.iter().all(|pgn| pgn.result() != crate::pgn::PgnResult::Unfinished));
+		assert!(score(pgns[0].result(), Color::White) + score(pgns[0].result(), Color::Black) == 1.0);
+
+		// stopping after the first batch
+		let mut batches = 0;
+		play_pairs(&openings, depth, random, 4, 7, |_| { batches += 1; false });
+		assert_eq!(batches, 1);
+	}
+}
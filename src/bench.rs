@@ -1,14 +1,64 @@
-use chesslib::{ai::{ChessAi, SimpleAi}, game::Position};
+use std::time::{Duration, Instant};
+
+use chesslib::{ai::count_nodes, game::Position};
+
+const USAGE: &str = "usage: bench [DEPTH]
+Searches a fixed set of positions to the given depth (default 5) and prints the
+nodes searched and the speed. The total node count only depends on the search
+itself, so a change to it means the search behaves differently.";
+
+const DEFAULT_DEPTH: u32 = 5;
+
+// openings, middlegames and endgames, none of them finished
+const BENCH_FENS: &[&str] = &[
+	Position::FEN_INITIAL,
+	"rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+	"r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+	"rnbqkb1r/pp2pppp/3p1n2/8/3NP3/2N5/PPP2PPP/R1BQKB1R b KQkq - 2 5",
+	"r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+	"r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+	"r1bq1rk1/ppp2ppp/2np1n2/2b1p3/2B1P3/2PP1N2/PP3PPP/RNBQ1RK1 w - - 1 7",
+	"2r3k1/pp3ppp/4p3/3pP3/1q1P4/1P3Q2/P4PPP/2R3K1 b - - 0 22",
+	"6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+	"8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+	"8/8/4k3/8/2p5/8/B2K4/8 w - - 0 1",
+	"8/5pk1/6p1/7p/7P/6P1/5PK1/8 w - - 0 1",
+];
 
 fn main() {
-	let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
-	let ai = SimpleAi::new(5);
-	loop {
-		let moves = pos.gen_legal();
-		if moves.len() == 0 {
-			break;
+	if let Err(err) = || -> Result<(), String> {
+		let mut depth = DEFAULT_DEPTH;
+		for arg in std::env::args().skip(1) {
+			match arg.as_str() {
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ => depth = arg.parse().ok().filter(|n| *n > 0)
+					.ok_or_else(|| format!("invalid depth: {}", arg))?,
+			}
+		}
+
+		let nps = |nodes: u64, time: Duration| (nodes as f64 / time.as_secs_f64().max(1e-9)) as u64;
+		let mut total_nodes = 0;
+		let mut total_time = Duration::ZERO;
+		for (i, fen) in BENCH_FENS.iter().enumerate() {
+			let pos = Position::from_fen(fen).unwrap();
+			let t0 = Instant::now();
+			let nodes = count_nodes(&pos, depth);
+			let time = t0.elapsed();
+			println!("{:>2}/{}  {:>10} nodes  {:>7} ms  {:>9} nps  {}",
+				i + 1, BENCH_FENS.len(), nodes, time.as_millis(), nps(nodes, time), fen);
+			total_nodes += nodes;
+			total_time += time;
 		}
-		let mov = ai.pick_move(&pos, &moves);
-		pos.apply_move(&mov);
+		println!("\ndepth {}", depth);
+		println!("nodes searched {}", total_nodes);
+		println!("total time     {} ms", total_time.as_millis());
+		println!("nodes/second   {}", nps(total_nodes, total_time));
+		Ok(())
+	}() {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
 	}
-}
\ No newline at end of file
+}
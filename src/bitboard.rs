@@ -1,45 +1,152 @@
-use core::fmt;
-use std::fmt::Write;
+use core::fmt::{self, Write};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
 
 use crate::state::Square;
 
 const fn rank_pattern(ranks: u8) -> u64 {
 	0x0101010101010101 * (ranks as u64)
 }
-fn first_bit(x: u64) -> Option<u8> {
-	let idx = x.trailing_zeros() as u8;
-	if idx == 64 { None } else { Some(idx) }
+
+// one step on the board, north being towards the 8th rank and east towards the h-file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+	North,
+	South,
+	East,
+	West,
+	NorthEast,
+	NorthWest,
+	SouthEast,
+	SouthWest,
 }
-fn last_bit(x: u64) -> Option<u8> {
-	let idx = x.leading_zeros() as u8;
-	if idx == 64 { None } else { Some(63 - idx) }
+impl Direction {
+	pub const ALL: [Direction; 8] = [
+		Direction::North, Direction::South, Direction::East, Direction::West,
+		Direction::NorthEast, Direction::NorthWest, Direction::SouthEast, Direction::SouthWest,
+	];
+	pub const fn opposite(self) -> Direction {
+		match self {
+			Direction::North => Direction::South,
+			Direction::South => Direction::North,
+			Direction::East => Direction::West,
+			Direction::West => Direction::East,
+			Direction::NorthEast => Direction::SouthWest,
+			Direction::NorthWest => Direction::SouthEast,
+			Direction::SouthEast => Direction::NorthWest,
+			Direction::SouthWest => Direction::NorthEast,
+		}
+	}
+	// whether a step increases the square index
+	pub const fn is_positive(self) -> bool {
+		matches!(self, Direction::North | Direction::East | Direction::NorthEast | Direction::SouthEast)
+	}
+	pub const fn is_diagonal(self) -> bool {
+		matches!(self, Direction::NorthEast | Direction::NorthWest | Direction::SouthEast | Direction::SouthWest)
+	}
+	// the direction to go from `a` to `b`, if they are different squares on a same line
+	pub fn towards(a: Square, b: Square) -> Option<Direction> {
+		let (dfile, drank) = (b.file() as i8 - a.file() as i8, b.rank() as i8 - a.rank() as i8);
+		if dfile != 0 && drank != 0 && dfile.abs() != drank.abs() {
+			return None;
+		}
+		Direction::ALL.into_iter().find(|dir| dir.delta() == (dfile.signum(), drank.signum()))
+	}
+	// (dfile, drank) of the step
+	pub const fn delta(self) -> (i8, i8) {
+		match self {
+			Direction::North => (0, 1),
+			Direction::South => (0, -1),
+			Direction::East => (1, 0),
+			Direction::West => (-1, 0),
+			Direction::NorthEast => (1, 1),
+			Direction::NorthWest => (-1, 1),
+			Direction::SouthEast => (1, -1),
+			Direction::SouthWest => (-1, -1),
+		}
+	}
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub struct Bb(pub u64);
 impl Bb {
 	pub const EMPTY: Bb = Bb(0);
-	pub fn one(squ: Square) -> Bb {
+	pub const ALL: Bb = Bb(!0);
+
+	// named regions, for evaluation and board overlays
+	pub const CENTER: Bb = Bb::rect(3, 4, 3, 4); // d4, e4, d5, e5
+	pub const EXTENDED_CENTER: Bb = Bb::rect(2, 5, 2, 5); // c3 to f6
+	pub const QUEENSIDE: Bb = Bb::rect(0, 3, 0, 7); // files a to d
+	pub const KINGSIDE: Bb = Bb::rect(4, 7, 0, 7); // files e to h
+	pub const DARK_SQUARES: Bb = Bb::color_complex(0); // a1, c1... h8
+	pub const LIGHT_SQUARES: Bb = Bb::color_complex(1);
+
+	// squares between files and ranks, inclusive
+	pub const fn rect(min_file: u8, max_file: u8, min_rank: u8, max_rank: u8) -> Bb {
+		let ranks = (0xffu8 >> (7 - max_rank + min_rank)) << min_rank;
+		let mut bits = 0;
+		let mut file = min_file;
+		while file <= max_file {
+			bits |= (ranks as u64) << (file * 8);
+			file += 1;
+		}
+		Bb(bits)
+	}
+	// squares where (file + rank) % 2 == parity
+	const fn color_complex(parity: u8) -> Bb {
+		let mut bits = 0;
+		let mut idx = 0u8;
+		while idx < 64 {
+			if ((idx >> 3) + (idx & 7)) % 2 == parity {
+				bits |= 1 << idx;
+			}
+			idx += 1;
+		}
+		Bb(bits)
+	}
+
+	pub const fn one(squ: Square) -> Bb {
 		Bb(1 << squ.idx)
 	}
-	pub fn at(self, squ: Square) -> bool {
+	pub const fn at(self, squ: Square) -> bool {
 		(self.0 >> squ.idx) & 1 == 1
 	}
-	pub fn rank(rank: u8) -> Bb {
+	pub const fn rank(rank: u8) -> Bb {
 		Bb(rank_pattern(1u8 << rank))
 	}
-	pub fn file(file: u8) -> Bb {
+	pub const fn file(file: u8) -> Bb {
 		debug_assert!(file < 8);
 		Bb(0x00000000000000ff).shift_right(file)
 	}
-	pub fn none(self) -> bool {
+	pub const fn none(self) -> bool {
 		self.0 == 0
 	}
-	pub fn count(self) -> u32 {
+	pub const fn count(self) -> u32 {
 		self.0.count_ones()
 	}
+	pub const fn more_than_one(self) -> bool {
+		self.0 & self.0.wrapping_sub(1) != 0
+	}
+	// the square with the lowest index (closest to a1, file by file)
+	pub const fn lsb(self) -> Option<Square> {
+		if self.0 == 0 { None } else { Some(Square { idx: self.0.trailing_zeros() as u8 }) }
+	}
+	// the square with the highest index (closest to h8)
+	pub const fn msb(self) -> Option<Square> {
+		if self.0 == 0 { None } else { Some(Square { idx: 63 - self.0.leading_zeros() as u8 }) }
+	}
+	pub fn pop_lsb(&mut self) -> Option<Square> {
+		let squ = self.lsb()?;
+		self.0 &= self.0 - 1;
+		Some(squ)
+	}
 }
 
+impl fmt::Debug for Bb {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Bb({:#018x})", self.0)
+	}
+}
 impl fmt::Display for Bb {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		writeln!(f, "Bb(")?;
@@ -54,51 +161,73 @@ impl fmt::Display for Bb {
 	}
 }
 
-impl std::ops::BitOr for Bb {
+impl core::ops::BitOr for Bb {
 	type Output = Bb;
 	fn bitor(self, rhs: Self) -> Bb {
 		Bb(self.0 | rhs.0)
 	}
 }
-impl std::ops::BitAnd for Bb {
+impl core::ops::BitAnd for Bb {
 	type Output = Bb;
 	fn bitand(self, rhs: Self) -> Bb {
 		Bb(self.0 & rhs.0)
 	}
 }
-impl std::ops::Not for Bb {
+impl core::ops::BitXor for Bb {
+	type Output = Bb;
+	fn bitxor(self, rhs: Self) -> Bb {
+		Bb(self.0 ^ rhs.0)
+	}
+}
+// squares of the left side that are not in the right side
+impl core::ops::Sub for Bb {
+	type Output = Bb;
+	fn sub(self, rhs: Self) -> Bb {
+		Bb(self.0 & !rhs.0)
+	}
+}
+impl core::ops::Not for Bb {
 	type Output = Bb;
 	fn not(self) -> Self::Output {
 		Bb(!self.0)
 	}
 }
-impl std::ops::BitOrAssign for Bb {
+impl core::ops::BitOrAssign for Bb {
 	fn bitor_assign(&mut self, rhs: Self) {
 		self.0 |= rhs.0;
 	}
 }
-impl std::ops::BitAndAssign for Bb {
+impl core::ops::BitAndAssign for Bb {
 	fn bitand_assign(&mut self, rhs: Self) {
 		self.0 &= rhs.0;
 	}
 }
+impl core::ops::BitXorAssign for Bb {
+	fn bitxor_assign(&mut self, rhs: Self) {
+		self.0 ^= rhs.0;
+	}
+}
+impl core::ops::SubAssign for Bb {
+	fn sub_assign(&mut self, rhs: Self) {
+		self.0 &= !rhs.0;
+	}
+}
+impl FromIterator<Square> for Bb {
+	fn from_iter<I: IntoIterator<Item=Square>>(iter: I) -> Bb {
+		iter.into_iter().fold(Bb::EMPTY, |bb, squ| bb | Bb::one(squ))
+	}
+}
 
-pub struct BbIter(u64);
-impl std::iter::Iterator for BbIter {
+pub struct BbIter(Bb);
+impl core::iter::Iterator for BbIter {
 	type Item = Square;
 	fn next(&mut self) -> Option<Square> {
-		let idx = self.0.trailing_zeros() as u8;
-		if idx == 64 {
-			None
-		} else {
-			self.0 ^= 1 << idx;
-			Some(Square { idx })
-		}
+		self.0.pop_lsb()
 	}
 }
 impl Bb {
 	pub fn iter(self) -> BbIter {
-		BbIter(self.0)
+		BbIter(self)
 	}
 }
 
@@ -133,6 +262,11 @@ impl Bb {
 			self.shift_left(-files as u8)
 		}
 	}
+	// squares one step away in `dir`, dropping those that would leave the board
+	pub const fn shift(self, dir: Direction) -> Bb {
+		let (dfile, drank) = dir.delta();
+		self.shift_hor(dfile).shift_ver(drank)
+	}
 }
 
 pub const KNIGHT_PATTERNS: [Bb; 64] = {
@@ -159,45 +293,311 @@ pub const KING_PATTERNS: [Bb; 64] = {
 };
 
 
-const DIAGONALS: [Bb; 15] = {
-	let mut res = [Bb::EMPTY; 15];
+// squares from each square to the edge of the board in each direction, excluding
+// the square itself: RAYS[square][direction]
+pub const RAYS: [[Bb; 8]; 64] = {
+	let mut res = [[Bb::EMPTY; 8]; 64];
 	let mut idx = 0u8;
-	while idx < 15 {
-		let bb = Bb(0x8040201008040201); // moves from main diagonal
-		res[idx as usize] = bb.shift_hor(idx as i8 - 7);
+	while idx < 64 {
+		let mut dir = 0;
+		while dir < 8 {
+			let mut bb = Bb(1 << idx);
+			let mut ray = Bb::EMPTY;
+			while !bb.none() {
+				bb = bb.shift(Direction::ALL[dir]);
+				ray.0 |= bb.0;
+			}
+			res[idx as usize][Direction::ALL[dir] as usize] = ray;
+			dir += 1;
+		}
 		idx += 1;
 	}
 	res
 };
-const ANTIDIAGONALS: [Bb; 15] = {
-	let mut res = [Bb::EMPTY; 15];
-	let mut idx = 0u8;
-	while idx < 15 {
-		let bb = Bb(0x0102040810204080); // moves from main antidiagonal
-		res[idx as usize] = bb.shift_hor(idx as i8 - 7);
-		idx += 1;
+pub fn ray(from: Square, dir: Direction) -> Bb {
+	RAYS[from.idx as usize][dir as usize]
+}
+
+// squares a slider on `from` attacks in `dir`, up to and including the first piece
+pub fn ray_attacks(from: Square, dir: Direction, pieces: Bb) -> Bb {
+	let ray = ray(from, dir);
+	let blockers = ray & pieces;
+	let blocker = if dir.is_positive() { blockers.lsb() } else { blockers.msb() };
+	match blocker {
+		Some(squ) => ray - RAYS[squ.idx as usize][dir as usize],
+		None => ray,
 	}
-	res
-};
+}
+// squares a slider on `from` would attack in `dir` through the first piece,
+// up to and including the second one
+pub fn xray_attacks(from: Square, dir: Direction, pieces: Bb) -> Bb {
+	ray_attacks(from, dir, pieces - ray_attacks(from, dir, pieces))
+}
+// ray casting, used to fill the attack tables, or instead of them without std
+fn ray_diagonals(from: Square, pieces: Bb) -> Bb {
+	[Direction::NorthEast, Direction::NorthWest, Direction::SouthEast, Direction::SouthWest]
+		.into_iter().fold(Bb::EMPTY, |res, dir| res | ray_attacks(from, dir, pieces))
+}
+fn ray_cardinals(from: Square, pieces: Bb) -> Bb {
+	[Direction::North, Direction::South, Direction::East, Direction::West]
+		.into_iter().fold(Bb::EMPTY, |res, dir| res | ray_attacks(from, dir, pieces))
+}
 
-pub fn cast_ray(from: Square, pattern: Bb, pieces: Bb) -> Bb {
-	let obstacles = pattern & pieces & !Bb::one(from);
-	let before = 0xffffffffffffffff >> (63 - from.idx);
-	let after = 0xffffffffffffffff << from.idx;
-	let obstacle1 = last_bit(obstacles.0 & before).unwrap_or(0);
-	let obstacle2 = first_bit(obstacles.0 & after).unwrap_or(63);
-	let ones = 1 + obstacle2 - obstacle1;
-	let mask = Bb(0xffffffffffffffff >> (64 - ones) << obstacle1);
-	let res = pattern & mask;
-	res
+// The attack tables take about 800 KB, built at the first use. Without std, as on
+// embedded targets, attacks are cast from the rays instead.
+
+// with the `pext` feature on a target with BMI2 (e.g. -C target-cpu=native),
+// occupancies are indexed with PEXT instead of magic multiplication
+#[cfg(feature = "std")]
+const USE_PEXT: bool = cfg!(all(feature = "pext", target_arch = "x86_64", target_feature = "bmi2"));
+
+// where the attacks from one square are in the table: the pieces on `mask`
+// (the squares that can block, edges excluded) give an index into a block
+// of 2^bits entries starting at `offset`
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "pext", allow(dead_code))] // magic and shift are not needed with PEXT
+struct Magic {
+	mask: u64,
+	magic: u64,
+	shift: u32,
+	offset: usize,
+}
+#[cfg(feature = "std")]
+impl Magic {
+	#[inline]
+	fn index(&self, pieces: u64) -> usize {
+		#[cfg(all(feature = "pext", target_arch = "x86_64", target_feature = "bmi2"))]
+		return self.offset + unsafe { core::arch::x86_64::_pext_u64(pieces, self.mask) } as usize;
+		#[cfg(not(all(feature = "pext", target_arch = "x86_64", target_feature = "bmi2")))]
+		return self.offset + ((pieces & self.mask).wrapping_mul(self.magic) >> self.shift) as usize;
+	}
+}
+
+// precomputed attacks of a sliding piece for every square and blocker configuration
+#[cfg(feature = "std")]
+struct SliderAttacks {
+	magics: Vec<Magic>,
+	attacks: Vec<Bb>,
+}
+#[cfg(feature = "std")]
+impl SliderAttacks {
+	fn new(slow: fn(Square, Bb) -> Bb) -> SliderAttacks {
+		// the magics are searched with a fixed seed, so the tables are the same every run
+		let mut rng = 0x9e3779b97f4a7c15u64;
+		let mut next = move || {
+			rng ^= rng << 13;
+			rng ^= rng >> 7;
+			rng ^= rng << 17;
+			rng
+		};
+		let mut magics = Vec::with_capacity(64);
+		let mut attacks = vec![];
+		for squ in Square::iter_all() {
+			let edges = ((Bb::rank(0) | Bb::rank(7)) & !Bb::rank(squ.rank()))
+				| ((Bb::file(0) | Bb::file(7)) & !Bb::file(squ.file()));
+			let mask = (slow(squ, Bb::EMPTY) & !edges & !Bb::one(squ)).0;
+			let bits = mask.count_ones();
+			// every subset of the mask, with its attacks
+			let mut subsets = Vec::with_capacity(1 << bits);
+			let mut subset = 0u64;
+			loop {
+				subsets.push((subset, slow(squ, Bb(subset))));
+				subset = subset.wrapping_sub(mask) & mask;
+				if subset == 0 {
+					break;
+				}
+			}
+			let mut entry = Magic { mask, magic: 0, shift: 64 - bits, offset: attacks.len() };
+			// slots are tagged with the attempt that filled them, to avoid clearing the block
+			let mut block = vec![(0u32, Bb::EMPTY); 1 << bits];
+			let mut attempt = 0;
+			loop {
+				if !USE_PEXT {
+					entry.magic = next() & next() & next();
+					if (mask.wrapping_mul(entry.magic) >> 56).count_ones() < 6 {
+						continue;
+					}
+				}
+				attempt += 1;
+				let fits = subsets.iter().all(|&(subset, attack)| {
+					let slot = &mut block[entry.index(subset) - entry.offset];
+					if slot.0 == attempt {
+						slot.1.0 == attack.0
+					} else {
+						*slot = (attempt, attack);
+						true
+					}
+				});
+				if fits {
+					break;
+				}
+			}
+			attacks.extend(block.into_iter().map(|(_, attack)| attack));
+			magics.push(entry);
+		}
+		SliderAttacks { magics, attacks }
+	}
+
+	#[inline]
+	fn get(&self, from: Square, pieces: Bb) -> Bb {
+		self.attacks[self.magics[from.idx as usize].index(pieces.0)]
+	}
+}
+
+#[cfg(feature = "std")]
+static DIAGONAL_ATTACKS: OnceLock<SliderAttacks> = OnceLock::new();
+#[cfg(feature = "std")]
+static CARDINAL_ATTACKS: OnceLock<SliderAttacks> = OnceLock::new();
+
+// squares strictly between two squares of a same rank, file or diagonal, none otherwise
+pub fn between(a: Square, b: Square) -> Bb {
+	match Direction::towards(a, b) {
+		Some(dir) => ray(a, dir) & ray(b, dir.opposite()),
+		None => Bb::EMPTY,
+	}
+}
+
+// the whole line (rank, file or diagonal) through two different squares, none if they are not aligned
+pub fn line(a: Square, b: Square) -> Bb {
+	match Direction::towards(a, b) {
+		Some(dir) => ray(a, dir) | ray(a, dir.opposite()) | Bb::one(a),
+		None => Bb::EMPTY,
+	}
+}
+
+#[cfg(feature = "std")]
+pub fn cast_diagonals(from: Square, pieces: Bb) -> Bb {
+	DIAGONAL_ATTACKS.get_or_init(|| SliderAttacks::new(ray_diagonals)).get(from, pieces)
+}
+#[cfg(feature = "std")]
+pub fn cast_cardinals(from: Square, pieces: Bb) -> Bb {
+	CARDINAL_ATTACKS.get_or_init(|| SliderAttacks::new(ray_cardinals)).get(from, pieces)
 }
+#[cfg(not(feature = "std"))]
 pub fn cast_diagonals(from: Square, pieces: Bb) -> Bb {
-	let diag = cast_ray(from, DIAGONALS[(7 + from.file() - from.rank()) as usize], pieces);
-	let antidiag = cast_ray(from, ANTIDIAGONALS[(from.file() + from.rank()) as usize], pieces);
-	diag | antidiag
+	ray_diagonals(from, pieces)
 }
+#[cfg(not(feature = "std"))]
 pub fn cast_cardinals(from: Square, pieces: Bb) -> Bb {
-	let hor = cast_ray(from, Bb::rank(from.rank()), pieces);
-	let ver = cast_ray(from, Bb::file(from.file()), pieces);
-	hor | ver
-}
\ No newline at end of file
+	ray_cardinals(from, pieces)
+}
+
+#[cfg(test)]
+mod test_bitboard {
+	use crate::state::Square;
+
+	use super::{
+		between, cast_cardinals, cast_diagonals, line, ray, ray_attacks, ray_cardinals, ray_diagonals, xray_attacks, Bb, Direction,
+	};
+
+	#[test]
+	fn test_regions() {
+		let squares = |bb: Bb| bb.iter().map(|squ| squ.to_string()).collect::<Vec<_>>();
+		assert_eq!(squares(Bb::CENTER), ["d4", "d5", "e4", "e5"]);
+		assert_eq!(Bb::EXTENDED_CENTER.count(), 16);
+		assert!(Bb::EXTENDED_CENTER.at(Square::C3) && Bb::EXTENDED_CENTER.at(Square::F6) && !Bb::EXTENDED_CENTER.at(Square::B2));
+		assert_eq!((Bb::QUEENSIDE | Bb::KINGSIDE).count(), 64);
+		assert!((Bb::QUEENSIDE & Bb::KINGSIDE).none());
+		assert!(Bb::KINGSIDE.at(Square::G1) && Bb::QUEENSIDE.at(Square::D8));
+		assert_eq!(Bb::DARK_SQUARES.count(), 32);
+		assert!(Bb::DARK_SQUARES.at(Square::A1) && Bb::DARK_SQUARES.at(Square::H8) && Bb::LIGHT_SQUARES.at(Square::H1));
+		assert_eq!((Bb::DARK_SQUARES | Bb::LIGHT_SQUARES).0, Bb::ALL.0);
+		assert_eq!(Bb::rect(0, 7, 2, 2).0, Bb::rank(2).0);
+		assert_eq!(Bb::rect(5, 5, 0, 7).0, Bb::file(5).0);
+	}
+
+	#[test]
+	fn test_operators() {
+		let a: Bb = [Square::A1, Square::B2, Square::C3].into_iter().collect();
+		let b: Bb = [Square::C3, Square::D4].into_iter().collect();
+		assert_eq!(a ^ b, [Square::A1, Square::B2, Square::D4].into_iter().collect());
+		assert_eq!(a - b, [Square::A1, Square::B2].into_iter().collect());
+		let mut c = a;
+		c ^= b;
+		c -= Bb::one(Square::D4);
+		assert_eq!(c, a - b);
+		assert!(a.more_than_one() && !Bb::one(Square::H8).more_than_one() && !Bb::EMPTY.more_than_one());
+		let mut d = b;
+		assert_eq!((d.lsb(), d.pop_lsb(), d.pop_lsb(), d.pop_lsb()), (Some(Square::C3), Some(Square::C3), Some(Square::D4), None));
+		assert!(d.none());
+		assert_eq!(format!("{:?}", Bb::one(Square::A2)), "Bb(0x0000000000000002)");
+	}
+
+	#[test]
+	fn test_shift() {
+		let squares = |bb: Bb| bb.iter().map(|squ| squ.to_string()).collect::<Vec<_>>();
+		let corners: Bb = [Square::A1, Square::H8, Square::D4].into_iter().collect();
+		assert_eq!(squares(corners.shift(Direction::North)), ["a2", "d5"]);
+		assert_eq!(squares(corners.shift(Direction::SouthWest)), ["c3", "g7"]);
+		assert_eq!(squares(corners.shift(Direction::East)), ["b1", "e4"]);
+		assert_eq!(squares(corners.shift(Direction::NorthWest)), ["c5"]);
+		for dir in Direction::ALL {
+			let (dfile, drank) = dir.delta();
+			for squ in Square::iter_all() {
+				assert_eq!(Bb::one(squ).shift(dir), squ.try_shift(dfile, drank).map_or(Bb::EMPTY, Bb::one));
+			}
+		}
+	}
+
+	#[test]
+	fn test_rays() {
+		let squares = |bb: Bb| bb.iter().map(|squ| squ.to_string()).collect::<Vec<_>>();
+		assert_eq!(squares(ray(Square::C3, Direction::NorthWest)), ["a5", "b4"]);
+		assert_eq!(squares(ray(Square::C3, Direction::South)), ["c1", "c2"]);
+		assert!(ray(Square::H8, Direction::NorthEast).none());
+		for squ in Square::iter_all() {
+			let rays = Direction::ALL.map(|dir| ray(squ, dir));
+			assert_eq!(rays.iter().fold(Bb::EMPTY, |a, b| a | *b).count(), rays.iter().map(|bb| bb.count()).sum::<u32>());
+			for dir in Direction::ALL {
+				assert_eq!(ray(squ, dir), ray(squ, dir).iter().collect());
+				assert!(ray(squ, dir).iter().all(|to| Direction::towards(squ, to) == Some(dir)));
+				assert_eq!(dir.opposite().opposite(), dir);
+			}
+		}
+		let pieces: Bb = [Square::C5, Square::C6, Square::F2, Square::C1].into_iter().collect();
+		assert_eq!(squares(ray_attacks(Square::C3, Direction::North, pieces)), ["c4", "c5"]);
+		assert_eq!(squares(xray_attacks(Square::C3, Direction::North, pieces)), ["c4", "c5", "c6"]);
+		assert_eq!(squares(xray_attacks(Square::C3, Direction::South, pieces)), ["c1", "c2"]);
+		assert_eq!(squares(xray_attacks(Square::H4, Direction::SouthWest, pieces)), ["e1", "f2", "g3"]);
+	}
+
+	#[test]
+	fn test_line() {
+		assert_eq!(line(Square::C2, Square::C7), Bb::file(2));
+		assert_eq!(line(Square::B5, Square::G5), Bb::rank(4));
+		assert_eq!(line(Square::B2, Square::D4).count(), 8);
+		assert!(line(Square::B2, Square::D4).at(Square::H8));
+		assert_eq!(line(Square::A8, Square::C6), line(Square::H1, Square::D5));
+		assert!(line(Square::A1, Square::B3).none() && line(Square::A1, Square::A1).none());
+		for a in Square::iter_all() {
+			for b in Square::iter_all() {
+				assert!(between(a, b) - line(a, b) == Bb::EMPTY);
+			}
+		}
+	}
+
+	#[test]
+	fn test_between() {
+		let squares = |bb: Bb| bb.iter().map(|squ| squ.to_string()).collect::<Vec<_>>();
+		assert_eq!(squares(between(Square::A1, Square::A4)), ["a2", "a3"]);
+		assert_eq!(squares(between(Square::H8, Square::E5)), ["f6", "g7"]);
+		assert_eq!(squares(between(Square::B7, Square::E4)), ["c6", "d5"]);
+		assert!(between(Square::A1, Square::B3).none() && between(Square::C2, Square::C3).none() && between(Square::D4, Square::D4).none());
+	}
+
+	#[test]
+	fn test_attack_tables() {
+		let mut rng = 0x2545f4914f6cdd1du64;
+		for _ in 0..500 {
+			rng ^= rng << 13;
+			rng ^= rng >> 7;
+			rng ^= rng << 17;
+			// sparse and dense occupancies
+			let pieces = Bb(if rng & 1 == 0 { rng & rng.rotate_left(17) } else { rng });
+			for squ in Square::iter_all() {
+				assert_eq!(cast_diagonals(squ, pieces).0, ray_diagonals(squ, pieces).0);
+				assert_eq!(cast_cardinals(squ, pieces).0, ray_cardinals(squ, pieces).0);
+			}
+		}
+	}
+}
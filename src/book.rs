@@ -0,0 +1,169 @@
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use crate::{game::Position, pgn::{Pgn, PgnResult}, state::{Color, Move}};
+
+// A book learned from the engine's own games: for the opening moves it
+// played, how the games went. Lines that keep losing are then avoided.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BookEntry {
+	pub wins: u32,
+	pub draws: u32,
+	pub losses: u32,
+}
+impl BookEntry {
+	pub fn games(&self) -> u32 {
+		self.wins + self.draws + self.losses
+	}
+	// average score, pulled towards 0.5 while there are few games
+	pub fn score(&self) -> f64 {
+		(self.wins as f64 + 0.5 * self.draws as f64 + 1.0) / (self.games() as f64 + 2.0)
+	}
+}
+
+#[derive(Default)]
+pub struct Book {
+	entries: BTreeMap<(u64, String), BookEntry>, // by position hash and UCI move
+}
+impl Book {
+	pub const MAX_PLY: u16 = 20; // only moves played before this ply are learned
+	pub const MIN_GAMES: u32 = 3; // before a move can be avoided
+	pub const AVOID_SCORE: f64 = 0.3;
+
+	pub fn new() -> Book {
+		Book::default()
+	}
+
+	// one "hash move wins draws losses" line per entry
+	pub fn parse(data: &str) -> Result<Book, String> {
+		let mut book = Book::new();
+		for (i, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+			let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+			let entry = match fields.as_slice() {
+				[hash, mov, wins, draws, losses] => (|| Some(((u64::from_str_radix(hash, 16).ok()?, mov.to_string()), BookEntry {
+					wins: wins.parse().ok()?,
+					draws: draws.parse().ok()?,
+					losses: losses.parse().ok()?,
+				})))(),
+				_ => None,
+			};
+			let (key, entry) = entry.ok_or_else(|| format!("invalid book entry on line {}", i + 1))?;
+			book.entries.insert(key, entry);
+		}
+		Ok(book)
+	}
+	// a missing file is an empty book
+	pub fn load(path: impl AsRef<Path>) -> Result<Book, String> {
+		let path = path.as_ref();
+		match std::fs::read_to_string(path) {
+			Ok(data) => Book::parse(&data).map_err(|err| format!("{}: {}", path.display(), err)),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Book::new()),
+			Err(err) => Err(format!("could not read {}: {}", path.display(), err)),
+		}
+	}
+	pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+		let mut data = String::new();
+		for ((hash, mov), entry) in &self.entries {
+			writeln!(data, "{:016x} {} {} {} {}", hash, mov, entry.wins, entry.draws, entry.losses).unwrap();
+		}
+		std::fs::write(path, data)
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+	pub fn entry(&self, pos: &Position, mov: &Move) -> Option<&BookEntry> {
+		self.entries.get(&(pos.hash_key(), mov.uci_notation()))
+	}
+
+	// records the outcome of a finished game for the opening moves `color` played;
+	// unfinished games are ignored
+	pub fn learn(&mut self, game: &Pgn, color: Color) {
+		let result = game.result();
+		if result == PgnResult::Unfinished {
+			return;
+		}
+		let mut pos = game.start_position().clone();
+		for mov in game.moves() {
+			if pos.get_ply() >= Book::MAX_PLY {
+				break;
+			}
+			if pos.side_to_move() == color {
+				let entry = self.entries.entry((pos.hash_key(), mov.uci_notation())).or_default();
+				match result {
+					PgnResult::Win(winner) if winner == color => entry.wins += 1,
+					PgnResult::Win(_) => entry.losses += 1,
+					_ => entry.draws += 1,
+				}
+			}
+			pos.apply_move(mov);
+		}
+	}
+
+	// the moves worth considering: those that lost too often are left out,
+	// unless that would leave nothing
+	pub fn filter_moves(&self, pos: &Position, moves: &[Move]) -> Vec<Move> {
+		if pos.get_ply() >= Book::MAX_PLY {
+			return moves.to_vec();
+		}
+		let kept: Vec<Move> = moves.iter().copied().filter(|mov| !self.entry(pos, mov)
+			.is_some_and(|entry| entry.games() >= Book::MIN_GAMES && entry.score() < Book::AVOID_SCORE)).collect();
+		if kept.is_empty() { moves.to_vec() } else { kept }
+	}
+}
+
+#[cfg(test)]
+mod test_book {
+	use crate::{game::Position, pgn::{Pgn, PgnResult}, state::{Color, Move}};
+	use super::Book;
+
+	fn game(moves: &[&str], result: PgnResult) -> Pgn {
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pgn = Pgn::new(&pos);
+		for uci in moves {
+			let mov = *Move::parse_uci(uci, &pos.gen_legal()).ok().unwrap();
+			pgn.push(mov);
+			pos.apply_move(&mov);
+		}
+		pgn.set_result(result);
+		pgn
+	}
+
+	#[test]
+	fn test_learn() {
+		let mut book = Book::new();
+		let start = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let moves = start.gen_legal();
+		let f3 = *Move::parse_uci("f2f3", &moves).ok().unwrap();
+		for _ in 0..3 {
+			book.learn(&game(&["f2f3", "e7e5", "g2g4", "d8h4"], PgnResult::Win(Color::Black)), Color::White);
+		}
+		book.learn(&game(&["e2e4", "e7e5"], PgnResult::Draw), Color::White);
+		book.learn(&game(&["d2d4"], PgnResult::Unfinished), Color::White);
+		// only white's moves are learned
+		assert_eq!(book.len(), 3);
+		let entry = book.entry(&start, &f3).unwrap();
+		assert_eq!((entry.wins, entry.draws, entry.losses), (0, 0, 3));
+		assert!((entry.score() - 0.2).abs() < 1e-9);
+
+		let kept = book.filter_moves(&start, &moves);
+		assert_eq!(kept.len(), moves.len() - 1);
+		assert!(!kept.contains(&f3));
+		// never filters out every move
+		assert_eq!(book.filter_moves(&start, &[f3]), vec![f3]);
+
+		let parsed = Book::parse(&{
+			let path = std::env::temp_dir().join(format!("book-test-{}.txt", std::process::id()));
+			book.save(&path).unwrap();
+			let data = std::fs::read_to_string(&path).unwrap();
+			std::fs::remove_file(&path).unwrap();
+			data
+		}).unwrap();
+		assert_eq!(parsed.entry(&start, &f3), book.entry(&start, &f3));
+		assert_eq!(parsed.len(), book.len());
+		assert!(Book::parse("0123 e2e4 1 x 0").is_err());
+	}
+}
@@ -1,494 +1,1458 @@
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, HashMap, VecDeque},
 	fmt::{Display, Write as _},
 	fs::{File, OpenOptions},
-	io::{Read, Write as _},
-	sync::mpsc,
-	thread::JoinHandle,
-	time::{Duration, Instant}
+	io::Write as _,
+	path::{Path, PathBuf},
+	sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex},
+	time::{Duration, Instant, SystemTime}
 };
 
-use chesslib::{ai::ChessAi, game::Position, state::{Color, Move}};
-use reqwest::{blocking::{Client, Response}, Method, Url};
-use serde::{de::DeserializeOwned, Deserialize};
-use toml::Table;
+use chesslib::{
+	ai::{mate_in, ChessAi, SearchLimits, SearchOutcome, SearchParams, SimpleAi},
+	book::Book,
+	game::{Game, Position},
+	lichess_api::{game_target, Account, CloudEval, Challenge, ChallengeEvent, Event, GameEvent, GameFull, JsonStream, LichessClient, Perf, PlayingGame},
+	pgn::{format_date, Pgn, PgnResult},
+	state::{Color, Move},
+	version::{build_info, GIT_HASH}
+};
+use clap::Parser;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use log::{debug, error, info, warn, Level, LevelFilter};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
 
 const BRIGHT_RED: &str = "\x1b[1;31m";
 const YELLOW: &str = "\x1b[1;33m";
 const RESET: &str = "\x1b[0m";
+const GRAY: &str = "\x1b[90m";
+
+// Logs to the console, filtered by the configured level. Records whose target
+// is "game/<id>" (see `game_target`) are also appended, down to the debug
+// level, to logs/<id>.log.
+struct BotLogger {
+	console_level: AtomicUsize, // a LevelFilter
+	game_files: Mutex<BTreeMap<String, File>>,
+}
+static LOGGER: BotLogger = BotLogger {
+	console_level: AtomicUsize::new(LevelFilter::Info as usize),
+	game_files: Mutex::new(BTreeMap::new()),
+};
+const GAME_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+fn init_logger() {
+	log::set_logger(&LOGGER).expect("logger already set");
+	log::set_max_level(LevelFilter::Info.max(GAME_LOG_LEVEL));
+}
+fn set_console_level(level: LevelFilter) {
+	LOGGER.console_level.store(level as usize, Ordering::Relaxed);
+	log::set_max_level(level.max(GAME_LOG_LEVEL));
+}
+// closes the log file of a game that has ended
+fn close_game_log(game_id: &str) {
+	LOGGER.game_files.lock().unwrap().remove(game_id);
+}
+
+// wall-clock time of day (UTC), with milliseconds
+fn timestamp() -> String {
+	let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+	let secs = now.as_secs() % 86400;
+	format!("{:02}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, now.subsec_millis())
+}
+
+impl log::Log for BotLogger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.level() as usize <= self.console_level.load(Ordering::Relaxed)
+			|| metadata.target().starts_with("game/") && metadata.level() <= GAME_LOG_LEVEL
+	}
+
+	fn log(&self, record: &log::Record) {
+		let game_id = record.target().strip_prefix("game/");
+		let time = timestamp();
+		if record.level() as usize <= self.console_level.load(Ordering::Relaxed) {
+			let prefix = match record.level() {
+				Level::Error => format!("{BRIGHT_RED}error:{RESET} "),
+				Level::Warn => format!("{YELLOW}warning:{RESET} "),
+				Level::Info => String::new(),
+				Level::Debug | Level::Trace => format!("{GRAY}{}:{RESET} ", record.level().as_str().to_lowercase()),
+			};
+			let game = game_id.map_or(String::new(), |id| format!("[{}] ", id));
+			let line = format!("{GRAY}{}{RESET} {}{}{}", time, game, prefix, record.args());
+			if record.level() <= Level::Warn {
+				eprintln!("{}", line);
+			} else {
+				println!("{}", line);
+			}
+		}
+		if let Some(game_id) = game_id.filter(|_| record.level() <= GAME_LOG_LEVEL) {
+			let mut files = self.game_files.lock().unwrap();
+			if !files.contains_key(game_id) {
+				let path = format!("logs/{}.log", game_id);
+				let file = std::fs::create_dir_all("logs")
+					.and_then(|_| OpenOptions::new().append(true).create(true).open(&path));
+				match file {
+					Ok(file) => { files.insert(game_id.to_owned(), file); },
+					Err(err) => {
+						eprintln!("{YELLOW}warning:{RESET} could not open {}: {}", path, err);
+						return;
+					},
+				}
+			}
+			let file = files.get_mut(game_id).unwrap();
+			writeln!(file, "{} {:5} {}", time, record.level(), record.args()).ok();
+		}
+	}
 
-fn config_get_integer(config: &Table, name: &str) -> Result<i64, String> {
-	let val = config.get(name)
-		.ok_or_else(|| format!("bot_config.toml: no {} key", name))?
-		.clone();
-	if let toml::Value::Integer(val) = val {
-		Ok(val)
-	 } else {
-		Err(format!("bot_config.toml: {} is not an integer", name))
+	fn flush(&self) {
+		for file in self.game_files.lock().unwrap().values_mut() {
+			file.flush().ok();
+		}
 	}
 }
+
+// A config value as deserialized: invalid values keep their error instead of
+// failing the whole file, so that every problem can be reported at once.
+struct Setting<T>(Option<Result<T, String>>);
+impl<T> Default for Setting<T> {
+	fn default() -> Self {
+		Setting(None)
+	}
+}
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Setting<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+		let value = toml::Value::deserialize(de)?;
+		Ok(Setting(Some(value.try_into().map_err(|e: toml::de::Error| e.message().to_owned()))))
+	}
+}
+impl<T> Setting<T> {
+	fn get(self, name: &str, errors: &mut Vec<String>) -> Option<T> {
+		match self.0? {
+			Ok(value) => Some(value),
+			Err(err) => {
+				errors.push(format!("{}: {}", name, err));
+				None
+			},
+		}
+	}
+	fn require(self, name: &str, errors: &mut Vec<String>) -> Option<T> {
+		if self.0.is_none() {
+			errors.push(format!("no {} key", name));
+		}
+		self.get(name, errors)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TimeControl {
+	initial: i64, // seconds
+	increment: i64,
+}
+impl TimeControl {
+	fn parse(initial: i64, increment: i64) -> Result<TimeControl, String> {
+		if !([0,15,30,45,60,90].contains(&initial) || initial % 60 == 0) || initial > 10800 {
+			return Err(format!("{} is not a valid initial clock time\n(0,15,30,45,90 or a multiple of 60 up to 10800)", initial));
+		}
+		if increment < 0 || increment > 60 {
+			return Err(format!("clock increment {} is not in [0, 60]", increment));
+		}
+		Ok(TimeControl { initial, increment })
+	}
+	// speed category, as lichess determines it from the estimated game duration
+	fn speed(self) -> &'static str {
+		match self.initial + 40 * self.increment {
+			..=29 => "ultraBullet",
+			30..=179 => "bullet",
+			180..=479 => "blitz",
+			480..=1499 => "rapid",
+			_ => "classical",
+		}
+	}
+}
+impl Display for TimeControl {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}+{}", self.initial, self.increment)
+	}
+}
+
+const SPEEDS: [&str; 5] = ["ultraBullet", "bullet", "blitz", "rapid", "classical"];
+// also covers unlimited games; these are played without a game stream
+const CORRESPONDENCE: &str = "correspondence";
+
+// messages sent in the player chat; {engine} (name and depth), {depth} and {eval} are filled in
+struct GameChat {
+	greeting: String, // when the game starts
+	goodbye: String, // when it ends
+}
+
+// a lichess tournament the bot takes part in, written as the end of its URL:
+// "tournament/<id>" for arenas, "swiss/<id>" for swiss tournaments
+#[derive(Clone, Debug, PartialEq)]
+struct Tournament {
+	swiss: bool,
+	id: String,
+}
+impl Tournament {
+	fn parse(desc: &str) -> Result<Tournament, String> {
+		let mut parts = desc.trim_end_matches('/').rsplit('/');
+		match (parts.next(), parts.next()) {
+			(Some(id), Some(kind @ ("tournament" | "swiss"))) if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) =>
+				Ok(Tournament { swiss: kind == "swiss", id: id.to_owned() }),
+			_ => Err(format!("{} is not of the form tournament/<id> or swiss/<id>", desc)),
+		}
+	}
+}
+impl Display for Tournament {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{} {}", if self.swiss { "swiss" } else { "arena" }, self.id)
+	}
+}
+
+// whether to berserk in an arena game: halving the clock is worth the extra
+// point when the bot is rated at least `margin` points above the opponent
+fn should_berserk(own_rating: Option<i32>, opponent_rating: Option<i32>, margin: Option<i32>) -> bool {
+	match (own_rating, opponent_rating, margin) {
+		(Some(own), Some(opponent), Some(margin)) => own - opponent >= margin,
+		_ => false,
+	}
+}
+
+// contempt of the search in a game: up to `contempt` against weaker opponents, to avoid
+// early draws, down to its opposite against stronger ones, to accept them; full at a
+// rating difference of CONTEMPT_RATING_SCALE, none when a rating is unknown
+const CONTEMPT_RATING_SCALE: i32 = 200;
+fn game_contempt(own_rating: Option<i32>, opponent_rating: Option<i32>, contempt: i16) -> i16 {
+	match (own_rating, opponent_rating) {
+		(Some(own), Some(opponent)) => {
+			let diff = (own - opponent).clamp(-CONTEMPT_RATING_SCALE, CONTEMPT_RATING_SCALE);
+			(contempt as i32 * diff / CONTEMPT_RATING_SCALE) as i16
+		},
+		_ => 0,
+	}
+}
+
+// how opponents are picked for outgoing challenges
+struct Matchmaking {
+	rating_band: i32, // initial maximum rating difference, widened if no one is found
+	prefer_stronger: bool,
+	perf: Option<String>, // rating used for matching, instead of that of the time control
+	allowlist: Vec<String>, // if not empty, only these bots are challenged, whatever their rating
+}
+
 struct Config {
 	token: String,
 	depth: u32,
+	threads: usize, // of each search
+	ponder: bool, // search on the opponent's time in real-time games
+	contempt: i16, // see game_contempt
 	play_rated: bool,
-	clock_initial: i64,
-	clock_increment: i64,
+	casual_max_rd: i32, // rated games are only played once the rating deviation is at most this
+	experimental: bool, // experimental builds only play casual games
+	time_controls: Vec<TimeControl>, // for outgoing challenges, in turn
+	accepted_speeds: Vec<String>, // for incoming challenges
+	correspondence_move_time: u64, // seconds of search per correspondence move
 	idle_timeout: u64,
 	challenge_timeout: u64,
+	draw_accept_score: Option<i16>,
+	draw_offer_score: Option<i16>,
+	draw_offer_max_pieces: u32,
+	resign_score: Option<i16>,
+	resign_moves: u32,
+	max_games: usize,
+	blacklist_days: u64, // declining bots are challenged again after this long
+	abandon_timeout: u64, // seconds the opponent has to make their first move before the game is aborted
+	abandon_penalty: u64, // seconds during which challenges from humans who did so are declined
+	matchmaking: Matchmaking,
+	book_learning: bool, // avoid opening moves that kept losing in past games
+	cloud_eval: bool, // log the evaluation of the lichess cloud next to the bot's
+	game_chat: Option<GameChat>,
+	tournaments: Vec<Tournament>, // joined on startup; no other bots are challenged while one is running
+	berserk_margin: Option<i32>, // see should_berserk
+	log_level: LevelFilter, // on the console; game logs always include debug records
+}
+// the config file as written; see parse_config for defaults and constraints
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+struct ConfigFile {
+	bot_token: Setting<String>,
+	search_depth: Setting<i64>,
+	search_threads: Setting<i64>,
+	ponder: Setting<bool>,
+	contempt: Setting<i16>,
+	play_rated: Setting<bool>,
+	casual_max_rd: Setting<i64>,
+	experimental: Setting<bool>,
+	time_controls: Setting<Vec<(i64, i64)>>,
+	clock_initial: Setting<i64>,
+	clock_increment: Setting<i64>,
+	accepted_speeds: Setting<Vec<String>>,
+	correspondence_move_time: Setting<i64>,
+	idle_timeout: Setting<i64>,
+	challenge_timeout: Setting<i64>,
+	draw_accept_score: Setting<i16>,
+	draw_offer_score: Setting<i16>,
+	draw_offer_max_pieces: Setting<i64>,
+	resign_score: Setting<i16>,
+	resign_moves: Setting<i64>,
+	max_games: Setting<i64>,
+	blacklist_days: Setting<i64>,
+	abandon_timeout: Setting<i64>,
+	abandon_penalty: Setting<i64>,
+	rating_band: Setting<i64>,
+	prefer_stronger: Setting<bool>,
+	match_perf: Setting<String>,
+	allowlist: Setting<Vec<String>>,
+	book_learning: Setting<bool>,
+	cloud_eval: Setting<bool>,
+	game_chat: Setting<bool>,
+	greeting: Setting<String>,
+	goodbye: Setting<String>,
+	tournaments: Setting<Vec<String>>,
+	berserk_margin: Setting<i64>,
+	log_level: Setting<String>,
+	#[serde(flatten)]
+	unknown: BTreeMap<String, toml::Value>,
 }
-fn load_config() -> Result<Config, String> {
-	let config = std::fs::read_to_string("bot_config.toml")
-		.map_err(|e| format!("could not read bot_config.toml: {}", e))?
-		.parse::<Table>()
-		.map_err(|e| format!("bot_config.toml: invalid syntax: {}", e))?;
-
-	let token = config.get("BOT_TOKEN")
-		.ok_or_else(|| format!("bot_config.toml: no BOT_TOKEN key"))?
-		.clone();
-	let token = if let toml::Value::String(token) = token { token } else {
-		return Err(format!("bot_config.toml: BOT_TOKEN is not a string"));
-	};
 
-	let depth = config_get_integer(&config, "SEARCH_DEPTH")?;
+// the token can be kept out of the config file, in the environment or in a secrets file
+const TOKEN_VAR: &str = "LICHESS_BOT_TOKEN";
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", deny_unknown_fields)]
+struct Secrets {
+	bot_token: String,
+}
+fn load_token(secrets_path: &Path) -> Result<Option<String>, String> {
+	if let Ok(token) = std::env::var(TOKEN_VAR) {
+		return Ok(Some(token));
+	}
+	match std::fs::read_to_string(secrets_path) {
+		Ok(text) => toml::from_str::<Secrets>(&text).map(|secrets| Some(secrets.bot_token))
+			.map_err(|e| format!("{}: {}", secrets_path.display(), e.message())),
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(format!("could not read {}: {}", secrets_path.display(), err)),
+	}
+}
+
+fn load_config(path: &Path, secrets_path: &Path) -> Result<Config, String> {
+	let text = std::fs::read_to_string(path)
+		.map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+	let token = load_token(secrets_path)?;
+	parse_config(&text, token).map_err(|e| format!("{}: {}", path.display(), e))
+}
+// `token` takes precedence over BOT_TOKEN; all errors are reported, one per line
+fn parse_config(text: &str, token: Option<String>) -> Result<Config, String> {
+	let file: ConfigFile = toml::from_str(text)
+		.map_err(|e| format!("invalid syntax: {}", e.message()))?;
+	let mut errors = vec![];
+
+	for key in file.unknown.keys() {
+		errors.push(format!("unknown key {}", key));
+	}
+
+	let config_token = file.bot_token.get("BOT_TOKEN", &mut errors);
+	let token = token.or(config_token).unwrap_or_else(|| {
+		errors.push(format!("no BOT_TOKEN key (nor {} variable or secrets file)", TOKEN_VAR));
+		String::new()
+	});
+
+	let depth = file.search_depth.require("SEARCH_DEPTH", &mut errors).unwrap_or(1);
 	if depth < 1 {
-		return Err(format!("bot_config.toml: SEARCH_DEPTH is not positive"));
+		errors.push(format!("SEARCH_DEPTH is not positive"));
+	}
+	let threads = file.search_threads.get("SEARCH_THREADS", &mut errors).unwrap_or(1);
+	if !(1..=256).contains(&threads) {
+		errors.push(format!("SEARCH_THREADS is not in [1, 256]"));
+	}
+	let ponder = file.ponder.get("PONDER", &mut errors).unwrap_or(true);
+	let contempt = file.contempt.get("CONTEMPT", &mut errors).unwrap_or(20);
+	if !(0..=200).contains(&contempt) {
+		errors.push(format!("CONTEMPT is not in [0, 200]"));
+	}
+	let play_rated = file.play_rated.require("PLAY_RATED", &mut errors).unwrap_or(false);
+
+	let casual_max_rd = file.casual_max_rd.get("CASUAL_MAX_RD", &mut errors).unwrap_or(110);
+	if !(30..=500).contains(&casual_max_rd) {
+		errors.push(format!("CASUAL_MAX_RD is not in [30, 500]"));
 	}
-	let depth = depth as u32;
+	// builds with uncommitted changes are experimental too
+	let experimental = file.experimental.get("EXPERIMENTAL", &mut errors).unwrap_or(false)
+		|| GIT_HASH.ends_with("-dirty");
 
-	let play_rated = config.get("PLAY_RATED")
-		.ok_or_else(|| format!("bot_config.toml: no PLAY_RATED key"))?
-		.clone();
-	let play_rated = if let toml::Value::Boolean(play_rated) = play_rated { play_rated } else {
-		return Err(format!("bot_config.toml: PLAY_RATED is not a boolean"));
+	// either a list of [initial, increment] pairs, or a single time control
+	let from_list = file.time_controls.0.is_some();
+	let time_controls = if from_list {
+		let list = file.time_controls.get("TIME_CONTROLS", &mut errors).unwrap_or_default();
+		if list.is_empty() {
+			errors.push(format!("TIME_CONTROLS is not a non-empty array"));
+		}
+		list
+	} else {
+		let clock_initial = file.clock_initial.require("CLOCK_INITIAL", &mut errors);
+		let clock_increment = file.clock_increment.require("CLOCK_INCREMENT", &mut errors);
+		clock_initial.zip(clock_increment).into_iter().collect()
 	};
+	let time_controls = time_controls.into_iter().filter_map(|(initial, increment)| {
+		TimeControl::parse(initial, increment)
+			.map_err(|e| errors.push(if from_list { format!("TIME_CONTROLS: {}", e) } else { e })).ok()
+	}).collect();
 
-	let clock_initial = config_get_integer(&config, "CLOCK_INITIAL")?;
-	if !([0,15,30,45,60,90].contains(&clock_initial) || clock_initial % 60 == 0) || clock_initial > 10800 {
-		return Err(format!("bot_config.toml: CLOCK_INITIAL is not a valid value\n(0,15,30,45,90 or a multiple of 60 up to 10800)"));
-	}
-	let clock_increment = config_get_integer(&config, "CLOCK_INCREMENT")?;
-	if clock_increment < 0 || clock_increment > 60 {
-		return Err(format!("bot_config.toml: CLOCK_INCREMENT is not in [0, 60]"));
+	let accepted_speeds = file.accepted_speeds.get("ACCEPTED_SPEEDS", &mut errors)
+		.unwrap_or_else(|| vec!["blitz".to_owned()]);
+	if accepted_speeds.iter().any(|speed| !SPEEDS.contains(&speed.as_str()) && speed != CORRESPONDENCE) {
+		errors.push(format!("ACCEPTED_SPEEDS entries must be among {}, {}", SPEEDS.join(", "), CORRESPONDENCE));
 	}
 
-	let idle_timeout = config_get_integer(&config, "IDLE_TIMEOUT")?;
+	let correspondence_move_time = file.correspondence_move_time.get("CORRESPONDENCE_MOVE_TIME", &mut errors).unwrap_or(60);
+	if correspondence_move_time < 1 {
+		errors.push(format!("CORRESPONDENCE_MOVE_TIME is not positive"));
+	}
+	let idle_timeout = file.idle_timeout.require("IDLE_TIMEOUT", &mut errors).unwrap_or(0);
 	if idle_timeout < 0 {
-		return Err(format!("bot_config.toml: IDLE_TIMEOUT is negative"));
+		errors.push(format!("IDLE_TIMEOUT is negative"));
 	}
-	let idle_timeout = idle_timeout as u64;
-
-	let challenge_timeout = config_get_integer(&config, "CHALLENGE_TIMEOUT")?;
+	let challenge_timeout = file.challenge_timeout.require("CHALLENGE_TIMEOUT", &mut errors).unwrap_or(0);
 	if challenge_timeout < 0 {
-		return Err(format!("bot_config.toml: CHALLENGE_TIMEOUT is negative"));
+		errors.push(format!("CHALLENGE_TIMEOUT is negative"));
 	}
-	let challenge_timeout = challenge_timeout as u64;
 
-	Ok(Config {
-		token, depth, play_rated, clock_initial, clock_increment, idle_timeout, challenge_timeout,
-	})
-}
+	// optional draw and resignation settings (in centipawns, from the bot's perspective)
+	let draw_accept_score = file.draw_accept_score.get("DRAW_ACCEPT_SCORE", &mut errors);
+	let draw_offer_score = file.draw_offer_score.get("DRAW_OFFER_SCORE", &mut errors);
+	let draw_offer_max_pieces = file.draw_offer_max_pieces.get("DRAW_OFFER_MAX_PIECES", &mut errors).unwrap_or(6);
+	if !(2..=32).contains(&draw_offer_max_pieces) {
+		errors.push(format!("DRAW_OFFER_MAX_PIECES is not in [2, 32]"));
+	}
+	let resign_score = file.resign_score.get("RESIGN_SCORE", &mut errors);
+	if resign_score.is_some_and(|score| score >= 0) {
+		errors.push(format!("RESIGN_SCORE is not negative"));
+	}
+	let resign_moves = file.resign_moves.get("RESIGN_MOVES", &mut errors).unwrap_or(3);
+	if resign_moves < 1 {
+		errors.push(format!("RESIGN_MOVES is not positive"));
+	}
 
-struct BotReq {
-	method: Method,
-	url: Url,
-	body: Option<Vec<(String, String)>>,
-}
-impl BotReq {
-	fn new(method: Method, url: &str) -> Self {
-		BotReq {
-			method,
-			url: Url::parse(&format!("https://lichess.org/api/{}", url)).expect("invalid base URL"),
-			body: None,
-		}
+	let max_games = file.max_games.get("MAX_GAMES", &mut errors).unwrap_or(1);
+	if max_games < 1 {
+		errors.push(format!("MAX_GAMES is not positive"));
 	}
-	fn path(mut self, part: impl Display) -> Self {
-		self.url.path_segments_mut().unwrap().push(&format!("{}", part));
-		self
+	let blacklist_days = file.blacklist_days.get("BLACKLIST_DAYS", &mut errors).unwrap_or(7);
+	if blacklist_days < 1 {
+		errors.push(format!("BLACKLIST_DAYS is not positive"));
 	}
-	fn query(mut self, key: &'static str, value: impl Display) -> Self {
-		self.url.query_pairs_mut().append_pair(key, &format!("{}", value));
-		self
+	let abandon_timeout = file.abandon_timeout.get("ABANDON_TIMEOUT", &mut errors).unwrap_or(60);
+	if abandon_timeout < 10 {
+		errors.push(format!("ABANDON_TIMEOUT is less than 10 seconds"));
 	}
-	fn body(mut self, key: &'static str, value: impl Display) -> Self {
-		if self.body.is_none() {
-			self.body = Some(vec![]);
-		}
-		self.body.as_mut().unwrap().push((key.to_owned(), format!("{}", value)));
-		self
+	let abandon_penalty = file.abandon_penalty.get("ABANDON_PENALTY", &mut errors).unwrap_or(3600);
+	if abandon_penalty < 0 {
+		errors.push(format!("ABANDON_PENALTY is a negative number of seconds"));
 	}
+
+	let rating_band = file.rating_band.get("RATING_BAND", &mut errors).unwrap_or(100);
+	if !(1..=1000).contains(&rating_band) {
+		errors.push(format!("RATING_BAND is not in [1, 1000]"));
+	}
+	let prefer_stronger = file.prefer_stronger.get("PREFER_STRONGER", &mut errors).unwrap_or(false);
+	let perf = file.match_perf.get("MATCH_PERF", &mut errors);
+	if perf.as_ref().is_some_and(|perf| !SPEEDS.contains(&perf.as_str())) {
+		errors.push(format!("MATCH_PERF must be one of {}", SPEEDS.join(", ")));
+	}
+	let allowlist = file.allowlist.get("ALLOWLIST", &mut errors).unwrap_or_default();
+	let matchmaking = Matchmaking { rating_band: rating_band as i32, prefer_stronger, perf, allowlist };
+
+	let book_learning = file.book_learning.get("BOOK_LEARNING", &mut errors).unwrap_or(true);
+	let cloud_eval = file.cloud_eval.get("CLOUD_EVAL", &mut errors).unwrap_or(false);
+
+	let greeting = file.greeting.get("GREETING", &mut errors);
+	let goodbye = file.goodbye.get("GOODBYE", &mut errors);
+	let game_chat = file.game_chat.get("GAME_CHAT", &mut errors).unwrap_or(false).then(|| GameChat {
+		greeting: greeting.unwrap_or_else(|| "gl hf, I'm running {engine}".to_owned()),
+		goodbye: goodbye.unwrap_or_else(|| "gg, my final eval was {eval}".to_owned()),
+	});
+
+	let tournaments = file.tournaments.get("TOURNAMENTS", &mut errors).unwrap_or_default().iter()
+		.filter_map(|desc| Tournament::parse(desc).map_err(|e| errors.push(format!("TOURNAMENTS: {}", e))).ok())
+		.collect();
+	let berserk_margin = file.berserk_margin.get("BERSERK_MARGIN", &mut errors);
+	if berserk_margin.is_some_and(|margin| !(0..=1000).contains(&margin)) {
+		errors.push(format!("BERSERK_MARGIN is not in [0, 1000]"));
+	}
+
+	let log_level = file.log_level.get("LOG_LEVEL", &mut errors).map_or(Ok(LevelFilter::Info), |level| level.parse())
+		.unwrap_or_else(|_| {
+			errors.push(format!("LOG_LEVEL must be one of off, error, warn, info, debug, trace"));
+			LevelFilter::Info
+		});
+
+	if !errors.is_empty() {
+		return Err(errors.join("\n"));
+	}
+	Ok(Config {
+		token, depth: depth as u32, threads: threads as usize, ponder, contempt, play_rated, casual_max_rd: casual_max_rd as i32, experimental, time_controls, accepted_speeds,
+		correspondence_move_time: correspondence_move_time as u64, idle_timeout: idle_timeout as u64, challenge_timeout: challenge_timeout as u64,
+		draw_accept_score, draw_offer_score, draw_offer_max_pieces: draw_offer_max_pieces as u32, resign_score, resign_moves: resign_moves as u32,
+		max_games: max_games as usize, blacklist_days: blacklist_days as u64,
+		abandon_timeout: abandon_timeout as u64, abandon_penalty: abandon_penalty as u64,
+		matchmaking, book_learning, cloud_eval, game_chat, tournaments, berserk_margin: berserk_margin.map(|margin| margin as i32), log_level,
+	})
 }
-fn get(url: &str) -> BotReq {
-	BotReq::new(Method::GET, url)
+
+// game streams that drop are reopened with exponential backoff, so that a
+// network hiccup does not lose the game on time
+const GAME_RECONNECT_ATTEMPTS: u32 = 8;
+const GAME_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+fn reconnect_delay(attempt: u32) -> Duration {
+	(Duration::from_secs(1) * (1 << attempt.min(5))).min(GAME_RECONNECT_MAX_DELAY)
 }
-fn post(url: &str) -> BotReq {
-	BotReq::new(Method::POST, url)
+
+fn unix_time() -> u64 {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
-struct JsonStream<Res: DeserializeOwned + Send + 'static> {
-	_listener: JoinHandle<()>,
-	recv: mpsc::Receiver<Result<Res, String>>,
+// Bots that did not accept a challenge, with the time they were added. The
+// file has one "username time" line per entry; entries expire after a while,
+// and are dropped from the file when it is loaded.
+struct Blacklist {
+	path: PathBuf,
+	entries: HashMap<String, u64>,
+	duration: u64, // seconds
 }
-impl<Res: DeserializeOwned + Send + 'static> JsonStream<Res> {
-	fn new(mut res: Response) -> Self {
-		let (send, recv) = mpsc::channel::<Result<Res, String>>();
-		let listener = std::thread::spawn(move || {
-			let mut buf = vec![];
-			loop {
-				if let Some(i) = buf.iter().position(|b| *b == b'\n') {
-					if i > 0 {
-						let msg = &buf[..i];
-						let msg = serde_json::from_slice(msg)
-							.map_err(|e| format!("failed to deserialize ndjson: {}\n{}", e, String::from_utf8_lossy(msg)));
-						if let Err(_) = send.send(msg) {
-							return;
-						}
-					}
-					buf.drain(0..(i+1));
-				} else {
-					let mut chunk = [0u8; 256];
-					let read = res.read(&mut chunk)
-						.map_err(|e| format!("failed to read from response: {}", e))
-						.unwrap();
-					if read == 0 {
-						return;
-					}
-					buf.extend_from_slice(&chunk[..read]);
-				}
-			}
-		});
-		JsonStream { _listener: listener, recv }
+impl Blacklist {
+	fn load(path: &Path, duration: u64) -> Result<Blacklist, String> {
+		let data = match std::fs::read_to_string(path) {
+			Ok(data) => data,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+			Err(err) => return Err(format!("could not read {}: {}", path.display(), err)),
+		};
+		let now = unix_time();
+		let mut blacklist = Blacklist { path: path.to_owned(), entries: HashMap::new(), duration };
+		blacklist.entries = Self::parse(&data, now);
+		blacklist.entries.retain(|_, added| now < *added + duration);
+		let mut compacted = String::new();
+		for (username, added) in &blacklist.entries {
+			writeln!(compacted, "{} {}", username, added).unwrap();
+		}
+		std::fs::write(path, compacted).map_err(|err| format!("could not write {}: {}", path.display(), err))?;
+		Ok(blacklist)
 	}
-	fn read(&self) -> Option<Result<Res, String>> {
-		self.recv.recv().ok()
+	// lines without a time, from older versions, count as added now
+	fn parse(data: &str, now: u64) -> HashMap<String, u64> {
+		data.lines().filter_map(|line| {
+			let mut parts = line.split_whitespace();
+			let username = parts.next()?.to_owned();
+			Some((username, parts.next().and_then(|t| t.parse().ok()).unwrap_or(now)))
+		}).collect()
 	}
-	fn read_timeout(&self, dur: Duration) -> Option<Result<Res, String>> {
-		self.recv.recv_timeout(dur).ok()
+
+	fn contains(&self, username: &str) -> bool {
+		self.entries.get(username).is_some_and(|added| unix_time() < added + self.duration)
+	}
+	fn add(&mut self, username: &str) -> Result<(), String> {
+		let now = unix_time();
+		OpenOptions::new().append(true).create(true).open(&self.path)
+			.and_then(|mut file| writeln!(file, "{} {}", username, now))
+			.map_err(|err| format!("could not write to {}: {}", self.path.display(), err))?;
+		self.entries.insert(username.to_owned(), now);
+		Ok(())
 	}
 }
 
-struct BotClient {
-	token: String,
-	client: Client,
-}
-impl BotClient {
-	fn request(&self, req: BotReq) -> Result<Response, String> {
-		loop {
-			let mut b = self.client.request(req.method.clone(), req.url.clone())
-				.bearer_auth(&self.token);
-			if let Some(body) = &req.body {
-				b = b.form(body);
-			}
-			let res = b.send().map_err(|e| format!("failed to send request: {}", e))?;
-			let status = res.status();
-			if status.as_u16() == 429 {
-				eprintln!("{YELLOW}warning:{RESET} received Too Many Requests, waiting 1 minute");
-				std::thread::sleep(Duration::from_secs(60));
-				continue
-			} else if !status.is_success() {
-				let mut msg = format!("HTTP {}", status.as_u16());
-				if let Some(reason) = status.canonical_reason() {
-					write!(msg, " {}", reason).unwrap();
-				}
-				#[derive(Deserialize)]
-				struct ErrorData {
-					error: String,
-				}
-				if let Ok(data) = res.json::<ErrorData>() {
-					write!(msg, ": {}", data.error).unwrap();
-				}
-				return Err(msg);
-			}
-			return Ok(res);
-		}
-	}
+const NEW_TOKEN_HINT: &str = "create one with the bot:play scope at https://lichess.org/account/oauth/token/create?scopes[]=bot:play";
+
+// the token was revoked or never valid, which no retry can fix
+fn is_auth_error(err: &str) -> bool {
+	err.contains("HTTP 401")
+}
 
-	fn json<Res: DeserializeOwned>(&self, req: BotReq) -> Result<Res, String> {
-		let res = self.request(req)?;
-		res.json::<Res>().map_err(|e| format!("unexpected response: {}", e))
+// lichess lists the scopes of the token in a header of every response; they
+// are not checked if it is missing
+fn check_bot_scope(scopes: Option<&str>) -> Result<(), String> {
+	match scopes {
+		Some(scopes) if !scopes.split(',').any(|scope| scope.trim() == "bot:play") =>
+			Err(format!("the token does not have the bot:play scope, {}", NEW_TOKEN_HINT)),
+		_ => Ok(()),
 	}
+}
 
-	fn stream_json<Res: DeserializeOwned + Send + 'static>(&self, req: BotReq) -> Result<JsonStream<Res>, String> {
-		Ok(JsonStream::new(self.request(req)?))
+// the account of the token, checking that it can play as a bot, so that
+// problems are reported on startup rather than as errors in games
+async fn bot_account(client: &LichessClient, upgrade: bool) -> Result<Account, String> {
+	let (account, scopes) = match client.account_with_scopes().await {
+		Err(err) if is_auth_error(&err) => return Err(format!("the token was rejected ({}), {}", err, NEW_TOKEN_HINT)),
+		res => res?,
+	};
+	check_bot_scope(scopes.as_deref())?;
+	if account.title.as_deref() != Some("BOT") {
+		if !upgrade {
+			return Err(format!("{} is not a bot account; run with --upgrade-account to make it one \
+				(this cannot be undone, and only works for accounts that have not played any games)", account.username));
+		}
+		info!("upgrading {} to a bot account", account.username);
+		client.upgrade_to_bot().await
+			.map_err(|e| format!("could not upgrade {} to a bot account: {}", account.username, e))?;
 	}
+	Ok(account)
+}
 
-	fn action(&self, req: BotReq) -> Result<(), String> {
-		#[derive(Deserialize, Debug)]
-		struct OkRes { ok: bool }
-		let data: OkRes = self.json(req)?;
-		if !data.ok {
-			return Err(format!("unexpected ok=false in 200 response"));
+// why games in a perf should be casual, if they should: rated games are only
+// played once the rating is established, and not with experimental builds,
+// so that experiments do not cost rating points
+fn casual_reason(play_rated: bool, experimental: bool, max_rd: i32, perf: Option<&Perf>) -> Option<String> {
+	if !play_rated {
+		Some(format!("PLAY_RATED is off"))
+	} else if experimental {
+		Some(format!("experimental build"))
+	} else {
+		match perf {
+			None => Some(format!("no rating yet")),
+			Some(perf) if perf.prov => Some(format!("provisional rating")),
+			Some(perf) if perf.rd > max_rd => Some(format!("rating deviation {} > {}", perf.rd, max_rd)),
+			Some(_) => None,
 		}
-		Ok(())
 	}
 }
 
-#[derive(Deserialize)]
-struct AccountData {
-	id: String,
-	username: String,
-	perfs: HashMap<String, PerfData>,
+struct Bot {
+	config: Arc<Config>,
+	client: Arc<LichessClient>,
+	blacklist: Blacklist,
+	recent_opponents: VecDeque<String>, // last challenged bots, most recent first
+	abandoners: Arc<Mutex<HashMap<String, Instant>>>, // humans who left games before moving, by lowercase name
+	book: Arc<Mutex<Book>>, // learned from the bot's own games
+	account: Account,
+	games: HashMap<String, (JoinHandle<()>, CancellationToken)>, // one task per game in progress
+	matchmaking_round: usize, // picks the time control of the next outgoing challenge
+	stopping: CancellationToken, // no new games are started once cancelled
+	aborting: CancellationToken, // games in progress are stopped once cancelled
+	mode: RunMode,
+	games_started: usize,
+	tournaments: Vec<Tournament>, // joined and not known to be finished
+	seed: u64, // of the engine in each game and of the choice of opponents, logged so that runs can be replayed
+	rng: StdRng,
 }
-#[derive(Deserialize)]
-struct PerfData {
-	rating: i32,
-	rd: i32,
+
+// what the bot does, from the command line
+struct RunMode {
+	once: bool, // play a single game, then exit
+	challenge: Option<String>, // the only user to challenge
+	matchmaking: bool, // challenge other bots when idle
 }
 
-struct Bot {
-	config: Config,
-	client: BotClient,
-	blacklist_file: File,
-	blacklist: Vec<String>,
-	account: AccountData
-}
-
-fn load_bot() -> Result<Bot, String> {
-	let config = load_config()?;
-
-	let mut blacklist_file = OpenOptions::new().read(true).append(true).create(true)
-		.open("bot_blacklist.txt")
-		.map_err(|err| format!("could not open bot_blacklist.txt: {}", err))?;
-	let mut blacklist = String::new();
-	blacklist_file.read_to_string(&mut blacklist).unwrap();
-	let blacklist: Vec<String> = blacklist.lines().map(|s| s.to_owned()).collect();
-
-	let client = BotClient {
-		token: config.token.clone(),
-		client: Client::new(),
-	};
+// plays a single game, in its own task
+struct GamePlayer {
+	config: Arc<Config>,
+	client: Arc<LichessClient>,
+	account_id: String,
+	abandoners: Arc<Mutex<HashMap<String, Instant>>>,
+	book: Arc<Mutex<Book>>,
+	seed: u64, // of the engine, breaking ties between moves
+}
 
-	let account: AccountData = client.json(get("account"))?;
-	let blitz_perf = &account.perfs["blitz"];
-	println!("playing as {} (blitz rating {} / dev {})",
-		account.username, blitz_perf.rating, blitz_perf.rd);
+async fn load_bot(args: &Args) -> Result<Bot, String> {
+	let config = load_config(&args.config, &args.secrets)?;
+	set_console_level(config.log_level);
+	info!("{}", build_info());
+
+	let blacklist = Blacklist::load(&args.blacklist, config.blacklist_days * 86400)?;
+	let book = Book::load(BOOK_PATH)?;
+	if config.book_learning {
+		info!("learned book: {} entries", book.len());
+	}
+
+	let client = LichessClient::new(&config.token);
+	let account = bot_account(&client, args.upgrade_account).await?;
+	info!("playing as {}", account.username);
+	if config.experimental {
+		info!("experimental build, only playing casual games");
+	}
+	for speed in SPEEDS {
+		if let Some(perf) = account.perfs.get(speed) {
+			info!("{} rating {} / dev {}", speed, perf.rating, perf.rd);
+		}
+	}
+
+	let seed = args.seed.unwrap_or_else(rand::random);
+	info!("seed {}", seed);
 
 	Ok(Bot {
-		config,
-		client, 
-		blacklist_file,
+		config: Arc::new(config),
+		client: Arc::new(client),
 		blacklist,
-		account
+		recent_opponents: VecDeque::new(),
+		abandoners: Arc::new(Mutex::new(HashMap::new())),
+		book: Arc::new(Mutex::new(book)),
+		account,
+		games: HashMap::new(),
+		matchmaking_round: 0,
+		stopping: CancellationToken::new(),
+		aborting: CancellationToken::new(),
+		mode: RunMode {
+			once: args.once,
+			challenge: args.challenge.clone(),
+			matchmaking: !args.no_matchmaking,
+		},
+		games_started: 0,
+		tournaments: vec![],
+		seed,
+		rng: StdRng::seed_from_u64(seed),
 	})
 }
 
-impl Bot {
-	fn play_game(&self, game_id: &str) -> Result<(), String> {
-		let ai = chesslib::ai::SimpleAi::new(self.config.depth);
-
-		#[derive(Deserialize, Debug)]
-		#[serde(tag = "type", rename_all = "camelCase")]
-		enum GameEvent {
-			#[serde(rename_all = "camelCase")]
-			GameFull {
-				initial_fen: String,
-				state: GameState,
-				white: PlayerData,
-				black: PlayerData,
-			},
-			GameState(GameState),
-			ChatLine {
-				username: String,
-				text: String,
+const BOOK_PATH: &str = "bot_book.txt";
+
+const CHAT_REPLY_INTERVAL: Duration = Duration::from_secs(5);
+
+// moves in standard algebraic notation, separated by spaces
+fn san_line(pos: &Position, moves: &[Move]) -> String {
+	let mut pos = pos.clone();
+	let mut line = vec![];
+	for mov in moves {
+		line.push(pos.algebraic_notation(mov));
+		pos.apply_move(mov);
+	}
+	line.join(" ")
+}
+
+// the bot's score and the cloud's differ by this much when one of them misjudges the position
+const CLOUD_EVAL_DIVERGENCE: i16 = 150;
+
+// the best line of the lichess cloud against the bot's score, both from the side to move
+fn cloud_eval_report(pos: &Position, score: i16, eval: &CloudEval) -> Option<String> {
+	let pv = eval.pvs.first()?;
+	let white_score = pv.white_score(pos.side_to_move());
+	let cloud_score = if pos.side_to_move() == Color::White { white_score } else { -white_score };
+	let mut line = pos.clone();
+	let mut moves = vec![];
+	for uci in pv.moves.split_ascii_whitespace() {
+		let mov = *Move::parse_uci(uci, &line.gen_legal()).ok()?;
+		line.apply_move(&mov);
+		moves.push(mov);
+	}
+	let diverging = score.saturating_sub(cloud_score).saturating_abs() >= CLOUD_EVAL_DIVERGENCE;
+	Some(format!("cloud eval: depth {}, score {} (ours {}){}, pv {}", eval.depth, cloud_score, score,
+		if diverging { ", diverging" } else { "" }, san_line(pos, &moves)))
+}
+
+// looks up the position in the lichess cloud in the background, not to delay the game
+fn log_cloud_eval(client: &Arc<LichessClient>, pos: &Position, score: i16, target: &str) {
+	let (client, pos, target) = (client.clone(), pos.clone(), target.to_owned());
+	tokio::spawn(async move {
+		match client.cloud_eval(&pos.to_fen(), 1).await {
+			Ok(Some(eval)) => match cloud_eval_report(&pos, score, &eval) {
+				Some(report) => info!(target: &target, "{}", report),
+				None => debug!(target: &target, "cloud eval: invalid line {:?}", eval.pvs.first().map(|pv| &pv.moves)),
 			},
-			OpponentGone,
+			Ok(None) => debug!(target: &target, "cloud eval: position not in the cloud"),
+			Err(err) => debug!(target: &target, "cloud eval failed: {}", err),
 		}
-		#[derive(Deserialize, Debug)]
-		#[serde(rename_all = "camelCase")]
-		struct GameState {
-			moves: String,
-			status: String,
-		}
-		#[derive(Deserialize, Debug)]
-		struct PlayerData {
-			id: Option<String>,
+	});
+}
+
+// a score in pawns from the bot's side, or the mate it announces
+fn format_eval(score: i16) -> String {
+	if let Some(moves) = mate_in(score) {
+		if moves > 0 {
+			format!("I have a forced mate in {}", moves)
+		} else {
+			format!("I am getting mated in {}", -moves)
 		}
+	} else {
+		format!("{:+.2}", score as f64 / 100.0)
+	}
+}
 
-		let stream = self.client.stream_json(get("bot/game/stream").path(&game_id))?;
+// fills in a GREETING or GOODBYE message
+fn chat_message(template: &str, engine: &str, depth: u32, last_search: Option<&SearchOutcome>) -> String {
+	template.replace("{engine}", engine)
+		.replace("{depth}", &depth.to_string())
+		.replace("{eval}", &last_search.map_or("unknown".to_owned(), |search| format_eval(search.score)))
+}
 
-		let event: GameEvent = stream.read()
+// answer to a chat command, if the message is one
+fn chat_reply(text: &str, max_depth: u32, last_search: Option<&SearchOutcome>, pv: &str) -> Option<String> {
+	let command = text.trim().strip_prefix('!')?.split_whitespace().next()?.to_lowercase();
+	let reply = match (command.as_str(), last_search) {
+		("help", _) => format!("Commands: !eval (my evaluation), !depth (search depth), !pv (the line I expect), !help"),
+		("eval" | "depth" | "pv", None) => format!("I have not thought about this game yet."),
+		("eval", Some(search)) => format!("Eval: {} (from my side)", format_eval(search.score)),
+		("depth", Some(search)) => format!("Last search reached depth {} (maximum {})", search.depth, max_depth),
+		("pv", Some(_)) => format!("Expected line: {}", pv),
+		_ => return None,
+	};
+	Some(reply)
+}
+
+// searches on the opponent's time in a blocking thread, until the next search
+// or the end of the game, when it is dropped
+struct Pondering {
+	stop: Arc<AtomicBool>,
+	task: JoinHandle<()>,
+}
+impl Pondering {
+	fn start(ai: Arc<SimpleAi>, pos: Position) -> Pondering {
+		let stop = Arc::new(AtomicBool::new(false));
+		let task = tokio::task::spawn_blocking({
+			let stop = stop.clone();
+			move || ai.ponder(&pos, &stop)
+		});
+		Pondering { stop, task }
+	}
+}
+impl Drop for Pondering {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+}
+
+impl GamePlayer {
+	async fn play_game(&self, game_id: &str, cancel: CancellationToken) -> Result<(), String> {
+		let mut pondering: Option<Pondering> = None;
+		let target = &game_target(game_id);
+
+		let mut stream = self.client.game_stream(game_id, true).await?;
+
+		let event = stream.read().await
 			.ok_or_else(|| format!("game event stream closed unexpectedly"))??;
 
-		let (mut pos, mut history, color) = if let GameEvent::GameFull { initial_fen, state, white, black } = event {
-			println!("initial: {}", initial_fen);
-			println!("history: {}", state.moves);
-			println!("white: {} / black: {}", white.id.as_deref().unwrap_or("?"), black.id.as_deref().unwrap_or("?"));
-			println!("status: {}", state.status);
+		let (ai, mut game, mut pgn, mut clock, color, mut record, opponent_is_human) = if let GameEvent::GameFull(GameFull { initial_fen, variant, speed, rated, state, white, black, tournament_id }) = event {
+			info!(target: target, "initial: {}", initial_fen);
+			info!(target: target, "history: {}", state.moves);
+			info!(target: target, "white: {} / black: {}", white.id.as_deref().unwrap_or("?"), black.id.as_deref().unwrap_or("?"));
+			info!(target: target, "status: {}", state.status);
 
-			let color = if white.id.as_ref() == Some(&self.account.id) {
+			let color = if white.id.as_ref() == Some(&self.account_id) {
 				Color::White
-			} else if black.id.as_ref() == Some(&self.account.id) {
+			} else if black.id.as_ref() == Some(&self.account_id) {
 				Color::Black
 			} else {
 				return Err(format!("bot is not a player in this game"));
 			};
 			
-			let mut pos = Position::from_fen(
-				if initial_fen == "startpos" { Position::FEN_INITIAL } else { &initial_fen }
-			).ok_or_else(|| format!("failed to parse initial FEN"))?;
+			let fen = match variant_setup(&variant.key) {
+				Some(VariantSetup::Standard) => Position::FEN_INITIAL,
+				Some(VariantSetup::FromFen) if initial_fen == "startpos" => Position::FEN_INITIAL,
+				Some(VariantSetup::FromFen) => &initial_fen,
+				None => return Err(format!("unsupported variant: {}", variant.key)),
+			};
+			let start = initial_position(fen)?;
 			if state.status != "started" {
 				return Err(format!("unexpected game status"));
 			}
 
-			let mut history = vec![];
+			let mut pgn = Pgn::new(&start)
+				.tag("Event", "Lichess bot game")
+				.tag("Site", format!("https://lichess.org/{}", game_id))
+				.tag("White", white.name.as_deref().unwrap_or("?"))
+				.tag("Black", black.name.as_deref().unwrap_or("?"));
+			if let Some(rating) = white.rating {
+				pgn.set_tag("WhiteElo", rating);
+			}
+			if let Some(rating) = black.rating {
+				pgn.set_tag("BlackElo", rating);
+			}
+
+			let (own, opponent) = if color == Color::White { (&white, &black) } else { (&black, &white) };
+			let contempt = game_contempt(own.rating, opponent.rating, self.config.contempt);
+			info!(target: target, "contempt: {}", contempt);
+			let params = SearchParams { contempt, ..SearchParams::default() };
+			let ai = Arc::new(SimpleAi::new(self.config.depth).with_threads(self.config.threads).with_params(params).with_seed(self.seed));
+			pgn.set_tag("EngineVersion", format!("{} / {}", build_info(), ai.name()));
+			let record = GameRecord {
+				id: game_id.to_owned(),
+				date: format_date(SystemTime::now()),
+				opponent: opponent.name.clone().unwrap_or_else(|| "?".to_owned()),
+				color: color.to_string().to_lowercase(),
+				speed,
+				rated,
+				result: String::new(),
+				termination: String::new(),
+				rating: own.rating,
+				rating_diff: None,
+				avg_move_ms: 0,
+			};
+
+			let mut game = Game::new(&start);
 			for mov_desc in state.moves.split_ascii_whitespace() {
-				let moves = pos.gen_legal();
+				let moves = game.position().gen_legal();
 				let mov = Move::parse_uci(mov_desc, &moves)
 					.map_err(|err| format!("failed to parse game history: {} is {}", mov_desc, err))?;
-				history.push(mov_desc.to_owned());
-				pos.apply_move(&mov);
+				game.push(*mov);
 			}
 
-			(pos, history, color)
+			let mut clock = state.clock(color);
+			// berserking is only possible before the bot's first move
+			let before_first_move = game.moves().len() < if start.side_to_move() == color { 1 } else { 2 };
+			if tournament_id.is_some() && before_first_move && should_berserk(own.rating, opponent.rating, self.config.berserk_margin) {
+				info!(target: target, "berserking against {} ({:?})", record.opponent, opponent.rating);
+				match self.client.berserk(game_id).await {
+					Ok(()) => clock = (clock.0 / 2, Duration::ZERO),
+					Err(err) => warn!(target: target, "could not berserk: {}", err),
+				}
+			}
+
+			let opponent_is_human = opponent.id.is_some() && opponent.title.as_deref() != Some("BOT");
+			(ai, game, pgn, clock, color, record, opponent_is_human)
 		} else {
 			return Err(format!("unexpected first message: {event:?}"));
 		};
 
-		let mut moves = pos.gen_legal();
+		let mut moves = game.position().gen_legal();
+		let mut last_search: Option<SearchOutcome> = None;
+		let mut last_pv = String::new();
+		let mut last_chat_reply: Option<Instant> = None;
+		// the greeting is not repeated when resuming a game
+		if let Some(chat) = self.config.game_chat.as_ref().filter(|_| game.moves().len() < 2) {
+			let text = chat_message(&chat.greeting, &ai.name(), self.config.depth, None);
+			if let Err(err) = self.say(game_id, text).await {
+				warn!(target: target, "could not send greeting: {}", err);
+			}
+		}
+		let mut hopeless_moves = 0;
+		let mut last_draw_offer_ply = 0;
+		let mut searched_ply = 0;
+		let mut move_times = vec![];
+		let mut finished = false;
+		// games where the opponent never makes their first move are aborted
+		let opponent_moves_first = game.start_position().side_to_move() != color;
+		let opponent_moved = |game: &Game| game.moves().len() >= if opponent_moves_first { 1 } else { 2 };
+		let mut abandon_deadline = Some(Instant::now() + Duration::from_secs(self.config.abandon_timeout));
+		info!(target: target, "fen: {}", game.position().to_fen());
 		'game_loop: loop {
-			println!("state: {}", pos.to_fen());
 
-			if pos.side_to_move() == color && !moves.is_empty() {
-				println!("thinking...");
-				let mov = ai.pick_move(&pos, &moves);
-				println!("playing {}", mov);
-				self.client.action(post("bot/game")
-					.path(&game_id).path("move").path(mov.uci_notation()))?;
+			if game.position().side_to_move() == color && !moves.is_empty() && game.position().get_ply() != searched_ply {
+				searched_ply = game.position().get_ply();
+				let t0 = Instant::now();
+				let (remaining, increment) = clock;
+				let budget = chesslib::ai::time_budget(remaining, increment);
+				debug!(target: target, "thinking... (clock {} ms, budget {} ms)", remaining.as_millis(), budget.as_millis());
+				let search_moves = if self.config.book_learning {
+					self.book.lock().unwrap().filter_moves(game.position(), &moves)
+				} else {
+					moves.to_vec()
+				};
+				if search_moves.len() < moves.len() {
+					debug!(target: target, "book: avoiding {} losing move(s)", moves.len() - search_moves.len());
+				}
+				if let Some(mut pondering) = pondering.take() {
+					pondering.stop.store(true, Ordering::Relaxed);
+					let _ = (&mut pondering.task).await;
+				}
+				// search on a blocking thread; the AI keeps its state between moves
+				ai.set_history(game.previous_keys().to_vec());
+				let (search_pos, search_ai, search_target) = (game.position().clone(), ai.clone(), target.clone());
+				let search = tokio::task::spawn_blocking(move || search_ai.search_reporting(&search_pos, &search_moves, SearchLimits::within(budget), |outcome| {
+					debug!(target: &search_target, "depth {}: score {}, {} nodes in {} ms, pv {}",
+						outcome.depth, outcome.score, outcome.nodes, outcome.elapsed.as_millis(), san_line(&search_pos, &outcome.pv));
+				}));
+				let outcome;
+				tokio::select! {
+					res = search => {
+						outcome = res.map_err(|err| format!("search failed: {}", err))?;
+					},
+					_ = cancel.cancelled() => break 'game_loop,
+				}
+				let (mov, score) = (outcome.best_move, outcome.score);
+				last_pv = san_line(game.position(), &outcome.pv);
+				info!(target: target, "search: depth {}, score {}, {} nodes in {} ms, hashfull {}, pv {}",
+					outcome.depth, score, outcome.nodes, outcome.elapsed.as_millis(), outcome.hashfull, last_pv);
+				last_search = Some(outcome);
+
+				if self.config.resign_score.is_some_and(|threshold| score <= threshold) {
+					hopeless_moves += 1;
+				} else {
+					hopeless_moves = 0;
+				}
+				if hopeless_moves >= self.config.resign_moves {
+					info!(target: target, "resigning (score {})", score);
+					self.client.resign(game_id).await?;
+					continue;
+				}
+
+				let offer_draw = self.config.draw_offer_score.is_some_and(|threshold| score.abs() <= threshold)
+					&& game.position().get_board().all_pieces().count() <= self.config.draw_offer_max_pieces
+					&& (last_draw_offer_ply == 0 || game.position().get_ply() >= last_draw_offer_ply + 20);
+				info!(target: target, "playing {} (score {}){}", mov, score, if offer_draw { ", offering draw" } else { "" });
+				if offer_draw {
+					last_draw_offer_ply = game.position().get_ply();
+				}
+				self.client.make_move(game_id, &mov.uci_notation(), offer_draw).await?;
+				move_times.push(t0.elapsed());
+				if self.config.cloud_eval {
+					log_cloud_eval(&self.client, game.position(), score, target);
+				}
+				if self.config.ponder {
+					let mut ponder_pos = game.position().clone();
+					ponder_pos.apply_move(&mov);
+					if let Some(reply) = last_search.as_ref().and_then(|search| search.pv.get(1)) {
+						debug!(target: target, "pondering on {}", reply);
+					}
+					pondering = Some(Pondering::start(ai.clone(), ponder_pos));
+				}
 			}
 
 			loop {
-				let event: GameEvent = stream.read()
-					.ok_or_else(|| format!("game event stream closed unexpectedly"))??;
+				let event = tokio::select! {
+					event = stream.read() => event,
+					_ = cancel.cancelled() => {
+						info!(target: target, "stopping");
+						break 'game_loop;
+					},
+					_ = tokio::time::sleep_until(abandon_deadline.unwrap_or_else(Instant::now).into()),
+						if abandon_deadline.is_some() && !opponent_moved(&game) => {
+						info!(target: target, "opponent did not move within {} s, aborting", self.config.abandon_timeout);
+						abandon_deadline = None;
+						if opponent_is_human {
+							let mut abandoners = self.abandoners.lock().unwrap();
+							abandoners.retain(|_, t| t.elapsed() < Duration::from_secs(self.config.abandon_penalty));
+							abandoners.insert(record.opponent.to_lowercase(), Instant::now());
+						}
+						// the opponent may have moved in the meantime
+						if let Err(err) = self.client.abort(game_id).await {
+							warn!(target: target, "could not abort: {}", err);
+						}
+						continue;
+					},
+				};
+				let event = match event {
+					Some(Ok(event)) => event,
+					lost => {
+						let reason = match lost {
+							Some(Err(err)) => err,
+							_ => format!("stream closed"),
+						};
+						warn!(target: target, "game stream lost: {}", reason);
+						match self.reconnect_game_stream(game_id, &cancel).await? {
+							Some(new_stream) => stream = new_stream,
+							None => break 'game_loop,
+						}
+						// the move may not have been received, so the position is searched again if needed
+						searched_ply = 0;
+						continue;
+					},
+				};
 
 				match event {
-					GameEvent::GameState(state) => {
+					// a reopened stream starts over with the full game, which is caught up on like any state
+					GameEvent::GameState(state) | GameEvent::GameFull(GameFull { state, .. }) => {
 						if state.status != "started" {
-							println!("game status: {}", state.status);
+							info!(target: target, "game status: {}", state.status);
+							let result = match state.winner.as_deref() {
+								Some("white") => PgnResult::Win(Color::White),
+								Some("black") => PgnResult::Win(Color::Black),
+								_ if ["aborted", "noStart"].contains(&state.status.as_str()) => PgnResult::Unfinished,
+								_ => PgnResult::Draw,
+							};
+							pgn.set_result(result);
+							record.result = match result {
+								PgnResult::Win(winner) if winner == color => "win",
+								PgnResult::Win(_) => "loss",
+								PgnResult::Draw => "draw",
+								PgnResult::Unfinished => "unfinished",
+							}.to_owned();
+							record.termination = state.status;
+							finished = result != PgnResult::Unfinished;
+							if let Some(chat) = self.config.game_chat.as_ref().filter(|_| finished) {
+								let text = chat_message(&chat.goodbye, &ai.name(), self.config.depth, last_search.as_ref());
+								if let Err(err) = self.say(game_id, text).await {
+									warn!(target: target, "could not send goodbye: {}", err);
+								}
+							}
 							break 'game_loop;
 						}
 
-						for (i, mov_desc) in state.moves.split_ascii_whitespace().enumerate() {
-							if i < history.len() {
-								if history[i] != mov_desc {
-									return Err(format!("new game history does not match old one: {} / {}",
-										history.join(" "), state.moves,
-									));
-								}
-							} else {
-								println!("move: {}", mov_desc);
-								let mov = Move::parse_uci(mov_desc, &moves)
-									.map_err(|err| format!("failed to parse new move: {}", err))?;
-								history.push(mov_desc.to_owned());
-								pos.apply_move(&mov);
-								moves = pos.gen_legal();
+						clock = state.clock(color);
+						if state.offers_draw(color.opponent()) && !state.offers_draw(color) {
+							let last_score = last_search.as_ref().map(|search| search.score);
+							let accept = match (self.config.draw_accept_score, last_score) {
+								(Some(threshold), Some(score)) => score <= threshold,
+								_ => false,
+							};
+							info!(target: target, "{} draw offer (score {:?})", if accept { "accepting" } else { "declining" }, last_score);
+							if let Err(err) = self.client.answer_draw(game_id, accept).await {
+								warn!(target: target, "could not answer the draw offer: {}", err);
+							}
+						}
+						if state.proposes_takeback(color.opponent()) {
+							info!(target: target, "declining takeback");
+							if let Err(err) = self.client.answer_takeback(game_id, false).await {
+								warn!(target: target, "could not answer the takeback: {}", err);
 							}
 						}
+						// after a takeback, the history is shorter and the moves are undone
+						let new_moves: Vec<&str> = state.moves.split_ascii_whitespace().collect();
+						let known = game.moves();
+						// lichess gives promotions in lowercase
+						let common = known.iter().zip(&new_moves).take_while(|(old, new)| old.uci_notation().eq_ignore_ascii_case(new)).count();
+						if common < known.len() {
+							info!(target: target, "takeback: {} move(s) undone", known.len() - common);
+							for _ in common..known.len() {
+								game.undo();
+							}
+							moves = game.position().gen_legal();
+							searched_ply = 0;
+						}
+						for (i, mov_desc) in new_moves.iter().enumerate().skip(common) {
+							let mov = Move::parse_uci(mov_desc, &moves)
+								.map_err(|err| format!("failed to parse new move: {}", err))?;
+							game.push(*mov);
+							moves = game.position().gen_legal();
+							info!(target: target, "move {}: {}, fen: {}", i + 1, mov_desc, game.position().to_fen());
+						}
 						break;
 					},
-					GameEvent::ChatLine { username, text } =>
-						println!("chat: [{}] {}", username, text),
+					GameEvent::ChatLine { username, text, room } => {
+						info!(target: target, "chat: [{}] {}", username, text);
+						// only answer in the player room, and not too often
+						if room != "player" || username.to_lowercase() == self.account_id
+							|| last_chat_reply.is_some_and(|t| t.elapsed() < CHAT_REPLY_INTERVAL) {
+							continue;
+						}
+						if let Some(reply) = chat_reply(&text, self.config.depth, last_search.as_ref(), &last_pv) {
+							last_chat_reply = Some(Instant::now());
+							if let Err(err) = self.say(game_id, reply).await {
+								warn!(target: target, "could not reply in the chat: {}", err);
+							}
+						}
+					},
 					_ =>
-						println!("unexpected game event: {event:?}"),
+						warn!(target: target, "unexpected game event: {event:?}"),
+				}
+			}
+		}
+
+		pgn.set_moves(&game.moves());
+		let pgn_path = format!("games/{}.pgn", game_id);
+		if let Err(err) = pgn.save(&pgn_path) {
+			warn!(target: target, "could not write {}: {}", pgn_path, err);
+		}
+
+		if finished {
+			if !move_times.is_empty() {
+				record.avg_move_ms = (move_times.iter().sum::<Duration>() / move_times.len() as u32).as_millis() as u64;
+			}
+			if record.rated {
+				match self.rating_diff(game_id, color).await {
+					Ok(diff) => record.rating_diff = diff,
+					Err(err) => warn!(target: target, "could not get rating change: {}", err),
+				}
+			}
+			if let Err(err) = record.save() {
+				warn!(target: target, "could not write {}: {}", GameRecord::PATH, err);
+			}
+			if self.config.book_learning {
+				let mut book = self.book.lock().unwrap();
+				book.learn(&pgn, color);
+				if let Err(err) = book.save(BOOK_PATH) {
+					warn!(target: target, "could not write {}: {}", BOOK_PATH, err);
 				}
 			}
 		}
 
 		Ok(())
 	}
+}
+
+impl GamePlayer {
+	// reopens the event stream of a game after it dropped; returns None if the game is cancelled meanwhile
+	async fn reconnect_game_stream(&self, game_id: &str, cancel: &CancellationToken) -> Result<Option<JsonStream<GameEvent>>, String> {
+		let target = &game_target(game_id);
+		for attempt in 0..GAME_RECONNECT_ATTEMPTS {
+			let delay = reconnect_delay(attempt);
+			info!(target: target, "reconnecting in {} s (attempt {}/{})", delay.as_secs(), attempt + 1, GAME_RECONNECT_ATTEMPTS);
+			tokio::select! {
+				_ = sleep(delay) => {},
+				_ = cancel.cancelled() => return Ok(None),
+			}
+			match self.client.game_stream(game_id, false).await {
+				Ok(stream) => {
+					info!(target: target, "reconnected");
+					return Ok(Some(stream));
+				},
+				Err(err) => warn!(target: target, "could not reconnect: {}", err),
+			}
+		}
+		Err(format!("game event stream lost, gave up after {} attempts", GAME_RECONNECT_ATTEMPTS))
+	}
+
+	// sends a message in the player chat
+	async fn say(&self, game_id: &str, text: String) -> Result<(), String> {
+		self.client.chat(game_id, "player", &text).await
+	}
+
+	// rating change of the bot in a finished game, as lichess computed it
+	async fn rating_diff(&self, game_id: &str, color: Color) -> Result<Option<i32>, String> {
+		let game = self.client.export_game(game_id).await?;
+		Ok(game.players.get(color).rating_diff)
+	}
+}
+
+// Correspondence games are not followed with a game stream: whenever it is the
+// bot's turn, it makes one move, then searches the reply to the move it expects
+// from the opponent, so that it is ready the next time. The pondered moves are
+// kept between runs.
+const CORRESPONDENCE_PATH: &str = "bot_correspondence.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Pondered {
+	fen: String, // position the move is for
+	best_move: String, // UCI
+	expected_reply: Option<String>,
+	score: i16,
+	depth: u32,
+}
+
+// pondered moves, by game ID
+fn load_pondered() -> Result<HashMap<String, Pondered>, String> {
+	match std::fs::read_to_string(CORRESPONDENCE_PATH) {
+		Ok(data) => serde_json::from_str(&data).map_err(|err| format!("{}: {}", CORRESPONDENCE_PATH, err)),
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+		Err(err) => Err(format!("could not read {}: {}", CORRESPONDENCE_PATH, err)),
+	}
+}
+fn save_pondered(pondered: &HashMap<String, Pondered>) -> std::io::Result<()> {
+	std::fs::write(CORRESPONDENCE_PATH, serde_json::to_string_pretty(pondered).unwrap())
+}
+
+impl GamePlayer {
+	// searches without keeping state between moves, on a blocking thread; `history`
+	// holds the keys of the previous positions of the game, see SimpleAi::set_history
+	async fn search(&self, pos: &Position, moves: &[Move], budget: Duration, params: SearchParams, history: Vec<u64>)
+			-> Result<SearchOutcome, String> {
+		let moves = if self.config.book_learning {
+			self.book.lock().unwrap().filter_moves(pos, moves)
+		} else {
+			moves.to_vec()
+		};
+		let (pos, depth, threads, seed) = (pos.clone(), self.config.depth, self.config.threads, self.seed);
+		tokio::task::spawn_blocking(move || {
+			let ai = SimpleAi::new(depth).with_threads(threads).with_params(params).with_seed(seed);
+			ai.set_history(history);
+			ai.pick_move_timed(&pos, &moves, budget)
+		}).await
+			.map_err(|err| format!("search failed: {}", err))
+	}
+
+	// makes one move in a correspondence game, if it is the bot's turn
+	async fn play_correspondence_move(&self, game_id: &str, pondered: &mut HashMap<String, Pondered>) -> Result<(), String> {
+		let target = &game_target(game_id);
+
+		// the game stream starts with the full game, which is all that is needed
+		let mut stream = self.client.game_stream(game_id, false).await?;
+		let event = stream.read().await
+			.ok_or_else(|| format!("game event stream closed unexpectedly"))??;
+		stream.close();
+		let GameEvent::GameFull(game) = event else {
+			return Err(format!("unexpected first message: {event:?}"));
+		};
+		if game.state.status != "started" {
+			return Ok(());
+		}
+		let color = if game.white.id.as_ref() == Some(&self.account_id) {
+			Color::White
+		} else if game.black.id.as_ref() == Some(&self.account_id) {
+			Color::Black
+		} else {
+			return Err(format!("bot is not a player in this game"));
+		};
+		let fen = match variant_setup(&game.variant.key) {
+			Some(VariantSetup::Standard) => Position::FEN_INITIAL,
+			Some(VariantSetup::FromFen) if game.initial_fen == "startpos" => Position::FEN_INITIAL,
+			Some(VariantSetup::FromFen) => &game.initial_fen,
+			None => return Err(format!("unsupported variant: {}", game.variant.key)),
+		};
+		let mut history = Game::new(&initial_position(fen)?);
+		for mov_desc in game.state.moves.split_ascii_whitespace() {
+			let mov = *Move::parse_uci(mov_desc, &history.position().gen_legal())
+				.map_err(|err| format!("failed to parse game history: {} is {}", mov_desc, err))?;
+			history.push(mov);
+		}
+		let pos = history.position().clone();
+		let moves = pos.gen_legal();
+		if pos.side_to_move() != color || moves.is_empty() {
+			return Ok(());
+		}
 
-	fn find_active_game(&self) -> Result<Option<String>, String> {
-		#[derive(Deserialize, Debug)]
-		#[serde(rename_all = "camelCase")]
-		struct PlayingData {
-			now_playing: Vec<GameData>,
+		let (own, opponent) = if color == Color::White { (&game.white, &game.black) } else { (&game.black, &game.white) };
+		let params = SearchParams { contempt: game_contempt(own.rating, opponent.rating, self.config.contempt), ..SearchParams::default() };
+		let budget = Duration::from_secs(self.config.correspondence_move_time);
+		let fen = pos.to_fen();
+		let ready = pondered.remove(game_id).filter(|p| p.fen == fen)
+			.and_then(|p| Some((*Move::parse_uci(&p.best_move, &moves).ok()?, p)));
+		let (mov, score, expected_reply) = if let Some((mov, p)) = ready {
+			info!(target: target, "playing pondered move {} (depth {}, score {})", mov, p.depth, p.score);
+			(mov, p.score, p.expected_reply)
+		} else {
+			let outcome = self.search(&pos, &moves, budget, params, history.previous_keys().to_vec()).await?;
+			info!(target: target, "playing {} (depth {}, score {}, pv {})", outcome.best_move, outcome.depth, outcome.score, san_line(&pos, &outcome.pv));
+			(outcome.best_move, outcome.score, outcome.pv.get(1).map(|reply| reply.uci_notation()))
+		};
+		self.client.make_move(game_id, &mov.uci_notation(), false).await?;
+		if self.config.cloud_eval {
+			log_cloud_eval(&self.client, &pos, score, target);
 		}
-		#[derive(Deserialize, Debug)]
-		#[serde(rename_all = "camelCase")]
-		struct GameData {
-			game_id: String,
+
+		// ponder on the expected reply, if any
+		history.push(mov);
+		let Some(reply) = expected_reply.and_then(|reply| Move::parse_uci(&reply, &history.position().gen_legal()).ok().copied()) else {
+			return Ok(());
+		};
+		history.push(reply);
+		let pos = history.position();
+		let moves = pos.gen_legal();
+		if moves.is_empty() {
+			return Ok(());
 		}
-		let playing: PlayingData = self.client.json(get("account/playing").query("nb", 10))?;
+		debug!(target: target, "pondering on {}", reply);
+		let outcome = self.search(pos, &moves, budget, params, history.previous_keys().to_vec()).await?;
+		pondered.insert(game_id.to_owned(), Pondered {
+			fen: pos.to_fen(),
+			best_move: outcome.best_move.uci_notation(),
+			expected_reply: outcome.pv.get(1).map(|reply| reply.uci_notation()),
+			score: outcome.score,
+			depth: outcome.depth,
+		});
+		Ok(())
+	}
+}
+
+// the rating band is widened, by these factors, until it contains bots
+const RATING_BAND_STEPS: [i32; 4] = [1, 2, 3, 5];
+// bots challenged recently are only picked again if there is no one else
+const RECENT_OPPONENTS: usize = 3;
 
-		Ok(playing.now_playing.first().map(|g| g.game_id.clone()))
+// opponents to pick from among (username, rating) candidates, with the rating band they are in
+fn pick_opponents<'a>(candidates: &'a [(String, i32)], own_rating: i32, recent: &VecDeque<String>, mm: &Matchmaking) -> Option<(Vec<&'a str>, i32)> {
+	let allowed = |name: &str| mm.allowlist.is_empty() || mm.allowlist.iter().any(|allowed| allowed.eq_ignore_ascii_case(name));
+	for avoid_recent in [true, false] {
+		for step in RATING_BAND_STEPS {
+			let band = mm.rating_band * step;
+			let in_band: Vec<&(String, i32)> = candidates.iter()
+				.filter(|(name, rating)| allowed(name) && !(avoid_recent && recent.contains(name))
+					&& (!mm.allowlist.is_empty() || (rating - own_rating).abs() <= band))
+				.collect();
+			let stronger: Vec<&str> = in_band.iter().filter(|(_, rating)| *rating >= own_rating).map(|(name, _)| name.as_str()).collect();
+			let names = if mm.prefer_stronger && !stronger.is_empty() {
+				stronger
+			} else {
+				in_band.iter().map(|(name, _)| name.as_str()).collect()
+			};
+			if !names.is_empty() {
+				return Some((names, band));
+			}
+		}
 	}
+	None
+}
 
-	fn find_bot_opponent(&self) -> Result<Option<String>, String> {
-		let blitz_rating = self.account.perfs["blitz"].rating;
-		let min_rating = blitz_rating - 100;
-		let max_rating = blitz_rating + 100;
-		println!("searching for bot with rating in [{}, {}]...", min_rating, max_rating);
+impl Bot {
+	async fn find_active_games(&self) -> Result<Vec<PlayingGame>, String> {
+		self.client.playing(self.config.max_games.max(10)).await
+	}
 
-		let stream = self.client.stream_json::<AccountData>(get("bot/online"))?;		
-		let mut matching_bots = vec![];
-		while let Some(res) = stream.read() {
+	async fn find_bot_opponent(&mut self, speed: &str) -> Result<Option<String>, String> {
+		let speed = self.config.matchmaking.perf.as_deref().unwrap_or(speed);
+		let own_rating = self.account.perfs.get(speed).map_or(1500, |perf| perf.rating);
+		info!("searching for bot with {} rating around {}...", speed, own_rating);
+
+		let mut stream = self.client.online_bots().await?;
+		let mut candidates = vec![];
+		let mut online = 0;
+		while let Some(res) = stream.read().await {
 			let bot = res?;
-			let blitz_rating = bot.perfs["blitz"].rating;
-			if blitz_rating >= min_rating && blitz_rating <= max_rating
-				&& self.blacklist.iter().all(|un| un != &bot.username) {
-				matching_bots.push(bot.username);
-				print!("o");
+			online += 1;
+			if let Some(perf) = bot.perfs.get(speed) {
+				if !self.blacklist.contains(&bot.username) {
+					candidates.push((bot.username, perf.rating));
+				}
+			}
+		}
+		debug!("{} of {} online bots are not blacklisted", candidates.len(), online);
+		let (names, band) = match pick_opponents(&candidates, own_rating, &self.recent_opponents, &self.config.matchmaking) {
+			Some(res) => res,
+			None => return Ok(None),
+		};
+		if self.config.matchmaking.allowlist.is_empty() {
+			info!("{} bot(s) within {} rating points", names.len(), band);
+		}
+		Ok(Some(names[self.rng.gen_range(0..names.len())].to_owned()))
+	}
+
+	// tournaments that cannot be joined (finished, restricted to a team...) are skipped
+	async fn join_tournaments(&mut self) {
+		for tournament in &self.config.tournaments {
+			match self.client.join_tournament(tournament.swiss, &tournament.id).await {
+				Ok(()) => {
+					info!("joined {}", tournament);
+					self.tournaments.push(tournament.clone());
+				},
+				Err(err) => warn!("could not join {}: {}", tournament, err),
+			}
+		}
+	}
+
+	// forgets the joined tournaments that are over; pairings come as gameStart
+	// events, so other bots are not challenged while one is still running
+	async fn in_tournament(&mut self) -> Result<bool, String> {
+		let mut running = vec![];
+		for tournament in std::mem::take(&mut self.tournaments) {
+			if self.client.tournament_finished(tournament.swiss, &tournament.id).await? {
+				info!("{} is over", tournament);
 			} else {
-				print!("x");
+				running.push(tournament);
 			}
-			std::io::stdout().flush().unwrap();
 		}
-		println!("");
-		Ok(if matching_bots.is_empty() {
-			None
-		} else {
-			let name = matching_bots[rand::random::<usize>() % matching_bots.len()].clone();
-			Some(name)
-		})
+		self.tournaments = running;
+		Ok(!self.tournaments.is_empty())
 	}
 
-	fn challenge_user(&mut self, username: &str) -> Result<Option<String>, String> {
-		println!("challenging user {}", username);
+	fn casual_reason(&self, speed: &str) -> Option<String> {
+		casual_reason(self.config.play_rated, self.config.experimental, self.config.casual_max_rd, self.account.perfs.get(speed))
+	}
 
-		#[derive(Deserialize, Debug)]
-		#[serde(untagged)]
-		enum ChallengeStreamData {
-			Challenge {
-				id: String,
-			},
-			#[serde(rename_all = "camelCase")]
-			Response {
-				done: String,
-			},
+	async fn challenge_user(&mut self, username: &str, time_control: TimeControl) -> Result<Option<String>, String> {
+		// the rating deviation changes with every game
+		self.account = self.client.account().await?;
+		let casual = self.casual_reason(time_control.speed());
+		match &casual {
+			Some(reason) => info!("challenging user {} ({}, casual: {})", username, time_control, reason),
+			None => info!("challenging user {} ({}, rated)", username, time_control),
 		}
-		let stream: JsonStream<ChallengeStreamData> = self.client.stream_json(post("challenge")
-			.path(username)
-			.body("rated", self.config.play_rated)
-			.body("clock.limit", self.config.clock_initial)
-			.body("clock.increment", self.config.clock_increment)
-			.body("color", "random")
-			.body("keepAliveStream", true)
-		)?;
-		let msg = stream.read_timeout(Duration::from_secs(5))
+		self.recent_opponents.retain(|name| name != username);
+		self.recent_opponents.push_front(username.to_owned());
+		self.recent_opponents.truncate(RECENT_OPPONENTS);
+
+		let mut stream = self.client.challenge(username, casual.is_none(), time_control.initial, time_control.increment).await?;
+		let msg = stream.read_timeout(Duration::from_secs(5)).await
 			.ok_or_else(|| format!("creation of challenge timed out"))??;
 		let game_id;
-		if let ChallengeStreamData::Challenge { id } = msg {
+		if let ChallengeEvent::Challenge { id } = msg {
 			game_id = id
 		} else {
 			return Err(format!("unexpected message in challenge event stream"));
 		}
-		println!("challenge sent, waiting...");
+		info!("challenge sent, waiting...");
 
 		let status;
-		if let Some(msg) = stream.read_timeout(Duration::from_secs(self.config.challenge_timeout)) {
-			if let ChallengeStreamData::Response { done } = msg? {
+		if let Some(msg) = stream.read_timeout(Duration::from_secs(self.config.challenge_timeout)).await {
+			if let ChallengeEvent::Response { done } = msg? {
 				status = done;
 			} else {
 				return Err(format!("unexpected message in challenge event stream"));
 			}
 		} else {
-			println!("challenge timed out.");
+			info!("challenge timed out.");
+			stream.close(); // this cancels the challenge
 			return Ok(None);
 		}
 		if status != "accepted" {
-			println!("challenge was not accepted (status: {})", status);
-			println!("adding bot {} to blacklist", username);
-			write!(self.blacklist_file, "{}\n", username)
-				.map_err(|err| format!("could not write to blacklist file: {}", err))?;
-			self.blacklist.push(username.to_owned());
+			info!("challenge was not accepted (status: {})", status);
+			// users challenged on request are not necessarily bots
+			if self.mode.challenge.is_none() {
+				info!("adding bot {} to blacklist for {} days", username, self.config.blacklist_days);
+				self.blacklist.add(username)?;
+			}
 			return Ok(None);
 		}
 
@@ -496,113 +1460,622 @@ impl Bot {
 	}
 }
 
-#[derive(Deserialize, Debug)]
-struct Challenge {
-	id: String,
-	status: String,
-	speed: String,
-	variant: Variant,
-	challenger: ChallengeUser,
+// how the initial position of a supported variant is set up; variants with
+// different rules (chess960 castling, crazyhouse drops...) need library support
+// before they can be added here
+#[derive(Clone, Copy)]
+enum VariantSetup {
+	Standard, // the standard initial position
+	FromFen,  // standard rules from the FEN given by lichess
 }
-#[derive(Deserialize, Debug)]
-struct ChallengeUser {
-	name: String,
+const SUPPORTED_VARIANTS: [(&str, VariantSetup); 2] = [
+	("standard", VariantSetup::Standard),
+	("fromPosition", VariantSetup::FromFen),
+];
+fn variant_setup(key: &str) -> Option<VariantSetup> {
+	SUPPORTED_VARIANTS.iter().find(|(k, _)| *k == key).map(|(_, setup)| *setup)
 }
-#[derive(Deserialize, Debug)]
-struct Variant {
-	key: String,
+// the initial position of a game, refusing positions the engine cannot play from
+fn initial_position(fen: &str) -> Result<Position, String> {
+	let pos = Position::from_fen(fen).map_err(|err| format!("failed to parse initial FEN {}: {}", fen, err))?;
+	pos.validate().map_err(|err| format!("illegal initial position {}: {}", fen, err))?;
+	Ok(pos)
 }
 
 impl Bot {
-	fn process_challenge(&self, chal: &Challenge) -> Result<bool, String> {
-		if chal.status == "created" || chal.status == "offline" {
-			if chal.speed != "blitz" {
-				println!("declining challenge {} from {}: not blitz", chal.id, chal.challenger.name);
-				self.client.action(post("challenge")
-					.path(&chal.id).path("decline")
-					.body("reason", "declineTimeControl")
-				)?;
-			} else if chal.variant.key != "standard" {
-				println!("declining challenge {} from {}: not standard", chal.id, chal.challenger.name);
-				self.client.action(post("challenge")
-					.path(&chal.id).path("decline")
-					.body("reason", "declineStandard")
-				)?;
-			} else if chal.status == "created" {
-				println!("accepting challenge {} from {}", chal.id, chal.challenger.name);
-				self.client.action(post("challenge")
-					.path(&chal.id).path("accept")
-				)?;
-				return Ok(true);
+	fn has_free_slot(&self) -> bool {
+		if self.mode.once {
+			return self.games_started == 0;
+		}
+		self.games.len() < self.config.max_games
+	}
+
+	fn game_player(&self) -> GamePlayer {
+		GamePlayer {
+			config: self.config.clone(),
+			client: self.client.clone(),
+			account_id: self.account.id.clone(),
+			abandoners: self.abandoners.clone(),
+			book: self.book.clone(),
+			seed: self.seed.wrapping_add(self.games_started as u64),
+		}
+	}
+
+	// starts a task for each real-time game in progress that does not have one yet
+	fn start_games(&mut self, playing: &[PlayingGame]) {
+		self.games.retain(|_, (task, _)| !task.is_finished());
+		for game in playing.iter().filter(|game| game.speed != CORRESPONDENCE) {
+			let game_id = game.game_id.clone();
+			if self.games.contains_key(&game_id) || (self.mode.once && self.games_started > 0) {
+				continue;
 			}
+			info!("active game: {}", game_id);
+			let player = self.game_player();
+			let id = game_id.clone();
+			let cancel = self.aborting.child_token();
+			let task = tokio::spawn({
+				let cancel = cancel.clone();
+				async move {
+					if let Err(err) = player.play_game(&id, cancel).await {
+						error!(target: &game_target(&id), "{}", err);
+					}
+					close_game_log(&id);
+				}
+			});
+			self.games.insert(game_id, (task, cancel));
+			self.games_started += 1;
+		}
+	}
+
+	// makes a move in each correspondence game where it is the bot's turn
+	async fn play_correspondence(&self, playing: &[PlayingGame]) -> Result<(), String> {
+		let mut pondered = load_pondered()?;
+		pondered.retain(|id, _| playing.iter().any(|game| &game.game_id == id));
+		let player = self.game_player();
+		for game in playing.iter().filter(|game| game.speed == CORRESPONDENCE && game.is_my_turn) {
+			if self.stopping.is_cancelled() {
+				break;
+			}
+			if let Err(err) = player.play_correspondence_move(&game.game_id, &mut pondered).await {
+				error!(target: &game_target(&game.game_id), "{}", err);
+			}
+			close_game_log(&game.game_id);
+		}
+		save_pondered(&pondered).map_err(|err| format!("could not write {}: {}", CORRESPONDENCE_PATH, err))
+	}
+
+	// stops the task of a game that has ended, if it has not noticed by itself
+	fn end_game(&self, game_id: &str) {
+		if let Some((_, cancel)) = self.games.get(game_id) {
+			let cancel = cancel.clone();
+			tokio::spawn(async move {
+				sleep(Duration::from_secs(5)).await; // leave time to record the result
+				cancel.cancel();
+			});
 		}
-		Ok(false)
 	}
 
-	fn await_challenge(&self) -> Result<bool, String> {
-		#[derive(Deserialize, Debug)]
-		struct Challenges {
-			r#in: Vec<Challenge>,
+	// waits for the games in progress to end
+	async fn finish_games(&mut self) {
+		for (game_id, (task, _)) in self.games.drain() {
+			if let Err(err) = task.await {
+				error!(target: &game_target(&game_id), "{}", err);
+			}
+		}
+	}
+
+	// looks after games in progress, and looks for new ones if there is room
+	async fn step(&mut self) -> Result<(), String> {
+		debug!("rate limiter: {}", self.client.limiter.stats());
+		let playing = self.find_active_games().await?;
+		self.start_games(&playing);
+		if self.mode.once && self.games_started > 0 {
+			if self.games.values().all(|(task, _)| task.is_finished()) {
+				info!("the game is over, exiting");
+				self.stopping.cancel();
+				return Ok(());
+			}
+		} else {
+			self.play_correspondence(&playing).await?;
+		}
+		if self.client.breaker.is_open() {
+			info!("lichess is unavailable, not looking for new games");
+			return Ok(());
+		}
+		// a user to challenge on request is challenged right away
+		if self.mode.challenge.is_none() || !self.has_free_slot() {
+			if self.has_free_slot() {
+				info!("{}/{} games in progress, waiting for challenges...", self.games.len(), self.config.max_games);
+			}
+			if self.await_events().await? || !self.has_free_slot() || !self.mode.matchmaking { return Ok(()) }
+			if self.in_tournament().await? {
+				info!("waiting for tournament pairings");
+				return Ok(());
+			}
+			info!("received no challenges, starting matchmaking");
+		}
+		let time_control = self.config.time_controls[self.matchmaking_round % self.config.time_controls.len()];
+		self.matchmaking_round += 1;
+		if let Some(username) = self.mode.challenge.clone() {
+			if self.challenge_user(&username, time_control).await?.is_none() {
+				warn!("{} did not accept the challenge, exiting after the games in progress", username);
+				self.stopping.cancel();
+			}
+		} else if let Some(username) = self.find_bot_opponent(time_control.speed()).await? {
+			self.challenge_user(&username, time_control).await?;
+		} else {
+			info!("found no suitable opponents.");
 		}
-		let challenges: Challenges = self.client.json(get("challenge"))?;
-		for chal in challenges.r#in {
-			if self.process_challenge(&chal)? {
+		Ok(())
+	}
+
+	async fn process_challenge(&self, chal: &Challenge) -> Result<bool, String> {
+		let abandoned_at = self.abandoners.lock().unwrap().get(&chal.challenger.name.to_lowercase()).copied();
+		if chal.status == "created" && abandoned_at.is_some_and(|t| t.elapsed() < Duration::from_secs(self.config.abandon_penalty)) {
+			info!("declining challenge {} from {}: abandoned a game recently", chal.id, chal.challenger.name);
+			self.client.decline_challenge(&chal.id, "later").await?;
+		} else if chal.status == "created" && !self.has_free_slot() {
+			info!("declining challenge {} from {}: too many games", chal.id, chal.challenger.name);
+			self.client.decline_challenge(&chal.id, "later").await?;
+		} else if chal.status == "created" || chal.status == "offline" {
+			if !self.config.accepted_speeds.contains(&chal.speed) {
+				info!("declining challenge {} from {}: {} is not accepted", chal.id, chal.challenger.name, chal.speed);
+				self.client.decline_challenge(&chal.id, "timeControl").await?;
+			} else if let Some(reason) = self.casual_reason(&chal.speed).filter(|_| chal.rated) {
+				info!("declining rated challenge {} from {}: {}", chal.id, chal.challenger.name, reason);
+				self.client.decline_challenge(&chal.id, "casual").await?;
+			} else if variant_setup(&chal.variant.key).is_none() {
+				info!("declining challenge {} from {}: {} is not supported", chal.id, chal.challenger.name, chal.variant.key);
+				self.client.decline_challenge(&chal.id, "variant").await?;
+			} else if chal.status == "created" {
+				info!("accepting challenge {} from {}", chal.id, chal.challenger.name);
+				self.client.accept_challenge(&chal.id).await?;
 				return Ok(true);
 			}
 		}
+		Ok(false)
+	}
 
-		#[derive(Deserialize, Debug)]
-		#[serde(tag = "type", rename_all = "camelCase")]
-		enum GameEvent {
-			GameStart,
-			GameFinish,
-			Challenge {
-				challenge: Challenge,
-			},
-			ChallengeCanceled,
-			ChallengeDeclined,
+	// waits for a challenge to accept or a game to start or finish
+	async fn await_events(&self) -> Result<bool, String> {
+		for chal in self.client.incoming_challenges().await? {
+			if self.process_challenge(&chal).await? {
+				return Ok(true);
+			}
 		}
 
 		let timeout_instant = Instant::now() + Duration::from_secs(self.config.idle_timeout);
-		let stream = self.client.stream_json(get("stream/event"))?;
-		while let Some(res) = stream.read_timeout(timeout_instant - Instant::now()) {
-			let event: GameEvent = res?;
-			if let GameEvent::Challenge { challenge } = event {
-				if challenge.challenger.name != self.account.username && self.process_challenge(&challenge)? {
+		let mut stream = self.client.event_stream().await?;
+		while let Some(res) = stream.read_timeout(timeout_instant - Instant::now()).await {
+			let event = res?;
+			if let Event::Challenge { challenge } = event {
+				if challenge.challenger.name != self.account.username && self.process_challenge(&challenge).await? {
 					return Ok(true);
 				}
-			} else if let GameEvent::GameStart = event {
+			} else if let Event::GameFinish { game } = event {
+				self.end_game(&game.id);
+				return Ok(true);
+			} else if let Event::GameStart = event {
 				return Ok(true);
 			} else {
-				println!("event: {:?}", event);
+				debug!("event: {:?}", event);
 			}
 		}
 		Ok(false)
 	}
 }
 
-fn main() {
-	if let Err(err) = || -> Result<(), String> {
-		let mut bot = load_bot()?;
-		loop {
-			if let Some(game_id) = bot.find_active_game()? {
-				println!("active game: {}", game_id);
-				if let Err(err) = bot.play_game(&game_id) {
-					eprintln!("{BRIGHT_RED}error:{RESET} {}", err);
+// A finished game, as recorded in bot_games.jsonl (one JSON object per line).
+#[derive(Serialize, Deserialize, Debug)]
+struct GameRecord {
+	id: String,
+	date: String,
+	opponent: String,
+	color: String,
+	speed: String,
+	rated: bool,
+	result: String, // "win", "loss" or "draw", from the bot's perspective
+	termination: String, // lichess game status: mate, resign, outoftime...
+	rating: Option<i32>, // before the game
+	rating_diff: Option<i32>,
+	avg_move_ms: u64,
+}
+impl GameRecord {
+	const PATH: &'static str = "bot_games.jsonl";
+
+	fn save(&self) -> std::io::Result<()> {
+		let mut line = serde_json::to_string(self).map_err(std::io::Error::other)?;
+		line.push('\n');
+		// a single write, as games in progress may finish at the same time
+		OpenOptions::new().append(true).create(true).open(Self::PATH)?.write_all(line.as_bytes())
+	}
+
+	fn load_all() -> Result<Vec<GameRecord>, String> {
+		let data = match std::fs::read_to_string(Self::PATH) {
+			Ok(data) => data,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+			Err(err) => return Err(format!("could not read {}: {}", Self::PATH, err)),
+		};
+		data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(i, line)| {
+			serde_json::from_str(line).map_err(|err| format!("{}:{}: {}", Self::PATH, i + 1, err))
+		}).collect()
+	}
+}
+
+// win/loss/draw counts per opponent, and the rating after each rated game
+fn stats_report(records: &[GameRecord]) -> String {
+	let mut out = String::new();
+	let count = |records: &[&GameRecord], result: &str| records.iter().filter(|r| r.result == result).count();
+	let all: Vec<&GameRecord> = records.iter().collect();
+	writeln!(out, "{} games: {} won, {} lost, {} drawn",
+		all.len(), count(&all, "win"), count(&all, "loss"), count(&all, "draw")).unwrap();
+
+	let mut opponents: Vec<&str> = records.iter().map(|r| r.opponent.as_str()).collect();
+	opponents.sort_by_key(|name| name.to_lowercase());
+	opponents.dedup();
+	writeln!(out, "\n{:<24} {:>5} {:>4} {:>4} {:>4} {:>7}", "opponent", "games", "won", "lost", "drawn", "+/-").unwrap();
+	for opponent in opponents {
+		let games: Vec<&GameRecord> = records.iter().filter(|r| r.opponent == opponent).collect();
+		let diff: i32 = games.iter().filter_map(|r| r.rating_diff).sum();
+		writeln!(out, "{:<24} {:>5} {:>4} {:>4} {:>4} {:>+7}",
+			opponent, games.len(), count(&games, "win"), count(&games, "loss"), count(&games, "draw"), diff).unwrap();
+	}
+
+	writeln!(out, "\nrating history:").unwrap();
+	for record in records.iter().filter(|r| r.rated) {
+		let rating = match (record.rating, record.rating_diff) {
+			(Some(rating), Some(diff)) => format!("{} ({:+})", rating + diff, diff),
+			(Some(rating), None) => format!("{} (?)", rating),
+			_ => format!("?"),
+		};
+		writeln!(out, "{} {:<11} {:<12} {} vs {} ({}, {}, {} ms/move)", record.date, record.speed, rating,
+			record.result, record.opponent, record.termination, record.color, record.avg_move_ms).unwrap();
+	}
+	out
+}
+
+// before looking for games again after an error
+const STEP_ERROR_DELAY: Duration = Duration::from_secs(10);
+
+async fn run(args: &Args) -> Result<(), String> {
+	let mut bot = load_bot(args).await?;
+	if !bot.mode.once && bot.mode.challenge.is_none() {
+		bot.join_tournaments().await;
+	}
+
+	// the first ctrl-C lets the games in progress finish, the second aborts them
+	let (stopping, aborting) = (bot.stopping.clone(), bot.aborting.clone());
+	tokio::spawn(async move {
+		tokio::signal::ctrl_c().await.ok();
+		info!("shutting down after the games in progress (ctrl-C again to abort them)");
+		stopping.cancel();
+		tokio::signal::ctrl_c().await.ok();
+		info!("aborting games in progress");
+		aborting.cancel();
+	});
+
+	while !bot.stopping.is_cancelled() {
+		if bot.client.breaker.is_failing() {
+			let delay = bot.client.breaker.delay();
+			info!("waiting {} s for lichess to recover...", delay.as_secs());
+			tokio::select! {
+				_ = sleep(delay) => {},
+				_ = bot.stopping.cancelled() => {},
+			}
+			continue;
+		}
+		let stopping = bot.stopping.clone();
+		let res = tokio::select! {
+			res = bot.step() => res,
+			_ = stopping.cancelled() => Ok(()),
+		};
+		if let Err(err) = res {
+			if bot.client.breaker.is_failing() {
+				// server outages are transient
+				if !bot.client.breaker.is_open() {
+					warn!("{}", err);
 				}
+			} else if is_auth_error(&err) {
+				// no new games without a valid token; those in progress are left to end
+				bot.stopping.cancel();
+				bot.finish_games().await;
+				return Err(format!("the token was rejected ({}), {}", err, NEW_TOKEN_HINT));
 			} else {
-				println!("no active game, waiting for challenges...");
-				if bot.await_challenge()? { continue }
-				println!("received no challenges, starting matchmaking");
-				if let Some(username) = bot.find_bot_opponent()? {
-					bot.challenge_user(&username)?;
-				} else {
-					println!("foud no suitable opponents.");
+				// a failed challenge or matchmaking request does not concern the games in progress
+				error!("{}", err);
+				tokio::select! {
+					_ = sleep(STEP_ERROR_DELAY) => {},
+					_ = bot.stopping.cancelled() => {},
 				}
 			}
 		}
-	}() {
-		eprintln!("{BRIGHT_RED}error:{RESET} {}", err);
+	}
+	bot.finish_games().await;
+	info!("rate limiter: {}", bot.client.limiter.stats());
+	Ok(())
+}
+
+// a single wake-up for correspondence games, e.g. from a scheduled job
+async fn run_correspondence(args: &Args) -> Result<(), String> {
+	let bot = load_bot(args).await?;
+	let playing = bot.find_active_games().await?;
+	bot.play_correspondence(&playing).await
+}
+
+/// Plays on lichess as a bot account
+#[derive(Parser)]
+struct Args {
+	/// Settings file
+	#[arg(long, value_name = "FILE", default_value = "bot_config.toml")]
+	config: PathBuf,
+	/// File holding BOT_TOKEN, unless it is in the LICHESS_BOT_TOKEN variable
+	#[arg(long, value_name = "FILE", default_value = "bot_secrets.toml")]
+	secrets: PathBuf,
+	/// File of the bots that declined challenges
+	#[arg(long, value_name = "FILE", default_value = "bot_blacklist.txt")]
+	blacklist: PathBuf,
+	/// Play exactly one game, then exit
+	#[arg(long)]
+	once: bool,
+	/// Challenge this user instead of bots found by matchmaking
+	#[arg(long, value_name = "USER")]
+	challenge: Option<String>,
+	/// Only play games from incoming challenges
+	#[arg(long, conflicts_with = "challenge")]
+	no_matchmaking: bool,
+	/// Make one move in each correspondence game where it is the bot's turn, then exit
+	#[arg(long, conflicts_with_all = ["once", "challenge"])]
+	correspondence: bool,
+	/// Turn the account into a bot account if it is not one yet (irreversible)
+	#[arg(long)]
+	upgrade_account: bool,
+	/// Print statistics of the games recorded in bot_games.jsonl, then exit
+	#[arg(long, exclusive = true)]
+	stats: bool,
+	/// Seed of the random choices, to replay a run (random by default)
+	#[arg(long, value_name = "N")]
+	seed: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+	init_logger();
+	let args = Args::parse();
+	let res = if args.stats {
+		GameRecord::load_all().map(|records| print!("{}", stats_report(&records)))
+	} else if args.correspondence {
+		run_correspondence(&args).await
+	} else {
+		run(&args).await
+	};
+	if let Err(err) = res {
+		error!("{}", err);
 		std::process::exit(1);
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test_config {
+	use super::parse_config;
+
+	#[test]
+	fn test_parse_config() {
+		let base = "SEARCH_DEPTH = 4\nPLAY_RATED = true\nCLOCK_INITIAL = 180\nCLOCK_INCREMENT = 2\nIDLE_TIMEOUT = 30\nCHALLENGE_TIMEOUT = 60\n";
+		let config = parse_config(&format!("BOT_TOKEN = \"abc\"\n{}", base), None).ok().unwrap();
+		assert_eq!(config.token, "abc");
+		assert_eq!((config.depth, config.threads, config.ponder, config.max_games, config.abandon_penalty), (4, 1, true, 1, 3600));
+		assert_eq!(config.accepted_speeds, ["blitz"]);
+		assert_eq!(config.time_controls[0].to_string(), "180+2");
+		assert!(config.book_learning && !config.cloud_eval && config.game_chat.is_none());
+		assert_eq!(config.contempt, 20);
+		// the token from the environment or secrets file wins
+		assert_eq!(parse_config(&format!("BOT_TOKEN = \"abc\"\n{}", base), Some("xyz".to_owned())).ok().unwrap().token, "xyz");
+		// in seconds, as ABANDON_TIMEOUT
+		assert_eq!(parse_config(&format!("{}ABANDON_PENALTY = 600\n", base), Some("xyz".to_owned())).ok().unwrap().abandon_penalty, 600);
+
+		let errors = parse_config("SEARCH_DEPTH = \"4\"\nPLAY_RATED = true\nTIME_CONTROLS = [[180, 2], [7, 0]]\n\
+			IDLE_TIMEOUT = -1\nRESIGN_SCORE = 100000\nMAXGAMES = 2\n", None).err().unwrap();
+		let errors: Vec<&str> = errors.lines().collect();
+		assert_eq!(errors[0], "unknown key MAXGAMES");
+		assert!(errors[1].starts_with("no BOT_TOKEN key"));
+		assert!(errors[2].starts_with("SEARCH_DEPTH: "));
+		assert!(errors[3].starts_with("TIME_CONTROLS: 7 is not a valid initial clock time"));
+		for error in ["IDLE_TIMEOUT is negative", "no CHALLENGE_TIMEOUT key"] {
+			assert!(errors.contains(&error));
+		}
+		assert!(errors.last().unwrap().starts_with("RESIGN_SCORE: "));
+		assert!(parse_config("SEARCH_DEPTH = ", Some("xyz".to_owned())).err().unwrap().starts_with("invalid syntax"));
+	}
+}
+
+#[cfg(test)]
+mod test_contempt {
+	use super::game_contempt;
+
+	#[test]
+	fn test_game_contempt() {
+		assert_eq!(game_contempt(Some(1900), Some(1700), 20), 20);
+		assert_eq!(game_contempt(Some(1800), Some(1750), 20), 5);
+		assert_eq!(game_contempt(Some(1500), Some(2500), 20), -20);
+		assert_eq!(game_contempt(Some(1500), None, 20), 0);
+	}
+}
+
+#[cfg(test)]
+mod test_tournament {
+	use super::{should_berserk, Tournament};
+
+	#[test]
+	fn test_tournament() {
+		assert_eq!(Tournament::parse("tournament/Ab12cd34").unwrap(), Tournament { swiss: false, id: "Ab12cd34".to_owned() });
+		assert_eq!(Tournament::parse("https://lichess.org/swiss/xyz789/").unwrap(), Tournament { swiss: true, id: "xyz789".to_owned() });
+		assert!(Tournament::parse("Ab12cd34").is_err());
+		assert!(Tournament::parse("study/Ab12cd34").is_err());
+		assert!(Tournament::parse("tournament/").is_err());
+
+		assert!(should_berserk(Some(1900), Some(1700), Some(200)));
+		assert!(!should_berserk(Some(1899), Some(1700), Some(200)));
+		assert!(!should_berserk(Some(1900), None, Some(0)));
+		assert!(!should_berserk(Some(2500), Some(1000), None));
+	}
+}
+
+#[cfg(test)]
+mod test_account {
+	use super::check_bot_scope;
+
+	#[test]
+	fn test_check_bot_scope() {
+		assert!(check_bot_scope(Some("challenge:read, bot:play,board:play")).is_ok());
+		assert!(check_bot_scope(None).is_ok());
+		assert!(check_bot_scope(Some("board:play")).unwrap_err().contains("bot:play scope"));
+		assert!(check_bot_scope(Some("")).is_err());
+	}
+}
+
+#[cfg(test)]
+mod test_stream {
+	use super::reconnect_delay;
+
+	#[test]
+	fn test_reconnect_delay() {
+		let delays: Vec<u64> = (0..8).map(|attempt| reconnect_delay(attempt).as_secs()).collect();
+		assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30]);
+	}
+}
+
+#[cfg(test)]
+mod test_rated {
+	use super::{casual_reason, Perf};
+
+	#[test]
+	fn test_casual_reason() {
+		let perf = |rd, prov| Perf { rating: 1500, rd, prov };
+		assert_eq!(casual_reason(true, false, 110, Some(&perf(80, false))), None);
+		assert_eq!(casual_reason(true, false, 110, Some(&perf(110, false))), None);
+		assert_eq!(casual_reason(true, false, 110, Some(&perf(150, false))).unwrap(), "rating deviation 150 > 110");
+		assert_eq!(casual_reason(true, false, 110, Some(&perf(80, true))).unwrap(), "provisional rating");
+		assert_eq!(casual_reason(true, false, 110, None).unwrap(), "no rating yet");
+		assert_eq!(casual_reason(true, true, 110, Some(&perf(80, false))).unwrap(), "experimental build");
+		assert_eq!(casual_reason(false, false, 110, Some(&perf(80, false))).unwrap(), "PLAY_RATED is off");
+	}
+}
+
+#[cfg(test)]
+mod test_variants {
+	use chesslib::game::Position;
+	use super::initial_position;
+
+	#[test]
+	fn test_initial_position() {
+		assert!(initial_position(Position::FEN_INITIAL).is_ok());
+		assert_eq!(initial_position("8/8/8/8 w - - 0 1").err().unwrap(), "failed to parse initial FEN 8/8/8/8 w - - 0 1: invalid piece placement");
+		assert_eq!(initial_position("4k3/8/8/8/8/8/8/4K2r b - - 0 1").err().unwrap(),
+			"illegal initial position 4k3/8/8/8/8/8/8/4K2r b - - 0 1: White is in check but not to move");
+	}
+}
+
+#[cfg(test)]
+mod test_chat {
+	use std::time::Duration;
+	use chesslib::{ai::{SearchOutcome, MATE}, state::{Move, PieceType, SpecialMove, Square}};
+	use super::chat_message;
+
+	#[test]
+	fn test_chat_message() {
+		assert_eq!(chat_message("gl hf, I'm running {engine}", "SimpleAI 6", 6, None), "gl hf, I'm running SimpleAI 6");
+		assert_eq!(chat_message("depth {depth}", "", 6, None), "depth 6");
+		let mov = Move { ptype: PieceType::Pawn, special: SpecialMove::None, from: Square::at(4, 1), to: Square::at(4, 3) };
+		let mut search = SearchOutcome { best_move: mov, score: -150, mate: None, depth: 6, pv: vec![mov], nodes: 1, elapsed: Duration::ZERO, hashfull: 0 };
+		assert_eq!(chat_message("gg ({eval})", "", 6, Some(&search)), "gg (-1.50)");
+		search.score = MATE - 5;
+		assert_eq!(chat_message("gg ({eval})", "", 6, Some(&search)), "gg (I have a forced mate in 3)");
+		search.score = -(MATE - 2);
+		assert_eq!(chat_message("gg ({eval})", "", 6, Some(&search)), "gg (I am getting mated in 1)");
+		assert_eq!(chat_message("gg ({eval})", "", 6, None), "gg (unknown)");
+	}
+}
+
+#[cfg(test)]
+mod test_cloud_eval {
+	use chesslib::{game::Position, lichess_api::CloudEval};
+	use super::cloud_eval_report;
+
+	#[test]
+	fn test_cloud_eval_report() {
+		let pos = Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+		let eval: CloudEval = serde_json::from_str(r#"{"fen":"","knodes":119726,"depth":36,"pvs":[{"moves":"c7c5 g1f3 d7d6","cp":26}]}"#).unwrap();
+		// the cloud scores from White's side
+		assert_eq!(cloud_eval_report(&pos, -10, &eval).unwrap(), "cloud eval: depth 36, score -26 (ours -10), pv c5 Nf3 d6");
+		assert_eq!(cloud_eval_report(&pos, 200, &eval).unwrap(), "cloud eval: depth 36, score -26 (ours 200), diverging, pv c5 Nf3 d6");
+		let invalid: CloudEval = serde_json::from_str(r#"{"knodes":1,"depth":20,"pvs":[{"moves":"e2e4","cp":0}]}"#).unwrap();
+		assert!(cloud_eval_report(&pos, 0, &invalid).is_none());
+	}
+}
+
+#[cfg(test)]
+mod test_stats {
+	use super::{stats_report, GameRecord};
+
+	#[test]
+	fn test_stats_report() {
+		let records: Vec<GameRecord> = [
+			r#"{"id":"a","date":"2024.06.01","opponent":"Bob","color":"white","speed":"blitz","rated":true,"result":"win","termination":"mate","rating":1500,"rating_diff":8,"avg_move_ms":900}"#,
+			r#"{"id":"b","date":"2024.06.02","opponent":"alice","color":"black","speed":"blitz","rated":true,"result":"draw","termination":"stalemate","rating":1508,"rating_diff":null,"avg_move_ms":700}"#,
+			r#"{"id":"c","date":"2024.06.02","opponent":"Bob","color":"black","speed":"bullet","rated":false,"result":"loss","termination":"resign","rating":null,"rating_diff":null,"avg_move_ms":300}"#,
+		].iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+		let report = stats_report(&records);
+		assert!(report.starts_with("3 games: 1 won, 1 lost, 1 drawn\n"));
+		let lines: Vec<&str> = report.lines().collect();
+		assert!(lines[3].starts_with("alice ") && lines[4].starts_with("Bob "));
+		assert!(lines[4].ends_with("2    1    1    0      +8"));
+		assert!(report.contains("2024.06.01 blitz       1508 (+8)"));
+		assert!(report.contains("2024.06.02 blitz       1508 (?)"));
+		assert_eq!(report.lines().filter(|line| line.starts_with("2024")).count(), 2);
+	}
+}
+
+#[cfg(test)]
+mod test_matchmaking {
+	use std::collections::VecDeque;
+
+	use super::{pick_opponents, Blacklist, Matchmaking};
+
+	#[test]
+	fn test_pick_opponents() {
+		let candidates: Vec<(String, i32)> = [("a", 1480), ("b", 1650), ("c", 1900), ("d", 1620)]
+			.iter().map(|(name, rating)| (name.to_string(), *rating)).collect();
+		let mut recent = VecDeque::new();
+		let mut mm = Matchmaking { rating_band: 100, prefer_stronger: false, perf: None, allowlist: vec![] };
+		assert_eq!(pick_opponents(&candidates, 1500, &recent, &mm), Some((vec!["a"], 100)));
+		assert_eq!(pick_opponents(&candidates, 1600, &recent, &mm), Some((vec!["b", "d"], 100)));
+		// the band widens when no one is close enough
+		assert_eq!(pick_opponents(&candidates, 1250, &recent, &mm), Some((vec!["a"], 300)));
+		assert_eq!(pick_opponents(&candidates, 2500, &recent, &mm), None);
+		// recent opponents are avoided, unless there is no one else
+		recent.push_front("a".to_owned());
+		assert_eq!(pick_opponents(&candidates, 1500, &recent, &mm), Some((vec!["b", "d"], 200)));
+		assert_eq!(pick_opponents(&candidates[..1], 1500, &recent, &mm), Some((vec!["a"], 100)));
+		recent.clear();
+		// configured band width and preference for stronger opponents
+		mm.rating_band = 200;
+		assert_eq!(pick_opponents(&candidates, 1500, &recent, &mm), Some((vec!["a", "b", "d"], 200)));
+		mm.prefer_stronger = true;
+		assert_eq!(pick_opponents(&candidates, 1500, &recent, &mm), Some((vec!["b", "d"], 200)));
+		assert_eq!(pick_opponents(&candidates, 1800, &recent, &mm), Some((vec!["c"], 200)));
+		// allowlisted bots are picked whatever their rating
+		mm.allowlist = vec!["C".to_owned()];
+		assert_eq!(pick_opponents(&candidates, 1000, &recent, &mm).unwrap().0, ["c"]);
+		mm.allowlist = vec!["e".to_owned()];
+		assert_eq!(pick_opponents(&candidates, 1500, &recent, &mm), None);
+	}
+
+	#[test]
+	fn test_blacklist_parse() {
+		let entries = Blacklist::parse("alice 1000\nbob\n\ncarol 2000 extra\n", 5000);
+		assert_eq!(entries.len(), 3);
+		assert_eq!(entries["alice"], 1000);
+		assert_eq!(entries["bob"], 5000);
+		assert_eq!(entries["carol"], 2000);
+	}
+}
@@ -0,0 +1,98 @@
+use std::{io::Write, path::PathBuf};
+
+use chesslib::{arena::{play_pairs, score, Opening, Setting}, state::Color, wdl::WdlModel};
+use rand::{rngs::StdRng, SeedableRng};
+
+const USAGE: &str = "usage: calibrate [--games N] [--random-plies N] [--random-elo ELO] [--seed N] [-o OUTPUT] [SETTINGS...]
+Estimates the strength of engine settings by playing a ladder: the first setting plays RandomAI,
+and each following setting plays the previous one. Settings are dN (fixed depth N) or tN (N ms
+per move, up to depth 32), with search parameters as for match, and default to d1 d2 d3 d4.
+Ratings are relative to RandomAI, which is rated --random-elo (default 0). The openings, random
+moves and ties are drawn from --seed, random by default and printed so that a run can be
+replayed. With -o, the results are also written as CSV.";
+
+// plays pairs of games with swapped colors on the same openings, returning the
+// score of `setting` against `reference`
+fn play_match(setting: Setting, reference: Setting, games: usize, random_plies: usize, seed: u64) -> f64 {
+	let mut rng = StdRng::seed_from_u64(seed);
+	let openings: Vec<Opening> = (0..games.div_ceil(2)).map(|_| Opening::random(random_plies, &mut rng)).collect();
+	let pgns = play_pairs(&openings, setting, reference, games, seed, |_| {
+		print!(".");
+		std::io::stdout().flush().ok();
+		true
+	});
+	let total: f64 = pgns.iter().enumerate()
+		.map(|(game, pgn)| score(pgn.result(), if game % 2 == 0 { Color::White } else { Color::Black })).sum();
+	total / games as f64
+}
+
+fn main() {
+	if let Err(err) = || -> Result<(), String> {
+		let mut games = 20;
+		let mut random_plies = 4;
+		let mut random_elo = 0;
+		let mut seed = None;
+		let mut output = None;
+		let mut settings = vec![];
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			match arg.as_str() {
+				"--games" => games = value("--games")?.parse().ok().filter(|n| *n > 0)
+					.ok_or_else(|| format!("--games is not a positive integer"))?,
+				"--random-plies" => random_plies = value("--random-plies")?.parse()
+					.map_err(|_| format!("--random-plies is not a non-negative integer"))?,
+				"--random-elo" => random_elo = value("--random-elo")?.parse()
+					.map_err(|_| format!("--random-elo is not an integer"))?,
+				"--seed" => seed = Some(value("--seed")?.parse::<u64>()
+					.map_err(|_| format!("--seed is not a non-negative integer"))?),
+				"-o" => output = Some(PathBuf::from(value("-o")?)),
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ => settings.push(Setting::parse(&arg)?),
+			}
+		}
+		if settings.is_empty() {
+			settings = (1..=4).map(|depth| Setting::Depth(depth, Default::default())).collect();
+		}
+
+		let seed = seed.unwrap_or_else(rand::random);
+		println!("seed {}", seed);
+
+		// a perfect score is counted as half a game short of it, to keep the rating finite
+		let model = WdlModel::new(400.0);
+		let margin = 0.5 / games as f64;
+		let mut results = vec![];
+		let (mut reference, mut reference_elo) = (Setting::Random, random_elo);
+		for (i, setting) in settings.into_iter().enumerate() {
+			print!("{} vs {} ", setting, reference);
+			std::io::stdout().flush().ok();
+			let score = play_match(setting, reference, games, random_plies, seed.wrapping_add(i as u64));
+			let elo = reference_elo + model.score_from_expected(score.clamp(margin, 1.0 - margin));
+			println!(" {:.1}% -> {} Elo", score * 100.0, elo);
+			results.push((setting, elo, score));
+			(reference, reference_elo) = (setting, elo);
+		}
+
+		println!("\nsetting  Elo");
+		for (setting, elo, _) in &results {
+			println!("{:<8} {}", setting.to_string(), elo);
+		}
+		if let Some(output) = output {
+			let mut file = std::fs::File::create(&output)
+				.map_err(|err| format!("could not create {}: {}", output.display(), err))?;
+			writeln!(file, "setting,elo,score,games")
+				.and_then(|_| results.iter().try_for_each(|(setting, elo, score)|
+					writeln!(file, "{},{},{:.3},{}", setting, elo, score, games)))
+				.map_err(|err| format!("could not write {}: {}", output.display(), err))?;
+			println!("wrote {}", output.display());
+		}
+		Ok(())
+	}() {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	}
+}
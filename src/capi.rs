@@ -0,0 +1,246 @@
+#![allow(clippy::missing_safety_doc)] // the contract of every function is in its header comment
+
+use std::{
+	ffi::{c_char, CStr},
+	ptr,
+	time::Duration,
+};
+
+use crate::{
+	ai::{SearchLimits, SimpleAi},
+	game::{GameResult, Position},
+	state::{Color, Move, MoveList},
+};
+
+// C bindings, built with the `capi` feature, whose header is generated from
+// this file into include/chesslib.h by the build script. Objects are opaque
+// pointers created and freed by the library, moves are NUL-terminated UCI
+// strings, and functions that can fail return false without side effects.
+
+/// The largest number of legal moves in any position, as a buffer size.
+pub const CHESS_MAX_MOVES: usize = 256;
+const _: () = assert!(CHESS_MAX_MOVES == crate::state::MAX_MOVES);
+
+/// A position with its legal moves.
+pub struct ChessPosition {
+	pos: Position,
+	legal_moves: MoveList,
+}
+
+impl ChessPosition {
+	fn set(&mut self, pos: Position) {
+		self.legal_moves = pos.gen_legal();
+		self.pos = pos;
+	}
+}
+
+/// A SimpleAI instance, whose transposition table persists between searches.
+pub struct ChessEngine {
+	ai: SimpleAi,
+}
+
+/// A move in UCI notation ("e2e4", "e7e8q"), NUL-terminated.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ChessMove {
+	pub uci: [c_char; 6],
+}
+
+impl ChessMove {
+	fn new(mov: &Move) -> ChessMove {
+		let mut uci = [0; 6];
+		for (c, b) in uci.iter_mut().zip(mov.uci_notation().bytes()) {
+			*c = b as c_char;
+		}
+		ChessMove { uci }
+	}
+}
+
+#[repr(C)]
+pub enum ChessResult {
+	Ongoing,
+	WhiteWins,
+	BlackWins,
+	Draw,
+}
+
+#[repr(C)]
+pub struct ChessSearchResult {
+	pub best_move: ChessMove,
+	/// In centipawns, from the perspective of the side to move.
+	pub score: i16,
+	/// Moves to a forced mate, negative if the side to move gets mated, 0 if none was found.
+	pub mate: i16,
+	/// The last completed iteration.
+	pub depth: u32,
+	pub nodes: u64,
+}
+
+unsafe fn parse_fen(fen: *const c_char) -> Option<Position> {
+	let pos = Position::from_fen(CStr::from_ptr(fen).to_str().ok()?).ok()?;
+	pos.validate().ok()?;
+	Some(pos)
+}
+
+/// Returns a new position, the initial one, to free with chess_position_free.
+#[no_mangle]
+pub extern "C" fn chess_position_new() -> *mut ChessPosition {
+	let pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+	Box::into_raw(Box::new(ChessPosition { legal_moves: pos.gen_legal(), pos }))
+}
+
+/// Frees a position; does nothing if it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_free(pos: *mut ChessPosition) {
+	if !pos.is_null() {
+		drop(Box::from_raw(pos));
+	}
+}
+
+/// Replaces the position by that of a FEN string; fails if the FEN is invalid
+/// or describes an impossible position.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_set_fen(pos: *mut ChessPosition, fen: *const c_char) -> bool {
+	match parse_fen(fen) {
+		Some(new_pos) => {
+			(*pos).set(new_pos);
+			true
+		}
+		None => false,
+	}
+}
+
+/// Writes the FEN of the position to `buf`, truncated to `len` bytes with the NUL
+/// terminator, and returns its full length without the terminator, as snprintf.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_get_fen(pos: *const ChessPosition, buf: *mut c_char, len: usize) -> usize {
+	let fen = (*pos).pos.to_fen();
+	if len > 0 {
+		let n = fen.len().min(len - 1);
+		ptr::copy_nonoverlapping(fen.as_ptr() as *const c_char, buf, n);
+		*buf.add(n) = 0;
+	}
+	fen.len()
+}
+
+/// Writes up to `len` legal moves to `moves`, and returns the number of legal
+/// moves, which never exceeds CHESS_MAX_MOVES.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_legal_moves(pos: *const ChessPosition, moves: *mut ChessMove, len: usize) -> usize {
+	let legal_moves = &(*pos).legal_moves;
+	for (i, mov) in legal_moves.iter().take(len).enumerate() {
+		*moves.add(i) = ChessMove::new(mov);
+	}
+	legal_moves.len()
+}
+
+/// Plays a move given in UCI notation; fails if it is invalid or illegal.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_apply_uci(pos: *mut ChessPosition, uci: *const c_char) -> bool {
+	let pos = &mut *pos;
+	let Some(mov) = CStr::from_ptr(uci).to_str().ok().and_then(|uci| Move::parse_uci(uci, &pos.legal_moves).ok()).copied() else {
+		return false;
+	};
+	let mut new_pos = pos.pos.clone();
+	new_pos.apply_move(&mov);
+	pos.set(new_pos);
+	true
+}
+
+/// The result of the game if it is over, claimable draws aside.
+#[no_mangle]
+pub unsafe extern "C" fn chess_position_result(pos: *const ChessPosition) -> ChessResult {
+	match (*pos).pos.game_result() {
+		None => ChessResult::Ongoing,
+		Some(GameResult::Checkmate(Color::White)) => ChessResult::WhiteWins,
+		Some(GameResult::Checkmate(Color::Black)) => ChessResult::BlackWins,
+		Some(GameResult::Draw) => ChessResult::Draw,
+	}
+}
+
+/// Returns a new engine searching up to `max_depth`, to free with chess_engine_free.
+#[no_mangle]
+pub extern "C" fn chess_engine_new(max_depth: u32) -> *mut ChessEngine {
+	Box::into_raw(Box::new(ChessEngine { ai: SimpleAi::new(max_depth) }))
+}
+
+/// Frees an engine; does nothing if it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn chess_engine_free(engine: *mut ChessEngine) {
+	if !engine.is_null() {
+		drop(Box::from_raw(engine));
+	}
+}
+
+/// Searches the position up to the maximum depth of the engine, or for about
+/// `time_ms` milliseconds if it is not 0, on the calling thread; fails if the
+/// game is over.
+#[no_mangle]
+pub unsafe extern "C" fn chess_engine_search(engine: *const ChessEngine, pos: *const ChessPosition, time_ms: u32,
+		result: *mut ChessSearchResult) -> bool {
+	let pos = &*pos;
+	if pos.pos.game_result().is_some() {
+		return false;
+	}
+	let limits = SearchLimits { time: (time_ms > 0).then(|| Duration::from_millis(time_ms as u64)), ..Default::default() };
+	let res = (*engine).ai.search_reporting(&pos.pos, &pos.legal_moves, limits, |_| {});
+	*result = ChessSearchResult {
+		best_move: ChessMove::new(&res.best_move),
+		score: res.score,
+		mate: res.mate.unwrap_or(0),
+		depth: res.depth,
+		nodes: res.nodes,
+	};
+	true
+}
+
+#[cfg(test)]
+mod test_capi {
+	use std::ffi::{CStr, CString};
+
+	use super::*;
+
+	fn uci(mov: &ChessMove) -> String {
+		unsafe { CStr::from_ptr(mov.uci.as_ptr()) }.to_str().unwrap().to_string()
+	}
+
+	#[test]
+	fn test_capi() {
+		unsafe {
+			let pos = chess_position_new();
+			let mut moves = [ChessMove { uci: [0; 6] }; CHESS_MAX_MOVES];
+			assert_eq!(chess_position_legal_moves(pos, moves.as_mut_ptr(), moves.len()), 20);
+			assert!(moves[..20].iter().any(|mov| uci(mov) == "e2e4"));
+			assert_eq!(chess_position_legal_moves(pos, moves.as_mut_ptr(), 0), 20);
+
+			assert!(chess_position_apply_uci(pos, c"e2e4".as_ptr()));
+			assert!(!chess_position_apply_uci(pos, c"e2e4".as_ptr()));
+			let mut buf = [0 as c_char; 100];
+			let len = chess_position_get_fen(pos, buf.as_mut_ptr(), buf.len());
+			let fen = CStr::from_ptr(buf.as_ptr()).to_str().unwrap();
+			assert_eq!(fen, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+			assert_eq!(len, fen.len());
+			assert_eq!(chess_position_get_fen(pos, buf.as_mut_ptr(), 5), len);
+			assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str().unwrap(), "rnbq");
+
+			assert!(!chess_position_set_fen(pos, c"8/8/8 w - - 0 1".as_ptr()));
+			assert!(!chess_position_set_fen(pos, c"k7/8/8/8/8/8/8/8 w - - 0 1".as_ptr()));
+			let fen = CString::new("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+			assert!(chess_position_set_fen(pos, fen.as_ptr()));
+			assert!(matches!(chess_position_result(pos), ChessResult::Ongoing));
+
+			let engine = chess_engine_new(3);
+			let mut result = std::mem::zeroed::<ChessSearchResult>();
+			assert!(chess_engine_search(engine, pos, 0, &mut result));
+			assert_eq!(uci(&result.best_move), "a1a8");
+			assert_eq!(result.mate, 1);
+			assert!(chess_position_apply_uci(pos, result.best_move.uci.as_ptr()));
+			assert!(matches!(chess_position_result(pos), ChessResult::WhiteWins));
+			assert!(!chess_engine_search(engine, pos, 0, &mut result));
+
+			chess_engine_free(engine);
+			chess_position_free(pos);
+			chess_position_free(ptr::null_mut());
+		}
+	}
+}
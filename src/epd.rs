@@ -0,0 +1,130 @@
+use std::{path::PathBuf, time::Duration};
+
+use chesslib::{ai::{SearchLimits, SimpleAi}, testsuite::EpdRecord};
+
+const USAGE: &str = "usage: epd [--time MS] [--depth N] [-v] FILES...
+Runs SimpleAI on the positions of EPD test suites (such as WAC or STS), for --time ms (default:
+1000) up to --depth (default: 32) each. A position is solved if the move found is one of its best
+moves (bm) and none of the moves to avoid (am); STS records score the points of their c0 comment
+instead. The time to solution is when the search settled on a solving move for good. With -v,
+every position is printed.";
+
+const MAX_DEPTH: u32 = 32;
+
+#[derive(Default)]
+struct Stats {
+	positions: usize,
+	solved: usize,
+	points: u32,
+	max_points: u32,
+	solution_time: Duration, // of the solved positions
+}
+
+// searches a position, returning the move found with its score, the depth
+// reached, and when the search last changed its mind to a solving move
+fn run(record: &EpdRecord, limits: SearchLimits) -> Result<(String, u32, u32, Option<Duration>), String> {
+	let ai = SimpleAi::new(MAX_DEPTH);
+	let legal_moves = record.position.gen_legal();
+	if legal_moves.is_empty() {
+		return Err("no legal moves".to_string());
+	}
+	let mut solved_at = None;
+	let mut error = None;
+	let res = ai.search_reporting(&record.position, &legal_moves, limits, |outcome| {
+		match record.score(&outcome.best_move) {
+			Ok(score) if score > 0 && score == record.max_score().unwrap_or(1) => {
+				solved_at.get_or_insert(outcome.elapsed);
+			},
+			Ok(_) => solved_at = None,
+			Err(err) => error = Some(err),
+		}
+	});
+	if let Some(err) = error {
+		return Err(err);
+	}
+	let score = record.score(&res.best_move)?;
+	Ok((record.position.algebraic_notation(&res.best_move), score, res.depth, solved_at.filter(|_| score > 0)))
+}
+
+fn main() {
+	match (|| -> Result<(), String> {
+		let mut time = Duration::from_millis(1000);
+		let mut depth = MAX_DEPTH;
+		let mut verbose = false;
+		let mut paths = vec![];
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			match arg.as_str() {
+				"--time" => time = Duration::from_millis(value("--time")?.parse()
+					.map_err(|_| format!("--time is not a number of milliseconds"))?),
+				"--depth" => depth = value("--depth")?.parse().ok().filter(|depth| *depth > 0)
+					.ok_or_else(|| format!("--depth is not a positive integer"))?,
+				"-v" | "--verbose" => verbose = true,
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ => paths.push(PathBuf::from(arg)),
+			}
+		}
+		if paths.is_empty() {
+			return Err(format!("no test suite given\n{}", USAGE));
+		}
+
+		let limits = SearchLimits { depth: Some(depth), time: Some(time), ..Default::default() };
+		let mut total = Stats::default();
+		for path in &paths {
+			let records = EpdRecord::load(path)?;
+			let mut stats = Stats::default();
+			for (i, record) in records.iter().enumerate() {
+				let id = record.id().map_or_else(|| format!("#{}", i + 1), |id| id.to_string());
+				let (san, score, depth, solved_at) = match run(record, limits) {
+					Ok(res) => res,
+					Err(err) => {
+						println!("{}: skipped ({})", id, err);
+						continue;
+					},
+				};
+				let max_score = record.max_score()?;
+				stats.positions += 1;
+				stats.points += score;
+				stats.max_points += max_score;
+				if let Some(time) = solved_at.filter(|_| score == max_score) {
+					stats.solved += 1;
+					stats.solution_time += time;
+				}
+				if verbose {
+					let expected = record.get("bm").map_or_else(
+						|| format!("not {}", record.get("am").unwrap_or_default().join(" ")), |best| best.join(" "));
+					println!("{:<24} {:<7} {:>2}/{:<2} depth {:>2}{}  (expected {})", id, san, score, max_score, depth,
+						solved_at.map_or(String::new(), |time| format!(", solved in {} ms", time.as_millis())), expected);
+				}
+			}
+			let file_name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+			print_stats(&file_name, &stats);
+			total.positions += stats.positions;
+			total.solved += stats.solved;
+			total.points += stats.points;
+			total.max_points += stats.max_points;
+			total.solution_time += stats.solution_time;
+		}
+		if paths.len() > 1 {
+			print_stats("total", &total);
+		}
+		Ok(())
+	})() {
+		Ok(()) => {},
+		Err(err) => {
+			eprintln!("error: {}", err);
+			std::process::exit(2);
+		},
+	}
+}
+
+fn print_stats(name: &str, stats: &Stats) {
+	let average = stats.solution_time.checked_div(stats.solved as u32).unwrap_or_default();
+	println!("{:<24} {:>9} solved, {:>11} points, {:>5} ms to solution on average", name,
+		format!("{}/{}", stats.solved, stats.positions), format!("{}/{}", stats.points, stats.max_points), average.as_millis());
+}
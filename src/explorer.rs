@@ -0,0 +1,291 @@
+use std::{
+	collections::HashMap,
+	fmt,
+	path::{Path, PathBuf},
+	sync::mpsc,
+	time::{Duration, Instant, SystemTime},
+};
+
+use log::warn;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{game::Position, lichess_api::retry_after};
+
+// Client for the lichess opening explorer, which gives the moves played from a
+// position in the games of masters or of lichess players. Queries are made one
+// at a time by a background thread, as the API asks, and answers are cached on
+// disk so that revisited positions cost no request.
+
+const EXPLORER_URL: &str = "https://explorer.lichess.ovh/";
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+const ERROR_RETRY: Duration = Duration::from_secs(10); // before querying a failed position again
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ExplorerDb {
+	Masters,
+	Lichess,
+}
+impl ExplorerDb {
+	fn path(self) -> &'static str {
+		match self {
+			ExplorerDb::Masters => "masters",
+			ExplorerDb::Lichess => "lichess",
+		}
+	}
+	// the masters database only grows by a few games a month
+	fn max_age(self) -> Duration {
+		match self {
+			ExplorerDb::Masters => Duration::from_secs(30 * 24 * 3600),
+			ExplorerDb::Lichess => Duration::from_secs(24 * 3600),
+		}
+	}
+}
+impl fmt::Display for ExplorerDb {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", match self {
+			ExplorerDb::Masters => "Masters",
+			ExplorerDb::Lichess => "Lichess",
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplorerMove {
+	pub uci: String,
+	pub san: String,
+	pub white: u64, // games won by White
+	pub draws: u64,
+	pub black: u64,
+	#[serde(default)]
+	pub average_rating: Option<u32>,
+}
+impl ExplorerMove {
+	pub fn games(&self) -> u64 {
+		self.white + self.draws + self.black
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExplorerOpening {
+	pub eco: String,
+	pub name: String,
+}
+
+// the games of a position, with its moves from the most played
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExplorerResult {
+	pub white: u64,
+	pub draws: u64,
+	pub black: u64,
+	pub moves: Vec<ExplorerMove>,
+	#[serde(default)]
+	pub opening: Option<ExplorerOpening>,
+}
+impl ExplorerResult {
+	pub fn games(&self) -> u64 {
+		self.white + self.draws + self.black
+	}
+}
+
+// one JSON file per database and position, dropped when too old
+pub struct ExplorerCache {
+	dir: PathBuf,
+}
+impl ExplorerCache {
+	pub fn new(dir: impl AsRef<Path>) -> Self {
+		ExplorerCache { dir: dir.as_ref().to_owned() }
+	}
+	// named after the fields of the FEN that the explorer looks at, without the clocks
+	fn path(&self, db: ExplorerDb, pos: &Position) -> PathBuf {
+		let fen = pos.to_fen();
+		let fields: Vec<&str> = fen.split(' ').take(4).collect();
+		self.dir.join(db.path()).join(format!("{}.json", fields.join("_").replace('/', "-")))
+	}
+	// a fresh enough answer, if any
+	pub fn get(&self, db: ExplorerDb, pos: &Position) -> Option<ExplorerResult> {
+		let path = self.path(db, pos);
+		let modified = std::fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+		if SystemTime::now().duration_since(modified).unwrap_or_default() > db.max_age() {
+			return None;
+		}
+		serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()
+	}
+	pub fn put(&self, db: ExplorerDb, pos: &Position, res: &ExplorerResult) -> std::io::Result<()> {
+		let path = self.path(db, pos);
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		std::fs::write(path, serde_json::to_string(res).unwrap())
+	}
+}
+
+type Answer = (ExplorerDb, String, Result<ExplorerResult, String>);
+
+// the explorer seen from a GUI: positions are queried in the background, and
+// their answers polled on every frame
+pub struct Explorer {
+	queries: mpsc::Sender<(ExplorerDb, Position)>,
+	answers: mpsc::Receiver<Answer>,
+	results: HashMap<(ExplorerDb, String), (Instant, Result<ExplorerResult, String>)>,
+	pending: Option<(ExplorerDb, String)>,
+}
+impl Explorer {
+	// the token of any lichess account, which the explorer may require
+	pub fn new(cache_dir: impl AsRef<Path>, token: Option<String>) -> Self {
+		Self::with_base_url(EXPLORER_URL, cache_dir, token).expect("invalid base URL")
+	}
+	// for tests against a mock server
+	pub fn with_base_url(base: &str, cache_dir: impl AsRef<Path>, token: Option<String>) -> Result<Self, String> {
+		let base = Url::parse(base).map_err(|err| format!("invalid URL {}: {}", base, err))?;
+		let cache = ExplorerCache::new(cache_dir);
+		let (queries, worker_queries) = mpsc::channel();
+		let (worker_answers, answers) = mpsc::channel();
+		std::thread::spawn(move || run_worker(base, token, cache, worker_queries, worker_answers));
+		Ok(Explorer { queries, answers, results: HashMap::new(), pending: None })
+	}
+
+	// the answer for a position, which is queried on the first call; None while it
+	// is pending, and errors (such as rate limits) until a retry succeeds
+	pub fn get(&mut self, db: ExplorerDb, pos: &Position) -> Option<&Result<ExplorerResult, String>> {
+		while let Ok((db, fen, res)) = self.answers.try_recv() {
+			if self.pending.as_ref().is_some_and(|(p_db, p_fen)| *p_db == db && *p_fen == fen) {
+				self.pending = None;
+			}
+			self.results.insert((db, fen), (Instant::now(), res));
+		}
+		let key = (db, pos.to_fen());
+		let stale = match self.results.get(&key) {
+			Some((_, Ok(_))) => false,
+			Some((t, Err(_))) => t.elapsed() >= ERROR_RETRY,
+			None => true,
+		};
+		if stale && self.pending.as_ref() != Some(&key) {
+			self.pending = Some(key.clone());
+			let _ = self.queries.send((db, pos.clone()));
+		}
+		self.results.get(&key).map(|(_, res)| res)
+	}
+}
+
+enum FetchError {
+	RateLimited(Duration),
+	Other(String),
+}
+
+async fn fetch(client: &Client, base: &Url, token: Option<&str>, db: ExplorerDb, pos: &Position) -> Result<ExplorerResult, FetchError> {
+	let mut url = base.join(db.path()).unwrap();
+	url.query_pairs_mut()
+		.append_pair("variant", "standard")
+		.append_pair("fen", &pos.to_fen())
+		.append_pair("topGames", "0")
+		.append_pair("recentGames", "0");
+	let mut req = client.get(url);
+	if let Some(token) = token {
+		req = req.bearer_auth(token);
+	}
+	let res = req.send().await.map_err(|err| FetchError::Other(format!("failed to send request: {}", err)))?;
+	let status = res.status();
+	if status.as_u16() == 429 {
+		return Err(FetchError::RateLimited(retry_after(res.headers().get(reqwest::header::RETRY_AFTER).and_then(|value| value.to_str().ok()))));
+	} else if !status.is_success() {
+		return Err(FetchError::Other(format!("HTTP {}", status.as_u16())));
+	}
+	res.json().await.map_err(|err| FetchError::Other(format!("unexpected response: {}", err)))
+}
+
+// answers the queries in order, skipping those overtaken by a newer one
+fn run_worker(base: Url, token: Option<String>, cache: ExplorerCache,
+		queries: mpsc::Receiver<(ExplorerDb, Position)>, answers: mpsc::Sender<Answer>) {
+	let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+	let client = Client::new();
+	let mut next_request = Instant::now();
+	let mut retry = None;
+	loop {
+		let mut query = match retry.take() {
+			Some(query) => query,
+			None => match queries.recv() {
+				Ok(query) => query,
+				Err(_) => return, // the Explorer was dropped
+			},
+		};
+		while let Ok(newer) = queries.try_recv() {
+			query = newer;
+		}
+		let (db, pos) = query;
+		let fen = pos.to_fen();
+		if let Some(res) = cache.get(db, &pos) {
+			let _ = answers.send((db, fen, Ok(res)));
+			continue;
+		}
+		std::thread::sleep(next_request.saturating_duration_since(Instant::now()));
+		// the position may have changed during the wait
+		if let Ok(newer) = queries.try_recv() {
+			retry = Some(newer);
+			continue;
+		}
+		let res = runtime.block_on(fetch(&client, &base, token.as_deref(), db, &pos));
+		next_request = Instant::now() + REQUEST_INTERVAL;
+		let res = match res {
+			Ok(res) => {
+				if let Err(err) = cache.put(db, &pos, &res) {
+					warn!("could not cache the explorer answer: {}", err);
+				}
+				Ok(res)
+			},
+			Err(FetchError::RateLimited(delay)) => {
+				next_request = Instant::now() + delay;
+				retry = Some((db, pos));
+				Err(format!("rate limited, retrying in {} s", delay.as_secs()))
+			},
+			Err(FetchError::Other(err)) => Err(err),
+		};
+		if answers.send((db, fen, res)).is_err() {
+			return;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test_explorer {
+	use std::time::{Duration, Instant};
+
+	use super::{Explorer, ExplorerCache, ExplorerDb, ExplorerResult};
+	use crate::{game::Position, test_util::mock_server};
+
+	const RESPONSE: &str = r#"{"white":1212,"draws":1584,"black":856,"moves":[
+		{"uci":"d2d4","san":"d4","averageRating":2414,"white":550,"draws":744,"black":386,"game":null},
+		{"uci":"c2c4","san":"c4","averageRating":2432,"white":140,"draws":215,"black":101,"game":null}],
+		"topGames":[],"opening":{"eco":"A40","name":"Queen's Pawn Game"}}"#;
+
+	#[test]
+	fn test_explorer() {
+		let (base, server) = mock_server(vec![(200, RESPONSE)]);
+
+		let dir = std::env::temp_dir().join(format!("explorer-test-{}", std::process::id()));
+		let pos = Position::from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1").unwrap();
+		let mut explorer = Explorer::with_base_url(&base, &dir, Some("secret".to_string())).unwrap();
+		assert!(explorer.get(ExplorerDb::Masters, &pos).is_none());
+		let t0 = Instant::now();
+		let res: ExplorerResult = loop {
+			if let Some(res) = explorer.get(ExplorerDb::Masters, &pos) {
+				break res.clone().unwrap();
+			}
+			assert!(t0.elapsed() < Duration::from_secs(10));
+			std::thread::sleep(Duration::from_millis(10));
+		};
+		assert_eq!(res.games(), 3652);
+		assert_eq!(res.moves.len(), 2);
+		assert_eq!((res.moves[0].san.as_str(), res.moves[0].games(), res.moves[0].average_rating), ("d4", 1680, Some(2414)));
+		assert_eq!(res.opening.unwrap().eco, "A40");
+		let req = &server.join().unwrap()[0];
+		assert!(req.starts_with("GET /masters?variant=standard&fen=rnbqkbnr%2Fpppppppp%2F8%2F8%2F8%2F5N2%2FPPPPPPPP%2FRNBQKB1R+b+KQkq+-+1+1&topGames=0&recentGames=0 HTTP/1.1\r\n"));
+		assert!(req.to_lowercase().contains("authorization: bearer secret\r\n"));
+
+		// the answer is on disk for the same position, whatever its clocks, and only in its database
+		let cache = ExplorerCache::new(&dir);
+		let later = Position::from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 5 9").unwrap();
+		assert_eq!(cache.get(ExplorerDb::Masters, &later).unwrap().moves[1].uci, "c2c4");
+		assert!(cache.get(ExplorerDb::Lichess, &later).is_none());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,13 +1,150 @@
+use core::fmt;
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+
 use crate::{
 	bitboard::{cast_cardinals, cast_diagonals, Bb, KING_PATTERNS, KNIGHT_PATTERNS},
-	state::{Board, Color, Move, Piece, PieceType, SpecialMove, Square}
+	state::{Board, Color, Move, MoveList, Piece, PieceType, SpecialMove, Square}
 };
+#[cfg(feature = "std")]
+use crate::pgn::Pgn;
+#[cfg(feature = "nnue")]
+use crate::nnue::{self, Accumulator};
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GameResult {
-	Checkmate(Color),
+	Checkmate(Color), // winning color
 	Draw,
 }
 
+// subsets of the pseudolegal moves, see gen_noisy and gen_quiet
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MoveKinds {
+	All,
+	Noisy,
+	Quiet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawReason {
+	Stalemate,
+	FiftyMoves, // claimable
+	SeventyFiveMoves, // automatic
+	InsufficientMaterial,
+	Repetition,
+}
+impl fmt::Display for DrawReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			DrawReason::Stalemate => "stalemate",
+			DrawReason::FiftyMoves => "50-move rule",
+			DrawReason::SeventyFiveMoves => "75-move rule",
+			DrawReason::InsufficientMaterial => "insufficient material",
+			DrawReason::Repetition => "threefold repetition",
+		})
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IllegalMoveReason {
+	NoPiece,
+	NotYourTurn,
+	OwnPieceOnTarget,
+	InvalidPattern,
+	CastlingRightsLost,
+	CastlingBlocked,
+	CastlingThroughCheck,
+	KingInCheck,
+	KingWouldBeInCheck,
+	PiecePinned,
+}
+impl fmt::Display for IllegalMoveReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			IllegalMoveReason::NoPiece => "there is no piece on this square",
+			IllegalMoveReason::NotYourTurn => "it is not this piece's turn to move",
+			IllegalMoveReason::OwnPieceOnTarget => "the target square is occupied by an allied piece",
+			IllegalMoveReason::InvalidPattern => "this piece cannot move there",
+			IllegalMoveReason::CastlingRightsLost => "cannot castle: king or rook has already moved",
+			IllegalMoveReason::CastlingBlocked => "cannot castle: pieces are in the way",
+			IllegalMoveReason::CastlingThroughCheck => "cannot castle out of, through, or into check",
+			IllegalMoveReason::KingInCheck => "the king is in check",
+			IllegalMoveReason::KingWouldBeInCheck => "the king would be in check",
+			IllegalMoveReason::PiecePinned => "this piece is pinned to the king",
+		})
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FenError {
+	MissingField(&'static str),
+	TooManyFields,
+	InvalidBoard,
+	InvalidSideToMove,
+	InvalidCastlingRights,
+	CastlingPiecesMissing(char), // the king or the rook of this castling right is not in place
+	InvalidEnPassant,
+	InvalidHalfMoveClock,
+	InvalidMoveNumber,
+}
+impl fmt::Display for FenError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			FenError::MissingField(field) => write!(f, "missing {} field", field),
+			FenError::TooManyFields => write!(f, "too many fields"),
+			FenError::InvalidBoard => write!(f, "invalid piece placement"),
+			FenError::InvalidSideToMove => write!(f, "side to move must be w or b"),
+			FenError::InvalidCastlingRights => write!(f, "invalid castling rights"),
+			FenError::CastlingPiecesMissing(c) => write!(f, "castling right {} without king and rook in place", c),
+			FenError::InvalidEnPassant => write!(f, "invalid en passant square"),
+			FenError::InvalidHalfMoveClock => write!(f, "invalid halfmove clock"),
+			FenError::InvalidMoveNumber => write!(f, "invalid move number"),
+		}
+	}
+}
+
+// reasons why a well-formed position could not arise in a game, see Position::validate
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PositionError {
+	KingCount(Color, u32),
+	PawnOnBackRank(Square),
+	TooManyPawns(Color, u32),
+	TooManyPieces(Color), // more pieces than the missing pawns could have promoted to
+	OpponentInCheck(Color),
+	InvalidEnPassant(Square),
+}
+impl fmt::Display for PositionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PositionError::KingCount(color, n) => write!(f, "{} has {} kings", color, n),
+			PositionError::PawnOnBackRank(squ) => write!(f, "pawns cannot stand on the first or last rank ({})", squ),
+			PositionError::TooManyPawns(color, n) => write!(f, "{} has {} pawns", color, n),
+			PositionError::TooManyPieces(color) => write!(f, "{} has more pieces than promotions can explain", color),
+			PositionError::OpponentInCheck(color) => write!(f, "{} is in check but not to move", color),
+			PositionError::InvalidEnPassant(squ) => write!(f, "no pawn can be captured en passant on {}", squ),
+		}
+	}
+}
+
+// pseudo-random keys for Zobrist hashing, generated with splitmix64
+const fn zobrist_keys<const N: usize>(seed: u64) -> [u64; N] {
+	let mut keys = [0; N];
+	let mut state = seed;
+	let mut i = 0;
+	while i < N {
+		state = state.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		keys[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	keys
+}
+const ZOBRIST_PIECES: [u64; 12 * 64] = zobrist_keys(1);
+const ZOBRIST_UNMOVED: [u64; 64] = zobrist_keys(2);
+const ZOBRIST_EN_PASSANT: [u64; 8] = zobrist_keys(3);
+const ZOBRIST_BLACK_TO_MOVE: u64 = zobrist_keys::<1>(4)[0];
+
 #[derive(Clone)]
 pub struct Position {
 	board: Board,
@@ -15,6 +152,8 @@ pub struct Position {
 	en_passant_target: Option<Square>,
 	ply_number: u16,
 	half_move_clock: u8,
+	#[cfg(feature = "nnue")]
+	accumulator: Option<Accumulator>, // kept up to date once enabled, see enable_nnue
 }
 impl Position {
 	pub const FEN_INITIAL: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
@@ -29,75 +168,99 @@ impl Position {
 		self.ply_number
 	}
 
-	pub fn from_fen(fen: &str) -> Option<Position> {
+	// parses a FEN; castling rights may also be given X-FEN/Shredder style by the file
+	// of the rook (HAha), as long as the rooks are in the corners. Only the syntax is
+	// checked, see validate for the legality of the position
+	pub fn from_fen(fen: &str) -> Result<Position, FenError> {
 		let mut fields = fen.split(' ');
+		let mut next_field = |name| fields.next().ok_or(FenError::MissingField(name));
 
-		let board = Board::from_fen(fields.next()?)?;
+		let board = Board::from_fen(next_field("piece placement")?).ok_or(FenError::InvalidBoard)?;
 		
 		let mut unmoved = Bb::EMPTY;
 		unmoved |= board.find_piece(Piece::new(Color::White, PieceType::Pawn)) & Bb::rank(1);
 		unmoved |= board.find_piece(Piece::new(Color::Black, PieceType::Pawn)) & Bb::rank(6);
 
-		let side_to_move = match fields.next()? {
+		let side_to_move = match next_field("side to move")? {
 			"w" => Color::White,
 			"b" => Color::Black,
-			_ => return None,
+			_ => return Err(FenError::InvalidSideToMove),
 		};
 
-		let castling_rights = fields.next()?;
+		let castling_rights = next_field("castling rights")?;
+		if castling_rights.is_empty() {
+			return Err(FenError::InvalidCastlingRights);
+		}
 		if castling_rights != "-" {
 			for c in castling_rights.chars() {
 				let (color, rook_pos, king_pos) = match c {
-					'K'|'Q' => (
+					'K'|'Q'|'H'|'A' => (
 						Color::White,
-						Square::at(if c == 'Q' { 0 } else { 7 }, 0),
-						Square::at(4, 0)
+						if c == 'Q' || c == 'A' { Square::A1 } else { Square::H1 },
+						Square::E1
 					),
-					'k'|'q' => (
+					'k'|'q'|'h'|'a' => (
 						Color::Black,
-						Square::at(if c == 'q' { 0 } else { 7 }, 7),
-						Square::at(4, 7)
+						if c == 'q' || c == 'a' { Square::A8 } else { Square::H8 },
+						Square::E8
 					),
-					_ => return None, // invalid syntax for castling rights
+					_ => return Err(FenError::InvalidCastlingRights),
 				};
 				if !board.find_piece(Piece::new(color, PieceType::Rook)).at(rook_pos)
 					|| !board.find_piece(Piece::new(color, PieceType::King)).at(king_pos) {
-					// invalid castling rights: rook and/or king are not in expected position
-					return None
+					return Err(FenError::CastlingPiecesMissing(c));
 				}
 				unmoved |= Bb::one(rook_pos) | Bb::one(king_pos);
 			}
 		}
 
-		let en_passant_target = fields.next()?;
+		let en_passant_target = next_field("en passant")?;
 		let en_passant_target = if en_passant_target == "-" {
 			None
 		} else {
-			Some(Square::parse(en_passant_target)?)
+			Some(Square::parse(en_passant_target).ok_or(FenError::InvalidEnPassant)?)
 		};
 
-		let half_move_clock: u8 = fields.next()?.parse().ok()?;
-		let move_number: u16 = fields.next()?.parse().ok()?;
+		let half_move_clock: u8 = next_field("halfmove clock")?.parse().map_err(|_| FenError::InvalidHalfMoveClock)?;
+		let move_number: u16 = next_field("move number")?.parse().map_err(|_| FenError::InvalidMoveNumber)?;
+		if move_number == 0 || move_number > (u16::MAX - 1) / 2 {
+			return Err(FenError::InvalidMoveNumber);
+		}
 		let ply_number = 2*move_number + side_to_move as u16 - 1;
 		if fields.next().is_some() {
-			return None
+			return Err(FenError::TooManyFields);
 		}
 
-		Some(Position { board, unmoved, en_passant_target, ply_number, half_move_clock })
+		Ok(Position {
+			board, unmoved, en_passant_target, ply_number, half_move_clock,
+			#[cfg(feature = "nnue")]
+			accumulator: None,
+		})
 	}
 
+	// the FEN of the position, such that from_fen(to_fen()) gives back the same position
+	// and to_fen(from_fen(fen)) == fen for any FEN this function could have produced
 	pub fn to_fen(&self) -> String {
-		use std::fmt::Write;
+		self.write_fen(false)
+	}
+	// the X-FEN of the position, which only gives the en passant target if a capture
+	// there is legal; the castling field is the same as the FEN's, since only the
+	// outermost rooks can castle here
+	pub fn to_xfen(&self) -> String {
+		self.write_fen(true)
+	}
+	fn write_fen(&self, xfen: bool) -> String {
+		use core::fmt::Write;
 
 		let mut res = self.board.to_fen();
 		write!(res, " {} ", self.side_to_move().to_fen()).unwrap();
 
-		let kw = self.unmoved.at(Square::at(4,0));
-		let kb = self.unmoved.at(Square::at(4,7));
-		let ckw = kw && self.unmoved.at(Square::at(7,0));
-		let cqw = kw && self.unmoved.at(Square::at(0,0));
-		let ckb = kb && self.unmoved.at(Square::at(7,7));
-		let cqb = kb && self.unmoved.at(Square::at(0,7));
+		let kw = self.unmoved.at(Square::E1);
+		let kb = self.unmoved.at(Square::E8);
+		let ckw = kw && self.unmoved.at(Square::H1);
+		let cqw = kw && self.unmoved.at(Square::A1);
+		let ckb = kb && self.unmoved.at(Square::H8);
+		let cqb = kb && self.unmoved.at(Square::A8);
 		if !ckw && !cqw && !ckb && !cqb {
 			res.push('-');
 		} else {
@@ -108,7 +271,10 @@ impl Position {
 		}
 		res.push(' ');
 
-		if let Some(squ) = self.en_passant_target {
+		let en_passant_target = self.en_passant_target.filter(|&squ| {
+			!xfen || self.gen_legal().iter().any(|mov| mov.special == SpecialMove::EnPassant && mov.to == squ)
+		});
+		if let Some(squ) = en_passant_target {
 			write!(res, "{}", squ).unwrap();
 		} else {
 			res.push('-');
@@ -121,6 +287,8 @@ impl Position {
 	}
 
 	pub fn apply_move(&mut self, mov: &Move) {
+		#[cfg(feature = "nnue")]
+		let before = self.accumulator.is_some().then(|| self.board.clone());
 		let color = self.side_to_move();
 		debug_assert!(self.board.find_piece(Piece::new(color, mov.ptype)).at(mov.from),
 			"invalid move: expected piece not found on source square");
@@ -163,14 +331,10 @@ impl Position {
 				self.board.add(middle_squ, rook_piece);
 			},
 			_ => {
-				for ptype in PieceType::all() {
-					let piece = Piece::new(color.opponent(), ptype);
-					let bb = self.board.find_piece(piece);
-					if bb.at(mov.to) { // capture
-						self.board.remove(mov.to, piece);
-						self.unmoved &= !Bb::one(mov.to);
-						capture = true;
-					}
+				if let Some(piece) = self.board.piece_at(mov.to) { // capture
+					self.board.remove(mov.to, piece);
+					self.unmoved &= !Bb::one(mov.to);
+					capture = true;
 				}
 			},
 		}
@@ -191,14 +355,33 @@ impl Position {
 		self.board.add(mov.to, my_piece);
 
 		self.unmoved &= !(Bb::one(mov.from) | Bb::one(mov.to));
+		#[cfg(feature = "nnue")]
+		if let (Some(acc), Some(before)) = (&mut self.accumulator, before) {
+			acc.update(nnue::network().unwrap(), &before, &self.board);
+		}
 		self.ply_number += 1;
 		if !capture && mov.ptype != PieceType::Pawn {
-			self.half_move_clock += 1;
+			// FENs can give any clock up to 255, and the game is no longer over at 150
+			self.half_move_clock = self.half_move_clock.saturating_add(1);
 		} else {
 			self.half_move_clock = 0;
 		}
 	}
 
+	// makes this position and the ones derived from it with apply_move keep the
+	// hidden layer of the NNUE network up to date; does nothing if no network is loaded
+	#[cfg(feature = "nnue")]
+	pub fn enable_nnue(&mut self) {
+		if self.accumulator.is_none() {
+			self.accumulator = nnue::network().map(|net| Accumulator::new(net, &self.board));
+		}
+	}
+	// evaluation by the NNUE network from the perspective of the side to move, once enabled
+	#[cfg(feature = "nnue")]
+	pub fn nnue_eval(&self) -> Option<i16> {
+		Some(self.accumulator.as_ref()?.evaluate(nnue::network()?, self.side_to_move()))
+	}
+
 	fn find_king(&self, color: Color) -> Option<Square> {
 		let bb = self.board.find_piece(Piece::new(color, PieceType::King));
 		assert!(bb.count() <= 1, "more than 1 king of the same color on board");
@@ -227,7 +410,7 @@ impl Position {
 		return attacked;
 	}
 
-	fn gen_pawn_moves(out: &mut Vec<Move>, color: Color, from: Square, to: Square) {
+	fn gen_pawn_moves(out: &mut MoveList, color: Color, from: Square, to: Square) {
 		let specials: &[SpecialMove] = if to.rank() == color.rel_rank(7) {
 			&[SpecialMove::PromoteN, SpecialMove::PromoteB, SpecialMove::PromoteR, SpecialMove::PromoteQ]
 		} else {
@@ -241,22 +424,54 @@ impl Position {
 		}
 	}
 
-	pub fn gen_pseudolegal(&self) -> Vec<Move> {
-		let mut moves = Vec::with_capacity(256);
+	pub fn gen_pseudolegal(&self) -> MoveList {
+		self.gen_pseudolegal_from(!Bb::EMPTY)
+	}
+	// pseudolegal captures and promotions, the moves that change the material balance
+	pub fn gen_noisy(&self) -> MoveList {
+		self.gen_kinds_from(!Bb::EMPTY, MoveKinds::Noisy)
+	}
+	// the other pseudolegal moves, castling included
+	pub fn gen_quiet(&self) -> MoveList {
+		self.gen_kinds_from(!Bb::EMPTY, MoveKinds::Quiet)
+	}
+
+	// whether a move, possibly coming from another position, is pseudolegal here
+	pub fn is_pseudolegal(&self, mov: &Move) -> bool {
+		self.gen_pseudolegal_from(Bb::one(mov.from)).contains(mov)
+	}
+	// whether a pseudolegal move would be generated by gen_quiet
+	pub fn is_quiet(&self, mov: &Move) -> bool {
+		mov.special.get_promotion().is_none() && mov.special != SpecialMove::EnPassant
+			&& !self.board.find_color(self.side_to_move().opponent()).at(mov.to)
+	}
+
+	// pseudolegal moves of the pieces on the squares of `from_mask`
+	fn gen_pseudolegal_from(&self, from_mask: Bb) -> MoveList {
+		self.gen_kinds_from(from_mask, MoveKinds::All)
+	}
+
+	fn gen_kinds_from(&self, from_mask: Bb, kinds: MoveKinds) -> MoveList {
+		let mut moves = MoveList::new();
 
 		let color = self.side_to_move();
 		let allies = self.board.find_color(color);
 		let enemies = self.board.find_color(color.opponent());
 		let pieces = allies | enemies;
+		let (noisy, quiet) = (kinds != MoveKinds::Quiet, kinds != MoveKinds::Noisy);
+		let targets = match kinds {
+			MoveKinds::All => !allies,
+			MoveKinds::Noisy => enemies,
+			MoveKinds::Quiet => !pieces,
+		};
 
 		// pawns
 
-		let pawns = self.board.find_piece(Piece::new(color, PieceType::Pawn));
+		let pawns = self.board.find_piece(Piece::new(color, PieceType::Pawn)) & from_mask;
 		let mut pawn_forward = pawns.shift_ver(color.up());
 		let pawn_cap_left = pawn_forward.shift_left(1);
 		let pawn_cap_right = pawn_forward.shift_right(1);
-		if self.en_passant_target.is_some() {
-			let squ = self.en_passant_target.unwrap();
+		if let Some(squ) = self.en_passant_target.filter(|_| noisy) {
 			if pawn_cap_left.at(squ) {
 				moves.push(Move {
 					ptype: PieceType::Pawn, special: SpecialMove::EnPassant,
@@ -271,25 +486,36 @@ impl Position {
 			}
 		}
 		pawn_forward &= !pieces;
+		// pushes to the last rank promote, so they are noisy
+		let promotion_rank = Bb::rank(color.rel_rank(7));
 		let pawn_push = pawn_forward.shift_ver(color.up()) & !pieces & self.unmoved.shift_ver(2 * color.up());
+		let pawn_forward = pawn_forward & match kinds {
+			MoveKinds::All => Bb::ALL,
+			MoveKinds::Noisy => promotion_rank,
+			MoveKinds::Quiet => !promotion_rank,
+		};
 		for to in pawn_forward.iter() {
 			Position::gen_pawn_moves(&mut moves, color, to.shift(0, color.down()), to);
 		}
-		for to in pawn_push.iter() {
-			Position::gen_pawn_moves(&mut moves, color, to.shift(0, color.down() * 2), to);
-		}
-		for to in (pawn_cap_left & enemies).iter() {
-			Position::gen_pawn_moves(&mut moves, color, to.shift(1, color.down()), to);
+		if quiet {
+			for to in pawn_push.iter() {
+				Position::gen_pawn_moves(&mut moves, color, to.shift(0, color.down() * 2), to);
+			}
 		}
-		for to in (pawn_cap_right & enemies).iter() {
-			Position::gen_pawn_moves(&mut moves, color, to.shift(-1, color.down()), to);
+		if noisy {
+			for to in (pawn_cap_left & enemies).iter() {
+				Position::gen_pawn_moves(&mut moves, color, to.shift(1, color.down()), to);
+			}
+			for to in (pawn_cap_right & enemies).iter() {
+				Position::gen_pawn_moves(&mut moves, color, to.shift(-1, color.down()), to);
+			}
 		}
 
 		// knights
 
-		let knights = self.board.find_piece(Piece::new(color, PieceType::Knight));
+		let knights = self.board.find_piece(Piece::new(color, PieceType::Knight)) & from_mask;
 		for from in knights.iter() {
-			for to in (KNIGHT_PATTERNS[from] & !allies).iter() {
+			for to in (KNIGHT_PATTERNS[from] & targets).iter() {
 				moves.push(Move {
 					ptype: PieceType::Knight, special: SpecialMove::None,
 					from, to,
@@ -299,65 +525,60 @@ impl Position {
 
 		// bishops
 
-		let bishops = self.board.find_piece(Piece::new(color, PieceType::Bishop));
+		let bishops = self.board.find_piece(Piece::new(color, PieceType::Bishop)) & from_mask;
 		for from in bishops.iter() {
-			for to in cast_diagonals(from, pieces).iter() {
-				if !allies.at(to) {
-					moves.push(Move {
-						ptype: PieceType::Bishop, special: SpecialMove::None,
-						from, to,
-					})
-				}
+			for to in (cast_diagonals(from, pieces) & targets).iter() {
+				moves.push(Move {
+					ptype: PieceType::Bishop, special: SpecialMove::None,
+					from, to,
+				})
 			}
 		}
 
 		// rooks
 
-		let rooks = self.board.find_piece(Piece::new(color, PieceType::Rook));
+		let rooks = self.board.find_piece(Piece::new(color, PieceType::Rook)) & from_mask;
 		for from in rooks.iter() {
-			for to in cast_cardinals(from, pieces).iter() {
-				if !allies.at(to) {
-					moves.push(Move {
-						ptype: PieceType::Rook, special: SpecialMove::None,
-						from, to,
-					})
-				}
+			for to in (cast_cardinals(from, pieces) & targets).iter() {
+				moves.push(Move {
+					ptype: PieceType::Rook, special: SpecialMove::None,
+					from, to,
+				})
 			}
 		}
 
 		// queens
 
-		let queens = self.board.find_piece(Piece::new(color, PieceType::Queen));
+		let queens = self.board.find_piece(Piece::new(color, PieceType::Queen)) & from_mask;
 		for from in queens.iter() {
-			for to in (cast_cardinals(from, pieces) | cast_diagonals(from, pieces)).iter() {
-				if !allies.at(to) {
-					moves.push(Move {
-						ptype: PieceType::Queen, special: SpecialMove::None,
-						from, to,
-					})
-				}
+			for to in ((cast_cardinals(from, pieces) | cast_diagonals(from, pieces)) & targets).iter() {
+				moves.push(Move {
+					ptype: PieceType::Queen, special: SpecialMove::None,
+					from, to,
+				})
 			}
 		}
 
 		// kings
 
-		if let Some(king_pos) = self.find_king(color) {
-			let attacked = self.gen_attacked(color.opponent(), pieces);
-			for to in (KING_PATTERNS[king_pos] & !allies).iter() {
+		if let Some(king_pos) = self.find_king(color).filter(|squ| from_mask.at(*squ)) {
+			for to in (KING_PATTERNS[king_pos] & targets).iter() {
 				moves.push(Move {
 					ptype: PieceType::King, special: SpecialMove::None,
 					from: king_pos, to,
 				})
 			}
-			if self.unmoved.at(king_pos) {
+			if quiet && self.unmoved.at(king_pos) {
+				let attacked = self.gen_attacked(color.opponent(), pieces);
 				let rank0 = color.rel_rank(0);
 				debug_assert!(king_pos.rank() == rank0 && king_pos.file() == 4);
 				let queen_corner = Square::at(0, rank0);
 				let king_corner = Square::at(7, rank0);
 				let queen_area = Bb(0x0000000101010000).shift_up(rank0);
 				let king_area = Bb(0x0001010100000000).shift_up(rank0);
-				let except_king = pieces & !Bb::one(king_pos);
-				let queen_side = self.unmoved.at(queen_corner) && (queen_area & except_king).none();
+				let except_king = pieces - Bb::one(king_pos);
+				let queen_path = queen_area | Bb::one(Square::at(1, rank0)); // b-file must be empty too
+				let queen_side = self.unmoved.at(queen_corner) && (queen_path & except_king).none();
 				let king_side = self.unmoved.at(king_corner) && (king_area & except_king).none();
 				if queen_side || king_side {
 					if queen_side && (attacked & queen_area).none() {
@@ -381,93 +602,452 @@ impl Position {
 
 	pub fn is_in_check(&self, color: Color) -> bool {
 		if let Some(king_pos) = self.find_king(color) {
-			self.gen_attacked(color.opponent(), self.board.all_pieces()).at(king_pos)
+			self.is_attacked(king_pos, color.opponent())
 		} else {
 			true // in the hypothetical that the king was captured
 		}
 	}
 
-	pub fn gen_legal(&self) -> Vec<Move> {
-		if self.half_move_clock >= 75 {
-			return vec![]; // draw
+	// pieces of `color` attacking `squ`, whatever is on it, found by looking from
+	// `squ` rather than by generating all the attacks of `color`
+	pub fn attackers_to(&self, squ: Square, color: Color) -> Bb {
+		let find = |ptype| self.board.find_piece(Piece::new(color, ptype));
+		let pieces = self.board.all_pieces();
+		let queens = find(PieceType::Queen);
+		let pawn_origins = Bb::one(squ).shift_ver(color.down());
+		((pawn_origins.shift_left(1) | pawn_origins.shift_right(1)) & find(PieceType::Pawn))
+			| (KNIGHT_PATTERNS[squ] & find(PieceType::Knight))
+			| (KING_PATTERNS[squ] & find(PieceType::King))
+			| (cast_diagonals(squ, pieces) & (find(PieceType::Bishop) | queens))
+			| (cast_cardinals(squ, pieces) & (find(PieceType::Rook) | queens))
+	}
+	pub fn is_attacked(&self, squ: Square, by_color: Color) -> bool {
+		!self.attackers_to(squ, by_color).none()
+	}
+	// pieces of `color` pinned to their king, see Board::pinned_pieces
+	pub fn pinned_pieces(&self, color: Color) -> Bb {
+		self.board.pinned_pieces(color)
+	}
+	// pieces of the side to move that give check by moving off the line between one
+	// of its sliders and the enemy king
+	pub fn discovered_check_candidates(&self) -> Bb {
+		let color = self.side_to_move();
+		match self.find_king(color.opponent()) {
+			Some(king_pos) => self.board.slider_blockers(king_pos, color) & self.board.find_color(color),
+			None => Bb::EMPTY,
+		}
+	}
+	// pieces giving check to the side to move
+	pub fn checkers(&self) -> Bb {
+		let color = self.side_to_move();
+		match self.find_king(color) {
+			Some(king_pos) => self.attackers_to(king_pos, color.opponent()),
+			None => Bb::EMPTY,
+		}
+	}
+
+	// Zobrist hash of the position, ignoring the move counters
+	pub fn hash_key(&self) -> u64 {
+		let mut key = 0;
+		for (squ, piece) in self.board.iter() {
+			key ^= ZOBRIST_PIECES[piece.ordinal() * 64 + squ.index() as usize];
+		}
+		for squ in self.unmoved.iter() {
+			key ^= ZOBRIST_UNMOVED[squ.index() as usize];
+		}
+		if let Some(squ) = self.en_passant_target {
+			key ^= ZOBRIST_EN_PASSANT[squ.file() as usize];
 		}
+		if self.side_to_move() == Color::Black {
+			key ^= ZOBRIST_BLACK_TO_MOVE;
+		}
+		key
+	}
+
+	pub fn gen_legal(&self) -> MoveList {
+		self.gen_legal_from(!Bb::EMPTY)
+	}
+
+	// legal moves of the piece on `squ` only, if it belongs to the side to move
+	pub fn moves_from(&self, squ: Square) -> MoveList {
+		self.gen_legal_from(Bb::one(squ))
+	}
+
+	fn gen_legal_from(&self, from_mask: Bb) -> MoveList {
 		let color = self.side_to_move();
-		let mut moves = self.gen_pseudolegal();
+		let mut moves = self.gen_pseudolegal_from(from_mask);
+		// out of check, only the moves of the king, of pinned pieces and en passant
+		// captures (which remove two pieces from a line) can expose the king; the
+		// others are legal without being played
+		let checked = !self.checkers().none();
+		let pinned = self.pinned_pieces(color);
 		moves.retain(|mov| {
+			if !checked && mov.ptype != PieceType::King && mov.special != SpecialMove::EnPassant && !pinned.at(mov.from) {
+				return true;
+			}
 			let mut pos = self.clone();
 			pos.apply_move(mov);
 			!pos.is_in_check(color)
 		});
 		moves
 	}
-}
 
-#[cfg(test)]
-mod test_movegen {
-	use serde::Deserialize;
+	// explains why moving the piece on `from` to `to` is not a legal move
+	pub fn explain_illegal(&self, from: Square, to: Square) -> Option<IllegalMoveReason> {
+		let color = self.side_to_move();
+		let piece = match self.board.piece_at(from) {
+			Some(piece) => piece,
+			None => return Some(IllegalMoveReason::NoPiece),
+		};
+		if piece.color != color {
+			return Some(IllegalMoveReason::NotYourTurn);
+		}
+		let candidates: Vec<Move> = self.gen_pseudolegal_from(Bb::one(from)).into_iter()
+			.filter(|mov| mov.to == to).collect();
+		if candidates.is_empty() {
+			let rank0 = color.rel_rank(0);
+			if piece.ptype == PieceType::King && from == Square::at(4, rank0)
+				&& to.rank() == rank0 && (to.file() == 2 || to.file() == 6) {
+				let corner = Square::at(if to.file() == 2 { 0 } else { 7 }, rank0);
+				if !self.unmoved.at(from) || !self.unmoved.at(corner) {
+					return Some(IllegalMoveReason::CastlingRightsLost);
+				}
+				let between = if to.file() == 2 { 1..4 } else { 5..7 };
+				let pieces = self.board.all_pieces();
+				if between.into_iter().any(|file| pieces.at(Square::at(file, rank0))) {
+					return Some(IllegalMoveReason::CastlingBlocked);
+				}
+				return Some(IllegalMoveReason::CastlingThroughCheck);
+			}
+			if self.board.find_color(color).at(to) {
+				return Some(IllegalMoveReason::OwnPieceOnTarget);
+			}
+			return Some(IllegalMoveReason::InvalidPattern);
+		}
+		for mov in &candidates {
+			let mut pos = self.clone();
+			pos.apply_move(mov);
+			if !pos.is_in_check(color) {
+				return None;
+			}
+		}
+		Some(if piece.ptype == PieceType::King {
+			IllegalMoveReason::KingWouldBeInCheck
+		} else if self.is_in_check(color) {
+			IllegalMoveReason::KingInCheck
+		} else {
+			IllegalMoveReason::PiecePinned
+		})
+	}
+
+	// checkmate, stalemate, or the 75-move rule, which is the only draw by the
+	// move counter that does not need to be claimed
+	pub fn game_result(&self) -> Option<GameResult> {
+		if !self.gen_legal().is_empty() {
+			return (self.half_move_clock >= 150).then_some(GameResult::Draw);
+		}
+		let color = self.side_to_move();
+		if self.is_in_check(color) {
+			Some(GameResult::Checkmate(color.opponent()))
+		} else {
+			Some(GameResult::Draw)
+		}
+	}
+
+	// plies since the last capture or pawn move
+	pub fn halfmove_clock(&self) -> u8 {
+		self.half_move_clock
+	}
+	// whether the side to move can claim a draw by the 50-move rule; claims by
+	// repetition need the previous positions, see Game::can_claim_draw
+	pub fn is_draw_claimable(&self) -> bool {
+		self.half_move_clock >= 100
+	}
+
+	// neither side can checkmate: bare kings, a single minor piece, or only
+	// bishops all on squares of the same color
+	pub fn has_insufficient_material(&self) -> bool {
+		let board = &self.board;
+		let count = |ptype| board.count_pieces(Color::White, ptype) + board.count_pieces(Color::Black, ptype);
+		if [PieceType::Pawn, PieceType::Rook, PieceType::Queen].into_iter().any(|ptype| count(ptype) > 0) {
+			return false;
+		}
+		let bishops = board.find_piece(Piece::new(Color::White, PieceType::Bishop))
+			| board.find_piece(Piece::new(Color::Black, PieceType::Bishop));
+		count(PieceType::Knight) + count(PieceType::Bishop) <= 1
+			|| (count(PieceType::Knight) == 0 && ((bishops & Bb::DARK_SQUARES).none() || (bishops & Bb::LIGHT_SQUARES).none()))
+	}
+
+	// why the game is drawn in this position, if it is; repetitions need the
+	// previous positions, see Pgn::draw_reason
+	pub fn draw_reason(&self) -> Option<DrawReason> {
+		let result = self.game_result();
+		if matches!(result, Some(GameResult::Checkmate(_))) {
+			None
+		} else if self.has_insufficient_material() {
+			Some(DrawReason::InsufficientMaterial)
+		} else if self.half_move_clock >= 150 {
+			Some(DrawReason::SeventyFiveMoves)
+		} else if self.is_draw_claimable() {
+			Some(DrawReason::FiftyMoves)
+		} else if result.is_some() {
+			Some(DrawReason::Stalemate)
+		} else {
+			None
+		}
+	}
+
+	// checks what from_fen does not, for positions set up by hand or received from
+	// elsewhere: one king per side, no pawns on the back ranks, no more pieces than
+	// promotions can explain, the side not to move not in check, and an en passant
+	// target behind a pawn that just moved two squares
+	pub fn validate(&self) -> Result<(), PositionError> {
+		let board = &self.board;
+		for color in [Color::White, Color::Black] {
+			match board.count_pieces(color, PieceType::King) {
+				1 => {},
+				n => return Err(PositionError::KingCount(color, n)),
+			}
+		}
+		let pawns = board.find_piece(Piece::new(Color::White, PieceType::Pawn))
+			| board.find_piece(Piece::new(Color::Black, PieceType::Pawn));
+		if let Some(squ) = (pawns & (Bb::rank(0) | Bb::rank(7))).iter().next() {
+			return Err(PositionError::PawnOnBackRank(squ));
+		}
+		for color in [Color::White, Color::Black] {
+			let pawns = board.count_pieces(color, PieceType::Pawn);
+			if pawns > 8 {
+				return Err(PositionError::TooManyPawns(color, pawns));
+			}
+			// pieces beyond the initial ones must have been promoted from missing pawns
+			let promoted: u32 = [(PieceType::Knight, 2), (PieceType::Bishop, 2), (PieceType::Rook, 2), (PieceType::Queen, 1)]
+				.into_iter().map(|(ptype, initial)| board.count_pieces(color, ptype).saturating_sub(initial)).sum();
+			if promoted > 8 - pawns {
+				return Err(PositionError::TooManyPieces(color));
+			}
+		}
+		let side = self.side_to_move();
+		if self.is_in_check(side.opponent()) {
+			return Err(PositionError::OpponentInCheck(side.opponent()));
+		}
+		if let Some(target) = self.en_passant_target {
+			let behind = |drank: i8| target.shift(0, drank);
+			let valid = target.rank() == side.rel_rank(5)
+				&& !board.all_pieces().at(target)
+				&& !board.all_pieces().at(behind(side.up()))
+				&& board.find_piece(Piece::new(side.opponent(), PieceType::Pawn)).at(behind(side.down()));
+			if !valid {
+				return Err(PositionError::InvalidEnPassant(target));
+			}
+		}
+		Ok(())
+	}
+
+	// number of leaf nodes of the legal move tree, to check move generation
+	pub fn perft(&self, depth: u32) -> u64 {
+		let moves = self.gen_legal();
+		if depth <= 1 {
+			return if depth == 1 { moves.len() as u64 } else { 1 };
+		}
+		moves.iter().map(|mov| {
+			let mut pos = self.clone();
+			pos.apply_move(mov);
+			pos.perft(depth - 1)
+		}).sum()
+	}
+
+	pub fn algebraic_notation(&self, mov: &Move) -> String {
+		let mut res = match mov.special {
+			SpecialMove::CastleQ => "O-O-O".to_string(),
+			SpecialMove::CastleK => "O-O".to_string(),
+			_ => {
+				let capture = mov.special == SpecialMove::EnPassant || self.board.all_pieces().at(mov.to);
+				let mut res = mov.ptype.algebraic().to_string();
+				if mov.ptype == PieceType::Pawn {
+					if capture {
+						res.push((b'a' + mov.from.file()) as char);
+					}
+				} else {
+					let legal_moves = self.gen_legal();
+					let mut ambiguous = false;
+					let mut same_file = false;
+					let mut same_rank = false;
+					for mov2 in legal_moves.iter() {
+						if mov2.ptype == mov.ptype && mov2.to == mov.to && mov2.from != mov.from {
+							ambiguous = true;
+							same_file |= mov2.from.file() == mov.from.file();
+							same_rank |= mov2.from.rank() == mov.from.rank();
+						}
+					}
+					if ambiguous && (!same_file || same_rank) {
+						res.push((b'a' + mov.from.file()) as char);
+					}
+					if ambiguous && same_file {
+						res.push((b'1' + mov.from.rank()) as char);
+					}
+				}
+				if capture {
+					res.push('x');
+				}
+				res.push_str(&mov.to.to_string());
+				if let Some(promote_to) = mov.special.get_promotion() {
+					res.push('=');
+					res.push_str(promote_to.algebraic());
+				}
+				res
+			},
+		};
+		let mut pos = self.clone();
+		pos.apply_move(mov);
+		if !pos.checkers().none() {
+			res.push(if pos.gen_legal().is_empty() { '#' } else { '+' });
+		}
+		res
+	}
+}
 
-use crate::{game::Position, state::{Move, ParseMoveError}};
+// a game being played: the current position with the earlier ones, to take moves
+// back and detect repetitions, and the moves taken back, to play them again
+#[derive(Clone)]
+pub struct Game {
+	position: Position,
+	previous: Vec<(Position, Move)>, // each earlier position, with the move played there
+	keys: Vec<u64>, // hash keys of the earlier positions, then of the current one
+	undone: Vec<Move>, // most recently undone last
+}
+impl Game {
+	pub fn new(start: &Position) -> Game {
+		Game { position: start.clone(), previous: vec![], keys: vec![start.hash_key()], undone: vec![] }
+	}
+	pub fn from_moves(start: &Position, moves: &[Move]) -> Game {
+		let mut game = Game::new(start);
+		for mov in moves {
+			game.push(*mov);
+		}
+		game
+	}
 
-	#[derive(Deserialize)]
-	#[serde(rename_all = "camelCase")]
-	struct TestFile {
-		description: String,
-		test_cases: Vec<TestCase>,
+	pub fn start_position(&self) -> &Position {
+		self.previous.first().map_or(&self.position, |(pos, _)| pos)
+	}
+	pub fn position(&self) -> &Position {
+		&self.position
+	}
+	pub fn moves(&self) -> Vec<Move> {
+		self.previous.iter().map(|(_, mov)| *mov).collect()
+	}
+	pub fn last_move(&self) -> Option<Move> {
+		self.previous.last().map(|(_, mov)| *mov)
+	}
+	// positions before each move, followed by the current one
+	pub fn positions(&self) -> Vec<Position> {
+		self.previous.iter().map(|(pos, _)| pos.clone()).chain([self.position.clone()]).collect()
+	}
+	// keys of the positions before the current one, see SimpleAi::set_history
+	pub fn previous_keys(&self) -> &[u64] {
+		&self.keys[..self.keys.len() - 1]
 	}
 
-	#[derive(Deserialize)]
-	struct TestCase {
-		start: TestStart,
-		expected: Vec<TestMove>,
+	// plays a move, which must be legal; playing the next move to redo keeps the
+	// ones after it, any other move forgets them
+	pub fn push(&mut self, mov: Move) {
+		if self.undone.last() == Some(&mov) {
+			self.undone.pop();
+		} else {
+			self.undone.clear();
+		}
+		let mut pos = self.position.clone();
+		pos.apply_move(&mov);
+		self.keys.push(pos.hash_key());
+		self.previous.push((core::mem::replace(&mut self.position, pos), mov));
+	}
+	pub fn undo(&mut self) -> Option<Move> {
+		let (pos, mov) = self.previous.pop()?;
+		self.position = pos;
+		self.keys.pop();
+		self.undone.push(mov);
+		Some(mov)
+	}
+	pub fn redo(&mut self) -> Option<Move> {
+		let mov = *self.undone.last()?;
+		self.push(mov);
+		Some(mov)
+	}
+	pub fn can_redo(&self) -> bool {
+		!self.undone.is_empty()
+	}
+	// the moves to redo, the next one last
+	pub fn undone(&self) -> &[Move] {
+		&self.undone
 	}
 
-	#[derive(Deserialize)]
-	struct TestStart {
-		description: Option<String>,
-		fen: String,
+	// times the current position occurred, counting this one; positions before the
+	// last capture or pawn move cannot be the same
+	pub fn repetitions(&self) -> usize {
+		let key = *self.keys.last().unwrap();
+		let window = self.position.halfmove_clock() as usize + 1;
+		self.keys.iter().rev().take(window).filter(|k| **k == key).count()
+	}
+	// the draw the side to move could claim: threefold repetition, or fifty moves
+	// without a capture nor a pawn move
+	pub fn can_claim_draw(&self) -> Option<DrawReason> {
+		if self.repetitions() >= 3 {
+			Some(DrawReason::Repetition)
+		} else if self.position.is_draw_claimable() {
+			Some(DrawReason::FiftyMoves)
+		} else {
+			None
+		}
+	}
+	// why the game is drawn, if it is; like everywhere else, claimable draws are
+	// treated as automatic
+	pub fn draw_reason(&self) -> Option<DrawReason> {
+		self.position.draw_reason().or_else(|| self.can_claim_draw())
+	}
+	pub fn result(&self) -> Option<GameResult> {
+		match self.position.game_result() {
+			Some(GameResult::Checkmate(winner)) => Some(GameResult::Checkmate(winner)),
+			_ => self.draw_reason().map(|_| GameResult::Draw),
+		}
 	}
 
-	#[derive(Deserialize)]
-	struct TestMove {
-		r#move: String,
-		fen: String,
+	// the moves in standard algebraic notation
+	pub fn san_moves(&self) -> Vec<String> {
+		self.previous.iter().map(|(pos, mov)| pos.algebraic_notation(mov)).collect()
+	}
+	// the moves and the result, if the game is over, with the default tags
+	#[cfg(feature = "std")]
+	pub fn to_pgn(&self) -> Pgn {
+		let mut pgn = Pgn::new(self.start_position());
+		for (_, mov) in &self.previous {
+			pgn.push(*mov);
+		}
+		if let Some(result) = self.result() {
+			pgn.set_result(result.into());
+		}
+		pgn
 	}
+}
+
+// the test files are read with movetest, which needs std
+#[cfg(all(test, feature = "std"))]
+mod test_movegen {
+	use crate::movetest::TestFile;
 
 	fn run_test_file(json: &str) {
-		let file: TestFile = serde_json::from_str(json).unwrap();
+		let file = TestFile::parse(json).unwrap();
 		println!("Description: {}", file.description);
 		let mut failures = 0;
-		for (i, case) in file.test_cases.into_iter().enumerate() {
-			if let Some(desc) = case.start.description {
+		for (i, case) in file.test_cases.iter().enumerate() {
+			if let Some(desc) = &case.start.description {
 				println!("Test #{}: {}", i, desc);
 			} else {
 				println!("Test #{}:", i);
 			}
-			let pos = Position::from_fen(&case.start.fen).expect("Invalid FEN");
 			println!("FEN: {}", case.start.fen);
-			println!("{}", pos.board);
-
-			let moves = pos.gen_legal();
-			for mov in moves.iter() {
-				let mut pos2 = pos.clone();
-				pos2.apply_move(&mov);
-				let fen_after = pos2.to_fen();
-				if case.expected.iter().all(|m| m.fen != fen_after) {
-					println!("(!) Our move {} -> FEN {} is unexpected", mov, fen_after);
-					failures += 1;
-				}
-			}
-
-			for mov in case.expected {
-				if let Err(err) = Move::parse_algebraic(&mov.r#move, &moves) {
-					let err_desc = match err {
-						ParseMoveError::AmbiguousMove => "ambiguous",
-						ParseMoveError::IllegalMove => "illegal",
-						ParseMoveError::InvalidSyntax => "invalid syntax",
-					};
-					println!("(!) Their move {} -> FEN {} is deemed {}", mov.r#move, mov.fen, err_desc);
-					failures += 1;
-				}
+			for failure in case.run(usize::MAX).failures {
+				println!("(!) {}", failure);
+				failures += 1;
 			}
 		}
 
@@ -498,4 +1078,375 @@ use crate::{game::Position, state::{Move, ParseMoveError}};
 	fn test_taxing() {
 		run_test_file(include_str!("../tests/taxing.json"));
 	}
+
+	// the attack queries agree with the attacks generated for castling, in every
+	// position of the move generation tests
+	#[test]
+	fn test_attack_queries() {
+		use crate::{game::Position, state::{Color, Square}};
+		let files = [
+			include_str!("../tests/standard.json"), include_str!("../tests/famous.json"), include_str!("../tests/pawns.json"),
+			include_str!("../tests/promotions.json"), include_str!("../tests/castling.json"), include_str!("../tests/taxing.json"),
+		];
+		for json in files {
+			let file = TestFile::parse(json).unwrap();
+			let fens = file.test_cases.iter().flat_map(|case| [&case.start.fen].into_iter().chain(case.expected.iter().map(|mov| &mov.fen)));
+			for fen in fens {
+				let pos = Position::from_fen(fen).unwrap();
+				for color in [Color::White, Color::Black] {
+					let attacked = pos.gen_attacked(color, pos.get_board().all_pieces());
+					for squ in Square::iter_all() {
+						assert_eq!(pos.is_attacked(squ, color), attacked.at(squ), "{} {} {}", fen, color, squ);
+						assert!((pos.attackers_to(squ, color) & !pos.get_board().find_color(color)).none());
+					}
+				}
+				let checkers = pos.checkers();
+				assert_eq!(checkers.none(), !pos.is_in_check(pos.side_to_move()), "{}", fen);
+				assert!(checkers.count() <= 2 && (checkers & !pos.get_board().find_color(pos.side_to_move().opponent())).none());
+			}
+		}
+	}
+
+	#[test]
+	fn test_board_caches() {
+		use crate::{bitboard::Bb, game::Position, state::{Board, Color, Piece, PieceType, Square}};
+		let check = |board: &Board| {
+			let mut all = Bb::EMPTY;
+			for color in Color::all() {
+				let mut own = Bb::EMPTY;
+				for ptype in PieceType::all() {
+					let piece = Piece::new(color, ptype);
+					own |= board.find_piece(piece);
+					assert!(board.find_piece(piece).iter().all(|squ| board.piece_at(squ) == Some(piece)));
+				}
+				assert_eq!(own, board.find_color(color));
+				all |= own;
+			}
+			assert_eq!(all, board.all_pieces());
+			assert!(Square::iter_all().all(|squ| board.piece_at(squ).is_some() == all.at(squ)));
+			assert_eq!(board.iter().map(|(squ, _)| squ).collect::<Bb>(), all);
+			assert!(board.iter().all(|(squ, piece)| board.piece_at(squ) == Some(piece)));
+			assert_eq!(board.iter().collect::<Board>().to_fen(), board.to_fen());
+		};
+		let file = TestFile::parse(include_str!("../tests/famous.json")).unwrap();
+		for case in &file.test_cases {
+			let pos = Position::from_fen(&case.start.fen).unwrap();
+			check(pos.get_board());
+			for mov in pos.gen_legal() {
+				let mut pos = pos.clone();
+				pos.apply_move(&mov);
+				check(pos.get_board());
+			}
+		}
+	}
+
+	#[test]
+	fn test_pins() {
+		use crate::{bitboard::Bb, game::Position, state::Color};
+		let squares = |bb: Bb| bb.iter().map(|squ| squ.to_string()).collect::<Vec<_>>();
+		// pins by the e8 rook, the h4 bishop and the a5 bishop; the c7 pawn is an enemy piece,
+		// so moving it cannot give a discovered check
+		let pos = Position::from_fen("3kr3/2p5/8/B7/7b/6P1/4N3/4K3 w - - 0 1").unwrap();
+		assert_eq!(squares(pos.pinned_pieces(Color::White)), ["e2", "g3"]);
+		assert_eq!(squares(pos.pinned_pieces(Color::Black)), ["c7"]);
+		assert!(pos.discovered_check_candidates().none());
+		let pos = Position::from_fen("3k4/8/8/3N4/8/3R2B1/8/4K3 w - - 0 1").unwrap();
+		assert_eq!(squares(pos.discovered_check_candidates()), ["d5"]);
+		assert!(pos.pinned_pieces(Color::White).none());
+	}
+
+	#[test]
+	fn test_noisy_quiet() {
+		use crate::{game::Position, state::Move};
+		let fens = [
+			Position::FEN_INITIAL,
+			"r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+			"rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+			"n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+		];
+		for fen in fens {
+			let pos = Position::from_fen(fen).unwrap();
+			let (noisy, quiet) = (pos.gen_noisy(), pos.gen_quiet());
+			assert!(noisy.iter().all(|mov| !pos.is_quiet(mov)), "{}", fen);
+			assert!(quiet.iter().all(|mov| pos.is_quiet(mov) && pos.is_pseudolegal(mov)), "{}", fen);
+			let sorted = |moves: &[Move]| {
+				let mut moves: Vec<String> = moves.iter().map(|mov| mov.uci_notation()).collect();
+				moves.sort();
+				moves
+			};
+			assert_eq!(sorted(&[&noisy[..], &quiet[..]].concat()), sorted(&pos.gen_pseudolegal()), "{}", fen);
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test_fen {
+	use crate::movetest::TestFile;
+	use super::Position;
+
+	#[test]
+	fn test_round_trip() {
+		let files = [
+			include_str!("../tests/standard.json"), include_str!("../tests/famous.json"), include_str!("../tests/pawns.json"),
+			include_str!("../tests/promotions.json"), include_str!("../tests/castling.json"), include_str!("../tests/taxing.json"),
+		];
+		for json in files {
+			let file = TestFile::parse(json).unwrap();
+			for case in &file.test_cases {
+				let pos = Position::from_fen(&case.start.fen).unwrap();
+				assert_eq!(pos.to_fen(), case.start.fen);
+				let pos = Position::from_fen(&pos.to_xfen()).unwrap();
+				assert_eq!(Position::from_fen(&pos.to_fen()).unwrap().to_fen(), pos.to_fen());
+			}
+		}
+	}
+
+	#[test]
+	fn test_xfen() {
+		// no pawn can take on d6
+		let fen = "rnbqkbnr/ppp1pppp/8/3p4/8/8/PPPPPPPP/RNBQKBNR w KQkq d6 0 2";
+		let pos = Position::from_fen(fen).unwrap();
+		assert_eq!(pos.to_fen(), fen);
+		assert_eq!(pos.to_xfen(), "rnbqkbnr/ppp1pppp/8/3p4/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 2");
+		let fen = "4k3/8/8/1Pp5/8/8/8/K7 w - c6 0 1";
+		assert_eq!(Position::from_fen(fen).unwrap().to_xfen(), fen);
+		// taking en passant would expose the king to the h5 rook
+		let fen = "4k3/8/8/KPp4r/8/8/8/8 w - c6 0 1";
+		assert_eq!(Position::from_fen(fen).unwrap().to_xfen(), "4k3/8/8/KPp4r/8/8/8/8 w - - 0 1");
+		// Shredder-style castling rights
+		let pos = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w HAh - 0 1").unwrap();
+		assert_eq!(pos.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1");
+		assert!(Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w B - 0 1").is_err());
+	}
+}
+
+#[cfg(test)]
+mod test_legality {
+	use crate::{game::{IllegalMoveReason, Position}, state::Square};
+
+	fn explain(fen: &str, from: &str, to: &str) -> Option<IllegalMoveReason> {
+		let pos = Position::from_fen(fen).unwrap();
+		pos.explain_illegal(Square::parse(from).unwrap(), Square::parse(to).unwrap())
+	}
+
+	#[test]
+	fn test_explanations() {
+		assert_eq!(explain(Position::FEN_INITIAL, "e2", "e4"), None);
+		assert_eq!(explain(Position::FEN_INITIAL, "e3", "e4"), Some(IllegalMoveReason::NoPiece));
+		assert_eq!(explain(Position::FEN_INITIAL, "e7", "e5"), Some(IllegalMoveReason::NotYourTurn));
+		assert_eq!(explain(Position::FEN_INITIAL, "b1", "b3"), Some(IllegalMoveReason::InvalidPattern));
+		assert_eq!(explain(Position::FEN_INITIAL, "b1", "d2"), Some(IllegalMoveReason::OwnPieceOnTarget));
+		assert_eq!(explain("4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1", "e2", "d3"), Some(IllegalMoveReason::PiecePinned));
+		assert_eq!(explain("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1", "e1", "d1"), Some(IllegalMoveReason::KingWouldBeInCheck));
+		assert_eq!(explain("4k3/4r3/8/8/8/8/8/R3K3 w - - 0 1", "a1", "a2"), Some(IllegalMoveReason::KingInCheck));
+	}
+
+	#[test]
+	fn test_moves_from() {
+		let pos = Position::from_fen("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+		for squ in [Square::E1, Square::E5, Square::A1, Square::D5, Square::E4] {
+			let mut expected: Vec<_> = pos.gen_legal().into_iter().filter(|mov| mov.from == squ)
+				.map(|mov| mov.uci_notation()).collect();
+			let mut actual: Vec<_> = pos.moves_from(squ).into_iter().map(|mov| mov.uci_notation()).collect();
+			expected.sort();
+			actual.sort();
+			assert_eq!(actual, expected);
+		}
+		assert_eq!(pos.moves_from(Square::E1).len(), 7); // including both castlings
+		assert_eq!(pos.moves_from(Square::E5).len(), 2); // including en passant
+	}
+
+	#[test]
+	fn test_castling_explanations() {
+		assert_eq!(explain("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1", "c1"), None);
+		assert_eq!(explain("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1", "g1"),
+			Some(IllegalMoveReason::CastlingThroughCheck));
+		assert_eq!(explain("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1", "e1", "c1"), Some(IllegalMoveReason::CastlingBlocked));
+		assert_eq!(explain("4k3/8/8/8/8/8/8/R3K2R w K - 0 1", "e1", "c1"), Some(IllegalMoveReason::CastlingRightsLost));
+	}
+}
+
+#[cfg(test)]
+mod test_draws {
+	use crate::state::Color;
+	use super::{DrawReason, GameResult, Position};
+
+	#[test]
+	fn test_draw_reasons() {
+		let reason = |fen| Position::from_fen(fen).unwrap().draw_reason();
+		assert_eq!(reason(Position::FEN_INITIAL), None);
+		assert_eq!(reason("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(DrawReason::Stalemate));
+		assert_eq!(reason("8/8/4k3/8/8/3K4/8/8 w - - 0 1"), Some(DrawReason::InsufficientMaterial));
+		assert_eq!(reason("8/8/4k3/8/8/3KN3/8/8 w - - 0 1"), Some(DrawReason::InsufficientMaterial));
+		// bishops on the same color only
+		assert_eq!(reason("8/8/3bk3/8/8/3KB3/8/8 w - - 0 1"), Some(DrawReason::InsufficientMaterial));
+		assert_eq!(reason("8/8/2b1k3/8/8/3KB3/8/8 w - - 0 1"), None);
+		assert_eq!(reason("8/8/4k3/8/8/3KNN2/8/8 w - - 0 1"), None);
+		assert_eq!(reason("8/8/4k3/8/8/3KR3/8/8 w - - 99 80"), None);
+		assert_eq!(reason("8/8/4k3/8/8/3KR3/8/8 w - - 100 80"), Some(DrawReason::FiftyMoves));
+		assert_eq!(reason("8/8/4k3/8/8/3KR3/8/8 w - - 150 80"), Some(DrawReason::SeventyFiveMoves));
+		// checkmate takes precedence over the 50-move rule
+		assert_eq!(reason("7k/6Q1/6K1/8/8/8/8/8 b - - 100 80"), None);
+	}
+
+	#[test]
+	fn test_move_counter() {
+		let pos = |fen| Position::from_fen(fen).unwrap();
+		assert!(!pos("8/8/4k3/8/8/3KR3/8/8 w - - 99 80").is_draw_claimable());
+		assert!(pos("8/8/4k3/8/8/3KR3/8/8 w - - 100 80").is_draw_claimable());
+		assert_eq!(pos("8/8/4k3/8/8/3KR3/8/8 w - - 100 80").game_result(), None);
+		// moves are still generated after 75 moves, but the game is over
+		let drawn = pos("8/8/4k3/8/8/3KR3/8/8 w - - 150 80");
+		assert!(!drawn.gen_legal().is_empty());
+		assert_eq!(drawn.game_result(), Some(GameResult::Draw));
+		// in check, this is not a checkmate
+		assert_eq!(pos("R3k3/8/8/8/8/8/8/4K3 b - - 150 80").game_result(), Some(GameResult::Draw));
+		assert_eq!(pos("R3k3/8/4K3/8/8/8/8/8 b - - 150 80").game_result(), Some(GameResult::Checkmate(Color::White)));
+		// the clock stops at its largest value
+		let mut pos = pos("8/8/4k3/8/8/3KR3/8/8 w - - 255 80");
+		let mov = *pos.gen_legal().iter().find(|mov| mov.to_string() == "Re3e1").unwrap();
+		pos.apply_move(&mov);
+		assert_eq!(pos.halfmove_clock(), 255);
+	}
+}
+
+#[cfg(test)]
+mod test_game {
+	use crate::state::Move;
+	#[cfg(feature = "std")]
+	use crate::{pgn::PgnResult, state::Color};
+	use super::{DrawReason, Game, GameResult, Position};
+
+	fn play(game: &mut Game, moves: &[&str]) {
+		for desc in moves {
+			let legal_moves = game.position().gen_legal();
+			game.push(*Move::parse_algebraic(desc, &legal_moves).ok().unwrap());
+		}
+	}
+
+	#[test]
+	fn test_undo_redo() {
+		let start = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut game = Game::new(&start);
+		play(&mut game, &["e4", "e5", "Nf3"]);
+		assert_eq!(game.san_moves(), ["e4", "e5", "Nf3"]);
+		assert_eq!(game.previous_keys().len(), 3);
+		assert_eq!(game.positions().len(), 4);
+		assert_eq!(game.positions()[3].to_fen(), game.position().to_fen());
+		assert_eq!(game.undo().unwrap().to_string(), "Ng1f3");
+		assert_eq!(game.undo().unwrap().to_string(), "e7e5");
+		assert!(game.can_redo());
+		assert_eq!(game.redo().unwrap().to_string(), "e7e5");
+		assert_eq!(game.position().to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+		// another move than the one to redo forgets it
+		play(&mut game, &["Nc3"]);
+		assert!(!game.can_redo() && game.redo().is_none());
+		while game.undo().is_some() {}
+		assert_eq!(game.position().to_fen(), Position::FEN_INITIAL);
+		assert_eq!(game.start_position().to_fen(), Position::FEN_INITIAL);
+		assert!(game.previous_keys().is_empty());
+	}
+
+	#[test]
+	fn test_draw_claims() {
+		let mut game = Game::new(&Position::from_fen(Position::FEN_INITIAL).unwrap());
+		play(&mut game, &["Nf3", "Nf6", "Ng1", "Ng8", "Nf3", "Nf6", "Ng1"]);
+		assert_eq!(game.repetitions(), 2);
+		assert_eq!(game.can_claim_draw(), None);
+		play(&mut game, &["Ng8"]);
+		assert_eq!(game.repetitions(), 3);
+		assert_eq!(game.can_claim_draw(), Some(DrawReason::Repetition));
+		assert_eq!(game.result(), Some(GameResult::Draw));
+		#[cfg(feature = "std")]
+		assert_eq!(game.to_pgn().result(), PgnResult::Draw);
+		game.undo();
+		assert_eq!(game.result(), None);
+
+		let mut game = Game::new(&Position::from_fen("8/8/4k3/8/8/3KR3/8/8 w - - 99 80").unwrap());
+		assert_eq!(game.can_claim_draw(), None);
+		play(&mut game, &["Re1"]);
+		assert_eq!(game.can_claim_draw(), Some(DrawReason::FiftyMoves));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_to_pgn() {
+		let mut game = Game::new(&Position::from_fen(Position::FEN_INITIAL).unwrap());
+		play(&mut game, &["f3", "e5", "g4", "Qh4#"]);
+		assert_eq!(game.result(), Some(GameResult::Checkmate(Color::Black)));
+		let pgn = game.to_pgn();
+		assert_eq!(pgn.moves(), game.moves());
+		assert!(pgn.to_string().contains("1. f3 e5 2. g4 Qh4# 0-1"));
+		assert_eq!(pgn.game().position().to_fen(), game.position().to_fen());
+	}
+}
+
+#[cfg(test)]
+mod test_hash {
+	use crate::{game::Position, state::Move};
+
+	fn play(pos: &mut Position, moves: &[&str]) {
+		for desc in moves {
+			let legal_moves = pos.gen_legal();
+			let mov = *Move::parse_algebraic(desc, &legal_moves).ok().unwrap();
+			pos.apply_move(&mov);
+		}
+	}
+
+	#[test]
+	fn test_hash_key() {
+		let initial = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pos = initial.clone();
+		play(&mut pos, &["Nf3", "Nf6", "Ng1", "Ng8"]);
+		assert_eq!(pos.hash_key(), initial.hash_key());
+		play(&mut pos, &["Nf3"]);
+		assert_ne!(pos.hash_key(), initial.hash_key());
+		// castling rights and side to move are part of the key
+		let with_rights = Position::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+		let without_rights = Position::from_fen("4k3/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap();
+		let black_to_move = Position::from_fen("4k3/8/8/8/8/8/8/R3K2R b KQ - 0 1").unwrap();
+		assert_ne!(with_rights.hash_key(), without_rights.hash_key());
+		assert_ne!(with_rights.hash_key(), black_to_move.hash_key());
+	}
+}
+
+#[cfg(test)]
+mod test_validate {
+	use crate::state::{Color, Square};
+	use super::{FenError, Position, PositionError};
+
+	#[test]
+	fn test_validate() {
+		let validate = |fen| Position::from_fen(fen).unwrap().validate();
+		assert_eq!(validate(Position::FEN_INITIAL), Ok(()));
+		assert_eq!(validate("8/8/8/8/8/8/8/4K3 w - - 0 1"), Err(PositionError::KingCount(Color::Black, 0)));
+		assert_eq!(validate("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"), Err(PositionError::KingCount(Color::White, 2)));
+		assert_eq!(validate("4k3/8/8/8/8/8/8/P3K3 w - - 0 1"), Err(PositionError::PawnOnBackRank(Square::A1)));
+		assert_eq!(validate("4k3/8/8/8/8/1P6/PPPPPPPP/4K3 w - - 0 1"), Err(PositionError::TooManyPawns(Color::White, 9)));
+		// two extra queens and a pawn for each of them is fine, three are too many with 7 pawns
+		assert!(validate("qqq1k3/pppppp2/8/8/8/8/8/4K3 w - - 0 1").is_ok());
+		assert_eq!(validate("qqq1k3/ppppppp1/8/8/8/8/8/4K3 w - - 0 1"), Err(PositionError::TooManyPieces(Color::Black)));
+		// Black to move could capture the king
+		assert!(validate("4k3/8/8/8/8/8/8/4K2r w - - 0 1").is_ok());
+		assert_eq!(validate("4k3/8/8/8/8/8/8/4K2r b - - 0 1"), Err(PositionError::OpponentInCheck(Color::White)));
+		assert!(validate("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").is_ok());
+		assert!(validate("4k3/8/8/4P3/8/8/8/4K3 w - d6 0 1").is_err());
+		assert_eq!(validate("4k3/8/8/3pP3/8/8/8/4K3 b - d6 0 1"), Err(PositionError::InvalidEnPassant(Square::D6)));
+	}
+
+	#[test]
+	fn test_fen_errors() {
+		let parse = |fen| Position::from_fen(fen).err();
+		assert_eq!(parse(Position::FEN_INITIAL), None);
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -"), Some(FenError::MissingField("halfmove clock")));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 x"), Some(FenError::TooManyFields));
+		assert_eq!(parse("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), Some(FenError::InvalidBoard));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1"), Some(FenError::InvalidBoard));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1"), Some(FenError::InvalidSideToMove));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1"), Some(FenError::InvalidCastlingRights));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1"), Some(FenError::CastlingPiecesMissing('K')));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1"), Some(FenError::InvalidEnPassant));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - -1 1"), Some(FenError::InvalidHalfMoveClock));
+		assert_eq!(parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0"), Some(FenError::InvalidMoveNumber));
+	}
 }
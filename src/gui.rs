@@ -1,27 +1,141 @@
-use std::fmt;
+use std::{collections::HashMap, fmt, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, time::{Duration, Instant, SystemTime}};
 
-use chesslib::ai::{ParallelAi, SimpleAi};
-use chesslib::state::{Move, PieceType, Square};
-use chesslib::game::Position;
+use chesslib::ai::{is_mate_score, mate_in, score_moves_until, time_budget, Analyzer, LimitedAi, ParallelAi, RandomAi, SearchLimits, SimpleAi};
+use chesslib::bitboard::Bb;
+use chesslib::explorer::{Explorer, ExplorerDb};
+use chesslib::lichess_api::{CloudEval, LichessClient};
+use chesslib::state::{self as chess, Board, Move, MoveList, Piece, PieceType, Square};
+use chesslib::game::{Game, GameResult, Position};
+use chesslib::pgn::{format_date, Pgn, PgnResult};
+use chesslib::puzzle::{Puzzle, PuzzleAttempt, PuzzleStats, PuzzleStep};
+use chesslib::wdl::WdlModel;
+use serde::Deserialize;
 use sdl2::{
-	event::Event,
+	event::{Event, WindowEvent},
 	gfx::primitives::DrawRenderer,
-	image::LoadTexture,
+	image::{ImageRWops, LoadTexture},
+	keyboard::{Keycode, Mod},
 	mouse::MouseButton,
-	pixels::Color,
+	pixels::{Color, PixelFormatEnum},
 	rect::Rect,
-	rwops::RWops
+	render::BlendMode,
+	rwops::RWops,
+	surface::Surface,
+	video::FullscreenType,
 };
 
 const SPRITE_SIZE: u32 = 16;
-const SPRITE_ZOOM: u32 = 5;
-const TILE_SIZE: u32 = SPRITE_SIZE * SPRITE_ZOOM;
-const STATUS_BAR_HEIGHT: u32 = 12 * SPRITE_ZOOM;
-const STATUS_FONT_SIZE: u16 = 4 * SPRITE_ZOOM as u16;
-const WINDOW_WIDTH: u32 = TILE_SIZE*8;
-const WINDOW_HEIGHT: u32 = TILE_SIZE*8 + STATUS_BAR_HEIGHT;
+// sizes in sprite pixels, multiplied by the zoom of the layout
+const BOARD_SIZE: u32 = SPRITE_SIZE * 8;
+const STATUS_BAR_HEIGHT: u32 = 12;
+const STATUS_FONT_SIZE: u32 = 4;
+const COORD_FONT_SIZE: u32 = 3;
+const EVAL_BAR_WIDTH: u32 = 10; // right of the board in analysis mode
+const EXPLORER_WIDTH: u32 = 64; // right of the board and the eval bar
+const EXPLORER_ROW_HEIGHT: u32 = 8; // of a move, with its bar of results
+const EXPLORER_MAX_MOVES: usize = 12;
+const DEFAULT_ZOOM: u32 = 5; // if the screen size is unknown
+const CONFIG_PATH: &str = "gui.toml";
+const PUZZLE_STATS_PATH: &str = "puzzle_stats.json";
+const EXPLORER_CACHE_DIR: &str = "explorer_cache";
 
-const BOT_DELAY: i64 = 30;
+// minimum time per AI move, so that the moves can be followed; +/- change it by steps
+const BOT_DELAY: Duration = Duration::from_millis(500);
+const BOT_DELAY_STEP: Duration = Duration::from_millis(250);
+const MAX_BOT_DELAY: Duration = Duration::from_secs(5);
+// an AI plays its best move so far when its clock goes below this
+const CLOCK_EMERGENCY: Duration = Duration::from_millis(200);
+const ANALYSIS_DEPTH: u32 = 32; // the analysis is restarted on every move long before that
+// eval graph below the status bar when two AIs play each other
+const GRAPH_HEIGHT: u32 = 16;
+const GRAPH_DEPTH: u32 = 5; // of the referee scoring every position for the graph
+const GRAPH_MIN_PLIES: i32 = 40; // horizontal scale of the graph until the game gets longer
+// blunder check against an AI; B toggles it
+const BLUNDER_THRESHOLD: i16 = 150; // centipawns lost against the best move, unless gui.toml sets blunder_check
+const BLUNDER_CHECK_DEPTH: u32 = 4; // of the search scoring the user's moves while they think
+
+const APP_NAME: &str = "Pyxyne's Chess Engine";
+const ICON_SIZE: u32 = SPRITE_SIZE * 4;
+const FONT: &[u8] = include_bytes!("../res/RobotoMono.ttf");
+
+// board colors; T cycles through them
+#[derive(Clone, Copy)]
+struct Theme {
+	name: &'static str,
+	tiles: [Color; 2], // dark and light, as in the tile sprites
+	use_sprites: bool, // draws the tiles of the sprite atlas instead, which have the same colors in the default atlas
+}
+impl Theme {
+	// file and rank labels, in the color of the other kind of tile
+	fn label_color(self, parity: u8) -> Color {
+		self.tiles[1 - parity as usize]
+	}
+}
+const THEMES: [Theme; 3] = [
+	Theme { name: "wood", tiles: [Color::RGB(208, 140, 71), Color::RGB(254, 206, 158)], use_sprites: true },
+	Theme { name: "green", tiles: [Color::RGB(118, 150, 86), Color::RGB(238, 238, 210)], use_sprites: false },
+	Theme { name: "blue", tiles: [Color::RGB(140, 162, 173), Color::RGB(222, 227, 230)], use_sprites: false },
+];
+
+// gui.toml in the working directory, all keys optional
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+	theme: Option<String>, // name of one of THEMES
+	sprites: Option<String>, // path to a PNG atlas laid out like res/sprites.png, 4 by 4 square sprites
+	lichess_token: Option<String>, // sent to the opening explorer
+	blunder_check: Option<i16>, // threshold in centipawns, turns the blunder check on at launch
+}
+impl Config {
+	fn load(path: &str) -> Result<Config, String> {
+		match std::fs::read_to_string(path) {
+			Ok(text) => toml::from_str(&text).map_err(|err| format!("{}: {}", path, err)),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+			Err(err) => Err(format!("could not read {}: {}", path, err)),
+		}
+	}
+	fn theme(&self) -> Result<usize, String> {
+		match &self.theme {
+			None => Ok(0),
+			Some(name) => THEMES.iter().position(|theme| theme.name == name)
+				.ok_or_else(|| format!("unknown theme {:?}, expected one of: {}", name,
+					THEMES.iter().map(|theme| theme.name).collect::<Vec<_>>().join(", "))),
+		}
+	}
+}
+
+// sizes in screen pixels, for the largest zoom at which everything fits in the window
+#[derive(Clone, Copy)]
+struct Layout {
+	zoom: u32,
+	tile_size: u32,
+	board_size: u32, // also the width of the status bar
+	status_bar_height: u32,
+	status_font_size: u16,
+	coord_font_size: u16,
+	eval_bar_width: u32,
+	explorer_width: u32,
+	graph_height: u32,
+}
+impl Layout {
+	fn new(zoom: u32) -> Self {
+		Layout {
+			zoom,
+			tile_size: SPRITE_SIZE * zoom,
+			board_size: BOARD_SIZE * zoom,
+			status_bar_height: STATUS_BAR_HEIGHT * zoom,
+			status_font_size: (STATUS_FONT_SIZE * zoom) as u16,
+			coord_font_size: (COORD_FONT_SIZE * zoom) as u16,
+			eval_bar_width: EVAL_BAR_WIDTH * zoom,
+			explorer_width: EXPLORER_WIDTH * zoom,
+			graph_height: GRAPH_HEIGHT * zoom,
+		}
+	}
+}
+
+fn load_font<'a>(ttf: &'a sdl2::ttf::Sdl2TtfContext, size: u16) -> sdl2::ttf::Font<'a, 'static> {
+	ttf.load_font_from_rwops(RWops::from_bytes(FONT).unwrap(), size).unwrap()
+}
 
 fn hsv_to_rgb(h: f32, s: f32, v: f32, a: f32) -> Color {
 	assert!(0.0 <= s && s <= 1.0 && 0.0 <= v && v <= 1.0);
@@ -71,6 +185,430 @@ impl fmt::Display for PlayerType {
 	}
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum PlayerKind {
+	User,
+	Random,
+	Limited,
+	Simple,
+}
+impl PlayerKind {
+	const ALL: [PlayerKind; 4] = [PlayerKind::User, PlayerKind::Random, PlayerKind::Limited, PlayerKind::Simple];
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct TimeControl {
+	initial: Duration,
+	increment: Duration,
+}
+impl TimeControl {
+	// minutes and seconds of increment, cycled through in the menu
+	const PRESETS: [(u64, u64); 5] = [(1, 0), (3, 2), (5, 3), (10, 5), (15, 10)];
+
+	fn new(minutes: f64, increment: u64) -> Self {
+		TimeControl { initial: Duration::from_secs_f64(minutes * 60.0), increment: Duration::from_secs(increment) }
+	}
+	// "minutes+seconds", e.g. "5+3"
+	fn parse(s: &str) -> Option<Self> {
+		let (minutes, increment) = s.split_once('+')?;
+		let minutes: f64 = minutes.parse().ok()?;
+		if !(minutes > 0.0 && minutes <= 24.0 * 60.0) {
+			return None;
+		}
+		Some(TimeControl::new(minutes, increment.parse().ok()?))
+	}
+	fn to_pgn(self) -> String {
+		format!("{}+{}", self.initial.as_secs(), self.increment.as_secs())
+	}
+}
+impl fmt::Display for TimeControl {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}+{}", self.initial.as_secs_f64() / 60.0, self.increment.as_secs())
+	}
+}
+
+// for the eval bar and graph, from White's point of view
+fn expected_score(white_score: i16) -> f64 {
+	if is_mate_score(white_score) {
+		if white_score > 0 { 1.0 } else { 0.0 }
+	} else {
+		WdlModel::default().expected_score(white_score as i32)
+	}
+}
+
+// m:ss, with tenths in the last ten seconds
+fn format_clock(time: Duration) -> String {
+	if time < Duration::from_secs(10) {
+		format!("0:{:04.1}", (time.as_millis() / 100) as f64 / 10.0)
+	} else {
+		format!("{}:{:02}", time.as_secs() / 60, time.as_secs() % 60)
+	}
+}
+
+// the players and starting position of a new game, as chosen in the menu
+#[derive(Clone)]
+struct GameSetup {
+	players: [PlayerKind; 2],
+	depths: [u32; 2], // of SimpleAI players
+	skills: [u32; 2], // of LimitedAI players
+	fen: String, // empty for the standard starting position
+	clock: Option<TimeControl>, // untimed if None
+}
+impl GameSetup {
+	const MAX_DEPTH: u32 = 10;
+
+	fn player(&self, color: chess::Color) -> PlayerType {
+		match self.players[color] {
+			PlayerKind::User => PlayerType::User,
+			PlayerKind::Random => PlayerType::Bot(ParallelAi::new(RandomAi::new(rand::random()))),
+			PlayerKind::Limited => PlayerType::Bot(ParallelAi::new(LimitedAi::new(self.skills[color]))),
+			PlayerKind::Simple => PlayerType::Bot(ParallelAi::new(SimpleAi::new(self.depths[color]))),
+		}
+	}
+	// a legal position, as the editor requires
+	fn start_position(&self) -> Result<Position, String> {
+		let fen = self.fen.trim();
+		let pos = Position::from_fen(if fen.is_empty() { Position::FEN_INITIAL } else { fen })
+			.map_err(|err| format!("Invalid FEN: {}", err))?;
+		pos.validate().map_err(|err| format!("Invalid position: {}", err))?;
+		Ok(pos)
+	}
+	// two humans on the same machine
+	fn is_hotseat(&self) -> bool {
+		self.players.iter().all(|kind| *kind == PlayerKind::User)
+	}
+	// two AIs, watched by the user
+	fn is_spectated(&self) -> bool {
+		self.players.iter().all(|kind| *kind != PlayerKind::User)
+	}
+}
+
+// the new game menu, navigated with the keyboard
+struct Menu {
+	setup: GameSetup,
+	row: usize,
+	error: Option<String>,
+}
+impl Menu {
+	const ROWS: usize = 7; // each player and their depth or skill, the starting position, the clock, and the start button
+	const FEN_ROW: usize = 4;
+	const CLOCK_ROW: usize = 5;
+	const FEN_DISPLAY_CHARS: usize = 40;
+
+	fn new(setup: GameSetup) -> Self {
+		Menu { setup, row: Menu::ROWS - 1, error: None }
+	}
+
+	fn lines(&self) -> Vec<String> {
+		let mut lines = vec![];
+		for color in [chess::Color::White, chess::Color::Black] {
+			lines.push(format!("{}: {}", color, match self.setup.players[color] {
+				PlayerKind::User => "Human",
+				PlayerKind::Random => "RandomAI",
+				PlayerKind::Limited => "LimitedAI",
+				PlayerKind::Simple => "SimpleAI",
+			}));
+			let bar = |n: u32, max: u32| format!("[{}{}] {}", "=".repeat(n as usize), "-".repeat((max - n) as usize), n);
+			lines.push(match self.setup.players[color] {
+				PlayerKind::Simple => format!("Depth: {}", bar(self.setup.depths[color], GameSetup::MAX_DEPTH)),
+				PlayerKind::Limited => format!("Skill: {}", bar(self.setup.skills[color], LimitedAi::MAX_SKILL)),
+				_ => format!("Depth: -"),
+			});
+		}
+		let fen = self.setup.fen.trim();
+		lines.push(if fen.is_empty() && self.row != Menu::FEN_ROW {
+			format!("Start: standard position")
+		} else if fen.chars().count() > Menu::FEN_DISPLAY_CHARS {
+			let tail: String = fen.chars().skip(fen.chars().count() - Menu::FEN_DISPLAY_CHARS).collect();
+			format!("FEN: ...{}", tail)
+		} else {
+			format!("FEN: {}", fen)
+		});
+		lines.push(match self.setup.clock {
+			Some(clock) => format!("Clock: {}", clock),
+			None => format!("Clock: none"),
+		});
+		lines.push(format!("Start game"));
+		lines
+	}
+
+	// Left/Right on the selected row
+	fn change(&mut self, delta: i32) {
+		let color = if self.row < 2 { chess::Color::White } else { chess::Color::Black };
+		match self.row {
+			0 | 2 => {
+				let i = PlayerKind::ALL.iter().position(|kind| *kind == self.setup.players[color]).unwrap();
+				self.setup.players[color] = PlayerKind::ALL[(i as i32 + delta).rem_euclid(PlayerKind::ALL.len() as i32) as usize];
+			},
+			1 | 3 => {
+				let (level, max) = match self.setup.players[color] {
+					PlayerKind::Limited => (&mut self.setup.skills[color], LimitedAi::MAX_SKILL),
+					_ => (&mut self.setup.depths[color], GameSetup::MAX_DEPTH),
+				};
+				*level = (*level as i32 + delta).clamp(1, max as i32) as u32;
+			},
+			Menu::CLOCK_ROW => {
+				let choices: Vec<Option<TimeControl>> = std::iter::once(None)
+					.chain(TimeControl::PRESETS.iter().map(|&(minutes, inc)| Some(TimeControl::new(minutes as f64, inc))))
+					.collect();
+				// a custom clock from the command line goes back to the start of the list
+				let i = choices.iter().position(|clock| *clock == self.setup.clock).unwrap_or(0);
+				self.setup.clock = choices[(i as i32 + delta).rem_euclid(choices.len() as i32) as usize];
+			},
+			_ => {},
+		}
+	}
+}
+
+// board setup mode, entered from the current position
+struct Editor {
+	pieces: [Option<Piece>; 64],
+	side: chess::Color,
+	castling: [bool; 4], // in the order of CASTLING
+	en_passant: Option<Square>,
+	brush: Piece, // placed by left clicks
+	error: Option<String>,
+}
+impl Editor {
+	const CASTLING: [char; 4] = ['K', 'Q', 'k', 'q'];
+
+	fn new(pos: &Position) -> Self {
+		let fen = pos.to_fen();
+		let fields: Vec<&str> = fen.split(' ').collect();
+		Editor {
+			pieces: pos.get_board().get_pieces(),
+			side: pos.side_to_move(),
+			castling: Editor::CASTLING.map(|c| fields[2].contains(c)),
+			en_passant: Square::parse(fields[3]),
+			brush: Piece::new(chess::Color::White, PieceType::Queen),
+			error: None,
+		}
+	}
+
+	fn castling_field(&self) -> String {
+		let field: String = Editor::CASTLING.iter().zip(self.castling).filter(|(_, on)| *on).map(|(c, _)| *c).collect();
+		if field.is_empty() { format!("-") } else { field }
+	}
+	fn fen(&self) -> String {
+		let board: Board = Square::iter_all().filter_map(|squ| Some((squ, self.pieces[squ]?))).collect();
+		let en_passant = self.en_passant.map_or(format!("-"), |squ| squ.to_string());
+		format!("{} {} {} {} 0 1", board.to_fen(), self.side.to_fen(), self.castling_field(), en_passant)
+	}
+	fn position(&self) -> Result<Position, String> {
+		let pos = Position::from_fen(&self.fen()).map_err(|err| err.to_string())?;
+		pos.validate().map_err(|err| err.to_string())?;
+		Ok(pos)
+	}
+
+	// a left click places the brush, or removes the same piece
+	fn click(&mut self, squ: Square) {
+		self.pieces[squ] = if self.pieces[squ] == Some(self.brush) { None } else { Some(self.brush) };
+		self.error = None;
+	}
+}
+
+// puzzle mode, with the puzzles of a CSV file in the format of the Lichess database
+struct Trainer {
+	puzzles: Vec<Puzzle>,
+	attempt: PuzzleAttempt,
+	stats: PuzzleStats, // saved in PUZZLE_STATS_PATH after every result
+	hint: bool, // the expected move is shown
+	reply_at: Option<Instant>, // when the opponent answers a correct move
+}
+impl Trainer {
+	// the unseen puzzle closest to the rating of the user, dropping the invalid ones
+	fn next_attempt(puzzles: &mut Vec<Puzzle>, stats: &PuzzleStats) -> Option<PuzzleAttempt> {
+		loop {
+			let puzzle = stats.pick(puzzles)?.clone();
+			match PuzzleAttempt::new(puzzle.clone()) {
+				Ok(attempt) => return Some(attempt),
+				Err(err) => {
+					eprintln!("skipping {}", err);
+					puzzles.retain(|p| p.id != puzzle.id);
+				},
+			}
+		}
+	}
+	// the first result for a puzzle counts; a hint is a failure
+	fn record(&mut self, solved: bool) -> Result<(), String> {
+		self.stats.record(&self.attempt.puzzle, solved);
+		self.stats.save(PUZZLE_STATS_PATH).map_err(|err| format!("Could not save {}: {}", PUZZLE_STATS_PATH, err))
+	}
+	fn summary(&self) -> String {
+		format!("Puzzle rating {:.0} | solved {}/{} | streak {}",
+			self.stats.rating, self.stats.solved, self.stats.attempted, self.stats.streak)
+	}
+}
+
+struct Options {
+	loaded_game: Option<Pgn>,
+	puzzles: Option<String>, // path of a CSV file to start in puzzle mode
+	hotseat: bool,
+	auto_flip: bool,
+	autosave: bool,
+	clock: Option<TimeControl>,
+	theme: usize,
+	lichess_token: Option<String>,
+	blunder_check: Option<i16>,
+}
+
+// the opening explorer panel, O cycling through its databases
+struct ExplorerPanel {
+	explorer: Explorer,
+	db: ExplorerDb,
+}
+
+// cut with an ellipsis to fit in a width
+fn fit_text(font: &sdl2::ttf::Font, text: &str, width: i32) -> String {
+	let mut text = text.to_string();
+	if font.size_of(&text).is_ok_and(|(w, _)| w as i32 <= width) {
+		return text;
+	}
+	while !text.is_empty() && font.size_of(&format!("{}\u{2026}", text)).is_ok_and(|(w, _)| w as i32 > width) {
+		text.pop();
+	}
+	format!("{}\u{2026}", text)
+}
+
+// in pawns, or the mate it announces, from White's point of view; mates are
+// counted in plies from the side to move
+fn format_white_score(score: i16, side_to_move: chess::Color) -> String {
+	if let Some(moves) = mate_in(if side_to_move == chess::Color::White { score } else { -score }) {
+		format!("{}M{}", if score > 0 { "+" } else { "-" }, moves.abs())
+	} else {
+		format!("{:+.1}", score as f64 / 100.0)
+	}
+}
+
+// evaluations of the lichess cloud, looked up in the background in analysis mode
+// as a reference for SimpleAI's
+struct CloudLookup {
+	queries: mpsc::Sender<String>,
+	answers: mpsc::Receiver<(String, Result<Option<CloudEval>, String>)>,
+	results: HashMap<String, Result<Option<CloudEval>, String>>, // by FEN
+	pending: Option<String>,
+}
+impl CloudLookup {
+	fn new() -> Self {
+		let (queries, worker_queries) = mpsc::channel::<String>();
+		let (worker_answers, answers) = mpsc::channel();
+		std::thread::spawn(move || {
+			let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+			let client = LichessClient::anonymous();
+			while let Ok(mut fen) = worker_queries.recv() {
+				// only the latest position matters when moves are played quickly
+				while let Ok(newer) = worker_queries.try_recv() {
+					fen = newer;
+				}
+				let res = runtime.block_on(client.cloud_eval(&fen, 1));
+				if worker_answers.send((fen, res)).is_err() {
+					return;
+				}
+			}
+		});
+		CloudLookup { queries, answers, results: HashMap::new(), pending: None }
+	}
+	// None while the position is looked up
+	fn get(&mut self, pos: &Position) -> Option<&Result<Option<CloudEval>, String>> {
+		while let Ok((fen, res)) = self.answers.try_recv() {
+			if self.pending.as_ref() == Some(&fen) {
+				self.pending = None;
+			}
+			self.results.insert(fen, res);
+		}
+		let fen = pos.to_fen();
+		if !self.results.contains_key(&fen) && self.pending.as_ref() != Some(&fen) {
+			let _ = self.queries.send(fen.clone());
+			self.pending = Some(fen.clone());
+		}
+		self.results.get(&fen)
+	}
+}
+
+// warns the user of a move against an AI that loses more than the threshold
+// compared to the best one, by the scores of a quick search of every move
+// made while they think; moves are let through until the search is done
+struct BlunderCheck {
+	threshold: i16, // centipawns
+	key: Option<u64>, // hash key of the position whose moves are scored
+	answer: Option<mpsc::Receiver<Vec<(Move, i16)>>>,
+	scores: Vec<(Move, i16)>, // best first, empty while searching
+	stop: Arc<AtomicBool>, // of the search in progress
+}
+impl BlunderCheck {
+	fn new(threshold: i16) -> Self {
+		BlunderCheck { threshold, key: None, answer: None, scores: vec![], stop: Arc::new(AtomicBool::new(false)) }
+	}
+	// starts scoring the moves of a new position in the background
+	fn update(&mut self, pos: &Position) {
+		let key = pos.hash_key();
+		if self.key != Some(key) {
+			self.key = Some(key);
+			self.scores.clear();
+			// the search for a position left since is stopped
+			self.stop.store(true, Ordering::Relaxed);
+			self.stop = Arc::new(AtomicBool::new(false));
+			let stop = self.stop.clone();
+			let (sender, answer) = mpsc::channel();
+			let pos = pos.clone();
+			std::thread::spawn(move || {
+				let legal_moves = pos.gen_legal();
+				if let Some(scores) = score_moves_until(&pos, &legal_moves, BLUNDER_CHECK_DEPTH, &stop).filter(|scores| !scores.is_empty()) {
+					let _ = sender.send(scores);
+				}
+			});
+			self.answer = Some(answer);
+		}
+		if let Some(scores) = self.answer.as_ref().and_then(|answer| answer.try_recv().ok()) {
+			self.scores = scores;
+			self.answer = None;
+		}
+	}
+	fn check(&mut self, pos: &Position, mov: Move) -> Option<Blunder> {
+		self.update(pos);
+		let &(_, best_score) = self.scores.first()?;
+		let &(_, score) = self.scores.iter().find(|(m, _)| *m == mov)?;
+		(best_score as i32 - score as i32 > self.threshold as i32)
+			.then(|| Blunder { mov, san: pos.algebraic_notation(&mov), best_score, score })
+	}
+}
+
+impl Drop for BlunderCheck {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+}
+
+// a move held back until the user confirms it or takes it back
+struct Blunder {
+	mov: Move,
+	san: String,
+	best_score: i16, // from the user's point of view
+	score: i16,
+}
+impl Blunder {
+	fn detail(&self) -> String {
+		if is_mate_score(self.score) && self.score < 0 {
+			format!("{} allows a forced mate", self.san)
+		} else if is_mate_score(self.best_score) && self.best_score > 0 && !is_mate_score(self.score) {
+			format!("{} misses a forced mate", self.san)
+		} else {
+			format!("{} loses {:.1} pawns", self.san, (self.best_score as i32 - self.score as i32) as f64 / 100.0)
+		}
+	}
+}
+
+// 1234 games as 1.2k
+fn format_count(n: u64) -> String {
+	if n < 1000 {
+		n.to_string()
+	} else if n < 1_000_000 {
+		format!("{:.1}k", n as f64 / 1e3)
+	} else {
+		format!("{:.1}M", n as f64 / 1e6)
+	}
+}
+
 #[derive(Clone)]
 struct Promotion {
 	move_to: Square,
@@ -82,14 +620,51 @@ struct App<'a> {
 	events: sdl2::EventPump,
 	texture_creator: &'a sdl2::render::TextureCreator<sdl2::video::WindowContext>,
 	atlas_texture: sdl2::render::Texture<'a>,
+	atlas_sprite_size: u32, // in the atlas, which may come from a file
+	ttf: &'a sdl2::ttf::Sdl2TtfContext,
 	font: sdl2::ttf::Font<'a,'static>,
+	coord_font: sdl2::ttf::Font<'a,'static>,
+	layout: Layout,
+	origin: (i32, i32), // of the drawing area, centered in the window
 
-	position: Position,
+	theme: usize, // in THEMES
+	game: Game, // with the undone moves to redo; AIs wait while there are some
 	players: [PlayerType; 2],
-	timer: i64,
-	move_from: Option<Square>,
+	bot_delay: Duration,
+	bots_paused: bool, // AIs do not start thinking
+	bot_step: bool, // lets one AI move through the pause
+	move_from: Option<Square>, // piece being dragged, or waiting for a promotion choice
+	drag_pos: (i32, i32), // cursor position
 	promotion: Option<Promotion>,
-	prev_move: Option<Move>,
+	pgn: Pgn, // tags and comments, exported with the moves of the game
+	message: Option<String>,
+	flipped: bool,
+	auto_flip: bool,
+	hotseat_flip: bool, // auto_flip in hotseat games, unless --no-flip is given
+	privacy_pause: bool,
+	paused: bool,
+	banner_hidden: bool, // game over banner, hidden with Escape to see the board
+	title: String,
+	thinking_since: Option<Instant>,
+	autosave: bool, // finished games are appended to a monthly archive
+	clocks: Option<[Duration; 2]>, // remaining time of each side, in timed games
+	clock_tick: Instant, // last update of the clocks
+	flagged: Option<chess::Color>, // ran out of time
+	analyzer: Option<Analyzer>, // in analysis mode
+	analyzed: Option<u64>, // hash key of the position being analyzed
+	cloud: Option<CloudLookup>, // in analysis mode
+	explorer: Option<ExplorerPanel>,
+	lichess_token: Option<String>,
+	blunder_check: Option<BlunderCheck>,
+	blunder_threshold: i16, // of the blunder check, when B turns it on
+	blunder: Option<Blunder>, // waiting for confirmation
+	referee: Option<Analyzer>, // scores the positions of spectated games for the graph
+	refereed: Option<usize>, // index of the position being scored
+	evals: Vec<Option<i16>>, // of every position of the game, from White's point of view
+	setup: GameSetup, // of the current game, for restarts
+	editor: Option<Editor>,
+	menu: Option<Menu>,
+	trainer: Option<Trainer>,
 }
 
 impl<'a> App<'a> {
@@ -98,38 +673,381 @@ impl<'a> App<'a> {
 		events: sdl2::EventPump,
 		texture_creator: &'a sdl2::render::TextureCreator<sdl2::video::WindowContext>,
 		atlas_texture: sdl2::render::Texture<'a>,
-		font: sdl2::ttf::Font<'a, 'static>,
+		ttf: &'a sdl2::ttf::Sdl2TtfContext,
+		zoom: u32,
+		options: Options,
 	) -> Self {
-		App {
-			canvas, events, texture_creator, atlas_texture, font,
-			position: Position::from_fen("nnnnnnnn/PPPPPPPP/8/8/8/8/8/K6k w - - 0 1").unwrap(),
-			players: [
-				PlayerType::User,
-				PlayerType::Bot(ParallelAi::new(SimpleAi::new(6))),
-			],
-			timer: 0,
+		let layout = Layout::new(zoom);
+		let mut setup = GameSetup {
+			players: [PlayerKind::User, if options.hotseat { PlayerKind::User } else { PlayerKind::Simple }],
+			depths: [6, 6],
+			skills: [3, 3],
+			fen: String::new(),
+			clock: options.clock,
+		};
+		// a loaded game is continued right away, otherwise the menu comes first
+		let menu = if options.loaded_game.is_none() { Some(Menu::new(setup.clone())) } else { None };
+		let pgn = options.loaded_game.unwrap_or_else(|| App::new_pgn(&setup.start_position().unwrap()));
+		if pgn.start_position().to_fen() != Position::FEN_INITIAL {
+			setup.fen = pgn.start_position().to_fen();
+		}
+		let mut app = App {
+			atlas_sprite_size: atlas_texture.query().width / 4,
+			canvas, events, texture_creator, atlas_texture, ttf,
+			theme: options.theme,
+			font: load_font(ttf, layout.status_font_size),
+			coord_font: load_font(ttf, layout.coord_font_size),
+			layout,
+			origin: (0, 0),
+			game: pgn.game(),
+			players: [PlayerType::User, PlayerType::User],
+			bot_delay: BOT_DELAY,
+			bots_paused: false,
+			bot_step: false,
 			move_from: None,
+			drag_pos: (0, 0),
 			promotion: None,
-			prev_move: None,
+			pgn,
+			message: None,
+			flipped: false,
+			auto_flip: false,
+			hotseat_flip: options.auto_flip,
+			privacy_pause: false,
+			paused: false,
+			banner_hidden: false,
+			title: String::new(),
+			thinking_since: None,
+			autosave: options.autosave,
+			clocks: None,
+			clock_tick: Instant::now(),
+			flagged: None,
+			analyzer: None,
+			analyzed: None,
+			cloud: None,
+			explorer: None,
+			lichess_token: options.lichess_token,
+			blunder_check: options.blunder_check.map(BlunderCheck::new),
+			blunder_threshold: options.blunder_check.unwrap_or(BLUNDER_THRESHOLD),
+			blunder: None,
+			referee: None,
+			refereed: None,
+			evals: vec![],
+			setup,
+			editor: None,
+			menu,
+			trainer: None,
+		};
+		app.start_game(app.pgn.clone());
+		if let Some(path) = options.puzzles {
+			app.menu = None;
+			app.open_puzzles(&path);
 		}
+		app
 	}
 }
 
 impl App<'_> {
+	fn new_pgn(start: &Position) -> Pgn {
+		Pgn::new(start).tag("Event", "Casual game").tag("Site", APP_NAME)
+	}
+
+	// plays on from the given game, with the players of the current setup
+	fn start_game(&mut self, pgn: Pgn) {
+		self.game = pgn.game();
+		self.pgn = pgn;
+		self.clocks = self.setup.clock.map(|clock| [clock.initial; 2]);
+		self.clock_tick = Instant::now();
+		self.flagged = None;
+		self.players = [self.setup.player(chess::Color::White), self.setup.player(chess::Color::Black)];
+		let hotseat = self.setup.is_hotseat();
+		self.auto_flip = hotseat && self.hotseat_flip;
+		self.privacy_pause = hotseat;
+		// against an AI, the user's side is at the bottom
+		self.flipped = if self.auto_flip {
+			self.game.position().side_to_move() == chess::Color::Black
+		} else {
+			self.setup.players[chess::Color::White] != PlayerKind::User && self.setup.players[chess::Color::Black] == PlayerKind::User
+		};
+		self.move_from = None;
+		self.promotion = None;
+		self.blunder = None;
+		self.message = None;
+		self.paused = false;
+		self.banner_hidden = false;
+		self.bots_paused = false;
+		self.referee = if self.setup.is_spectated() { Some(Analyzer::new(GRAPH_DEPTH)) } else { None };
+		self.refereed = None;
+		self.evals.clear();
+		self.update_window_size();
+		self.thinking_since = None;
+	}
+
+	// a new game from the starting position of the setup
+	fn restart(&mut self) -> Result<(), String> {
+		let start = self.setup.start_position()?;
+		self.trainer = None;
+		self.start_game(App::new_pgn(&start));
+		Ok(())
+	}
+
+	// continues a loaded game, whose starting position becomes the one of restarts
+	fn load_game(&mut self, pgn: Pgn) {
+		let start = pgn.start_position().to_fen();
+		self.setup.fen = if start == Position::FEN_INITIAL { String::new() } else { start };
+		self.trainer = None;
+		self.start_game(pgn);
+	}
+
+	// enters puzzle mode with the puzzles of a CSV file, starting with the unseen
+	// one closest to the rating of the user
+	fn open_puzzles(&mut self, path: &str) {
+		let result = Puzzle::load_csv(path).and_then(|mut puzzles| {
+			let stats = PuzzleStats::load(PUZZLE_STATS_PATH)?;
+			let attempt = Trainer::next_attempt(&mut puzzles, &stats).ok_or_else(|| format!("No new puzzle in {}", path))?;
+			Ok(Trainer { puzzles, attempt, stats, hint: false, reply_at: None })
+		});
+		match result {
+			Ok(trainer) => {
+				self.trainer = Some(trainer);
+				self.show_puzzle();
+			},
+			Err(err) => self.message = Some(err),
+		}
+	}
+	fn next_puzzle(&mut self) {
+		let Some(trainer) = &mut self.trainer else { return };
+		// skipping a puzzle fails it
+		if !trainer.attempt.is_solved() {
+			if let Err(err) = trainer.record(false) {
+				self.message = Some(err);
+				return;
+			}
+		}
+		match Trainer::next_attempt(&mut trainer.puzzles, &trainer.stats) {
+			Some(attempt) => {
+				trainer.attempt = attempt;
+				self.show_puzzle();
+			},
+			None => self.message = Some(format!("No more puzzles")),
+		}
+	}
+	// the board of the current attempt, as a game between two users
+	fn show_puzzle(&mut self) {
+		let Some(trainer) = &mut self.trainer else { return };
+		trainer.hint = false;
+		trainer.reply_at = None;
+		let mut pgn = App::new_pgn(trainer.attempt.start_position()).tag("Event", "Puzzle")
+			.tag("Site", &trainer.attempt.puzzle.game_url);
+		for mov in trainer.attempt.played_moves() {
+			pgn.push(*mov);
+		}
+		let solver = trainer.attempt.solver();
+		self.start_game(pgn);
+		self.players = [PlayerType::User, PlayerType::User];
+		self.clocks = None;
+		self.auto_flip = false;
+		self.privacy_pause = false;
+		self.referee = None;
+		self.flipped = solver == chess::Color::Black;
+	}
+	fn retry_puzzle(&mut self) {
+		let Some(trainer) = &mut self.trainer else { return };
+		trainer.attempt = PuzzleAttempt::new(trainer.attempt.puzzle.clone()).unwrap(); // valid the first time
+		self.show_puzzle();
+	}
+	fn show_hint(&mut self) {
+		let Some(trainer) = &mut self.trainer else { return };
+		if trainer.attempt.hint().is_some() && !trainer.hint {
+			trainer.hint = true;
+			if let Err(err) = trainer.record(false) {
+				self.message = Some(err);
+			}
+		}
+	}
+	fn puzzle_solved(&self) -> bool {
+		self.trainer.as_ref().is_some_and(|trainer| trainer.attempt.is_solved())
+	}
+
+	// Ctrl+C copies the FEN of the current position, Ctrl+Shift+C the whole game as PGN
+	fn copy_to_clipboard(&mut self, pgn: bool) {
+		let text = if pgn {
+			self.update_pgn();
+			self.pgn.to_string()
+		} else {
+			self.game.position().to_fen()
+		};
+		let what = if pgn { "PGN" } else { "FEN" };
+		self.message = Some(match self.canvas.window().subsystem().clipboard().set_clipboard_text(&text) {
+			Ok(()) => format!("{} copied to the clipboard", what),
+			Err(err) => format!("Could not copy the {}: {}", what, err),
+		});
+	}
+	// Ctrl+V starts a game from a FEN, or continues a PGN game
+	fn paste_from_clipboard(&mut self) {
+		let text = match self.canvas.window().subsystem().clipboard().clipboard_text() {
+			Ok(text) => text,
+			Err(err) => {
+				self.message = Some(format!("Could not paste: {}", err));
+				return;
+			},
+		};
+		let text = text.trim();
+		if let Ok(pos) = Position::from_fen(text) {
+			if let Err(err) = pos.validate() {
+				self.message = Some(format!("Invalid FEN: {}", err));
+				return;
+			}
+			self.load_game(App::new_pgn(&pos));
+			self.message = Some(format!("Position loaded from the clipboard"));
+			return;
+		}
+		match Pgn::parse(text) {
+			Ok(pgn) => {
+				self.load_game(pgn);
+				self.message = Some(format!("Game loaded from the clipboard"));
+			},
+			Err(err) => self.message = Some(format!("Neither a FEN nor a PGN game: {}", err)),
+		}
+	}
+	// a PGN file dropped on the window
+	fn open_pgn(&mut self, path: &str) {
+		match Pgn::load(path) {
+			Ok(games) if !games.is_empty() => {
+				self.load_game(games.into_iter().next().unwrap());
+				self.message = Some(format!("Game loaded from {}", path));
+			},
+			Ok(_) => self.message = Some(format!("No game found in {}", path)),
+			Err(err) => self.message = Some(format!("Could not load {}: {}", path, err)),
+		}
+	}
+
+	fn process_menu_frame(&mut self) -> bool {
+		let mut menu = self.menu.take().unwrap();
+		self.clear();
+		let line_height = self.layout.status_font_size as i32 * 2;
+		self.draw_text_centered("New game", line_height * 2);
+		for (i, line) in menu.lines().iter().enumerate() {
+			let text = if i == menu.row { format!("> {} <", line) } else { line.clone() };
+			self.draw_text_centered(&text, line_height * (4 + i as i32));
+		}
+		if let Some(error) = &menu.error {
+			self.draw_text_centered(error, line_height * (5 + Menu::ROWS as i32));
+		}
+		let bottom = self.height() - line_height;
+		self.draw_text_centered("Up/Down: select, Left/Right: change", bottom - line_height * 2);
+		self.draw_text_centered("Type or paste (Ctrl+V) a FEN", bottom - line_height);
+		self.draw_text_centered("Enter: start, Escape: back to the game", bottom);
+		self.canvas.present();
+
+		let mut start = false;
+		while let Some(event) = self.poll_event() {
+			match event {
+				Event::Quit { .. } => return false,
+				Event::KeyDown { keycode: Some(key), keymod, .. } => match key {
+					Keycode::Up => menu.row = (menu.row + Menu::ROWS - 1) % Menu::ROWS,
+					Keycode::Down => menu.row = (menu.row + 1) % Menu::ROWS,
+					Keycode::Left => menu.change(-1),
+					Keycode::Right => menu.change(1),
+					Keycode::Return | Keycode::KpEnter => start = true,
+					Keycode::Escape => return true,
+					Keycode::Backspace if menu.row == Menu::FEN_ROW => { menu.setup.fen.pop(); },
+					Keycode::V if menu.row == Menu::FEN_ROW && is_ctrl(keymod) => {
+						if let Ok(text) = self.canvas.window().subsystem().clipboard().clipboard_text() {
+							menu.setup.fen = text.trim().to_owned();
+						}
+					},
+					_ => {},
+				},
+				Event::TextInput { text, .. } if menu.row == Menu::FEN_ROW => menu.setup.fen.push_str(&text),
+				_ => {},
+			}
+		}
+		if start {
+			let previous = std::mem::replace(&mut self.setup, menu.setup.clone());
+			match self.restart() {
+				Ok(()) => return true,
+				Err(err) => {
+					self.setup = previous;
+					menu.error = Some(err);
+				},
+			}
+		}
+		self.menu = Some(menu);
+		return true;
+	}
+
+	// converts board coordinates to screen tile coordinates, and vice versa
+	fn to_screen(&self, file: u8, rank: u8) -> (u8, u8) {
+		if self.flipped { (7 - file, rank) } else { (file, 7 - rank) }
+	}
+	fn square_at(&self, x: i32, y: i32) -> Option<Square> {
+		if x < 0 || y < 0 {
+			return None;
+		}
+		let (tile_x, tile_y) = (x as u32 / self.layout.tile_size, y as u32 / self.layout.tile_size);
+		let tile = Square::try_at(tile_x.try_into().ok()?, tile_y.try_into().ok()?)?;
+		let (file, rank) = self.to_screen(tile.file(), tile.rank());
+		Some(Square::at(file, rank))
+	}
+
+	fn atlas_rect(&self, sx: u8, sy: u8) -> Rect {
+		let size = self.atlas_sprite_size;
+		Rect::new((sx as u32 * size) as i32, (sy as u32 * size) as i32, size, size)
+	}
+	fn draw_sprite_at(&mut self, sx: u8, sy: u8, col: u8, row: u8) {
+		let tile = self.layout.tile_size;
+		self.canvas.copy(&self.atlas_texture, self.atlas_rect(sx, sy),
+			Rect::new((col as u32 * tile) as i32, (row as u32 * tile) as i32, tile, tile)).unwrap();
+	}
 	fn draw_sprite(&mut self, sx: u8, sy: u8, x: u8, y: u8) {
-		self.canvas.copy(&self.atlas_texture,
-			Rect::new((sx as u32 * SPRITE_SIZE) as i32, (sy as u32 * SPRITE_SIZE) as i32, SPRITE_SIZE, SPRITE_SIZE),
-			Rect::new((x as u32 * TILE_SIZE) as i32, ((7 - y as u32)*TILE_SIZE) as i32, TILE_SIZE, TILE_SIZE)).unwrap();
+		let (col, row) = self.to_screen(x, y);
+		self.draw_sprite_at(sx, sy, col, row);
+	}
+	// a tile-sized sprite centered on a pixel position, e.g. the dragged piece
+	fn draw_sprite_centered(&mut self, sx: u8, sy: u8, x: i32, y: i32) {
+		let tile = self.layout.tile_size;
+		self.canvas.copy(&self.atlas_texture, self.atlas_rect(sx, sy),
+			Rect::new(x - tile as i32 / 2, y - tile as i32 / 2, tile, tile)).unwrap();
+	}
+
+	fn tile_center(&self, squ: Square) -> (i16, i16) {
+		let tile = self.layout.tile_size;
+		let (col, row) = self.to_screen(squ.file(), squ.rank());
+		((col as u32 * tile + tile/2) as i16, (row as u32 * tile + tile/2) as i16)
 	}
 
 	fn draw_move(&mut self, from: Square, to: Square, color: Color) {
-		let x1 = from.file() as u32 * TILE_SIZE + TILE_SIZE/2;
-		let y1 = (7 - from.rank()) as u32 * TILE_SIZE + TILE_SIZE/2;
-		let x2 = to.file() as u32 * TILE_SIZE + TILE_SIZE/2;
-		let y2 = (7 - to.rank()) as u32 * TILE_SIZE + TILE_SIZE/2;
-		
-		self.canvas.thick_line(x1 as i16, y1 as i16, x2 as i16, y2 as i16,
-			(TILE_SIZE/10) as u8, color).unwrap();
+		let (x1, y1) = self.tile_center(from);
+		let (x2, y2) = self.tile_center(to);
+		self.canvas.thick_line(x1, y1, x2, y2, (self.layout.tile_size/10) as u8, color).unwrap();
+	}
+
+	fn draw_arrow(&mut self, from: Square, to: Square, color: Color) {
+		let (x1, y1) = self.tile_center(from);
+		let (x2, y2) = self.tile_center(to);
+		let (dx, dy) = ((x2 - x1) as f32, (y2 - y1) as f32);
+		let len = (dx * dx + dy * dy).sqrt();
+		let (ux, uy) = (dx / len, dy / len);
+		let head = self.layout.tile_size as f32 / 3.0;
+		// the shaft stops at the base of the head, so that they do not overlap
+		let (bx, by) = (x2 as f32 - ux * head, y2 as f32 - uy * head);
+		self.canvas.thick_line(x1, y1, bx as i16, by as i16, (self.layout.tile_size/10) as u8, color).unwrap();
+		let (px, py) = (-uy * head / 2.0, ux * head / 2.0);
+		self.canvas.filled_trigon(x2, y2, (bx + px) as i16, (by + py) as i16, (bx - px) as i16, (by - py) as i16, color).unwrap();
+	}
+
+	// a dot on an empty destination, a ring around a capturable piece
+	fn draw_target(&mut self, to: Square, capture: bool) {
+		let (x, y) = self.tile_center(to);
+		let color = Color::RGBA(0, 0, 0, 80);
+		if capture {
+			let outer = (self.layout.tile_size / 2 - self.layout.zoom) as i16;
+			for r in outer - 2 * self.layout.zoom as i16..outer {
+				self.canvas.aa_circle(x, y, r, color).unwrap();
+			}
+		} else {
+			self.canvas.filled_circle(x, y, (self.layout.tile_size / 6) as i16, color).unwrap();
+		}
 	}
 
 	fn draw_text(&mut self, text: &str, x: i32, y: i32) {
@@ -141,55 +1059,903 @@ impl App<'_> {
 		)).unwrap();
 	}
 
-	fn make_move(&mut self, mov: Move) {
-		self.position.apply_move(&mov);
-		self.prev_move = Some(mov);
-		self.timer = 0;
+	// files along the bottom edge and ranks along the left one, following the orientation
+	fn draw_coordinates(&mut self) {
+		let edge = if self.flipped { 7 } else { 0 }; // file or rank of the edge squares
+		let pad = self.layout.zoom as i32;
+		for i in 0..8u8 {
+			let file = if self.flipped { 7 - i } else { i };
+			let right = (i as u32 + 1) as i32 * self.layout.tile_size as i32 - pad;
+			self.draw_label(&((b'a' + file) as char).to_string(), (file + edge) % 2, right, self.layout.board_size as i32, true);
+			let rank = if self.flipped { i } else { 7 - i };
+			self.draw_label(&(rank + 1).to_string(), (edge + rank) % 2, pad, i as i32 * self.layout.tile_size as i32, false);
+		}
+	}
+	// a coordinate on a tile of the given parity, aligned left from x, or right and bottom
+	fn draw_label(&mut self, text: &str, tile: u8, x: i32, y: i32, bottom_right: bool) {
+		let surf = self.coord_font.render(text).blended(THEMES[self.theme].label_color(tile)).unwrap();
+		let tex = self.texture_creator.create_texture_from_surface(&surf).unwrap();
+		let (x, y) = if bottom_right { (x - surf.width() as i32, y - surf.height() as i32) } else { (x, y) };
+		self.canvas.copy(&tex, None, Rect::new(x, y, surf.width(), surf.height())).unwrap();
 	}
 
-	fn process_frame(&mut self) -> bool {
+	fn draw_text_centered(&mut self, text: &str, y: i32) {
+		self.draw_text_centered_at(text, self.width() / 2, y);
+	}
+	fn draw_text_centered_at(&mut self, text: &str, center_x: i32, y: i32) {
+		let (width, _) = self.font.size_of(text).unwrap();
+		self.draw_text(text, center_x - width as i32 / 2, y);
+	}
+
+	fn width(&self) -> i32 {
+		(self.content_units().0 * self.layout.zoom) as i32
+	}
+
+	fn toggle_analysis(&mut self) {
+		self.analyzer = match self.analyzer {
+			Some(_) => None,
+			None => Some(Analyzer::new(ANALYSIS_DEPTH)),
+		};
+		self.cloud = self.analyzer.as_ref().map(|_| CloudLookup::new());
+		self.analyzed = None;
+		self.update_window_size();
+	}
+
+	// masters, then lichess players, then no explorer
+	fn cycle_explorer(&mut self) {
+		self.explorer = match self.explorer.take() {
+			None => Some(ExplorerPanel {
+				explorer: Explorer::new(EXPLORER_CACHE_DIR, self.lichess_token.clone()),
+				db: ExplorerDb::Masters,
+			}),
+			Some(panel) if panel.db == ExplorerDb::Masters => Some(ExplorerPanel { db: ExplorerDb::Lichess, ..panel }),
+			Some(_) => None,
+		};
+		self.update_window_size();
+	}
+
+	// size of the drawing area in sprite pixels: the eval bar, the explorer and the eval graph extend it
+	fn content_units(&self) -> (u32, u32) {
+		let width = BOARD_SIZE + if self.analyzer.is_some() { EVAL_BAR_WIDTH } else { 0 }
+			+ if self.explorer.is_some() { EXPLORER_WIDTH } else { 0 };
+		let height = BOARD_SIZE + STATUS_BAR_HEIGHT + if self.referee.is_some() { GRAPH_HEIGHT } else { 0 };
+		(width, height)
+	}
+	fn height(&self) -> i32 {
+		(self.content_units().1 * self.layout.zoom) as i32
+	}
+
+	// the window grows with the drawing area, unless its size is imposed,
+	// in which case the zoom shrinks
+	fn update_window_size(&mut self) {
+		let window = self.canvas.window();
+		if !window.is_maximized() && window.fullscreen_state() == FullscreenType::Off {
+			let (width, height) = self.content_units();
+			let size = (width * self.layout.zoom, height * self.layout.zoom);
+			if window.size() != size {
+				self.canvas.window_mut().set_size(size.0, size.1).unwrap();
+			}
+		}
+		self.relayout();
+	}
+	// largest zoom at which the drawing area fits in the window, centered
+	fn relayout(&mut self) {
+		let (width, height) = self.canvas.window().size();
+		let (units_x, units_y) = self.content_units();
+		let zoom = (width / units_x).min(height / units_y).max(1);
+		if zoom != self.layout.zoom {
+			self.layout = Layout::new(zoom);
+			self.font = load_font(self.ttf, self.layout.status_font_size);
+			self.coord_font = load_font(self.ttf, self.layout.coord_font_size);
+		}
+		self.origin = ((width as i32 - (units_x * zoom) as i32) / 2, (height as i32 - (units_y * zoom) as i32) / 2);
+		self.origin = (self.origin.0.max(0), self.origin.1.max(0));
+	}
+	fn toggle_fullscreen(&mut self) {
+		let window = self.canvas.window_mut();
+		let state = if window.fullscreen_state() == FullscreenType::Off { FullscreenType::Desktop } else { FullscreenType::Off };
+		if let Err(err) = window.set_fullscreen(state) {
+			self.message = Some(format!("Could not toggle fullscreen: {}", err));
+		}
+	}
+
+	// handles resizes and F11 for every mode; mouse positions become relative to the drawing area
+	fn poll_event(&mut self) -> Option<Event> {
+		let mut event = self.events.poll_event()?;
+		match &mut event {
+			Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => self.relayout(),
+			Event::KeyDown { keycode: Some(Keycode::F11), .. } => self.toggle_fullscreen(),
+			Event::MouseButtonDown { x, y, .. } | Event::MouseButtonUp { x, y, .. } | Event::MouseMotion { x, y, .. } => {
+				*x -= self.origin.0;
+				*y -= self.origin.1;
+			},
+			_ => {},
+		}
+		Some(event)
+	}
+	// called before drawing each frame
+	fn clear(&mut self) {
+		let (width, height) = self.content_units();
+		self.canvas.set_viewport(None);
 		self.canvas.set_draw_color(Color::BLACK);
 		self.canvas.clear();
+		self.canvas.set_viewport(Rect::new(self.origin.0, self.origin.1, width * self.layout.zoom, height * self.layout.zoom));
+	}
 
-		let pieces = self.position.get_board().get_pieces();
+	// scores the positions of the game one after the other, at a fixed depth
+	fn update_evals(&mut self) {
+		let Some(referee) = &mut self.referee else { return };
+		let positions = self.game.positions();
+		self.evals.resize(positions.len(), None);
+		if let Some(idx) = self.refereed {
+			if idx >= positions.len() {
+				self.refereed = None; // undone
+			} else if let Some(outcome) = referee.latest().filter(|outcome| outcome.depth >= GRAPH_DEPTH) {
+				let white = positions[idx].side_to_move() == chess::Color::White;
+				self.evals[idx] = Some(if white { outcome.score } else { -outcome.score });
+				self.refereed = None;
+			}
+		}
+		if self.refereed.is_none() {
+			if let Some(idx) = self.evals.iter().position(|eval| eval.is_none()) {
+				match positions[idx].game_result() {
+					Some(GameResult::Checkmate(winner)) =>
+						self.evals[idx] = Some(if winner == chess::Color::White { i16::MAX } else { -i16::MAX }),
+					Some(GameResult::Draw) => self.evals[idx] = Some(0),
+					None => {
+						referee.analyze(&positions[idx]);
+						self.refereed = Some(idx);
+					},
+				}
+			}
+		}
+	}
+
+	// referee scores along the game, White's advantage upwards, and the current move
+	fn draw_eval_graph(&mut self) {
+		if self.referee.is_none() {
+			return;
+		}
+		let top = (self.layout.board_size + self.layout.status_bar_height) as i32;
+		let (width, height) = (self.layout.board_size as i32, self.layout.graph_height as i32);
+		self.canvas.set_draw_color(Color::RGB(40, 40, 40));
+		self.canvas.fill_rect(Rect::new(0, top, self.layout.board_size, self.layout.graph_height)).unwrap();
+		self.canvas.set_draw_color(Color::RGB(90, 90, 90));
+		self.canvas.draw_line((0, top + height / 2), (width, top + height / 2)).unwrap();
+		let plies = (self.evals.len() as i32 - 1).max(GRAPH_MIN_PLIES);
+		let x_of = |ply: usize| ply as i32 * (width - 1) / plies;
+		let current = x_of(self.game.moves().len());
+		self.canvas.set_draw_color(Color::RGB(120, 120, 200));
+		self.canvas.draw_line((current, top), (current, top + height)).unwrap();
+		let mut prev = None;
+		for (ply, eval) in self.evals.iter().enumerate() {
+			let Some(score) = *eval else {
+				prev = None;
+				continue;
+			};
+			let point = (x_of(ply) as i16, (top as f64 + (1.0 - expected_score(score)) * (height - 1) as f64) as i16);
+			if let Some((x, y)) = prev {
+				self.canvas.thick_line(x, y, point.0, point.1, 2, Color::WHITE).unwrap();
+			}
+			prev = Some(point);
+		}
+	}
+
+	// eval bar to the right of the board, and the engine's preferred move as an arrow
+	fn draw_analysis(&mut self) {
+		let key = self.game.position().hash_key();
+		let Some(analyzer) = &mut self.analyzer else { return };
+		if self.analyzed != Some(key) {
+			analyzer.analyze(self.game.position());
+			self.analyzed = Some(key);
+		}
+		let latest = analyzer.latest().map(|outcome| (outcome.best_move, outcome.score, outcome.depth));
+		let white_score = latest.map(|(_, score, _)| if self.game.position().side_to_move() == chess::Color::White { score } else { -score });
+		let expected = white_score.map_or(0.5, expected_score);
+
+		let height = self.layout.board_size;
+		let white_height = (expected * height as f64).round() as u32;
+		self.canvas.set_draw_color(Color::RGB(40, 40, 40));
+		self.canvas.fill_rect(Rect::new(self.layout.board_size as i32, 0, self.layout.eval_bar_width, height)).unwrap();
+		// White's share grows from White's side of the board
+		let white_y = if self.flipped { 0 } else { height - white_height };
+		self.canvas.set_draw_color(Color::RGB(230, 230, 230));
+		self.canvas.fill_rect(Rect::new(self.layout.board_size as i32, white_y as i32, self.layout.eval_bar_width, white_height)).unwrap();
+
+		let center_x = (self.layout.board_size + self.layout.eval_bar_width / 2) as i32;
+		let status_y = height as i32 + self.layout.status_bar_height as i32 / 2;
+		if let (Some(score), Some((mov, _, depth))) = (white_score, latest) {
+			self.draw_small_text(&format_white_score(score, self.game.position().side_to_move()), center_x, status_y - self.layout.coord_font_size as i32 * 2 / 3);
+			self.draw_small_text(&format!("d{}", depth), center_x, status_y + self.layout.coord_font_size as i32 * 2 / 3);
+			self.draw_arrow(mov.from, mov.to, Color::RGBA(40, 120, 255, 160));
+		}
+	}
+	// moves played from the current position in the games of the explorer database,
+	// most played first, with the share of White wins, draws and Black wins as a bar
+	fn draw_explorer(&mut self) {
+		let Some(panel) = &mut self.explorer else { return };
+		let db = panel.db;
+		let res = panel.explorer.get(db, self.game.position()).cloned();
+		let left = (self.layout.board_size + if self.analyzer.is_some() { self.layout.eval_bar_width } else { 0 }) as i32;
+		let (width, zoom) = (self.layout.explorer_width as i32, self.layout.zoom as i32);
+		self.canvas.set_draw_color(Color::RGB(40, 40, 40));
+		self.canvas.fill_rect(Rect::new(left, 0, width as u32, self.layout.board_size + self.layout.status_bar_height)).unwrap();
+		let (x, right) = (left + 2 * zoom, left + width - 2 * zoom);
+		let line = self.layout.coord_font_size as i32 * 3 / 2;
+		let mut y = 2 * zoom + line / 2;
+		let res = match res {
+			Some(Ok(res)) => res,
+			Some(Err(err)) => {
+				self.draw_small_text_left(db.to_string(), x, y);
+				self.draw_small_text_left(fit_text(&self.coord_font, &err, right - x), x, y + line);
+				return;
+			},
+			None => {
+				self.draw_small_text_left(format!("{}: loading...", db), x, y);
+				return;
+			},
+		};
+		self.draw_small_text_left(format!("{}: {} games", db, format_count(res.games())), x, y);
+		y += line;
+		if let Some(opening) = &res.opening {
+			let name = fit_text(&self.coord_font, &format!("{} {}", opening.eco, opening.name), right - x);
+			self.draw_small_text_left(name, x, y);
+		}
+		y += line;
+		if res.moves.is_empty() {
+			self.draw_small_text_left("No games".to_string(), x, y + line);
+		}
+		let row = (EXPLORER_ROW_HEIGHT * self.layout.zoom) as i32;
+		for mov in res.moves.iter().take(EXPLORER_MAX_MOVES) {
+			y += row;
+			let games = mov.games();
+			let share = games as f64 * 100.0 / res.games().max(1) as f64;
+			self.draw_small_text_left(mov.san.clone(), x, y - row / 4);
+			self.draw_small_text_left(format!("{:.0}%", share), x + width / 3, y - row / 4);
+			let count = format_count(games);
+			let (count_width, _) = self.coord_font.size_of(&count).unwrap();
+			self.draw_small_text_left(count, right - count_width as i32, y - row / 4);
+			self.draw_result_bar(mov.white, mov.draws, mov.black, Rect::new(x, y + row / 8, (right - x) as u32, 2 * zoom as u32));
+		}
+	}
+	// white wins, draws and black wins, side by side in proportion
+	fn draw_result_bar(&mut self, white: u64, draws: u64, black: u64, rect: Rect) {
+		let games = (white + draws + black).max(1);
+		let white_width = (white * rect.width() as u64 / games) as u32;
+		let draws_width = (draws * rect.width() as u64 / games) as u32;
+		let black_width = rect.width() - white_width - draws_width;
+		let mut x = rect.x();
+		for (width, color) in [(white_width, Color::RGB(230, 230, 230)), (draws_width, Color::RGB(128, 128, 128)), (black_width, Color::RGB(0, 0, 0))] {
+			if width > 0 {
+				self.canvas.set_draw_color(color);
+				self.canvas.fill_rect(Rect::new(x, rect.y(), width, rect.height())).unwrap();
+			}
+			x += width as i32;
+		}
+	}
+	fn draw_small_text_left(&mut self, text: String, x: i32, center_y: i32) {
+		let surf = self.coord_font.render(&text).blended(Color::WHITE).unwrap();
+		let tex = self.texture_creator.create_texture_from_surface(&surf).unwrap();
+		self.canvas.copy(&tex, None, Rect::new(x, center_y - surf.height() as i32 / 2, surf.width(), surf.height())).unwrap();
+	}
+	// the reference of the cloud, as a mark across the eval bar and an arrow for its best move
+	fn draw_cloud_eval(&mut self, eval: &CloudEval) {
+		let Some(pv) = eval.pvs.first() else { return };
+		let color = Color::RGBA(255, 160, 0, 160);
+		let height = self.layout.board_size;
+		let white_height = (expected_score(pv.white_score(self.game.position().side_to_move())) * height as f64).round() as i32;
+		let y = if self.flipped { white_height } else { height as i32 - white_height };
+		let zoom = self.layout.zoom;
+		self.canvas.set_draw_color(color);
+		self.canvas.fill_rect(Rect::new(self.layout.board_size as i32, (y - zoom as i32 / 2).max(0), self.layout.eval_bar_width, zoom)).unwrap();
+		let best = pv.moves.split_ascii_whitespace().next().and_then(|uci| Move::parse_uci(uci, &self.game.position().gen_legal()).ok().copied());
+		if let Some(mov) = best {
+			self.draw_arrow(mov.from, mov.to, color);
+		}
+	}
+	// what the cloud says of the current position, for the status bar
+	fn cloud_status(&self, cloud: Option<&Result<Option<CloudEval>, String>>) -> String {
+		let eval = match cloud {
+			None => return format!("Cloud: looking up..."),
+			Some(Err(err)) => return format!("Cloud: {}", err),
+			Some(Ok(None)) => return format!("Cloud: position not analyzed"),
+			Some(Ok(Some(eval))) => eval,
+		};
+		let Some(pv) = eval.pvs.first() else { return format!("Cloud: no line") };
+		// the start of the line, in SAN
+		let mut pos = self.game.position().clone();
+		let mut line = vec![];
+		for uci in pv.moves.split_ascii_whitespace().take(4) {
+			let Ok(&mov) = Move::parse_uci(uci, &pos.gen_legal()) else { break };
+			line.push(pos.algebraic_notation(&mov));
+			pos.apply_move(&mov);
+		}
+		format!("Cloud {} d{}: {}", format_white_score(pv.white_score(self.game.position().side_to_move()), self.game.position().side_to_move()), eval.depth, line.join(" "))
+	}
+	fn draw_small_text(&mut self, text: &str, center_x: i32, center_y: i32) {
+		let surf = self.coord_font.render(text).blended(Color::WHITE).unwrap();
+		let tex = self.texture_creator.create_texture_from_surface(&surf).unwrap();
+		self.canvas.copy(&tex, None, Rect::new(
+			center_x - surf.width() as i32 / 2, center_y - surf.height() as i32 / 2,
+			surf.width(), surf.height(),
+		)).unwrap();
+	}
+
+	// the game also ends on the draws that do not leave the side to move without moves
+	// a flag fall is scored like a checkmate
+	fn game_result(&self) -> Option<GameResult> {
+		if let Some(color) = self.flagged {
+			return Some(GameResult::Checkmate(color.opponent()));
+		}
+		self.game.position().game_result().or(self.game.draw_reason().map(|_| GameResult::Draw))
+	}
+
+	// short description of the game state for the window title
+	fn state_summary(&self) -> String {
+		if self.menu.is_some() {
+			return format!("New game");
+		}
+		if self.editor.is_some() {
+			return format!("Position editor");
+		}
+		if self.game.can_redo() {
+			let ply = self.game.moves().len();
+			return format!("Viewing move {} of {}", ply, ply + self.game.undone().len());
+		}
+		if let Some(color) = self.flagged {
+			return format!("{} lost on time", color);
+		}
+		if let Some(trainer) = &self.trainer {
+			return if trainer.attempt.is_solved() {
+				format!("Puzzle solved")
+			} else {
+				format!("Puzzle {}", trainer.attempt.puzzle.id)
+			};
+		}
+		let player = self.game.position().side_to_move();
+		match self.game_result() {
+			Some(GameResult::Checkmate(winner)) => format!("Checkmate, {} wins", winner),
+			Some(GameResult::Draw) => match self.game.draw_reason() {
+				Some(reason) => format!("Draw by {}", reason),
+				None => format!("Draw"),
+			},
+			None => match &self.players[player] {
+				PlayerType::Bot(_) => match self.thinking_since {
+					Some(t0) => format!("Thinking... ({} s)", t0.elapsed().as_secs()),
+					None => format!("Thinking..."),
+				},
+				PlayerType::User if self.players.iter().all(|p| matches!(p, PlayerType::User)) =>
+					format!("{} to move", player),
+				PlayerType::User => format!("Your move"),
+			},
+		}
+	}
+	fn update_title(&mut self) {
+		let title = format!("{} \u{2014} {}", self.state_summary(), APP_NAME);
+		if title != self.title {
+			self.canvas.window_mut().set_title(&title).unwrap();
+			self.title = title;
+		}
+	}
+
+	// animated strip along the top of the status bar while the bot is thinking
+	fn draw_thinking_indicator(&mut self) {
+		if let Some(t0) = self.thinking_since {
+			let width = self.layout.board_size / 4;
+			let period = 1500;
+			let phase = (t0.elapsed().as_millis() % period) as u32;
+			let x = (phase * (self.layout.board_size + width) / period as u32) as i32 - width as i32;
+			self.canvas.set_draw_color(Color::RGBA(255, 255, 255, 160));
+			self.canvas.fill_rect(Rect::new(x, self.layout.board_size as i32, width, self.layout.zoom)).unwrap();
+		}
+	}
+
+	// runs the clock of the side to move, while the game is live and on screen
+	fn update_clock(&mut self) {
+		let now = Instant::now();
+		let elapsed = now - self.clock_tick;
+		self.clock_tick = now;
+		let running = self.menu.is_none() && !self.paused && !self.game.can_redo() && self.game_result().is_none();
+		let player = self.game.position().side_to_move();
+		match &mut self.clocks {
+			Some(clocks) if running => clocks[player] = clocks[player].saturating_sub(elapsed),
+			_ => return,
+		}
+		if self.clocks.unwrap()[player].is_zero() {
+			self.flagged = Some(player);
+			self.move_from = None;
+			self.promotion = None;
+			self.blunder = None;
+			if self.autosave {
+				self.archive_pgn();
+			}
+		}
+	}
+
+	// the blunder check only covers the user's moves against an AI
+	fn blunder_check_applies(&self) -> bool {
+		let player = self.game.position().side_to_move();
+		self.blunder_check.is_some() && self.trainer.is_none() && matches!(self.players[player], PlayerType::User)
+			&& matches!(self.players[player.opponent()], PlayerType::Bot(_))
+	}
+	fn toggle_blunder_check(&mut self) {
+		self.blunder_check = match self.blunder_check {
+			Some(_) => None,
+			None => Some(BlunderCheck::new(self.blunder_threshold)),
+		};
+		self.message = Some(match self.blunder_check {
+			Some(_) => format!("Blunder check on: moves losing over {:.1} pawns ask for confirmation", self.blunder_threshold as f64 / 100.0),
+			None => format!("Blunder check off"),
+		});
+	}
+	// a move of the user, held back if the blunder check objects to it
+	fn user_move(&mut self, mov: Move) {
+		if self.blunder_check_applies() {
+			if let Some(blunder) = self.blunder_check.as_mut().unwrap().check(self.game.position(), mov) {
+				self.blunder = Some(blunder);
+				return;
+			}
+		}
+		self.make_move(mov);
+	}
+	fn confirm_blunder(&mut self) {
+		if let Some(blunder) = self.blunder.take() {
+			self.make_move(blunder.mov);
+		}
+	}
+	fn take_back_blunder(&mut self) {
+		self.blunder = None;
+		self.message = Some(format!("Move taken back"));
+	}
+
+	// a new move drops the undone ones
+	fn make_move(&mut self, mov: Move) {
+		if let Some(trainer) = &mut self.trainer {
+			let step = trainer.attempt.play(mov);
+			trainer.hint = false;
+			let result = match step {
+				PuzzleStep::Wrong => trainer.record(false),
+				PuzzleStep::Correct => {
+					trainer.reply_at = Some(Instant::now() + BOT_DELAY);
+					Ok(())
+				},
+				PuzzleStep::Solved => trainer.record(trainer.attempt.mistakes == 0),
+			};
+			if step == PuzzleStep::Wrong {
+				self.message = Some(result.err().unwrap_or_else(|| format!("Wrong move, try again (H: hint)")));
+				return;
+			}
+			self.apply_move(mov);
+			if let Err(err) = result {
+				self.message = Some(err);
+			}
+			return;
+		}
+		if let (Some(clocks), Some(clock)) = (&mut self.clocks, self.setup.clock) {
+			clocks[self.game.position().side_to_move()] += clock.increment;
+		}
+		self.apply_move(mov);
+	}
+	fn apply_move(&mut self, mov: Move) {
+		self.game.push(mov);
+		self.moved();
+	}
+	// after a move is played or redone
+	fn moved(&mut self) {
+		self.message = None;
+		self.banner_hidden = false;
+		if self.auto_flip {
+			self.flipped = self.game.position().side_to_move() == chess::Color::Black;
+		}
+		if self.privacy_pause && self.game_result().is_none() {
+			self.paused = true;
+		}
+		if self.autosave && self.trainer.is_none() && self.game_result().is_some() {
+			self.archive_pgn();
+		}
+	}
+
+	// steps back one move, which can be redone until another move is played
+	fn undo(&mut self) {
+		if self.game.undo().is_none() {
+			return;
+		}
+		// a search from the undone position would not apply anymore
+		for color in [chess::Color::White, chess::Color::Black] {
+			if let PlayerType::Bot(bot) = &mut self.players[color] {
+				bot.cancel();
+			}
+		}
+		self.thinking_since = None;
+		self.flagged = None; // takebacks do not give the time back, so the flag falls again on the live position
+		self.move_from = None;
+		self.promotion = None;
+		self.blunder = None;
+		self.message = None;
+		if self.auto_flip {
+			self.flipped = self.game.position().side_to_move() == chess::Color::Black;
+		}
+	}
+	fn redo(&mut self) {
+		if self.game.redo().is_some() {
+			self.move_from = None;
+			self.promotion = None;
+			self.moved();
+			self.paused = false; // the position was already seen
+		}
+	}
+
+	// hides the board between moves in hotseat games
+	fn process_pause_frame(&mut self) -> bool {
+		self.clear();
+		let player = self.game.position().side_to_move();
+		let center = self.height() / 2;
+		self.draw_text_centered(&format!("{} to move", player), center - self.layout.status_font_size as i32);
+		self.draw_text_centered("Click or press a key to continue", center + self.layout.status_font_size as i32);
+		self.canvas.present();
+
+		while let Some(event) = self.poll_event() {
+			match event {
+				Event::Quit { .. } => return false,
+				Event::MouseButtonUp { .. } | Event::KeyDown { .. } => self.paused = false,
+				_ => {},
+			}
+		}
+		return true;
+	}
+
+	// the game as it stands, with the tags of the setup
+	fn update_pgn(&mut self) {
+		self.pgn.set_moves(&self.game.moves());
+		self.pgn.set_result(self.game_result().map_or(PgnResult::Unfinished, |result| result.into()));
+		self.pgn.set_tag("White", &self.players[0]);
+		self.pgn.set_tag("Black", &self.players[1]);
+		if let Some(clock) = self.setup.clock {
+			self.pgn.set_tag("TimeControl", clock.to_pgn());
+		}
+		if self.flagged.is_some() {
+			self.pgn.set_tag("Termination", "time forfeit");
+		}
+	}
+
+	fn save_pgn(&mut self) {
+		self.update_pgn();
+		let secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+			.map(|d| d.as_secs()).unwrap_or(0);
+		let path = format!("games/gui-{}.pgn", secs);
+		self.message = Some(match self.pgn.save(&path) {
+			Ok(()) => format!("Game saved to {}", path),
+			Err(err) => format!("Could not save game: {}", err),
+		});
+	}
+
+	// appends the finished game to the archive of the current month
+	fn archive_pgn(&mut self) {
+		self.update_pgn();
+		let month = &format_date(SystemTime::now())[..7];
+		let path = format!("games/gui-archive-{}.pgn", month);
+		// on success, the result stays in the status bar
+		if let Err(err) = self.pgn.append_to(&path) {
+			self.message = Some(format!("Could not archive game: {}", err));
+		}
+	}
+
+	// tiles, coordinates and pieces, leaving the origin of a dragged piece dimmed and empty,
+	// the square of a king in check tinted red and those of the pieces giving check lighter
+	fn draw_board(&mut self, pieces: &[Option<Piece>; 64], dragged: Option<Square>, check: Option<(Square, Bb)>) {
+		let theme = THEMES[self.theme];
+		for x in 0..8u8 {
+			for y in 0..8u8 {
+				if theme.use_sprites {
+					self.draw_sprite(3, (x+y) % 2, x, y); // board tile
+				} else {
+					self.fill_square(Square::at(x, y), theme.tiles[((x+y) % 2) as usize]);
+				}
+			}
+		}
+		self.draw_coordinates();
+		if let Some(from) = dragged {
+			self.fill_square(from, Color::RGBA(0, 0, 0, 96));
+		}
+		if let Some((king, checkers)) = check {
+			self.fill_square(king, Color::RGBA(230, 20, 20, 128));
+			for squ in checkers.iter() {
+				self.fill_square(squ, Color::RGBA(230, 20, 20, 64));
+			}
+		}
 		for x in 0..8u8 {
 			for y in 0..8u8 {
-				self.draw_sprite(3, (x+y) % 2, x, y); // board tile
-				if let Some(piece) = pieces[Square::at(x as u8, y as u8)] {
+				if dragged == Some(Square::at(x, y)) {
+					continue;
+				}
+				if let Some(piece) = pieces[Square::at(x, y)] {
 					let type_idx = piece.ptype as u8;
 					let color_idx = piece.color as u8;
 					self.draw_sprite(type_idx % 3, type_idx / 3 + 2 * color_idx, x, y);
 				}
 			}
 		}
+	}
+
+	// screen tiles of the promotion choices, in a column from the promotion square toward the center
+	fn promotion_tiles(&self, promotion: &Promotion) -> Vec<(u8, u8)> {
+		let (col, row) = self.to_screen(promotion.move_to.file(), promotion.move_to.rank());
+		(0..promotion.choices.len() as u8).map(|i| if row < 4 { (col, row + i) } else { (col, row - i) }).collect()
+	}
+	fn promotion_choice_at(&self, promotion: &Promotion, x: i32, y: i32) -> Option<PieceType> {
+		let tile = self.layout.tile_size as i32;
+		let (col, row) = (x.div_euclid(tile), y.div_euclid(tile));
+		let idx = self.promotion_tiles(promotion).iter().position(|&(c, r)| c as i32 == col && r as i32 == row)?;
+		Some(promotion.choices[idx])
+	}
+	fn draw_promotion_popup(&mut self, promotion: &Promotion, color: chess::Color) {
+		let tile = self.layout.tile_size;
+		let tiles = self.promotion_tiles(promotion);
+		let (col, top) = (tiles[0].0, tiles.iter().map(|&(_, row)| row).min().unwrap());
+		let panel = Rect::new((col as u32 * tile) as i32, (top as u32 * tile) as i32, tile, tile * tiles.len() as u32);
+		self.canvas.set_draw_color(Color::RGBA(240, 240, 240, 230));
+		self.canvas.fill_rect(panel).unwrap();
+		self.canvas.set_draw_color(Color::RGB(60, 60, 60));
+		self.canvas.draw_rect(panel).unwrap();
+		for (&ptype, (col, row)) in promotion.choices.iter().zip(tiles) {
+			let spr_idx = ptype as u8;
+			self.draw_sprite_at(spr_idx % 3, color as u8 * 2 + spr_idx / 3, col, row);
+		}
+	}
+	fn cancel_promotion(&mut self) {
+		self.promotion = None;
+		self.move_from = None;
+	}
+
+	fn fill_square(&mut self, squ: Square, color: Color) {
+		let tile = self.layout.tile_size;
+		let (col, row) = self.to_screen(squ.file(), squ.rank());
+		self.canvas.set_draw_color(color);
+		self.canvas.fill_rect(Rect::new((col as u32 * tile) as i32, (row as u32 * tile) as i32, tile, tile)).unwrap();
+	}
+
+	// result and how the game ended, once it is over and the final position is shown
+	fn banner_text(&self) -> Option<(String, String)> {
+		if self.banner_hidden || self.game.can_redo() {
+			return None;
+		}
+		if let Some(trainer) = self.trainer.as_ref().filter(|trainer| trainer.attempt.is_solved()) {
+			let detail = if trainer.attempt.mistakes == 0 { format!("rating {:.0}", trainer.stats.rating) } else { format!("with mistakes") };
+			return Some((format!("Puzzle solved"), detail));
+		}
+		Some(match (self.game_result()?, self.flagged) {
+			(_, Some(color)) => (format!("{} wins", color.opponent()), format!("{} ran out of time", color)),
+			(GameResult::Checkmate(winner), None) => (format!("{} wins", winner), format!("by checkmate")),
+			(GameResult::Draw, None) => (format!("Draw"), self.game.draw_reason().map_or(String::new(), |reason| format!("by {}", reason))),
+		})
+	}
+	// panel centered on the board, and its new game (or next puzzle) button
+	fn banner_rects(&self) -> (Rect, Rect) {
+		let tile = self.layout.tile_size as i32;
+		let panel = Rect::new(tile, tile * 5 / 2, (tile * 6) as u32, (tile * 3) as u32);
+		let button = Rect::new(tile * 5 / 2, tile * 17 / 4, (tile * 3) as u32, (tile * 3 / 4) as u32);
+		(panel, button)
+	}
+	// darkens the board under a banner with the result
+	fn draw_banner(&mut self) {
+		let Some((title, detail)) = self.banner_text() else { return };
+		let (board, tile) = (self.layout.board_size, self.layout.tile_size as i32);
+		self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 128));
+		self.canvas.fill_rect(Rect::new(0, 0, board, board)).unwrap();
+		let (panel, button) = self.banner_rects();
+		self.canvas.set_draw_color(Color::RGBA(30, 30, 30, 230));
+		self.canvas.fill_rect(panel).unwrap();
+		self.canvas.set_draw_color(Color::RGB(200, 200, 200));
+		self.canvas.draw_rect(panel).unwrap();
+		let center_x = board as i32 / 2;
+		self.draw_text_centered_at(&title, center_x, tile * 3 + tile / 8);
+		self.draw_text_centered_at(&detail, center_x, tile * 29 / 8);
+		self.canvas.set_draw_color(Color::RGB(70, 110, 170));
+		self.canvas.fill_rect(button).unwrap();
+		let label = if self.trainer.is_some() { "Next puzzle" } else { "New game" };
+		self.draw_text_centered_at(label, center_x, button.center().y());
+		self.draw_small_text("Escape to hide", center_x, tile * 21 / 4);
+	}
+
+	// panel centered on the board, and its buttons to play the move anyway or take it back
+	fn blunder_rects(&self) -> (Rect, Rect, Rect) {
+		let tile = self.layout.tile_size as i32;
+		let (panel, _) = self.banner_rects();
+		let play = Rect::new(tile * 3 / 2, tile * 17 / 4, (tile * 9 / 4) as u32, (tile * 3 / 4) as u32);
+		let take_back = Rect::new(tile * 17 / 4, tile * 17 / 4, (tile * 9 / 4) as u32, (tile * 3 / 4) as u32);
+		(panel, play, take_back)
+	}
+	// the move held back as a red arrow, under a panel asking for confirmation
+	fn draw_blunder_prompt(&mut self) {
+		let Some(blunder) = &self.blunder else { return };
+		let (mov, detail) = (blunder.mov, blunder.detail());
+		self.draw_arrow(mov.from, mov.to, Color::RGBA(230, 20, 20, 160));
+		let tile = self.layout.tile_size as i32;
+		let (panel, play, take_back) = self.blunder_rects();
+		self.canvas.set_draw_color(Color::RGBA(30, 30, 30, 230));
+		self.canvas.fill_rect(panel).unwrap();
+		self.canvas.set_draw_color(Color::RGB(200, 200, 200));
+		self.canvas.draw_rect(panel).unwrap();
+		let center_x = self.layout.board_size as i32 / 2;
+		self.draw_text_centered_at("Are you sure?", center_x, tile * 3 + tile / 8);
+		self.draw_text_centered_at(&detail, center_x, tile * 29 / 8);
+		for (button, label) in [(play, "Play it"), (take_back, "Take back")] {
+			self.canvas.set_draw_color(Color::RGB(70, 110, 170));
+			self.canvas.fill_rect(button).unwrap();
+			self.draw_text_centered_at(label, button.center().x(), button.center().y());
+		}
+		self.draw_small_text("Enter: play it, Escape: take back", center_x, tile * 21 / 4);
+	}
+
+	fn process_editor_frame(&mut self) -> bool {
+		let mut editor = self.editor.take().unwrap();
+		self.clear();
+		self.draw_board(&editor.pieces, None, None);
+		if let Some(squ) = editor.en_passant {
+			self.draw_target(squ, false);
+		}
 
-		if let Some(mov) = self.prev_move {
+		let line1 = format!("Setup | {} to move | castling {} | ep {}",
+			editor.side, editor.castling_field(), editor.en_passant.map_or(format!("-"), |squ| squ.to_string()));
+		let line2 = if let Some(error) = &editor.error {
+			error.clone()
+		} else if let Some(message) = &self.message {
+			message.clone()
+		} else {
+			let brush = format!("{} {:?}", editor.brush.color, editor.brush.ptype).to_lowercase();
+			format!("Brush: {} (PNBRQK, Shift: White)", brush)
+		};
+		let status_x = self.layout.status_font_size as i32 / 2;
+		let status_y = self.layout.board_size as i32 + self.layout.status_bar_height as i32 / 2;
+		self.draw_text(&line1, status_x, status_y - self.layout.status_font_size as i32 * 2 / 3);
+		self.draw_text(&line2, status_x, status_y + self.layout.status_font_size as i32 * 2 / 3);
+		self.canvas.present();
+
+		while let Some(event) = self.poll_event() {
+			match event {
+				Event::Quit { .. } => return false,
+				Event::KeyDown { keycode: Some(key), keymod, .. } => {
+					let ptype = match key {
+						Keycode::P => Some(PieceType::Pawn),
+						Keycode::N => Some(PieceType::Knight),
+						Keycode::B => Some(PieceType::Bishop),
+						Keycode::R => Some(PieceType::Rook),
+						Keycode::Q => Some(PieceType::Queen),
+						Keycode::K => Some(PieceType::King),
+						_ => None,
+					};
+					if let Some(ptype) = ptype {
+						// as in FEN, upper case for White
+						let white = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+						editor.brush = Piece::new(if white { chess::Color::White } else { chess::Color::Black }, ptype);
+					}
+					match key {
+						Keycode::Space => editor.side = editor.side.opponent(),
+						Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 => {
+							let i = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4].iter().position(|k| *k == key).unwrap();
+							editor.castling[i] = !editor.castling[i];
+						},
+						Keycode::Delete => editor.pieces = [None; 64],
+						Keycode::F => self.flipped = !self.flipped,
+						Keycode::C => {
+							let fen = editor.fen();
+							self.message = Some(match self.canvas.window().subsystem().clipboard().set_clipboard_text(&fen) {
+								Ok(()) => format!("FEN copied to the clipboard"),
+								Err(err) => format!("Could not copy the FEN: {}", err),
+							});
+						},
+						// the players are chosen in the menu, with the FEN filled in
+						Keycode::Return | Keycode::KpEnter => match editor.position() {
+							Ok(pos) => {
+								self.setup.fen = pos.to_fen();
+								self.message = None;
+								self.menu = Some(Menu::new(self.setup.clone()));
+								return true;
+							},
+							Err(err) => editor.error = Some(err),
+						},
+						Keycode::Escape => {
+							self.message = None;
+							return true;
+						},
+						_ => {},
+					}
+				},
+				Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+					if let Some(squ) = self.square_at(x, y) {
+						match mouse_btn {
+							MouseButton::Left => editor.click(squ),
+							MouseButton::Right => editor.pieces[squ] = None,
+							MouseButton::Middle => {
+								editor.en_passant = if editor.en_passant == Some(squ) { None } else { Some(squ) };
+							},
+							_ => {},
+						}
+						editor.error = None;
+					}
+				},
+				_ => {},
+			}
+		}
+		self.editor = Some(editor);
+		return true;
+	}
+
+	fn process_frame(&mut self) -> bool {
+		self.update_clock();
+		self.update_title();
+		if self.menu.is_some() {
+			return self.process_menu_frame();
+		}
+		if self.editor.is_some() {
+			return self.process_editor_frame();
+		}
+		if self.paused {
+			return self.process_pause_frame();
+		}
+
+		// the opponent's answer to a correct move in a puzzle
+		if let Some(trainer) = self.trainer.as_mut().filter(|trainer| trainer.reply_at.is_some_and(|t| Instant::now() >= t)) {
+			trainer.reply_at = None;
+			if let Some(reply) = trainer.attempt.play_reply() {
+				self.apply_move(reply);
+			}
+		}
+
+		// the user's moves are scored while they think
+		if self.blunder_check_applies() && !self.game.can_redo() {
+			self.blunder_check.as_mut().unwrap().update(self.game.position());
+		}
+
+		self.clear();
+
+		let pieces = self.game.position().get_board().get_pieces();
+		// the dragged piece follows the cursor instead
+		let dragged = self.move_from.filter(|_| self.promotion.is_none());
+		let side = self.game.position().side_to_move();
+		let checkers = self.game.position().checkers();
+		let check = if checkers.none() {
+			None
+		} else {
+			self.game.position().get_board().find_piece(Piece::new(side, PieceType::King)).iter().next().map(|king| (king, checkers))
+		};
+		self.draw_board(&pieces, dragged, check);
+
+		if let Some(mov) = self.game.last_move() {
 			self.draw_move(mov.from, mov.to, hsv_to_rgb(mov.ptype as u8 as f32 / 6.0, 1.0, 1.0, 0.5));
 		}
+		self.draw_analysis();
+		let cloud = self.cloud.as_mut().map(|cloud| cloud.get(self.game.position()).cloned());
+		if let Some(Some(Ok(Some(eval)))) = &cloud {
+			self.draw_cloud_eval(eval);
+		}
+		if let Some(hint) = self.trainer.as_ref().filter(|trainer| trainer.hint).and_then(|trainer| trainer.attempt.hint()) {
+			self.draw_arrow(hint.from, hint.to, Color::RGBA(40, 200, 80, 160));
+		}
 
-		let moves = self.position.gen_legal();
-		let player = self.position.side_to_move();
-		let user_to_move = matches!(self.players[player], PlayerType::User);
+		let moves = if self.game.draw_reason().is_some() || self.flagged.is_some() { MoveList::new() } else { self.game.position().gen_legal() };
+		let player = self.game.position().side_to_move();
+		let user_to_move = matches!(self.players[player], PlayerType::User) && self.blunder.is_none()
+			&& self.trainer.as_ref().is_none_or(|trainer| trainer.attempt.hint().is_some());
 
 		if user_to_move {
 			if let Some(from) = self.move_from {
-				self.draw_sprite(3, 2, from.file(), from.rank());
 				if let Some(promotion) = self.promotion.clone() { // choosing promotion
+					self.draw_sprite(3, 2, from.file(), from.rank());
 					self.draw_sprite(3, 3, promotion.move_to.file(), promotion.move_to.rank());
 					self.draw_move(from, promotion.move_to, Color::RGBA(255, 255, 255, 128));
 
 					self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 64));
 					self.canvas.fill_rect(None).unwrap();
-
-					for (i, ptype) in promotion.choices.into_iter().enumerate() {
-						let spr_idx = ptype as u8;
-						self.draw_sprite(spr_idx % 3, player as u8 * 2 + spr_idx / 3, 2 + i as u8, 4);
-					}
+					self.draw_promotion_popup(&promotion, player);
 				} else {
-					for mov in &moves {
-						if mov.from == from {
-							self.draw_sprite(3, 3, mov.to.file(), mov.to.rank());
-						}
+					for mov in self.game.position().moves_from(from) {
+						self.draw_target(mov.to, pieces[mov.to].is_some());
+					}
+					if let Some(piece) = pieces[from] {
+						let type_idx = piece.ptype as u8;
+						let (x, y) = self.drag_pos;
+						self.draw_sprite_centered(type_idx % 3, type_idx / 3 + 2 * piece.color as u8, x, y);
 					}
 				}
 			} else {
@@ -199,56 +1965,163 @@ impl App<'_> {
 			}
 		}
 
-		let line1 = format!("Ply {:<3} | {} ({})'s turn",
-			self.position.get_ply(),
-			player, self.players[player]
-		);
-		let line2 = if moves.len() == 0 {
-			if self.position.is_in_check(player) {
+		let line1 = match (&self.trainer, &cloud) {
+			(Some(trainer), _) => trainer.summary(),
+			(None, Some(cloud)) => {
+				// left of White's clock
+				let clock_width = self.clocks.map_or(0, |clocks| {
+					let text = format!("{} {}", chess::Color::White, format_clock(clocks[chess::Color::White]));
+					self.font.size_of(&text).unwrap().0 + self.layout.status_font_size as u32
+				});
+				fit_text(&self.font, &format!("Ply {:<3} | {}", self.game.position().get_ply(), self.cloud_status(cloud.as_ref())),
+					(self.layout.board_size - clock_width) as i32 - self.layout.status_font_size as i32)
+			},
+			(None, None) => format!("Ply {:<3} | {} ({})'s turn",
+				self.game.position().get_ply(),
+				player, self.players[player]
+			),
+		};
+		let line2 = if self.blunder.is_some() {
+			format!("Are you sure? (Enter: play it, Escape: take back)")
+		} else if let Some(message) = &self.message {
+			message.clone()
+		} else if let Some(trainer) = &self.trainer {
+			if trainer.attempt.is_solved() {
+				format!("Solved! (Enter: next puzzle, R: retry)")
+			} else {
+				format!("Best move for {} (H: hint, Enter: skip)", trainer.attempt.solver())
+			}
+		} else if let Some(color) = self.flagged {
+			format!("{} lost on time. Win for {}.", color, color.opponent())
+		} else if moves.len() == 0 {
+			if let Some(reason) = self.game.draw_reason() {
+				format!("It's a draw ({}).", reason)
+			} else if self.game.position().is_in_check(player) {
 				format!("Checkmate! Win for {}.", player.opponent())
 			} else {
 				format!("It's a draw.")
 			}
+		} else if self.game.can_redo() {
+			let ply = self.game.moves().len();
+			format!("Move {} of {} (Left/Right to navigate)", ply, ply + self.game.undone().len())
+		} else if self.bots_paused && !user_to_move {
+			format!("Paused (Space: resume, Period: one move)")
+		} else if self.setup.is_spectated() {
+			format!("Thinking... (delay {:.2} s, +/- to change)", self.bot_delay.as_secs_f64())
 		} else {
 			self.players[player].status()
 		};
-		let status_x = STATUS_FONT_SIZE as i32 / 2;
-		let status_y = 8 * TILE_SIZE as i32 + STATUS_BAR_HEIGHT as i32 / 2;
-		self.draw_text(&line1, status_x, status_y - STATUS_FONT_SIZE as i32 * 2 / 3);
-		self.draw_text(&line2, status_x, status_y + STATUS_FONT_SIZE as i32 * 2 / 3);
+		let status_x = self.layout.status_font_size as i32 / 2;
+		let status_y = self.layout.board_size as i32 + self.layout.status_bar_height as i32 / 2;
+		self.draw_text(&line1, status_x, status_y - self.layout.status_font_size as i32 * 2 / 3);
+		self.draw_text(&line2, status_x, status_y + self.layout.status_font_size as i32 * 2 / 3);
+		if let Some(clocks) = self.clocks {
+			for (color, dy) in [(chess::Color::White, -1), (chess::Color::Black, 1)] {
+				let text = format!("{} {}", color, format_clock(clocks[color]));
+				let (width, _) = self.font.size_of(&text).unwrap();
+				let x = self.layout.board_size as i32 - status_x - width as i32; // left of the eval bar
+				self.draw_text(&text, x, status_y + dy * self.layout.status_font_size as i32 * 2 / 3);
+			}
+		}
+		self.draw_thinking_indicator();
+		self.update_evals();
+		self.draw_eval_graph();
+		self.draw_explorer();
+		self.draw_banner();
+		self.draw_blunder_prompt();
 
 		self.canvas.present();
 
-		loop {
-			let event = if let Some(event) = self.events.poll_event() { event } else { break };
+		while let Some(event) = self.poll_event() {
 			match event {
 				Event::Quit { .. } => return false,
+				Event::KeyDown { keycode: Some(Keycode::S), .. } => self.save_pgn(),
+				Event::KeyDown { keycode: Some(Keycode::C), keymod, .. } if is_ctrl(keymod) =>
+					self.copy_to_clipboard(keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)),
+				Event::KeyDown { keycode: Some(Keycode::V), keymod, .. } if is_ctrl(keymod) => self.paste_from_clipboard(),
+				Event::DropFile { filename, .. } if filename.to_lowercase().ends_with(".csv") => self.open_puzzles(&filename),
+				Event::DropFile { filename, .. } => self.open_pgn(&filename),
+				Event::KeyDown { keycode: Some(Keycode::F), .. } => self.flipped = !self.flipped,
+				Event::KeyDown { keycode: Some(Keycode::A), .. } => self.toggle_analysis(),
+				Event::KeyDown { keycode: Some(Keycode::O), .. } => self.cycle_explorer(),
+				Event::KeyDown { keycode: Some(Keycode::B), .. } => self.toggle_blunder_check(),
+				Event::KeyDown { keycode: Some(Keycode::Return | Keycode::KpEnter), .. } if self.blunder.is_some() => self.confirm_blunder(),
+				Event::KeyDown { keycode: Some(Keycode::Escape), .. } if self.blunder.is_some() => self.take_back_blunder(),
+				Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if self.blunder.is_some() => {
+					let (_, play, take_back) = self.blunder_rects();
+					if play.contains_point((x, y)) {
+						self.confirm_blunder();
+					} else if take_back.contains_point((x, y)) {
+						self.take_back_blunder();
+					}
+				},
+				Event::KeyDown { keycode: Some(Keycode::T), .. } => {
+					self.theme = (self.theme + 1) % THEMES.len();
+					self.message = Some(format!("Theme: {}", THEMES[self.theme].name));
+				},
+				Event::KeyDown { keycode: Some(Keycode::Space), .. } => self.bots_paused = !self.bots_paused,
+				Event::KeyDown { keycode: Some(Keycode::Period), .. } => self.bot_step = true,
+				Event::KeyDown { keycode: Some(Keycode::M), .. } => {
+					if let PlayerType::Bot(bot) = &self.players[player] {
+						bot.move_now();
+					}
+				},
+				Event::KeyDown { keycode: Some(Keycode::Equals | Keycode::KpPlus), .. } =>
+					self.bot_delay = (self.bot_delay + BOT_DELAY_STEP).min(MAX_BOT_DELAY),
+				Event::KeyDown { keycode: Some(Keycode::Minus | Keycode::KpMinus), .. } =>
+					self.bot_delay = self.bot_delay.saturating_sub(BOT_DELAY_STEP),
+				Event::KeyDown { keycode: Some(Keycode::E), .. } => {
+					self.message = None;
+					self.editor = Some(Editor::new(self.game.position()));
+				},
+				// puzzles are played forward only
+				Event::KeyDown { keycode: Some(Keycode::H), .. } => self.show_hint(),
+				Event::KeyDown { keycode: Some(Keycode::Return | Keycode::KpEnter), .. } => self.next_puzzle(),
+				Event::KeyDown { keycode: Some(Keycode::R), .. } if self.trainer.is_some() => self.retry_puzzle(),
+				Event::KeyDown { keycode: Some(Keycode::Left | Keycode::Right), .. } if self.trainer.is_some() => {},
+				Event::KeyDown { keycode: Some(Keycode::Left), .. } => self.undo(),
+				Event::KeyDown { keycode: Some(Keycode::Right), .. } => self.redo(),
+				Event::KeyDown { keycode: Some(Keycode::N), .. } => self.menu = Some(Menu::new(self.setup.clone())),
+				Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+					if let Err(err) = self.restart() {
+						self.message = Some(err);
+					}
+				},
+				Event::MouseMotion { x, y, .. } => self.drag_pos = (x, y),
+				Event::KeyDown { keycode: Some(Keycode::Escape), .. } if self.promotion.is_some() => self.cancel_promotion(),
+				Event::KeyDown { keycode: Some(Keycode::Escape), .. } if self.game_result().is_some() || self.puzzle_solved() =>
+					self.banner_hidden = !self.banner_hidden,
+				Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. }
+					if self.banner_text().is_some() && self.banner_rects().1.contains_point((x, y)) => {
+					if self.trainer.is_some() {
+						self.next_puzzle();
+					} else {
+						self.menu = Some(Menu::new(self.setup.clone()));
+					}
+				},
 				Event::MouseButtonDown { mouse_btn, x, y, .. } => {
-					if mouse_btn == MouseButton::Left
-						&& x >= 0 && y >= 0 && x < 8*TILE_SIZE as i32 && y < 8*TILE_SIZE as i32
-						&& user_to_move {
-						let gx = x as u32 / TILE_SIZE;
-						let gy = y as u32 / TILE_SIZE;
-						if let Some(promotion) = &self.promotion {
-							if gy == 3 && gx >= 2 && gx < 2 + promotion.choices.len() as u32 {
-								let ptype = promotion.choices[gx as usize - 2];
-								
-								let matching: Vec<Move> = moves.iter().filter(|m|
-									m.from == self.move_from.unwrap()
-									&& m.to == promotion.move_to
-									&& m.special.get_promotion() == Some(ptype)
-								).copied().collect();
-								debug_assert!(matching.len() == 1);
-
-								self.move_from = None;
-								self.promotion = None;
-								
-								self.make_move(matching[0]);
-							}
-						} else if self.move_from.is_none() {
-							let squ = Square::at(gx as u8, 7 - gy as u8);
+					if !user_to_move {
+						// nothing to click
+					} else if let Some(promotion) = self.promotion.clone() {
+						// any click but on a choice cancels the move
+						let from = self.move_from.unwrap();
+						self.cancel_promotion();
+						let choice = if mouse_btn == MouseButton::Left { self.promotion_choice_at(&promotion, x, y) } else { None };
+						if let Some(ptype) = choice {
+							let matching: Vec<Move> = moves.iter().filter(|m|
+								m.from == from
+								&& m.to == promotion.move_to
+								&& m.special.get_promotion() == Some(ptype)
+							).copied().collect();
+							debug_assert!(matching.len() == 1);
+							self.user_move(matching[0]);
+						}
+					} else if mouse_btn == MouseButton::Left && self.move_from.is_none() {
+						if let Some(squ) = self.square_at(x, y) {
 							if moves.iter().any(|m| m.from == squ) {
 								self.move_from = Some(squ);
+								self.drag_pos = (x, y);
+								self.message = None;
 							}
 						}
 					}
@@ -256,27 +2129,24 @@ impl App<'_> {
 				Event::MouseButtonUp { mouse_btn, x, y, .. } => {
 					if mouse_btn == MouseButton::Left && user_to_move && self.promotion.is_none() {
 						if let Some(from) = self.move_from {
-							if x >= 0 && y >= 0 && x < 8*TILE_SIZE as i32 && y < 8*TILE_SIZE as i32 {
-								let gx = x as u32 / TILE_SIZE;
-								let gy = y as u32 / TILE_SIZE;
-								let squ = Square::at(gx as u8, 7 - gy as u8);
-								let mut matching_moves = Vec::with_capacity(1);
-								for mov in moves.iter() {
-									if mov.from == from && mov.to == squ {
-										matching_moves.push(*mov);
-									}
-								}
+							if let Some(squ) = self.square_at(x, y) {
+								let mut matching_moves = self.game.position().moves_from(from);
+								matching_moves.retain(|mov| mov.to == squ);
 								if matching_moves.is_empty() {
 									self.move_from = None;
+									if squ != from {
+										self.message = self.game.position().explain_illegal(from, squ)
+											.map(|reason| format!("Illegal move: {}", reason));
+									}
 								} else if matching_moves.len() == 1 {
 									self.move_from = None;
 									if let Some(mov) = matching_moves.first() {
-										self.make_move(*mov);
+										self.user_move(*mov);
 									}
 								} else {
-									let ptypes: Vec<PieceType> = matching_moves.into_iter().map(|m| m.special.get_promotion()
+									let mut ptypes: Vec<PieceType> = matching_moves.into_iter().map(|m| m.special.get_promotion()
 										.expect("non-promotion move found among multiple matching moves")).collect();
-									assert!(ptypes.len() == 4, "!= 4 promotions found");
+									ptypes.sort_by_key(|ptype| std::cmp::Reverse(*ptype as u8)); // queen first
 									self.promotion = Some(Promotion {
 										move_to: squ,
 										choices: ptypes,
@@ -292,45 +2162,152 @@ impl App<'_> {
 			}
 		}
 
-		if let PlayerType::Bot(bot) = &mut self.players[player] {
-			if bot.is_thinking() {
-				if self.timer >= BOT_DELAY {
-					if let Some(mov) = bot.try_get_result() {
-						self.make_move(mov);
+		// AIs do not play while past positions are viewed
+		match &mut self.players[player] {
+			PlayerType::Bot(bot) if !self.game.can_redo() && self.flagged.is_none() => {
+				if bot.is_thinking() {
+					if self.clocks.is_some_and(|clocks| clocks[player] < CLOCK_EMERGENCY) {
+						bot.move_now();
+					}
+					// in timed games, the delay would come out of the AI's clock
+					let delay = if self.clocks.is_some() { Duration::ZERO } else { self.bot_delay };
+					if self.thinking_since.is_some_and(|t0| t0.elapsed() >= delay) {
+						// a result for another position is dropped, and the bot searches again
+						if let Some((_, outcome)) = bot.try_get_result().filter(|(key, _)| *key == self.game.position().hash_key()) {
+							self.thinking_since = None;
+							self.make_move(outcome.best_move);
+							// the AI keeps thinking while the human does
+							let ponder = matches!(self.players[player.opponent()], PlayerType::User) && self.game_result().is_none();
+							if let PlayerType::Bot(bot) = &mut self.players[player] {
+								if ponder {
+									bot.ponder(self.game.position());
+								}
+							}
+						}
 					}
+				} else if !moves.is_empty() && (!self.bots_paused || self.bot_step) {
+					self.bot_step = false;
+					let limits = match self.clocks.zip(self.setup.clock) {
+						Some((clocks, clock)) => SearchLimits::within(time_budget(clocks[player], clock.increment)),
+						None => SearchLimits::default(),
+					};
+					bot.search_async(self.game.position(), &moves, limits);
+					self.thinking_since = Some(Instant::now());
 				}
-			} else if !moves.is_empty() {
-				bot.pick_move_async(&self.position, &moves);
-			}
+			},
+			_ => {},
 		}
 
-		self.timer += 1;
-
 		return true;
 	}
 }
 
+fn is_ctrl(keymod: Mod) -> bool {
+	keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+}
+
+// a white king on a board tile, taken from the sprite atlas
+fn make_icon() -> Surface<'static> {
+	let mut atlas = RWops::from_bytes(include_bytes!("../res/sprites.png")).unwrap().load_png().unwrap();
+	let mut icon = Surface::new(ICON_SIZE, ICON_SIZE, PixelFormatEnum::RGBA32).unwrap();
+	let sprite = |sx: u32, sy: u32| Rect::new((sx * SPRITE_SIZE) as i32, (sy * SPRITE_SIZE) as i32, SPRITE_SIZE, SPRITE_SIZE);
+	atlas.set_blend_mode(BlendMode::None).unwrap();
+	atlas.blit_scaled(sprite(3, 0), &mut icon, None).unwrap();
+	atlas.set_blend_mode(BlendMode::Blend).unwrap();
+	let king = PieceType::King as u32;
+	atlas.blit_scaled(sprite(king % 3, king / 3), &mut icon, None).unwrap();
+	icon
+}
+
 fn main() {
+	let config = Config::load(CONFIG_PATH).unwrap_or_else(|err| {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	});
+	let theme = config.theme().unwrap_or_else(|err| {
+		eprintln!("error: {}: {}", CONFIG_PATH, err);
+		std::process::exit(1);
+	});
+	if config.blunder_check.is_some_and(|threshold| threshold <= 0) {
+		eprintln!("error: {}: blunder_check must be a positive number of centipawns", CONFIG_PATH);
+		std::process::exit(1);
+	}
+	let mut options = Options { loaded_game: None, puzzles: None, hotseat: false, auto_flip: true, autosave: true, clock: None, theme,
+		lichess_token: config.lichess_token.clone(), blunder_check: config.blunder_check };
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--clock" => options.clock = match args.next().as_deref().and_then(TimeControl::parse) {
+				Some(clock) => Some(clock),
+				None => {
+					eprintln!("error: --clock expects minutes and an increment in seconds, e.g. 5+3");
+					std::process::exit(1);
+				},
+			},
+			"--puzzles" => options.puzzles = match args.next() {
+				Some(path) => Some(path),
+				None => {
+					eprintln!("error: --puzzles expects a CSV file from the Lichess puzzle database");
+					std::process::exit(1);
+				},
+			},
+			"--hotseat" => options.hotseat = true,
+			"--no-flip" => options.auto_flip = false,
+			"--no-autosave" => options.autosave = false,
+			path => options.loaded_game = Some(match Pgn::load(path) {
+				Ok(games) if !games.is_empty() => games.into_iter().next().unwrap(),
+				Ok(_) => {
+					eprintln!("error: no game found in {}", path);
+					std::process::exit(1);
+				},
+				Err(err) => {
+					eprintln!("error: could not load {}: {}", path, err);
+					std::process::exit(1);
+				},
+			}),
+		}
+	}
+
 	let sdl = sdl2::init().unwrap();
 	let video = sdl.video().unwrap();
-	let window = video.window("Pyxyne's Chess Engine", WINDOW_WIDTH, WINDOW_HEIGHT)
+	// as large as fits comfortably on the screen, for high-resolution displays
+	let (min_width, min_height) = (BOARD_SIZE, BOARD_SIZE + STATUS_BAR_HEIGHT);
+	let zoom = match video.display_usable_bounds(0) {
+		Ok(bounds) => (bounds.width() * 9 / 10 / min_width).min(bounds.height() * 9 / 10 / min_height).max(1),
+		Err(_) => DEFAULT_ZOOM,
+	};
+	let mut window = video.window(APP_NAME, min_width * zoom, min_height * zoom)
 		.position_centered()
+		.resizable()
 		.build().unwrap();
-	let canvas = window.into_canvas()
+	window.set_minimum_size(min_width, min_height).unwrap();
+	window.set_icon(make_icon());
+	let mut canvas = window.into_canvas()
 		.present_vsync()
 		.build().unwrap();
+	canvas.set_blend_mode(BlendMode::Blend); // for the translucent overlays
 	let texture_creator = canvas.texture_creator();
 	let atlas_texture = {
 		let _ = sdl2::image::init(sdl2::image::InitFlag::PNG).unwrap();
-		texture_creator.load_texture_bytes(include_bytes!("../res/sprites.png")).unwrap()
+		match &config.sprites {
+			Some(path) => {
+				let texture = texture_creator.load_texture(path).unwrap_or_else(|err| {
+					eprintln!("error: could not load sprites from {}: {}", path, err);
+					std::process::exit(1);
+				});
+				let query = texture.query();
+				if query.width != query.height || query.width % 4 != 0 {
+					eprintln!("error: {} must be a square of 4 by 4 sprites, not {}x{}", path, query.width, query.height);
+					std::process::exit(1);
+				}
+				texture
+			},
+			None => texture_creator.load_texture_bytes(include_bytes!("../res/sprites.png")).unwrap(),
+		}
 	};
 	let ttf = sdl2::ttf::init().unwrap();
-	let font = {
-		let rwops = RWops::from_bytes(include_bytes!("../res/RobotoMono.ttf")).unwrap();
-		ttf.load_font_from_rwops(rwops, STATUS_FONT_SIZE).unwrap()
-	};
 	let events = sdl.event_pump().unwrap();
 
-	let mut app = App::new(canvas, events, &texture_creator, atlas_texture, font);
+	let mut app = App::new(canvas, events, &texture_creator, atlas_texture, &ttf, zoom, options);
 	while app.process_frame() {}
 }
@@ -1,4 +1,46 @@
+// without the `std` feature, only the core (board, move generation and FEN) is
+// built, on `core` and `alloc`, for embedded targets
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+extern crate alloc;
+
 pub mod bitboard;
 pub mod state;
 pub mod game;
+#[cfg(feature = "std")]
 pub mod ai;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod pgn;
+#[cfg(feature = "std")]
+pub mod puzzle;
+#[cfg(feature = "std")]
+pub mod version;
+#[cfg(feature = "std")]
+pub mod wdl;
+#[cfg(feature = "std")]
+pub mod tt;
+#[cfg(feature = "std")]
+pub mod lichess;
+#[cfg(feature = "lichess")]
+pub mod lichess_api;
+#[cfg(feature = "lichess")]
+pub mod explorer;
+#[cfg(all(test, feature = "lichess"))]
+mod test_util;
+#[cfg(feature = "std")]
+pub mod book;
+#[cfg(feature = "std")]
+pub mod movetest;
+#[cfg(feature = "std")]
+pub mod testsuite;
+#[cfg(feature = "nnue")]
+pub mod nnue;
+#[cfg(feature = "serde")]
+pub mod serialize;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,316 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	game::{GameResult, Position},
+	pgn::{format_date, Pgn, PgnResult},
+	state::{Color, Move}
+};
+
+// A game in the JSON format of the lichess export API (as returned with
+// `Accept: application/json`, optionally with clocks and opening), which can
+// be converted to and from PGN.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LichessGame {
+	pub id: String,
+	#[serde(default)]
+	pub rated: bool,
+	#[serde(default = "default_variant")]
+	pub variant: String,
+	#[serde(default)]
+	pub speed: String,
+	#[serde(default)]
+	pub created_at: u64, // milliseconds since the Unix epoch
+	pub status: String, // mate, resign, outoftime, draw, started...
+	pub players: LichessPlayers,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub winner: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub opening: Option<LichessOpening>,
+	#[serde(default)]
+	pub moves: String, // SAN, separated by spaces
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub clocks: Vec<u32>, // remaining time after each move, in centiseconds
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub clock: Option<LichessClock>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub initial_fen: Option<String>,
+}
+fn default_variant() -> String {
+	"standard".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LichessPlayers {
+	pub white: LichessPlayer,
+	pub black: LichessPlayer,
+}
+impl LichessPlayers {
+	pub fn get(&self, color: Color) -> &LichessPlayer {
+		match color {
+			Color::White => &self.white,
+			Color::Black => &self.black,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LichessPlayer {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub user: Option<LichessUser>, // None for anonymous players and the lichess AI
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rating: Option<i32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rating_diff: Option<i32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ai_level: Option<u32>,
+}
+impl LichessPlayer {
+	pub fn name(&self) -> String {
+		match (&self.user, self.ai_level) {
+			(Some(user), _) => user.name.clone(),
+			(None, Some(level)) => format!("lichess AI level {}", level),
+			(None, None) => "Anonymous".to_owned(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LichessUser {
+	pub name: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LichessOpening {
+	pub eco: String,
+	pub name: String,
+	pub ply: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct LichessClock {
+	pub initial: u32, // seconds
+	pub increment: u32,
+}
+
+// remaining time as in the [%clk] commands of lichess PGN exports
+fn format_clock(centis: u32) -> String {
+	let secs = centis / 100;
+	format!("[%clk {}:{:02}:{:02}]", secs / 3600, secs / 60 % 60, secs % 60)
+}
+fn parse_clock(comment: &str) -> Option<u32> {
+	let start = comment.find("[%clk ")? + 6;
+	let end = start + comment[start..].find(']')?;
+	let mut centis = 0.0;
+	for part in comment[start..end].trim().split(':') {
+		centis = centis * 60.0 + part.parse::<f64>().ok()?;
+	}
+	Some((centis * 100.0).round() as u32)
+}
+
+fn termination(status: &str) -> &'static str {
+	match status {
+		"created" | "started" => "Unterminated",
+		"aborted" | "noStart" => "Abandoned",
+		"outoftime" | "timeout" => "Time forfeit",
+		"cheat" => "Rules infraction",
+		_ => "Normal",
+	}
+}
+
+impl LichessGame {
+	pub fn parse(json: &str) -> Result<LichessGame, String> {
+		serde_json::from_str(json).map_err(|err| format!("invalid lichess game: {}", err))
+	}
+	// several games, one per line, as in bulk exports
+	pub fn parse_ndjson(ndjson: &str) -> Result<Vec<LichessGame>, String> {
+		ndjson.lines().enumerate().filter(|(_, line)| !line.trim().is_empty())
+			.map(|(i, line)| LichessGame::parse(line).map_err(|err| format!("line {}: {}", i + 1, err)))
+			.collect()
+	}
+	pub fn to_json(&self) -> String {
+		serde_json::to_string(self).unwrap()
+	}
+
+	pub fn result(&self) -> PgnResult {
+		match self.winner.as_deref() {
+			Some("white") => PgnResult::Win(Color::White),
+			Some("black") => PgnResult::Win(Color::Black),
+			_ if ["created", "started", "aborted", "noStart"].contains(&self.status.as_str()) => PgnResult::Unfinished,
+			_ => PgnResult::Draw,
+		}
+	}
+
+	pub fn to_pgn(&self) -> Result<Pgn, String> {
+		let start = match (self.variant.as_str(), &self.initial_fen) {
+			("standard", _) | ("fromPosition", None) => Position::from_fen(Position::FEN_INITIAL).unwrap(),
+			("fromPosition", Some(fen)) => Position::from_fen(fen).map_err(|err| format!("invalid initial FEN {}: {}", fen, err))?,
+			(variant, _) => return Err(format!("unsupported variant: {}", variant)),
+		};
+		let date = SystemTime::UNIX_EPOCH + Duration::from_millis(self.created_at);
+		let mut pgn = Pgn::new(&start)
+			.tag("Event", format!("{} {} game", if self.rated { "Rated" } else { "Casual" }, self.speed))
+			.tag("Site", format!("https://lichess.org/{}", self.id))
+			.tag("Date", format_date(date))
+			.tag("White", self.players.white.name())
+			.tag("Black", self.players.black.name());
+		for (color, player) in [("White", &self.players.white), ("Black", &self.players.black)] {
+			if let Some(rating) = player.rating {
+				pgn.set_tag(&format!("{}Elo", color), rating);
+			}
+			if let Some(diff) = player.rating_diff {
+				pgn.set_tag(&format!("{}RatingDiff", color), format!("{:+}", diff));
+			}
+			if let Some(title) = player.user.as_ref().and_then(|user| user.title.as_ref()) {
+				pgn.set_tag(&format!("{}Title", color), title);
+			}
+		}
+		if let Some(clock) = self.clock {
+			pgn.set_tag("TimeControl", format!("{}+{}", clock.initial, clock.increment));
+		}
+		if let Some(opening) = &self.opening {
+			pgn.set_tag("ECO", &opening.eco);
+			pgn.set_tag("Opening", &opening.name);
+		}
+		pgn.set_tag("Termination", termination(&self.status));
+
+		let mut pos = start;
+		for (i, san) in self.moves.split_ascii_whitespace().enumerate() {
+			let legal_moves = pos.gen_legal();
+			let mov = *Move::parse_algebraic(san, &legal_moves)
+				.map_err(|err| format!("move {}: {} is {}", i + 1, san, err))?;
+			pos.apply_move(&mov);
+			pgn.push(mov);
+			if let Some(centis) = self.clocks.get(i) {
+				pgn.annotation_mut(i).comment = Some(format_clock(*centis));
+			}
+		}
+		pgn.set_result(self.result());
+		Ok(pgn)
+	}
+
+	// the lichess id is taken from the Site tag if it is a lichess URL
+	pub fn from_pgn(pgn: &Pgn) -> LichessGame {
+		let tag = |key: &str| pgn.get_tag(key).filter(|v| !v.is_empty() && *v != "?");
+		let player = |color: &str| LichessPlayer {
+			user: tag(color).map(|name| LichessUser {
+				name: name.to_owned(),
+				title: tag(&format!("{}Title", color)).map(|t| t.to_owned()),
+			}),
+			rating: tag(&format!("{}Elo", color)).and_then(|r| r.parse().ok()),
+			rating_diff: tag(&format!("{}RatingDiff", color)).and_then(|r| r.trim_start_matches('+').parse().ok()),
+			ai_level: None,
+		};
+		let clock = tag("TimeControl").and_then(|tc| {
+			let (initial, increment) = tc.split_once('+')?;
+			Some(LichessClock { initial: initial.parse().ok()?, increment: increment.parse().ok()? })
+		});
+		let opening = tag("ECO").zip(tag("Opening")).map(|(eco, name)| LichessOpening {
+			eco: eco.to_owned(),
+			name: name.to_owned(),
+			ply: 0,
+		});
+
+		let mut pos = pgn.start_position().clone();
+		let mut moves = vec![];
+		let mut clocks = vec![];
+		for (i, mov) in pgn.moves().iter().enumerate() {
+			moves.push(pos.algebraic_notation(mov));
+			pos.apply_move(mov);
+			clocks.push(pgn.annotation(i).comment.as_deref().and_then(parse_clock));
+		}
+		let status = match (pgn.result(), pos.game_result()) {
+			(PgnResult::Unfinished, _) => "started",
+			(_, Some(GameResult::Checkmate(_))) => "mate",
+			(_, Some(GameResult::Draw)) if pos.gen_legal().is_empty() => "stalemate",
+			(_, Some(GameResult::Draw)) => "draw", // 75-move rule
+			(_, None) if tag("Termination") == Some("Time forfeit") => "outoftime",
+			(PgnResult::Win(_), None) => "resign",
+			(PgnResult::Draw, None) => "draw",
+		};
+		let fen = pgn.start_position().to_fen();
+		LichessGame {
+			id: tag("Site").and_then(|site| site.strip_prefix("https://lichess.org/"))
+				.map_or(String::new(), |id| id.chars().take(8).collect()),
+			rated: tag("Event").is_some_and(|event| event.starts_with("Rated")),
+			variant: if fen == Position::FEN_INITIAL { "standard" } else { "fromPosition" }.to_owned(),
+			speed: tag("Event").and_then(|event| event.split_whitespace().nth(1)).unwrap_or("").to_owned(),
+			created_at: 0,
+			status: status.to_owned(),
+			players: LichessPlayers { white: player("White"), black: player("Black") },
+			winner: match pgn.result() {
+				PgnResult::Win(Color::White) => Some("white".to_owned()),
+				PgnResult::Win(Color::Black) => Some("black".to_owned()),
+				_ => None,
+			},
+			opening,
+			moves: moves.join(" "),
+			// only kept if every move has one
+			clocks: clocks.into_iter().collect::<Option<Vec<_>>>().unwrap_or_default(),
+			clock,
+			initial_fen: if fen == Position::FEN_INITIAL { None } else { Some(fen) },
+		}
+	}
+}
+
+#[cfg(test)]
+mod test_lichess {
+	use crate::{pgn::{Pgn, PgnResult}, state::Color};
+
+	use super::{format_clock, parse_clock, LichessGame};
+
+	const GAME: &str = r#"{"id":"q7ZvsdUF","rated":true,"variant":"standard","speed":"blitz","perf":"blitz","createdAt":1514505150384,"lastMoveAt":1514505592843,"status":"mate","players":{"white":{"user":{"name":"Lance5500","title":"LM","id":"lance5500"},"rating":2389,"ratingDiff":4},"black":{"user":{"name":"TryingHard87","id":"tryinghard87"},"rating":2498,"ratingDiff":-4}},"winner":"black","opening":{"eco":"C20","name":"King's Pawn Game","ply":2},"moves":"f3 e5 g4 Qh4#","clocks":[18003,18003,17915,17627],"clock":{"initial":180,"increment":0,"totalTime":180}}"#;
+
+	#[test]
+	fn test_clock() {
+		assert_eq!(format_clock(18003), "[%clk 0:03:00]");
+		assert_eq!(format_clock(372000), "[%clk 1:02:00]");
+		assert_eq!(parse_clock("[%clk 0:02:55]"), Some(17500));
+		assert_eq!(parse_clock("good move [%clk 1:00:00.5] "), Some(360050));
+		assert_eq!(parse_clock("no clock"), None);
+	}
+
+	#[test]
+	fn test_to_pgn() {
+		let game = LichessGame::parse(GAME).unwrap();
+		let pgn = game.to_pgn().unwrap();
+		assert_eq!(pgn.result(), PgnResult::Win(Color::Black));
+		assert_eq!(pgn.get_tag("Event"), Some("Rated blitz game"));
+		assert_eq!(pgn.get_tag("Site"), Some("https://lichess.org/q7ZvsdUF"));
+		assert_eq!(pgn.get_tag("Date"), Some("2017.12.28"));
+		assert_eq!(pgn.get_tag("WhiteTitle"), Some("LM"));
+		assert_eq!(pgn.get_tag("BlackRatingDiff"), Some("-4"));
+		assert_eq!(pgn.get_tag("TimeControl"), Some("180+0"));
+		assert_eq!(pgn.get_tag("Opening"), Some("King's Pawn Game"));
+		assert_eq!(pgn.moves().len(), 4);
+		assert_eq!(pgn.annotation(3).comment.as_deref(), Some("[%clk 0:02:56]"));
+		assert!(pgn.to_string().contains("1. f3 {[%clk 0:03:00]} 1... e5 {[%clk 0:03:00]}"));
+	}
+
+	#[test]
+	fn test_round_trip() {
+		let game = LichessGame::parse(GAME).unwrap();
+		let pgn = Pgn::parse(&game.to_pgn().unwrap().to_string()).ok().unwrap();
+		let game2 = LichessGame::from_pgn(&pgn);
+		assert_eq!(game2.id, "q7ZvsdUF");
+		assert_eq!(game2.moves, game.moves);
+		assert_eq!(game2.status, "mate");
+		assert_eq!(game2.winner.as_deref(), Some("black"));
+		assert_eq!(game2.clocks, [18000, 18000, 17900, 17600]);
+		assert_eq!(game2.players.white.user.as_ref().unwrap().title.as_deref(), Some("LM"));
+		assert_eq!(game2.players.black.rating_diff, Some(-4));
+		let pgn2 = LichessGame::parse(&game2.to_json()).unwrap().to_pgn().unwrap();
+		assert_eq!(pgn2.moves(), pgn.moves());
+		assert_eq!(pgn2.annotation(3), pgn.annotation(3));
+		assert_eq!(pgn2.get_tag("BlackElo"), Some("2498"));
+
+		let games = LichessGame::parse_ndjson(&format!("{}\n\n{}\n", GAME, game2.to_json())).unwrap();
+		assert_eq!(games.len(), 2);
+		assert!(LichessGame::parse_ndjson("{}").unwrap_err().starts_with("line 1:"));
+	}
+}
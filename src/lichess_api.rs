@@ -0,0 +1,760 @@
+use std::{
+	collections::HashMap,
+	fmt::{Display, Write as _},
+	marker::PhantomData,
+	sync::{atomic::{AtomicU32, Ordering}, Mutex},
+	time::{Duration, Instant}
+};
+
+use log::{debug, info, warn};
+use reqwest::{Client, Method, Response, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::time::sleep;
+
+use crate::{ai::MATE, lichess::LichessGame, state::Color};
+
+// Client for the lichess API, with typed wrappers for the endpoints used by
+// bot accounts. Requests go through a rate limiter and a circuit breaker, and
+// are retried on server errors when marked persistent.
+
+// log target of the records about a game, which the bot also writes to the game's log
+pub fn game_target(game_id: &str) -> String {
+	format!("game/{}", game_id)
+}
+
+pub struct LichessReq {
+	method: Method,
+	url: Url,
+	body: Option<Vec<(String, String)>>,
+	persistent: bool, // keep retrying through server outages
+	game_id: Option<String>, // to log the latency in the game's log
+	accept: Option<&'static str>,
+	optional: bool, // a 404 is an answer rather than an error
+}
+impl LichessReq {
+	pub fn persistent(mut self) -> Self {
+		self.persistent = true;
+		self
+	}
+	pub fn game(mut self, game_id: &str) -> Self {
+		self.game_id = Some(game_id.to_owned());
+		self
+	}
+	pub fn optional(mut self) -> Self {
+		self.optional = true;
+		self
+	}
+	pub fn accept(mut self, mime: &'static str) -> Self {
+		self.accept = Some(mime);
+		self
+	}
+	pub fn path(mut self, part: impl Display) -> Self {
+		self.url.path_segments_mut().unwrap().push(&format!("{}", part));
+		self
+	}
+	pub fn query(mut self, key: &'static str, value: impl Display) -> Self {
+		self.url.query_pairs_mut().append_pair(key, &format!("{}", value));
+		self
+	}
+	pub fn body(mut self, key: &'static str, value: impl Display) -> Self {
+		self.body.get_or_insert_with(Vec::new).push((key.to_owned(), format!("{}", value)));
+		self
+	}
+}
+
+// lichess sends an empty line every few seconds on idle streams
+const STREAM_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// takes the next non-blank line out of the buffer, without its line ending
+fn take_line(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+	while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+		let mut line: Vec<u8> = buf.drain(0..(i+1)).collect();
+		line.pop();
+		if line.last() == Some(&b'\r') {
+			line.pop();
+		}
+		if !line.iter().all(|b| b.is_ascii_whitespace()) {
+			return Some(line);
+		}
+	}
+	None
+}
+
+// newline-delimited JSON stream
+pub struct JsonStream<Res: DeserializeOwned> {
+	res: Option<Response>, // None once closed
+	buf: Vec<u8>,
+	_marker: PhantomData<Res>,
+}
+impl<Res: DeserializeOwned> JsonStream<Res> {
+	fn new(res: Response) -> Self {
+		JsonStream { res: Some(res), buf: vec![], _marker: PhantomData }
+	}
+	fn parse(line: &[u8]) -> Result<Res, String> {
+		serde_json::from_slice(line)
+			.map_err(|e| format!("failed to deserialize ndjson: {}\n{}", e, String::from_utf8_lossy(line)))
+	}
+	// returns None once the stream is closed; read errors and stalls close the stream
+	pub async fn read(&mut self) -> Option<Result<Res, String>> {
+		loop {
+			if let Some(line) = take_line(&mut self.buf) {
+				return Some(Self::parse(&line));
+			}
+			let res = self.res.as_mut()?;
+			match tokio::time::timeout(STREAM_STALL_TIMEOUT, res.chunk()).await {
+				Ok(Ok(Some(chunk))) => self.buf.extend_from_slice(&chunk),
+				Ok(Ok(None)) => {
+					self.close();
+					// a last message may lack its line ending
+					let rest = std::mem::take(&mut self.buf);
+					if rest.iter().all(|b| b.is_ascii_whitespace()) {
+						return None;
+					}
+					return Some(Self::parse(rest.trim_ascii()));
+				},
+				Ok(Err(e)) => {
+					self.close();
+					return Some(Err(format!("failed to read from response: {}", e)));
+				},
+				Err(_) => {
+					self.close();
+					return Some(Err(format!("stream stalled: no data for {} s", STREAM_STALL_TIMEOUT.as_secs())));
+				},
+			}
+		}
+	}
+	pub async fn read_timeout(&mut self, dur: Duration) -> Option<Result<Res, String>> {
+		tokio::time::timeout(dur, self.read()).await.ok().flatten()
+	}
+	// drops the connection; buffered messages can still be read
+	pub fn close(&mut self) {
+		self.res = None;
+	}
+}
+
+// Counts consecutive server failures (5xx responses, including maintenance,
+// and network errors). Once enough have piled up, the circuit is open:
+// matchmaking stops and only in-progress games are looked after until
+// lichess answers again.
+pub struct CircuitBreaker {
+	failures: AtomicU32,
+}
+impl CircuitBreaker {
+	const THRESHOLD: u32 = 3;
+	const MAX_DELAY: Duration = Duration::from_secs(600);
+
+	fn new() -> Self {
+		CircuitBreaker { failures: AtomicU32::new(0) }
+	}
+	pub fn is_open(&self) -> bool {
+		self.failures.load(Ordering::Relaxed) >= Self::THRESHOLD
+	}
+	pub fn is_failing(&self) -> bool {
+		self.failures.load(Ordering::Relaxed) > 0
+	}
+	// how long to wait before trying again
+	pub fn delay(&self) -> Duration {
+		let failures = self.failures.load(Ordering::Relaxed);
+		if failures < Self::THRESHOLD {
+			Duration::from_secs(5 * failures as u64)
+		} else {
+			let exp = (failures - Self::THRESHOLD).min(5);
+			(Duration::from_secs(30) * (1 << exp)).min(Self::MAX_DELAY)
+		}
+	}
+	fn record_failure(&self, msg: &str) {
+		if self.failures.fetch_add(1, Ordering::Relaxed) + 1 == Self::THRESHOLD {
+			warn!("lichess seems to be down ({}), pausing matchmaking", msg);
+		}
+	}
+	fn record_success(&self) {
+		if self.is_open() {
+			info!("lichess is reachable again, resuming");
+		}
+		self.failures.store(0, Ordering::Relaxed);
+	}
+}
+
+// Token bucket shared by all requests: bursts of up to CAPACITY requests, then
+// RATE per second on average, so that lichess rarely has to answer 429. After
+// a 429, no request is sent until the Retry-After delay has passed.
+pub struct RateLimiter {
+	state: Mutex<RateState>,
+}
+struct RateState {
+	tokens: f64, // negative when requests are waiting for their turn
+	refilled_at: Instant,
+	blocked_until: Instant,
+	requests: u64,
+	delayed: u64,
+	total_delay: Duration,
+	rate_limited: u64, // 429 responses
+}
+impl RateLimiter {
+	const CAPACITY: f64 = 10.0;
+	const RATE: f64 = 2.0;
+
+	fn new() -> Self {
+		let now = Instant::now();
+		RateLimiter { state: Mutex::new(RateState {
+			tokens: Self::CAPACITY, refilled_at: now, blocked_until: now,
+			requests: 0, delayed: 0, total_delay: Duration::ZERO, rate_limited: 0,
+		}) }
+	}
+	// takes a token, returning how long to wait before the request can be sent
+	fn reserve(&self, now: Instant) -> Duration {
+		let mut state = self.state.lock().unwrap();
+		let elapsed = now.saturating_duration_since(state.refilled_at).as_secs_f64();
+		state.tokens = (state.tokens + elapsed * Self::RATE).min(Self::CAPACITY) - 1.0;
+		state.refilled_at = now;
+		let delay = Duration::from_secs_f64((-state.tokens).max(0.0) / Self::RATE)
+			.max(state.blocked_until.saturating_duration_since(now));
+		state.requests += 1;
+		if !delay.is_zero() {
+			state.delayed += 1;
+			state.total_delay += delay;
+		}
+		delay
+	}
+	async fn acquire(&self) {
+		let delay = self.reserve(Instant::now());
+		if !delay.is_zero() {
+			sleep(delay).await;
+		}
+	}
+	fn block(&self, delay: Duration) {
+		let mut state = self.state.lock().unwrap();
+		state.blocked_until = state.blocked_until.max(Instant::now() + delay);
+		state.rate_limited += 1;
+	}
+	pub fn stats(&self) -> String {
+		let state = self.state.lock().unwrap();
+		format!("{} requests, {} delayed ({} ms in total), {} rate limited",
+			state.requests, state.delayed, state.total_delay.as_millis(), state.rate_limited)
+	}
+}
+
+// the Retry-After header of a 429 response, in seconds; lichess asks for a
+// minute when it does not say
+pub(crate) fn retry_after(header: Option<&str>) -> Duration {
+	Duration::from_secs(header.and_then(|secs| secs.trim().parse().ok()).unwrap_or(60))
+}
+
+pub struct LichessClient {
+	token: String,
+	client: Client,
+	base: Url,
+	pub breaker: CircuitBreaker,
+	pub limiter: RateLimiter,
+}
+impl LichessClient {
+	pub fn new(token: &str) -> Self {
+		Self::with_base_url(token, "https://lichess.org/").expect("invalid base URL")
+	}
+	// without a token, for the public endpoints
+	pub fn anonymous() -> Self {
+		Self::new("")
+	}
+	// for tests against a mock server
+	pub fn with_base_url(token: &str, base: &str) -> Result<Self, String> {
+		let mut base = Url::parse(base).map_err(|e| format!("invalid base URL: {}", e))?;
+		if base.cannot_be_a_base() {
+			return Err(format!("invalid base URL: {}", base));
+		}
+		base.path_segments_mut().unwrap().pop_if_empty();
+		Ok(LichessClient {
+			token: token.to_owned(),
+			client: Client::new(),
+			base,
+			breaker: CircuitBreaker::new(),
+			limiter: RateLimiter::new(),
+		})
+	}
+
+	// a request to an /api endpoint, e.g. api(Method::GET, "bot/online")
+	pub fn api(&self, method: Method, path: &str) -> LichessReq {
+		self.site(method, &format!("api/{}", path))
+	}
+	// for the few endpoints outside of /api
+	pub fn site(&self, method: Method, path: &str) -> LichessReq {
+		let mut url = self.base.clone();
+		url.path_segments_mut().unwrap().extend(path.split('/'));
+		LichessReq { method, url, body: None, persistent: false, game_id: None, accept: None, optional: false }
+	}
+	pub fn get(&self, path: &str) -> LichessReq {
+		self.api(Method::GET, path)
+	}
+	pub fn post(&self, path: &str) -> LichessReq {
+		self.api(Method::POST, path)
+	}
+
+	pub async fn request(&self, req: LichessReq) -> Result<Response, String> {
+		let target = req.game_id.as_deref().map_or("api".to_owned(), game_target);
+		let mut attempts = 0;
+		loop {
+			self.limiter.acquire().await;
+			let t0 = Instant::now();
+			let mut b = self.client.request(req.method.clone(), req.url.clone());
+			if !self.token.is_empty() {
+				b = b.bearer_auth(&self.token);
+			}
+			if let Some(body) = &req.body {
+				b = b.form(body);
+			}
+			if let Some(mime) = req.accept {
+				b = b.header(reqwest::header::ACCEPT, mime);
+			}
+			let res = b.send().await.map_err(|e| format!("failed to send request: {}", e));
+			debug!(target: &target, "{} {} -> {} in {} ms", req.method, req.url.path(),
+				res.as_ref().map_or("error".to_owned(), |res| res.status().as_u16().to_string()),
+				t0.elapsed().as_millis());
+			let server_error = match &res {
+				Ok(res) if res.status().is_server_error() => Some(format!("HTTP {}", res.status().as_u16())),
+				Ok(_) => None,
+				Err(err) => Some(err.clone()),
+			};
+			if let Some(msg) = server_error {
+				self.breaker.record_failure(&msg);
+				attempts += 1;
+				if req.persistent && attempts < 5 {
+					let delay = self.breaker.delay().min(Duration::from_secs(10));
+					warn!(target: &target, "{}, retrying in {} s", msg, delay.as_secs());
+					sleep(delay).await;
+					continue
+				}
+			} else {
+				self.breaker.record_success();
+			}
+			let res = res?;
+			let status = res.status();
+			if status.as_u16() == 429 {
+				let delay = retry_after(res.headers().get(reqwest::header::RETRY_AFTER).and_then(|value| value.to_str().ok()));
+				self.limiter.block(delay);
+				warn!(target: &target, "received Too Many Requests, waiting {} s ({})", delay.as_secs(), self.limiter.stats());
+				continue
+			} else if !(status.is_success() || (req.optional && status.as_u16() == 404)) {
+				let mut msg = format!("HTTP {}", status.as_u16());
+				if let Some(reason) = status.canonical_reason() {
+					write!(msg, " {}", reason).unwrap();
+				}
+				#[derive(Deserialize)]
+				struct ErrorData {
+					error: String,
+				}
+				if let Ok(data) = res.json::<ErrorData>().await {
+					write!(msg, ": {}", data.error).unwrap();
+				}
+				return Err(msg);
+			}
+			return Ok(res);
+		}
+	}
+
+	pub async fn json<Res: DeserializeOwned>(&self, req: LichessReq) -> Result<Res, String> {
+		let res = self.request(req).await?;
+		res.json::<Res>().await.map_err(|e| format!("unexpected response: {}", e))
+	}
+
+	pub async fn stream_json<Res: DeserializeOwned>(&self, req: LichessReq) -> Result<JsonStream<Res>, String> {
+		Ok(JsonStream::new(self.request(req).await?))
+	}
+
+	pub async fn action(&self, req: LichessReq) -> Result<(), String> {
+		#[derive(Deserialize, Debug)]
+		struct OkRes { ok: bool }
+		let data: OkRes = self.json(req).await?;
+		if !data.ok {
+			return Err(format!("unexpected ok=false in 200 response"));
+		}
+		Ok(())
+	}
+}
+
+
+impl LichessClient {
+	pub async fn account(&self) -> Result<Account, String> {
+		self.json(self.get("account")).await
+	}
+	// with the scopes of the token, from the X-OAuth-Scopes header, if lichess sent it
+	pub async fn account_with_scopes(&self) -> Result<(Account, Option<String>), String> {
+		let res = self.request(self.get("account")).await?;
+		let scopes = res.headers().get("x-oauth-scopes").and_then(|scopes| scopes.to_str().ok()).map(str::to_owned);
+		let account = res.json().await.map_err(|e| format!("unexpected response: {}", e))?;
+		Ok((account, scopes))
+	}
+	// irreversible, and only possible for accounts that have not played any games
+	pub async fn upgrade_to_bot(&self) -> Result<(), String> {
+		self.action(self.post("bot/account/upgrade")).await
+	}
+	pub async fn playing(&self, max_games: usize) -> Result<Vec<PlayingGame>, String> {
+		#[derive(Deserialize, Debug)]
+		#[serde(rename_all = "camelCase")]
+		struct PlayingData {
+			now_playing: Vec<PlayingGame>,
+		}
+		let playing: PlayingData = self.json(self.get("account/playing").query("nb", max_games).persistent()).await?;
+		Ok(playing.now_playing)
+	}
+	pub async fn online_bots(&self) -> Result<JsonStream<Account>, String> {
+		self.stream_json(self.get("bot/online")).await
+	}
+	pub async fn event_stream(&self) -> Result<JsonStream<Event>, String> {
+		self.stream_json(self.get("stream/event")).await
+	}
+
+	pub async fn incoming_challenges(&self) -> Result<Vec<Challenge>, String> {
+		#[derive(Deserialize, Debug)]
+		struct Challenges {
+			r#in: Vec<Challenge>,
+		}
+		let challenges: Challenges = self.json(self.get("challenge")).await?;
+		Ok(challenges.r#in)
+	}
+	pub async fn accept_challenge(&self, id: &str) -> Result<(), String> {
+		self.action(self.post("challenge").path(id).path("accept")).await
+	}
+	// `reason` is one of the lichess decline reasons: later, timeControl, casual, variant...
+	pub async fn decline_challenge(&self, id: &str, reason: &str) -> Result<(), String> {
+		self.action(self.post("challenge").path(id).path("decline").body("reason", reason)).await
+	}
+	// the stream first gives the challenge, then its outcome; closing it early cancels the challenge
+	pub async fn challenge(&self, username: &str, rated: bool, clock_limit: i64, clock_increment: i64) -> Result<JsonStream<ChallengeEvent>, String> {
+		self.stream_json(self.post("challenge").path(username)
+			.body("rated", rated)
+			.body("clock.limit", clock_limit)
+			.body("clock.increment", clock_increment)
+			.body("color", "random")
+			.body("keepAliveStream", true)
+		).await
+	}
+
+	// starts with the full game, then gives its states; `persistent` retries through server outages
+	pub async fn game_stream(&self, game_id: &str, persistent: bool) -> Result<JsonStream<GameEvent>, String> {
+		let mut req = self.get("bot/game/stream").path(game_id).game(game_id);
+		if persistent {
+			req = req.persistent();
+		}
+		self.stream_json(req).await
+	}
+	fn game_action(&self, game_id: &str, action: &str) -> LichessReq {
+		self.post("bot/game").path(game_id).path(action).game(game_id)
+	}
+	pub async fn make_move(&self, game_id: &str, uci: &str, offering_draw: bool) -> Result<(), String> {
+		let mut req = self.game_action(game_id, "move").path(uci).persistent();
+		if offering_draw {
+			req = req.query("offeringDraw", true);
+		}
+		self.action(req).await
+	}
+	pub async fn resign(&self, game_id: &str) -> Result<(), String> {
+		self.action(self.game_action(game_id, "resign").persistent()).await
+	}
+	pub async fn abort(&self, game_id: &str) -> Result<(), String> {
+		self.action(self.game_action(game_id, "abort").persistent()).await
+	}
+	// in arenas, before the first move
+	pub async fn berserk(&self, game_id: &str) -> Result<(), String> {
+		self.action(self.game_action(game_id, "berserk")).await
+	}
+	pub async fn answer_draw(&self, game_id: &str, accept: bool) -> Result<(), String> {
+		self.action(self.game_action(game_id, "draw").path(if accept { "yes" } else { "no" }).persistent()).await
+	}
+	pub async fn answer_takeback(&self, game_id: &str, accept: bool) -> Result<(), String> {
+		self.action(self.game_action(game_id, "takeback").path(if accept { "yes" } else { "no" }).persistent()).await
+	}
+	// `room` is "player" or "spectator"
+	pub async fn chat(&self, game_id: &str, room: &str, text: &str) -> Result<(), String> {
+		self.action(self.game_action(game_id, "chat").body("room", room).body("text", text)).await
+	}
+	pub async fn export_game(&self, game_id: &str) -> Result<LichessGame, String> {
+		self.json(self.site(Method::GET, "game/export").path(game_id)
+			.accept("application/json").game(game_id).persistent()).await
+	}
+
+	// arenas are under tournament/, swiss tournaments under swiss/
+	fn tournament_path(swiss: bool) -> &'static str {
+		if swiss { "swiss" } else { "tournament" }
+	}
+	pub async fn join_tournament(&self, swiss: bool, id: &str) -> Result<(), String> {
+		self.action(self.post(Self::tournament_path(swiss)).path(id).path("join")).await
+	}
+	pub async fn tournament_finished(&self, swiss: bool, id: &str) -> Result<bool, String> {
+		#[derive(Deserialize, Debug)]
+		#[serde(rename_all = "camelCase")]
+		struct TournamentInfo {
+			#[serde(default)]
+			is_finished: bool, // arenas
+			status: Option<String>, // swiss tournaments
+		}
+		let info: TournamentInfo = self.json(self.get(Self::tournament_path(swiss)).path(id)).await?;
+		Ok(info.is_finished || info.status.as_deref() == Some("finished"))
+	}
+
+	// the evaluation of a position by the lichess cloud, which only has the positions
+	// that someone analyzed deeply enough
+	pub async fn cloud_eval(&self, fen: &str, multi_pv: u32) -> Result<Option<CloudEval>, String> {
+		let res = self.request(self.get("cloud-eval").query("fen", fen).query("multiPv", multi_pv).optional()).await?;
+		if res.status().as_u16() == 404 {
+			return Ok(None);
+		}
+		res.json().await.map(Some).map_err(|e| format!("unexpected response: {}", e))
+	}
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CloudEval {
+	pub depth: u32,
+	pub knodes: u64,
+	pub pvs: Vec<CloudPv>, // best line first
+}
+#[derive(Deserialize, Debug, Clone)]
+pub struct CloudPv {
+	pub moves: String, // in UCI notation, separated by spaces
+	pub cp: Option<i32>, // from White's point of view, as the mate
+	pub mate: Option<i32>, // in moves, negative if White gets mated
+}
+impl CloudPv {
+	// as a score of our engine, from White's point of view; a mate in n moves is
+	// 2n - 1 plies away for the side to move and 2n plies for the other side
+	pub fn white_score(&self, side_to_move: Color) -> i16 {
+		let plies = |moves: i32, mating: Color| (moves * 2 - (mating == side_to_move) as i32) as i16;
+		match self.mate {
+			Some(moves) if moves > 0 => MATE - plies(moves, Color::White),
+			Some(moves) => -(MATE - plies(-moves, Color::Black)),
+			None => self.cp.unwrap_or(0).clamp(-(MATE as i32) / 2, MATE as i32 / 2) as i16,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Account {
+	pub id: String,
+	pub username: String,
+	pub title: Option<String>, // "BOT" for bot accounts
+	#[serde(default)]
+	pub perfs: HashMap<String, Perf>,
+}
+#[derive(Deserialize, Debug)]
+pub struct Perf {
+	pub rating: i32,
+	pub rd: i32,
+	#[serde(default)]
+	pub prov: bool, // provisional rating
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayingGame {
+	pub game_id: String,
+	pub speed: String,
+	pub is_my_turn: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Challenge {
+	pub id: String,
+	pub status: String,
+	pub speed: String,
+	#[serde(default)]
+	pub rated: bool,
+	pub variant: Variant,
+	pub challenger: ChallengeUser,
+}
+#[derive(Deserialize, Debug)]
+pub struct ChallengeUser {
+	pub name: String,
+}
+#[derive(Deserialize, Debug)]
+pub struct Variant {
+	pub key: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ChallengeEvent {
+	Challenge {
+		id: String,
+	},
+	Response {
+		done: String, // accepted, declined...
+	},
+}
+
+// from the account's event stream
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+	GameStart,
+	GameFinish {
+		game: GameInfo,
+	},
+	Challenge {
+		challenge: Challenge,
+	},
+	ChallengeCanceled,
+	ChallengeDeclined,
+}
+#[derive(Deserialize, Debug)]
+pub struct GameInfo {
+	pub id: String,
+}
+
+// from a game stream; the full game only comes once per stream, so it is not
+// worth boxing for the smaller events that follow it
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+	GameFull(GameFull),
+	GameState(GameState),
+	ChatLine {
+		username: String,
+		text: String,
+		room: String,
+	},
+	OpponentGone,
+}
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GameFull {
+	pub initial_fen: String,
+	pub variant: Variant,
+	pub speed: String,
+	pub rated: bool,
+	pub state: GameState,
+	pub white: Player,
+	pub black: Player,
+	pub tournament_id: Option<String>, // arenas only
+}
+#[derive(Deserialize, Debug)]
+pub struct GameState {
+	pub moves: String, // UCI, separated by spaces
+	pub status: String,
+	pub winner: Option<String>,
+	pub wtime: u64, // milliseconds
+	pub btime: u64,
+	pub winc: u64,
+	pub binc: u64,
+	#[serde(default)]
+	pub wdraw: bool,
+	#[serde(default)]
+	pub bdraw: bool,
+	#[serde(default)]
+	pub wtakeback: bool,
+	#[serde(default)]
+	pub btakeback: bool,
+}
+impl GameState {
+	pub fn offers_draw(&self, color: Color) -> bool {
+		match color {
+			Color::White => self.wdraw,
+			Color::Black => self.bdraw,
+		}
+	}
+	pub fn proposes_takeback(&self, color: Color) -> bool {
+		match color {
+			Color::White => self.wtakeback,
+			Color::Black => self.btakeback,
+		}
+	}
+	// remaining time and increment
+	pub fn clock(&self, color: Color) -> (Duration, Duration) {
+		let (time, inc) = match color {
+			Color::White => (self.wtime, self.winc),
+			Color::Black => (self.btime, self.binc),
+		};
+		(Duration::from_millis(time), Duration::from_millis(inc))
+	}
+}
+#[derive(Deserialize, Debug)]
+pub struct Player {
+	pub id: Option<String>, // None for anonymous players and the AI
+	pub name: Option<String>,
+	pub rating: Option<i32>,
+	pub title: Option<String>,
+}
+
+#[cfg(test)]
+mod test_lichess_api {
+	use std::time::{Duration, Instant};
+	use super::{retry_after, take_line, CloudPv, Event, LichessClient, RateLimiter};
+	use crate::{ai::mate_in, state::Color, test_util::mock_server};
+
+	#[test]
+	fn test_take_line() {
+		let mut buf = b"\n{\"a\":1}\r\n \r\n\n{\"b\":2}\n{\"c\"".to_vec();
+		assert_eq!(take_line(&mut buf).unwrap(), b"{\"a\":1}");
+		assert_eq!(take_line(&mut buf).unwrap(), b"{\"b\":2}");
+		assert_eq!(take_line(&mut buf), None);
+		assert_eq!(buf, b"{\"c\"");
+	}
+
+	#[test]
+	fn test_rate_limiter() {
+		let limiter = RateLimiter::new();
+		let t0 = Instant::now();
+		// a full bucket lets a burst through, then requests are spaced out
+		for _ in 0..10 {
+			assert_eq!(limiter.reserve(t0), Duration::ZERO);
+		}
+		assert_eq!(limiter.reserve(t0), Duration::from_millis(500));
+		assert_eq!(limiter.reserve(t0), Duration::from_millis(1000));
+		assert_eq!(limiter.reserve(t0 + Duration::from_secs(5)), Duration::ZERO);
+		assert_eq!(limiter.stats(), "13 requests, 2 delayed (1500 ms in total), 0 rate limited");
+
+		assert_eq!(retry_after(Some("120")), Duration::from_secs(120));
+		assert_eq!(retry_after(Some("Wed, 21 Oct 2015 07:28:00 GMT")), Duration::from_secs(60));
+		assert_eq!(retry_after(None), Duration::from_secs(60));
+	}
+
+	#[tokio::test]
+	async fn test_mock_server() {
+		let (base, server) = mock_server(vec![
+			(200, r#"{"id":"bot1","username":"Bot1","title":"BOT","perfs":{"blitz":{"rating":1500,"rd":80}}}"#),
+			(200, r#"{"ok":true}"#),
+			(400, r#"{"error":"Not your turn"}"#),
+			(200, "{\"type\":\"gameStart\",\"game\":{\"gameId\":\"abcd1234\"}}\n\n{\"type\":\"gameFinish\",\"game\":{\"id\":\"abcd1234\"}}\n"),
+		]);
+		let client = LichessClient::with_base_url("secret", &base).unwrap();
+
+		let account = client.account().await.unwrap();
+		assert_eq!((account.username.as_str(), account.perfs["blitz"].rating), ("Bot1", 1500));
+		client.make_move("abcd1234", "e2e4", true).await.unwrap();
+		assert_eq!(client.resign("abcd1234").await.unwrap_err(), "HTTP 400 Bad Request: Not your turn");
+		let mut events = client.event_stream().await.unwrap();
+		assert!(matches!(events.read().await, Some(Ok(Event::GameStart))));
+		assert!(matches!(events.read().await, Some(Ok(Event::GameFinish { game })) if game.id == "abcd1234"));
+		assert!(events.read().await.is_none());
+
+		let requests = server.join().unwrap();
+		assert!(requests[0].starts_with("GET /api/account HTTP/1.1\r\n"));
+		assert!(requests[0].to_lowercase().contains("authorization: bearer secret\r\n"));
+		assert!(requests[1].starts_with("POST /api/bot/game/abcd1234/move/e2e4?offeringDraw=true HTTP/1.1\r\n"));
+		assert!(requests[2].starts_with("POST /api/bot/game/abcd1234/resign HTTP/1.1\r\n"));
+		assert!(requests[3].starts_with("GET /api/stream/event HTTP/1.1\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_cloud_eval() {
+		let (base, server) = mock_server(vec![
+			(200, r#"{"fen":"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1","knodes":119726,"depth":36,"pvs":[{"moves":"c7c5 g1f3 d7d6","cp":26}]}"#),
+			(404, r#"{"error":"No cloud evaluation available for that position"}"#),
+		]);
+		let client = LichessClient::with_base_url("", &base).unwrap();
+		let eval = client.cloud_eval("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1", 1).await.unwrap().unwrap();
+		assert_eq!((eval.depth, eval.pvs.len(), eval.pvs[0].white_score(Color::Black)), (36, 1, 26));
+		assert!(eval.pvs[0].moves.starts_with("c7c5 "));
+		assert!(client.cloud_eval("8/8/8/8/8/8/8/K6k w - - 0 1", 1).await.unwrap().is_none());
+
+		let requests = server.join().unwrap();
+		assert!(requests[0].starts_with("GET /api/cloud-eval?fen=rnbqkbnr%2Fpppppppp%2F8%2F8%2F4P3%2F8%2FPPPP1PPP%2FRNBQKBNR+b+KQkq+-+0+1&multiPv=1 HTTP/1.1\r\n"));
+		assert!(!requests[0].to_lowercase().contains("authorization:"));
+
+		let mate = |mate| CloudPv { moves: String::new(), cp: None, mate: Some(mate) };
+		assert_eq!(mate_in(mate(3).white_score(Color::White)), Some(3));
+		assert_eq!(mate_in(mate(-2).white_score(Color::White)), Some(-2));
+		// with Black to move, the mates are counted from Black's side
+		assert_eq!(mate_in(-mate(3).white_score(Color::Black)), Some(-3));
+		assert_eq!(mate_in(-mate(-2).white_score(Color::Black)), Some(2));
+	}
+}
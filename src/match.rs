@@ -0,0 +1,189 @@
+use std::{fmt, io::Write, path::PathBuf};
+
+use chesslib::{
+	arena::{play_pairs, Opening, Setting, MAX_PLIES},
+	pgn::{Pgn, PgnResult},
+	state::Color,
+	wdl::{Sprt, WdlModel}
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+const USAGE: &str = "usage: match [--games N] [--sprt ELO0,ELO1] [--seed N] [--openings PGN] [--random-plies N] [--nnue WEIGHTS] [-o OUTPUT] ENGINE1 ENGINE2
+Plays games between two engines, alternating colors, and prints the results.
+With --sprt, ENGINE1 is tested against the baseline ENGINE2: games go on until
+the hypothesis that it is ELO1 stronger is accepted over it being ELO0 stronger,
+or the reverse (5% error rates), or --games (default 20000) have been played.
+Engines are dN (fixed depth N), tN (N ms per move, up to depth 32) or random.
+Search parameters can follow a colon, as d4:lmr_reduction=2,futility_margin=150
+(lmr_min_depth, lmr_min_moves, lmr_reduction, futility_max_depth, futility_margin, contempt),
+and eval=nnue evaluates with the network of the --nnue file (in builds with --features nnue).
+Each pair of games starts from the same opening: the moves of the games in the
+--openings file in turn, or --random-plies random moves (default 4) chosen with --seed.
+Games are drawn by the usual rules, and adjudicated as draws after 300 plies.
+Every game is written to OUTPUT (default match.pgn).";
+
+const DEFAULT_GAMES: usize = 10;
+const DEFAULT_SPRT_GAMES: usize = 20000;
+
+// wins, draws and losses of one engine
+#[derive(Default, Clone, Copy)]
+struct Record {
+	wins: usize,
+	draws: usize,
+	losses: usize,
+}
+impl Record {
+	fn add(&mut self, result: PgnResult, color: Color) {
+		match result {
+			PgnResult::Win(winner) if winner == color => self.wins += 1,
+			PgnResult::Win(_) => self.losses += 1,
+			_ => self.draws += 1,
+		}
+	}
+	fn games(&self) -> usize {
+		self.wins + self.draws + self.losses
+	}
+	fn score(&self) -> f64 {
+		(self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+	}
+}
+impl fmt::Display for Record {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:>5} {:>5} {:>5} {:>6} {:>6.1}%", self.games(), self.wins, self.draws, self.losses, self.score() * 100.0)
+	}
+}
+
+fn main() {
+	if let Err(err) = || -> Result<(), String> {
+		let mut games = None;
+		let mut sprt = None;
+		let mut seed = None;
+		let mut openings_path = None;
+		let mut random_plies = 4;
+		let mut output = PathBuf::from("match.pgn");
+		let mut engines = vec![];
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			match arg.as_str() {
+				"--games" => games = Some(value("--games")?.parse().ok().filter(|n| *n > 0)
+					.ok_or_else(|| format!("--games is not a positive integer"))?),
+				"--sprt" => {
+					let bounds = value("--sprt")?;
+					let (elo0, elo1) = bounds.split_once(',')
+						.and_then(|(elo0, elo1)| Some((elo0.parse::<f64>().ok()?, elo1.parse::<f64>().ok()?)))
+						.filter(|(elo0, elo1)| elo0 < elo1)
+						.ok_or_else(|| format!("--sprt expects ELO0,ELO1 with ELO0 < ELO1"))?;
+					sprt = Some(Sprt::new(elo0, elo1));
+				},
+				"--seed" => seed = Some(value("--seed")?.parse::<u64>()
+					.map_err(|_| format!("--seed is not a non-negative integer"))?),
+				"--openings" => openings_path = Some(PathBuf::from(value("--openings")?)),
+				"--random-plies" => random_plies = value("--random-plies")?.parse()
+					.map_err(|_| format!("--random-plies is not a non-negative integer"))?,
+				#[cfg(feature = "nnue")]
+				"--nnue" => chesslib::nnue::set_network(chesslib::nnue::Network::load(&value("--nnue")?)?)?,
+				"-o" => output = PathBuf::from(value("-o")?),
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ => engines.push(Setting::parse(&arg)?),
+			}
+		}
+		let [engine1, engine2] = engines[..] else {
+			return Err(format!("expected two engines\n{}", USAGE));
+		};
+		#[cfg(feature = "nnue")]
+		if chesslib::nnue::network().is_none() && engines.iter().any(|engine|
+				engine.params().is_some_and(|params| params.eval == chesslib::ai::EvalBackend::Nnue)) {
+			return Err(format!("eval=nnue needs a network, given with --nnue"));
+		}
+
+		let games = games.unwrap_or(if sprt.is_some() { DEFAULT_SPRT_GAMES } else { DEFAULT_GAMES });
+
+		// the seed is printed so that a match can be replayed
+		let seed = seed.unwrap_or_else(rand::random);
+		let mut rng = StdRng::seed_from_u64(seed);
+		let pairs = games.div_ceil(2);
+		let openings: Vec<Opening> = if let Some(path) = &openings_path {
+			let book = Pgn::load(path).map_err(|err| err.to_string())?;
+			if book.is_empty() {
+				return Err(format!("no openings found in {}", path.display()));
+			}
+			(0..pairs).map(|i| Opening::from_pgn(&book[i % book.len()])).collect()
+		} else {
+			(0..pairs).map(|_| Opening::random(random_plies, &mut rng)).collect()
+		};
+		if let Some(sprt) = sprt {
+			println!("SPRT {} vs {} [{}, {}], up to {} games, seed {}", engine1, engine2, sprt.elo0, sprt.elo1, games, seed);
+		} else {
+			println!("{} vs {}, {} games, seed {}", engine1, engine2, games, seed);
+		}
+
+		let mut tally = Record::default();
+		let mut decision = None;
+		let pgns = play_pairs(&openings, engine1, engine2, games, seed, |batch| {
+			match sprt {
+				Some(sprt) => {
+					for (game, pgn) in (tally.games()..).zip(batch) {
+						tally.add(pgn.result(), if game % 2 == 0 { Color::White } else { Color::Black });
+					}
+					let llr = sprt.llr(tally.wins, tally.draws, tally.losses);
+					let (lower, upper) = sprt.bounds();
+					println!("{:>6} games  +{} ={} -{}  LLR {:.2} ({:.2}, {:.2})",
+						tally.games(), tally.wins, tally.draws, tally.losses, llr, lower, upper);
+					decision = sprt.decision(llr);
+				},
+				None => {
+					print!(".");
+					std::io::stdout().flush().ok();
+				},
+			}
+			decision.is_none()
+		}).into_iter().map(|pgn| pgn.tag("Event", "Engine match")).collect::<Vec<_>>();
+		println!();
+		if let Some(sprt) = sprt {
+			match decision {
+				Some(true) => println!("H1 accepted: {} is {:+} Elo or more against {}", engine1, sprt.elo1, engine2),
+				Some(false) => println!("H0 accepted: {} is {:+} Elo or less against {}", engine1, sprt.elo0, engine2),
+				None => println!("no decision after {} games", pgns.len()),
+			}
+		}
+
+		// results of the first engine, overall and with each color
+		let mut records = [Record::default(); 3];
+		let mut adjudicated = 0;
+		for (game, pgn) in pgns.iter().enumerate() {
+			let color = if game % 2 == 0 { Color::White } else { Color::Black };
+			records[0].add(pgn.result(), color);
+			records[if color == Color::White { 1 } else { 2 }].add(pgn.result(), color);
+			if pgn.get_tag("Termination") == Some("adjudication") {
+				adjudicated += 1;
+			}
+		}
+		let flipped = Record { wins: records[0].losses, draws: records[0].draws, losses: records[0].wins };
+		let width = [engine1, engine2].iter().map(|engine| engine.to_string().len()).max().unwrap().max(10);
+		println!("\n{:<width$} {:>5} {:>5} {:>5} {:>6} {:>7}", "engine", "games", "wins", "draws", "losses", "score");
+		println!("{:<width$} {}", engine1.to_string(), records[0]);
+		println!("{:<width$} {}", "  as white", records[1]);
+		println!("{:<width$} {}", "  as black", records[2]);
+		println!("{:<width$} {}", engine2.to_string(), flipped);
+		if adjudicated > 0 {
+			println!("{} games adjudicated as draws after {} plies", adjudicated, MAX_PLIES);
+		}
+		// a perfect score is counted as half a game short of it, to keep the difference finite
+		let margin = 0.5 / pgns.len() as f64;
+		let elo = WdlModel::new(400.0).score_from_expected(records[0].score().clamp(margin, 1.0 - margin));
+		println!("{} vs {}: {:+} Elo", engine1, engine2, elo);
+
+		let text = pgns.iter().map(|pgn| pgn.to_string()).collect::<Vec<_>>().join("\n");
+		std::fs::write(&output, text).map_err(|err| format!("could not write {}: {}", output.display(), err))?;
+		println!("wrote {}", output.display());
+		Ok(())
+	}() {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	}
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{game::Position, state::{Move, ParseMoveError}};
+
+// The JSON format of the move generation tests in tests/: each case gives a
+// starting position with every legal move and the FEN it leads to, and
+// optionally tags (castling, ep, promotion, pins...) and perft node counts.
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestFile {
+	pub description: String,
+	#[serde(default)]
+	pub tags: Vec<String>, // apply to every case of the file
+	pub test_cases: Vec<TestCase>,
+}
+
+#[derive(Deserialize)]
+pub struct TestCase {
+	pub start: TestStart,
+	#[serde(default)]
+	pub tags: Vec<String>,
+	#[serde(default)]
+	pub perft: Vec<u64>, // leaf nodes at depth 1, 2...
+	pub expected: Vec<TestMove>,
+}
+
+#[derive(Deserialize)]
+pub struct TestStart {
+	pub description: Option<String>,
+	pub fen: String,
+}
+
+#[derive(Deserialize)]
+pub struct TestMove {
+	pub r#move: String,
+	pub fen: String,
+}
+
+impl TestFile {
+	pub fn parse(json: &str) -> Result<TestFile, String> {
+		serde_json::from_str(json).map_err(|err| format!("invalid test file: {}", err))
+	}
+	pub fn load(path: impl AsRef<Path>) -> Result<TestFile, String> {
+		let path = path.as_ref();
+		let json = std::fs::read_to_string(path)
+			.map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+		TestFile::parse(&json).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+
+	// cases with any of the tags, or all cases if there are none
+	pub fn cases_tagged<'a>(&'a self, tags: &'a [String]) -> impl Iterator<Item=&'a TestCase> {
+		self.test_cases.iter().filter(move |case| case.has_any_tag(self, tags))
+	}
+}
+
+#[derive(Default)]
+pub struct CaseReport {
+	pub failures: Vec<String>,
+	pub moves: usize, // legal moves checked
+	pub perft_nodes: u64,
+}
+
+impl TestCase {
+	// whether the case, or the file it is from, has any of the tags; true if there are none
+	pub fn has_any_tag(&self, file: &TestFile, tags: &[String]) -> bool {
+		tags.is_empty() || self.tags.iter().chain(&file.tags).any(|tag| tags.contains(tag))
+	}
+
+	// checks the generated moves against the expected ones, and the perft
+	// counts up to `max_perft_depth`
+	pub fn run(&self, max_perft_depth: usize) -> CaseReport {
+		let mut report = CaseReport::default();
+		let pos = match Position::from_fen(&self.start.fen) {
+			Ok(pos) => pos,
+			Err(err) => {
+				report.failures.push(format!("invalid FEN {}: {}", self.start.fen, err));
+				return report;
+			},
+		};
+
+		let moves = pos.gen_legal();
+		report.moves = moves.len();
+		for mov in moves.iter() {
+			let mut pos2 = pos.clone();
+			pos2.apply_move(mov);
+			let fen_after = pos2.to_fen();
+			if self.expected.iter().all(|m| m.fen != fen_after) {
+				report.failures.push(format!("our move {} -> FEN {} is unexpected", mov, fen_after));
+			}
+		}
+		for mov in &self.expected {
+			if let Err(err) = Move::parse_algebraic(&mov.r#move, &moves) {
+				let err_desc = match err {
+					ParseMoveError::AmbiguousMove => "ambiguous",
+					ParseMoveError::IllegalMove => "illegal",
+					ParseMoveError::InvalidSyntax => "invalid syntax",
+				};
+				report.failures.push(format!("their move {} -> FEN {} is deemed {}", mov.r#move, mov.fen, err_desc));
+			}
+		}
+
+		for (i, expected) in self.perft.iter().enumerate().take(max_perft_depth) {
+			let nodes = pos.perft(i as u32 + 1);
+			report.perft_nodes += nodes;
+			if nodes != *expected {
+				report.failures.push(format!("perft({}) is {}, expected {}", i + 1, nodes, expected));
+			}
+		}
+		report
+	}
+}
+
+#[cfg(test)]
+mod test_movetest {
+	use super::TestFile;
+
+	#[test]
+	fn test_tags() {
+		let file = TestFile::parse(r#"{
+			"description": "test",
+			"tags": ["pawns"],
+			"testCases": [
+				{ "start": { "fen": "7k/8/8/8/pPp5/8/8/7K b - b3 0 1" }, "tags": ["ep"], "perft": [7], "expected": [] },
+				{ "start": { "fen": "7k/8/8/8/pPp5/8/8/7K b - - 0 1" }, "expected": [] }
+			]
+		}"#).unwrap();
+		let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+		assert_eq!(file.cases_tagged(&[]).count(), 2);
+		assert_eq!(file.cases_tagged(&tags(&["ep"])).count(), 1);
+		assert_eq!(file.cases_tagged(&tags(&["pawns"])).count(), 2);
+		assert_eq!(file.cases_tagged(&tags(&["castling"])).count(), 0);
+
+		// no expected moves: every generated move is a failure, and perft is checked
+		let report = file.test_cases[0].run(1);
+		assert_eq!((report.moves, report.perft_nodes), (7, 7));
+		assert_eq!(report.failures.len(), 7);
+		assert_eq!(file.test_cases[0].run(0).perft_nodes, 0);
+	}
+}
@@ -0,0 +1,202 @@
+use std::sync::OnceLock;
+
+use crate::state::{Board, Color, Piece, PieceType, Square};
+
+// A small NNUE-style network: each side's perspective of the board, as 768 inputs
+// (the color of a piece relative to that side, its type and its square, mirrored
+// for Black), feeds a hidden layer of HIDDEN neurons. The hidden layer of both
+// perspectives, the side to move first, goes through a clipped ReLU into the output.
+// Since a move only changes a few inputs, the hidden layer values (the accumulator)
+// are updated incrementally by Position::apply_move rather than recomputed.
+
+pub const HIDDEN: usize = 128;
+const INPUTS: usize = 768;
+const QA: i32 = 255; // quantization of the hidden layer, whose activations are clipped to [0, QA]
+const QB: i32 = 64; // quantization of the output weights
+const SCALE: i32 = 400; // from the network output to centipawns
+const MAX_SCORE: i32 = 20000; // well below mate scores
+
+pub struct Network {
+	feature_weights: Vec<[i16; HIDDEN]>, // per input
+	feature_biases: [i16; HIDDEN],
+	output_weights: [i16; 2 * HIDDEN], // side to move, then the other side
+	output_bias: i16,
+}
+impl Network {
+	// size of a weights file: the feature weights (input by input), the feature biases,
+	// the output weights and the output bias, as little-endian 16-bit integers
+	pub const FILE_SIZE: usize = (INPUTS * HIDDEN + HIDDEN + 2 * HIDDEN + 1) * 2;
+
+	pub fn load(path: &str) -> Result<Network, String> {
+		let data = std::fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+		Network::from_bytes(&data).map_err(|err| format!("{}: {}", path, err))
+	}
+
+	pub fn from_bytes(data: &[u8]) -> Result<Network, String> {
+		if data.len() != Network::FILE_SIZE {
+			return Err(format!("expected {} bytes of weights (hidden layer of {}), found {}", Network::FILE_SIZE, HIDDEN, data.len()));
+		}
+		let mut values = data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]));
+		let mut next = || values.next().unwrap();
+		let feature_weights = (0..INPUTS).map(|_| std::array::from_fn(|_| next())).collect();
+		let feature_biases = std::array::from_fn(|_| next());
+		let output_weights = std::array::from_fn(|_| next());
+		let output_bias = next();
+		Ok(Network { feature_weights, feature_biases, output_weights, output_bias })
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.feature_weights.iter().flatten().chain(&self.feature_biases).chain(&self.output_weights)
+			.chain([&self.output_bias]).flat_map(|value| value.to_le_bytes()).collect()
+	}
+}
+
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+// the network of the NNUE evaluation, which can only be set once per process
+pub fn set_network(net: Network) -> Result<(), String> {
+	NETWORK.set(net).map_err(|_| format!("an NNUE network is already loaded"))
+}
+pub fn network() -> Option<&'static Network> {
+	NETWORK.get()
+}
+
+fn feature(perspective: Color, piece: Piece, squ: Square) -> usize {
+	let (color, squ) = match perspective {
+		Color::White => (piece.color, squ.index()),
+		Color::Black => (piece.color.opponent(), squ.index() ^ 7), // mirrored ranks
+	};
+	(color as usize * 6 + piece.ptype as usize) * 64 + squ as usize
+}
+
+// hidden layer values before activation, for each perspective
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Accumulator([[i16; HIDDEN]; 2]);
+impl Accumulator {
+	pub fn new(net: &Network, board: &Board) -> Accumulator {
+		let mut acc = Accumulator([net.feature_biases; 2]);
+		for (squ, piece) in board.iter() {
+			acc.add(net, piece, squ);
+		}
+		acc
+	}
+
+	fn add(&mut self, net: &Network, piece: Piece, squ: Square) {
+		for perspective in [Color::White, Color::Black] {
+			let weights = &net.feature_weights[feature(perspective, piece, squ)];
+			for (value, weight) in self.0[perspective as usize].iter_mut().zip(weights) {
+				*value = value.wrapping_add(*weight);
+			}
+		}
+	}
+	fn remove(&mut self, net: &Network, piece: Piece, squ: Square) {
+		for perspective in [Color::White, Color::Black] {
+			let weights = &net.feature_weights[feature(perspective, piece, squ)];
+			for (value, weight) in self.0[perspective as usize].iter_mut().zip(weights) {
+				*value = value.wrapping_sub(*weight);
+			}
+		}
+	}
+
+	// applies the pieces that differ between two boards, typically before and after a move
+	pub fn update(&mut self, net: &Network, before: &Board, after: &Board) {
+		for color in [Color::White, Color::Black] {
+			for ptype in PieceType::all() {
+				let piece = Piece::new(color, ptype);
+				let (old, new) = (before.find_piece(piece), after.find_piece(piece));
+				for squ in (old & !new).iter() {
+					self.remove(net, piece, squ);
+				}
+				for squ in (new & !old).iter() {
+					self.add(net, piece, squ);
+				}
+			}
+		}
+	}
+
+	// score from the perspective of `color`, in centipawns
+	pub fn evaluate(&self, net: &Network, color: Color) -> i16 {
+		let perspectives = [&self.0[color as usize], &self.0[color.opponent() as usize]];
+		let mut sum = 0;
+		for (values, weights) in perspectives.into_iter().zip(net.output_weights.chunks_exact(HIDDEN)) {
+			for (value, weight) in values.iter().zip(weights) {
+				sum += (*value as i32).clamp(0, QA) * *weight as i32;
+			}
+		}
+		let score = (sum + net.output_bias as i32 * QA) * SCALE / (QA * QB);
+		score.clamp(-MAX_SCORE, MAX_SCORE) as i16
+	}
+}
+
+#[cfg(test)]
+mod test_nnue {
+	use std::time::Duration;
+	use super::{network, set_network, Accumulator, Network};
+	use crate::{ai::{EvalBackend, SearchParams, SimpleAi}, game::Position, state::Move};
+
+	// weights from a fixed xorshift sequence, small enough not to overflow
+	fn random_network() -> Network {
+		let mut state = 0x2545f4914f6cdd1du64;
+		let data: Vec<u8> = (0..Network::FILE_SIZE / 2).flat_map(|_| {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			((state % 64) as i16 - 32).to_le_bytes()
+		}).collect();
+		Network::from_bytes(&data).unwrap()
+	}
+
+	#[test]
+	fn test_weights_file() {
+		let net = random_network();
+		let data = net.to_bytes();
+		assert_eq!(data.len(), Network::FILE_SIZE);
+		assert_eq!(Network::from_bytes(&data).unwrap().to_bytes(), data);
+		assert!(Network::from_bytes(&data[1..]).is_err());
+	}
+
+	#[test]
+	fn test_incremental_updates() {
+		let net = random_network();
+		// castling, captures, en passant and promotion
+		let mut pos = Position::from_fen("r3k2r/1P4p1/8/5P2/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+		let mut acc = Accumulator::new(&net, pos.get_board());
+		for uci in ["g7g5", "f5g6", "e8g8", "b7b8q", "a8b8", "e1c1", "b8b1", "c1b1"] {
+			let moves = pos.gen_legal();
+			let mov = *Move::parse_uci(uci, &moves).ok().unwrap();
+			let before = pos.get_board().clone();
+			pos.apply_move(&mov);
+			acc.update(&net, &before, pos.get_board());
+			assert_eq!(acc, Accumulator::new(&net, pos.get_board()), "after {}", uci);
+		}
+	}
+
+	#[test]
+	fn test_search() {
+		// the only test that sets the network of the process
+		set_network(random_network()).unwrap();
+		let net = network().unwrap();
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		assert_eq!(pos.nnue_eval(), None);
+		pos.enable_nnue();
+		for uci in ["e2e4", "d7d5", "e4d5", "d8d5"] {
+			pos.apply_move(Move::parse_uci(uci, &pos.gen_legal()).ok().unwrap());
+			let expected = Accumulator::new(net, pos.get_board()).evaluate(net, pos.side_to_move());
+			assert_eq!(pos.nnue_eval(), Some(expected));
+		}
+		let params = SearchParams { eval: EvalBackend::Nnue, ..SearchParams::default() };
+		let outcome = SimpleAi::new(3).with_params(params).pick_move_timed(&pos, &pos.gen_legal(), Duration::from_secs(60));
+		assert_eq!(outcome.depth, 3);
+	}
+
+	#[test]
+	fn test_symmetry() {
+		// the same position with colors swapped is scored the same for the side to move
+		let net = random_network();
+		let pos = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+		let mirrored = Position::from_fen("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3").unwrap();
+		let score = Accumulator::new(&net, pos.get_board()).evaluate(&net, pos.side_to_move());
+		let mirrored_score = Accumulator::new(&net, mirrored.get_board()).evaluate(&net, mirrored.side_to_move());
+		assert_eq!(score, mirrored_score);
+	}
+}
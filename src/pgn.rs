@@ -0,0 +1,667 @@
+use std::{
+	fmt::{self, Write},
+	path::Path,
+	time::SystemTime
+};
+
+use crate::{
+	game::{DrawReason, Game, GameResult, Position},
+	state::{Color, Move}
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PgnResult {
+	Win(Color),
+	Draw,
+	Unfinished,
+}
+impl PgnResult {
+	pub fn to_pgn(self) -> &'static str {
+		match self {
+			PgnResult::Win(Color::White) => "1-0",
+			PgnResult::Win(Color::Black) => "0-1",
+			PgnResult::Draw => "1/2-1/2",
+			PgnResult::Unfinished => "*",
+		}
+	}
+	pub fn from_pgn(s: &str) -> Option<PgnResult> {
+		match s {
+			"1-0" => Some(PgnResult::Win(Color::White)),
+			"0-1" => Some(PgnResult::Win(Color::Black)),
+			"1/2-1/2" => Some(PgnResult::Draw),
+			"*" => Some(PgnResult::Unfinished),
+			_ => None,
+		}
+	}
+}
+impl From<GameResult> for PgnResult {
+	fn from(res: GameResult) -> Self {
+		match res {
+			GameResult::Checkmate(winner) => PgnResult::Win(winner),
+			GameResult::Draw => PgnResult::Draw,
+		}
+	}
+}
+impl fmt::Display for PgnResult {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.to_pgn())
+	}
+}
+
+// converts days since the Unix epoch to a (year, month, day) civil date
+// algorithm from: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+	let doy = doe - (365*yoe + yoe/4 - yoe/100);
+	let mp = (5*doy + 2) / 153;
+	let day = (doy - (153*mp + 2)/5 + 1) as u32;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+pub fn format_date(time: SystemTime) -> String {
+	match time.duration_since(SystemTime::UNIX_EPOCH) {
+		Ok(dur) => {
+			let (year, month, day) = civil_from_days((dur.as_secs() / 86400) as i64);
+			format!("{:04}.{:02}.{:02}", year, month, day)
+		},
+		Err(_) => "????.??.??".to_string(),
+	}
+}
+
+const MAX_LINE_LENGTH: usize = 79;
+
+// Numeric Annotation Glyphs and commentary following a move
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Annotation {
+	pub nags: Vec<u8>,
+	pub comment: Option<String>,
+}
+impl Annotation {
+	pub fn is_empty(&self) -> bool {
+		self.nags.is_empty() && self.comment.is_none()
+	}
+}
+
+#[derive(Clone)]
+pub struct Pgn {
+	tags: Vec<(String, String)>,
+	start: Position,
+	moves: Vec<Move>,
+	annotations: Vec<Annotation>,
+	result: PgnResult,
+}
+impl Pgn {
+	pub fn new(start: &Position) -> Pgn {
+		let mut pgn = Pgn {
+			tags: vec![],
+			start: start.clone(),
+			moves: vec![],
+			annotations: vec![],
+			result: PgnResult::Unfinished,
+		};
+		// seven tag roster
+		pgn.set_tag("Event", "?");
+		pgn.set_tag("Site", "?");
+		pgn.set_tag("Date", format_date(SystemTime::now()));
+		pgn.set_tag("Round", "-");
+		pgn.set_tag("White", "?");
+		pgn.set_tag("Black", "?");
+		pgn.set_tag("Result", PgnResult::Unfinished);
+		let fen = start.to_fen();
+		if fen != Position::FEN_INITIAL {
+			pgn.set_tag("SetUp", "1");
+			pgn.set_tag("FEN", fen);
+		}
+		pgn
+	}
+
+	pub fn tag(mut self, key: &str, value: impl fmt::Display) -> Self {
+		self.set_tag(key, value);
+		self
+	}
+	pub fn set_tag(&mut self, key: &str, value: impl fmt::Display) {
+		let value = value.to_string();
+		if let Some(tag) = self.tags.iter_mut().find(|(k, _)| k == key) {
+			tag.1 = value;
+		} else {
+			self.tags.push((key.to_owned(), value));
+		}
+	}
+	pub fn get_tag(&self, key: &str) -> Option<&str> {
+		self.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+	}
+
+	pub fn start_position(&self) -> &Position {
+		&self.start
+	}
+	pub fn moves(&self) -> &[Move] {
+		&self.moves
+	}
+	pub fn push(&mut self, mov: Move) {
+		self.moves.push(mov);
+		self.annotations.push(Annotation::default());
+	}
+	// keeps only the first `len` moves, as after a takeback
+	pub fn truncate(&mut self, len: usize) {
+		self.moves.truncate(len);
+		self.annotations.truncate(len);
+	}
+	// the moves of a game played on from this one, keeping the annotations of
+	// the moves they start with
+	pub fn set_moves(&mut self, moves: &[Move]) {
+		let kept = self.moves.iter().zip(moves).take_while(|(a, b)| a == b).count();
+		self.truncate(kept);
+		for mov in &moves[kept..] {
+			self.push(*mov);
+		}
+	}
+	pub fn annotation(&self, idx: usize) -> &Annotation {
+		&self.annotations[idx]
+	}
+	pub fn annotation_mut(&mut self, idx: usize) -> &mut Annotation {
+		&mut self.annotations[idx]
+	}
+
+	// positions before each move, followed by the final position
+	pub fn positions(&self) -> Vec<Position> {
+		let mut positions = Vec::with_capacity(self.moves.len() + 1);
+		let mut pos = self.start.clone();
+		for mov in &self.moves {
+			positions.push(pos.clone());
+			pos.apply_move(mov);
+		}
+		positions.push(pos);
+		positions
+	}
+	// the moves replayed from the start position
+	pub fn game(&self) -> Game {
+		Game::from_moves(&self.start, &self.moves)
+	}
+	// why the game is drawn at the final position, if it is, including by
+	// threefold repetition
+	pub fn draw_reason(&self) -> Option<DrawReason> {
+		self.game().draw_reason()
+	}
+	pub fn final_position(&self) -> Position {
+		let mut pos = self.start.clone();
+		for mov in &self.moves {
+			pos.apply_move(mov);
+		}
+		pos
+	}
+
+	pub fn result(&self) -> PgnResult {
+		self.result
+	}
+	pub fn set_result(&mut self, result: PgnResult) {
+		self.result = result;
+		self.set_tag("Result", result);
+	}
+
+	pub fn parse(s: &str) -> Result<Pgn, PgnError> {
+		parse_games(s)?.into_iter().next()
+			.ok_or_else(|| PgnError { line: 1, msg: "no game found".to_string() })
+	}
+	pub fn parse_all(s: &str) -> Result<Vec<Pgn>, PgnError> {
+		parse_games(s)
+	}
+	pub fn load(path: impl AsRef<Path>) -> Result<Vec<Pgn>, PgnError> {
+		let path = path.as_ref();
+		let text = std::fs::read_to_string(path).map_err(|err| PgnError {
+			line: 0,
+			msg: format!("could not read {}: {}", path.display(), err),
+		})?;
+		parse_games(&text)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+		let path = path.as_ref();
+		if let Some(dir) = path.parent() {
+			std::fs::create_dir_all(dir)?;
+		}
+		std::fs::write(path, self.to_string())
+	}
+	// adds the game at the end of a file, which may already contain other games
+	pub fn append_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+		let path = path.as_ref();
+		if let Some(dir) = path.parent() {
+			std::fs::create_dir_all(dir)?;
+		}
+		let mut file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+		let separator = if file.metadata()?.len() > 0 { "\n" } else { "" };
+		std::io::Write::write_all(&mut file, format!("{}{}", separator, self).as_bytes())
+	}
+}
+
+impl fmt::Display for Pgn {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (key, value) in &self.tags {
+			writeln!(f, "[{} \"{}\"]", key, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+		}
+		writeln!(f)?;
+
+		let mut tokens = vec![];
+		let mut pos = self.start.clone();
+		let mut need_number = true;
+		for (mov, annotation) in self.moves.iter().zip(&self.annotations) {
+			let move_number = (pos.get_ply() - 1) / 2 + 1;
+			if pos.side_to_move() == Color::White {
+				tokens.push(format!("{}.", move_number));
+			} else if need_number {
+				tokens.push(format!("{}...", move_number));
+			}
+			tokens.push(pos.algebraic_notation(mov));
+			pos.apply_move(mov);
+			for nag in &annotation.nags {
+				tokens.push(format!("${}", nag));
+			}
+			need_number = false;
+			if let Some(comment) = &annotation.comment {
+				// comments cannot contain braces, and long ones have to be split across lines
+				let comment = comment.replace('}', ")");
+				let first = tokens.len();
+				tokens.extend(comment.split_whitespace().map(|w| w.to_string()));
+				if tokens.len() == first {
+					tokens.push(String::new());
+				}
+				tokens[first].insert(0, '{');
+				tokens.last_mut().unwrap().push('}');
+				need_number = true;
+			}
+		}
+		tokens.push(self.result.to_pgn().to_string());
+
+		let mut line = String::new();
+		for token in tokens {
+			if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+				writeln!(f, "{}", line)?;
+				line.clear();
+			}
+			if !line.is_empty() {
+				line.push(' ');
+			}
+			line.write_str(&token)?;
+		}
+		writeln!(f, "{}", line)
+	}
+}
+
+#[derive(Debug)]
+pub struct PgnError {
+	pub line: usize,
+	pub msg: String,
+}
+impl fmt::Display for PgnError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.msg)
+	}
+}
+
+struct PgnReader<'a> {
+	chars: std::iter::Peekable<std::str::Chars<'a>>,
+	line: usize,
+	line_start: bool,
+}
+impl PgnReader<'_> {
+	fn peek(&mut self) -> Option<char> {
+		self.chars.peek().copied()
+	}
+	fn next(&mut self) -> Option<char> {
+		let c = self.chars.next();
+		self.line_start = c == Some('\n');
+		if self.line_start {
+			self.line += 1;
+		}
+		c
+	}
+	fn error(&self, msg: impl fmt::Display) -> PgnError {
+		PgnError { line: self.line, msg: msg.to_string() }
+	}
+	fn expect(&mut self, expected: char) -> Result<(), PgnError> {
+		match self.next() {
+			Some(c) if c == expected => Ok(()),
+			Some(c) => Err(self.error(format!("expected '{}', found '{}'", expected, c))),
+			None => Err(self.error(format!("expected '{}', found end of file", expected))),
+		}
+	}
+
+	// skips whitespace, rest-of-line comments and escaped lines
+	fn skip_whitespace(&mut self) {
+		while let Some(c) = self.peek() {
+			if c == ';' || (c == '%' && self.line_start) {
+				while !matches!(self.next(), Some('\n') | None) {}
+			} else if c.is_whitespace() {
+				self.next();
+			} else {
+				break;
+			}
+		}
+	}
+	fn read_symbol(&mut self) -> String {
+		let mut res = String::new();
+		while let Some(c) = self.peek() {
+			if c.is_whitespace() || "[]{}()<>;$\"".contains(c) {
+				break;
+			}
+			res.push(c);
+			self.next();
+		}
+		res
+	}
+	fn read_string(&mut self) -> Result<String, PgnError> {
+		self.expect('"')?;
+		let mut res = String::new();
+		loop {
+			match self.next() {
+				Some('"') => return Ok(res),
+				Some('\\') => res.push(self.next().ok_or_else(|| self.error("unterminated string"))?),
+				Some(c) => res.push(c),
+				None => return Err(self.error("unterminated string")),
+			}
+		}
+	}
+	fn read_comment(&mut self) -> Result<String, PgnError> {
+		self.expect('{')?;
+		let mut res = String::new();
+		loop {
+			match self.next() {
+				Some('}') => return Ok(res.split_whitespace().collect::<Vec<_>>().join(" ")),
+				Some(c) => res.push(c),
+				None => return Err(self.error("unterminated comment")),
+			}
+		}
+	}
+}
+
+fn parse_suffix_annotation(s: &str) -> Option<u8> {
+	match s {
+		"!" => Some(1),
+		"?" => Some(2),
+		"!!" => Some(3),
+		"??" => Some(4),
+		"!?" => Some(5),
+		"?!" => Some(6),
+		_ => None,
+	}
+}
+
+fn parse_game(reader: &mut PgnReader) -> Result<Pgn, PgnError> {
+	let mut tags = vec![];
+	loop {
+		reader.skip_whitespace();
+		if reader.peek() != Some('[') {
+			break;
+		}
+		reader.next();
+		reader.skip_whitespace();
+		let key = reader.read_symbol();
+		if key.is_empty() {
+			return Err(reader.error("expected tag name"));
+		}
+		reader.skip_whitespace();
+		let value = reader.read_string()?;
+		reader.skip_whitespace();
+		reader.expect(']')?;
+		tags.push((key, value));
+	}
+
+	let start = match tags.iter().find(|(k, _)| k == "FEN") {
+		Some((_, fen)) => Position::from_fen(fen).map_err(|err| reader.error(format!("invalid FEN tag: {}", err)))?,
+		None => Position::from_fen(Position::FEN_INITIAL).unwrap(),
+	};
+	let mut pgn = Pgn {
+		tags,
+		start: start.clone(),
+		moves: vec![],
+		annotations: vec![],
+		result: PgnResult::Unfinished,
+	};
+	let mut pos = start;
+	let mut variation_depth = 0;
+	loop {
+		reader.skip_whitespace();
+		match reader.peek() {
+			None => break,
+			Some('[') if variation_depth == 0 => break, // next game without a termination marker
+			Some('{') => {
+				let comment = reader.read_comment()?;
+				if variation_depth == 0 {
+					if let Some(annotation) = pgn.annotations.last_mut() {
+						annotation.comment = Some(match annotation.comment.take() {
+							Some(prev) => format!("{} {}", prev, comment),
+							None => comment,
+						});
+					}
+				}
+			},
+			Some('(') => {
+				reader.next();
+				variation_depth += 1;
+			},
+			Some(')') => {
+				if variation_depth == 0 {
+					return Err(reader.error("unbalanced parenthesis"));
+				}
+				reader.next();
+				variation_depth -= 1;
+			},
+			Some('$') => {
+				reader.next();
+				let nag = reader.read_symbol().parse::<u8>().map_err(|_| reader.error("invalid NAG"))?;
+				if variation_depth == 0 {
+					if let Some(annotation) = pgn.annotations.last_mut() {
+						annotation.nags.push(nag);
+					}
+				}
+			},
+			Some(c) => {
+				let symbol = reader.read_symbol();
+				if symbol.is_empty() {
+					return Err(reader.error(format!("unexpected character '{}'", c)));
+				}
+				if variation_depth > 0 {
+					continue;
+				}
+				if let Some(result) = PgnResult::from_pgn(&symbol) {
+					pgn.set_result(result);
+					break;
+				}
+				// strip move number indications
+				let san = symbol.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches('.');
+				if san.is_empty() {
+					continue;
+				}
+				let suffix_start = san.find(['!', '?']).unwrap_or(san.len());
+				let (san, suffix) = san.split_at(suffix_start);
+				let legal_moves = pos.gen_legal();
+				let mov = *Move::parse_algebraic(san, &legal_moves)
+					.map_err(|err| reader.error(format!("{} is {}", san, err)))?;
+				pos.apply_move(&mov);
+				pgn.push(mov);
+				if !suffix.is_empty() {
+					let nag = parse_suffix_annotation(suffix)
+						.ok_or_else(|| reader.error(format!("invalid move suffix {}", suffix)))?;
+					pgn.annotations.last_mut().unwrap().nags.push(nag);
+				}
+			},
+		}
+	}
+	if variation_depth > 0 {
+		return Err(reader.error("unterminated variation"));
+	}
+	Ok(pgn)
+}
+
+fn parse_games(s: &str) -> Result<Vec<Pgn>, PgnError> {
+	let mut reader = PgnReader {
+		chars: s.trim_start_matches('\u{feff}').chars().peekable(),
+		line: 1,
+		line_start: true,
+	};
+	let mut games = vec![];
+	loop {
+		reader.skip_whitespace();
+		if reader.peek().is_none() {
+			break;
+		}
+		games.push(parse_game(&mut reader)?);
+	}
+	Ok(games)
+}
+
+#[cfg(test)]
+mod test_pgn {
+	use crate::{game::{DrawReason, Position}, state::{Color, Move}};
+
+	use super::{civil_from_days, Annotation, Pgn, PgnResult};
+
+	fn play(pgn: &mut Pgn, pos: &mut Position, moves: &[&str]) {
+		for mov in moves {
+			let legal_moves = pos.gen_legal();
+			let mov = *Move::parse_algebraic(mov, &legal_moves).ok().unwrap();
+			pgn.push(mov);
+			pos.apply_move(&mov);
+		}
+	}
+
+	#[test]
+	fn test_dates() {
+		assert_eq!(civil_from_days(0), (1970, 1, 1));
+		assert_eq!(civil_from_days(11016), (2000, 2, 29));
+		assert_eq!(civil_from_days(20743), (2026, 10, 17));
+	}
+
+	#[test]
+	fn test_export() {
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pgn = Pgn::new(&pos).tag("White", "Alice").tag("Date", "2024.01.01");
+		play(&mut pgn, &mut pos, &["f3", "e5", "g4", "Qh4#"]);
+		pgn.set_result(pos.game_result().unwrap().into());
+		assert_eq!(pgn.result(), PgnResult::Win(Color::Black));
+		assert_eq!(pgn.to_string(), concat!(
+			"[Event \"?\"]\n",
+			"[Site \"?\"]\n",
+			"[Date \"2024.01.01\"]\n",
+			"[Round \"-\"]\n",
+			"[White \"Alice\"]\n",
+			"[Black \"?\"]\n",
+			"[Result \"0-1\"]\n",
+			"\n",
+			"1. f3 e5 2. g4 Qh4# 0-1\n",
+		));
+	}
+
+	#[test]
+	fn test_custom_start() {
+		let fen = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 10";
+		let mut pos = Position::from_fen(fen).unwrap();
+		let mut pgn = Pgn::new(&pos);
+		play(&mut pgn, &mut pos, &["O-O", "Rd1", "Rfd8"]);
+		let text = pgn.to_string();
+		assert!(text.contains("[SetUp \"1\"]\n"));
+		assert!(text.contains(&format!("[FEN \"{}\"]\n", fen)));
+		assert!(text.ends_with("\n10... O-O 11. Rd1 Rfd8 *\n"));
+	}
+
+	#[test]
+	fn test_import() {
+		let text = concat!(
+			"[Event \"Test \\\"quoted\\\"\"]\n",
+			"[White \"Alice\"]\n",
+			"[Result \"1-0\"]\n",
+			"\n",
+			"1. e4 e5 {Open game} 2. Nf3! (2. Bc4 Nc6 (2... Nf6)) 2... Nc6 $1\n",
+			"; line comment\n",
+			"3. Bb5 a6?! 4.Ba4 1-0\n",
+			"\n",
+			"[Event \"Second\"]\n",
+			"[SetUp \"1\"]\n",
+			"[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n",
+			"\n",
+			"1... Kd7 2. e4 *\n",
+		);
+		let games = Pgn::parse_all(text).ok().unwrap();
+		assert_eq!(games.len(), 2);
+
+		let game = &games[0];
+		assert_eq!(game.get_tag("Event"), Some("Test \"quoted\""));
+		assert_eq!(game.get_tag("White"), Some("Alice"));
+		assert_eq!(game.result(), PgnResult::Win(Color::White));
+		assert_eq!(game.moves().len(), 7);
+		assert_eq!(game.annotation(1), &Annotation { nags: vec![], comment: Some("Open game".to_string()) });
+		assert_eq!(game.annotation(2).nags, vec![1]);
+		assert_eq!(game.annotation(3).nags, vec![1]);
+		assert_eq!(game.annotation(5).nags, vec![6]);
+		assert_eq!(game.final_position().to_fen(),
+			"r1bqkbnr/1ppp1ppp/p1n5/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 4");
+
+		let mut game = games[1].clone();
+		assert_eq!(game.moves().len(), 2);
+		assert_eq!(game.result(), PgnResult::Unfinished);
+		assert_eq!(game.positions().len(), 3);
+		game.truncate(1);
+		assert_eq!(game.final_position().to_fen(), "8/3k4/8/8/8/8/4P3/4K3 w - - 1 2");
+
+		// round trip
+		let reparsed = Pgn::parse(&games[0].to_string()).ok().unwrap();
+		assert!(reparsed.moves() == games[0].moves());
+		assert_eq!(reparsed.annotation(1), games[0].annotation(1));
+		assert_eq!(reparsed.to_string(), games[0].to_string());
+	}
+
+	#[test]
+	fn test_repetition() {
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pgn = Pgn::new(&pos);
+		play(&mut pgn, &mut pos, &["Nf3", "Nf6", "Ng1", "Ng8", "Nf3", "Nf6", "Ng1"]);
+		assert_eq!(pgn.draw_reason(), None);
+		play(&mut pgn, &mut pos, &["Ng8"]);
+		assert_eq!(pgn.draw_reason(), Some(DrawReason::Repetition));
+	}
+
+	#[test]
+	fn test_set_moves() {
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pgn = Pgn::new(&pos);
+		play(&mut pgn, &mut pos, &["e4", "e5", "Nf3"]);
+		pgn.annotation_mut(0).comment = Some("best by test".to_owned());
+		pgn.annotation_mut(2).comment = Some("the main line".to_owned());
+		let mut game = pgn.game();
+		game.undo();
+		let legal_moves = game.position().gen_legal();
+		game.push(*Move::parse_algebraic("Bc4", &legal_moves).ok().unwrap());
+		pgn.set_moves(&game.moves());
+		assert_eq!(pgn.moves(), game.moves());
+		assert_eq!(pgn.annotation(0).comment.as_deref(), Some("best by test"));
+		assert!(pgn.annotation(2).is_empty());
+	}
+
+	#[test]
+	fn test_append() {
+		let path = std::env::temp_dir().join(format!("chess-test-append-{}.pgn", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let mut pos = Position::from_fen(Position::FEN_INITIAL).unwrap();
+		let mut pgn = Pgn::new(&pos);
+		play(&mut pgn, &mut pos, &["e4"]);
+		pgn.append_to(&path).unwrap();
+		pgn.set_result(PgnResult::Draw);
+		pgn.append_to(&path).unwrap();
+		let games = Pgn::load(&path).ok().unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(games.len(), 2);
+		assert_eq!(games[0].result(), PgnResult::Unfinished);
+		assert_eq!(games[1].result(), PgnResult::Draw);
+	}
+
+	#[test]
+	fn test_import_errors() {
+		assert_eq!(Pgn::parse("1. e4 e5 2. Ke3 *").err().unwrap().line, 1);
+		assert_eq!(Pgn::parse("[Event \"?\"]\n\n1. e4 {unterminated").err().unwrap().line, 3);
+		assert!(Pgn::parse("[FEN \"garbage\"]\n*").is_err());
+	}
+}
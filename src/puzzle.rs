@@ -0,0 +1,408 @@
+use std::{collections::BTreeSet, fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	analysis::{Criticality, PositionAnalysis},
+	game::{GameResult, Position},
+	pgn::Pgn,
+	state::{Color, Move}
+};
+
+// A puzzle in the format of the Lichess puzzle database: the position is
+// given before the opponent's move, which is the first move of the line.
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+	pub id: String,
+	pub fen: String,
+	pub moves: Vec<String>, // UCI notation
+	pub rating: u32,
+	pub themes: Vec<String>,
+	pub game_url: String,
+}
+impl Puzzle {
+	pub const CSV_HEADER: &'static str = "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags";
+
+	pub fn to_csv(&self) -> String {
+		format!("{},{},{},{},500,0,0,{},{},",
+			self.id, self.fen, self.moves.join(" "), self.rating, self.themes.join(" "), self.game_url)
+	}
+	pub fn from_csv(line: &str) -> Option<Puzzle> {
+		let fields: Vec<&str> = line.trim_end().split(',').collect();
+		if fields.len() < 3 || fields[0] == "PuzzleId" {
+			return None;
+		}
+		Some(Puzzle {
+			id: fields[0].to_string(),
+			fen: fields[1].to_string(),
+			moves: fields[2].split_whitespace().map(|s| s.to_string()).collect(),
+			rating: fields.get(3).and_then(|r| r.parse().ok()).unwrap_or(1500),
+			themes: fields.get(7).map(|t| t.split_whitespace().map(|s| s.to_string()).collect()).unwrap_or_default(),
+			game_url: fields.get(8).unwrap_or(&"").to_string(),
+		})
+	}
+	// the puzzles of a CSV file, skipping the header and invalid lines
+	pub fn load_csv(path: impl AsRef<Path>) -> Result<Vec<Puzzle>, String> {
+		let path = path.as_ref();
+		let text = std::fs::read_to_string(path)
+			.map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+		Ok(text.lines().filter_map(Puzzle::from_csv).collect())
+	}
+}
+impl fmt::Display for Puzzle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} ({}): {}", self.id, self.fen, self.moves.join(" "))
+	}
+}
+
+// A puzzle being solved: the opponent's move that starts the line is played
+// right away, then each move of the solver is checked against the line, and
+// answered with the next move of the opponent. As on Lichess, any move that
+// checkmates is accepted too.
+#[derive(Clone)]
+pub struct PuzzleAttempt {
+	pub puzzle: Puzzle,
+	line: Vec<Move>, // the opponent's move first
+	played: usize, // moves of the line on the board
+	start: Position, // before the opponent's first move
+	position: Position,
+	pub mistakes: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PuzzleStep {
+	Wrong, // the move is not played
+	Correct, // the opponent replies next, see play_reply
+	Solved,
+}
+
+impl PuzzleAttempt {
+	pub fn new(puzzle: Puzzle) -> Result<PuzzleAttempt, String> {
+		let start = Position::from_fen(&puzzle.fen).map_err(|err| format!("puzzle {}: invalid FEN: {}", puzzle.id, err))?;
+		let mut pos = start.clone();
+		let mut line = vec![];
+		for uci in &puzzle.moves {
+			let mov = *Move::parse_uci(uci, &pos.gen_legal())
+				.map_err(|err| format!("puzzle {}: {}: {}", puzzle.id, uci, err))?;
+			pos.apply_move(&mov);
+			line.push(mov);
+		}
+		if line.len() < 2 {
+			return Err(format!("puzzle {}: no solution", puzzle.id));
+		}
+		let mut position = start.clone();
+		position.apply_move(&line[0]);
+		Ok(PuzzleAttempt { puzzle, line, played: 1, start, position, mistakes: 0 })
+	}
+
+	pub fn position(&self) -> &Position {
+		&self.position
+	}
+	// the position and moves so far, for the board and its history
+	pub fn start_position(&self) -> &Position {
+		&self.start
+	}
+	pub fn played_moves(&self) -> &[Move] {
+		&self.line[..self.played]
+	}
+	pub fn solver(&self) -> Color {
+		self.start.side_to_move().opponent()
+	}
+	pub fn is_solved(&self) -> bool {
+		self.played == self.line.len()
+	}
+	// the move expected from the solver, if it is their turn
+	pub fn hint(&self) -> Option<Move> {
+		self.line.get(self.played).copied().filter(|_| self.position.side_to_move() == self.solver())
+	}
+
+	pub fn play(&mut self, mov: Move) -> PuzzleStep {
+		if self.hint().is_none() {
+			return PuzzleStep::Wrong;
+		}
+		let mut next = self.position.clone();
+		next.apply_move(&mov);
+		let mates = matches!(next.game_result(), Some(GameResult::Checkmate(_)));
+		if Some(mov) != self.hint() && !mates {
+			self.mistakes += 1;
+			return PuzzleStep::Wrong;
+		}
+		self.position = next;
+		if mates {
+			self.line.truncate(self.played);
+			self.line.push(mov);
+		}
+		self.played += 1;
+		if self.is_solved() { PuzzleStep::Solved } else { PuzzleStep::Correct }
+	}
+	// plays the opponent's next move, after a correct one
+	pub fn play_reply(&mut self) -> Option<Move> {
+		let reply = self.line.get(self.played).copied().filter(|_| self.position.side_to_move() != self.solver())?;
+		self.position.apply_move(&reply);
+		self.played += 1;
+		Some(reply)
+	}
+}
+
+// the results of a solver, kept between sessions
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PuzzleStats {
+	pub attempted: u32,
+	pub solved: u32, // without mistakes
+	pub streak: u32,
+	pub best_streak: u32,
+	pub rating: f64, // Elo-like, updated against the ratings of the puzzles
+	pub seen: BTreeSet<String>, // IDs of the attempted puzzles
+}
+impl Default for PuzzleStats {
+	fn default() -> Self {
+		PuzzleStats { attempted: 0, solved: 0, streak: 0, best_streak: 0, rating: 1500.0, seen: BTreeSet::new() }
+	}
+}
+impl PuzzleStats {
+	const K_FACTOR: f64 = 32.0;
+
+	pub fn load(path: impl AsRef<Path>) -> Result<PuzzleStats, String> {
+		let path = path.as_ref();
+		match std::fs::read_to_string(path) {
+			Ok(data) => serde_json::from_str(&data).map_err(|err| format!("{}: {}", path.display(), err)),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(PuzzleStats::default()),
+			Err(err) => Err(format!("could not read {}: {}", path.display(), err)),
+		}
+	}
+	pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+		std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+	}
+
+	// counts the first attempt at a puzzle only
+	pub fn record(&mut self, puzzle: &Puzzle, solved: bool) {
+		if !self.seen.insert(puzzle.id.clone()) {
+			return;
+		}
+		self.attempted += 1;
+		if solved {
+			self.solved += 1;
+			self.streak += 1;
+			self.best_streak = self.best_streak.max(self.streak);
+		} else {
+			self.streak = 0;
+		}
+		let expected = 1.0 / (1.0 + 10f64.powf((puzzle.rating as f64 - self.rating) / 400.0));
+		self.rating += PuzzleStats::K_FACTOR * (solved as u8 as f64 - expected);
+	}
+
+	// the unseen puzzle with the rating closest to the solver's
+	pub fn pick<'a>(&self, puzzles: &'a [Puzzle]) -> Option<&'a Puzzle> {
+		puzzles.iter().filter(|puzzle| !self.seen.contains(&puzzle.id))
+			.min_by_key(|puzzle| (puzzle.rating as i64 - self.rating.round() as i64).abs())
+	}
+}
+
+pub struct PuzzleFinder {
+	pub scan_depth: u32,
+	pub verify_depth: u32,
+	pub min_swing: i32,       // minimum eval loss of the opponent's blunder
+	pub min_advantage: i16,   // minimum advantage after the winning move
+	pub only_move_margin: i32,
+	pub max_solution_moves: usize,
+}
+impl Default for PuzzleFinder {
+	fn default() -> Self {
+		PuzzleFinder {
+			scan_depth: 3,
+			verify_depth: 5,
+			min_swing: 200,
+			min_advantage: 200,
+			only_move_margin: 200,
+			max_solution_moves: 3,
+		}
+	}
+}
+impl PuzzleFinder {
+	fn is_winning_only_move(&self, analysis: &PositionAnalysis) -> bool {
+		analysis.criticality(self.only_move_margin) == Criticality::OnlyMove
+			&& analysis.best_score() >= self.min_advantage
+	}
+
+	// extends a verified winning move into a line of unique moves
+	fn solution_line(&self, pos: &Position, first: Move) -> Vec<Move> {
+		let mut line = vec![first];
+		let mut pos = pos.clone();
+		pos.apply_move(&first);
+		while line.len() < self.max_solution_moves * 2 - 1 {
+			// the opponent's reply is searched one ply shallower, but at least one ply deep
+			let reply = match PositionAnalysis::new(&pos, self.verify_depth.saturating_sub(1).max(1)) {
+				Some(analysis) => analysis.best_move(),
+				None => break, // checkmate or stalemate
+			};
+			let mut pos2 = pos.clone();
+			pos2.apply_move(&reply);
+			match PositionAnalysis::new(&pos2, self.verify_depth) {
+				Some(analysis) if self.is_winning_only_move(&analysis) => {
+					let mov = analysis.best_move();
+					line.push(reply);
+					line.push(mov);
+					pos2.apply_move(&mov);
+					pos = pos2;
+				},
+				_ => break,
+			}
+		}
+		line
+	}
+
+	pub fn find_in_game(&self, game: &Pgn, game_id: &str) -> Vec<Puzzle> {
+		let game_url = game.get_tag("Site").filter(|s| s.starts_with("http")).unwrap_or("").to_string();
+		let positions = game.positions();
+		let mut puzzles = vec![];
+		let mut prev_analysis = PositionAnalysis::new(&positions[0], self.scan_depth);
+		for (i, blunder) in game.moves().iter().enumerate() {
+			let before = &positions[i];
+			let after = &positions[i + 1];
+			let analysis = PositionAnalysis::new(after, self.scan_depth);
+			let candidate = match (&prev_analysis, &analysis) {
+				(Some(prev), Some(cur)) => {
+					// the score of the played move, from the opponent's perspective, is best known
+					// from the search one ply further
+					let played = -cur.best_score();
+					prev.best_score() as i32 - played as i32 >= self.min_swing
+						&& self.is_winning_only_move(cur)
+				},
+				_ => false,
+			};
+			if candidate {
+				// verify with a deeper search
+				if let Some(deep) = PositionAnalysis::new(after, self.verify_depth) {
+					if self.is_winning_only_move(&deep) {
+						let solution = self.solution_line(after, deep.best_move());
+						let mut moves = vec![blunder.uci_notation()];
+						moves.extend(solution.iter().map(|m| m.uci_notation()));
+						let mut themes = vec![];
+						let mut end = after.clone();
+						for mov in &solution {
+							end.apply_move(mov);
+						}
+						if matches!(end.game_result(), Some(GameResult::Checkmate(_))) {
+							themes.push(format!("mateIn{}", solution.len().div_ceil(2)));
+						}
+						themes.push(match solution.len() {
+							1 => "oneMove",
+							3 => "short",
+							_ => "long",
+						}.to_string());
+						puzzles.push(Puzzle {
+							id: format!("{}-{}", game_id, before.get_ply()),
+							fen: before.to_fen(),
+							moves,
+							rating: 1500,
+							themes,
+							game_url: game_url.clone(),
+						});
+					}
+				}
+			}
+			prev_analysis = analysis;
+		}
+		puzzles
+	}
+}
+
+#[cfg(test)]
+mod test_puzzle {
+	use crate::{pgn::Pgn, state::Color};
+
+	use super::{Puzzle, PuzzleAttempt, PuzzleFinder, PuzzleStats, PuzzleStep};
+
+	#[test]
+	fn test_csv_round_trip() {
+		let line = "00sHx,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2e6 d7d8 f7f8,1760,80,83,72,mate mateIn2 middlegame short,https://lichess.org/yyznGmXs/black#34,Italian_Game";
+		let puzzle = Puzzle::from_csv(line).unwrap();
+		assert_eq!(puzzle.id, "00sHx");
+		assert_eq!(puzzle.moves.len(), 4);
+		assert_eq!(puzzle.rating, 1760);
+		assert_eq!(puzzle.themes, ["mate", "mateIn2", "middlegame", "short"]);
+		let puzzle2 = Puzzle::from_csv(&puzzle.to_csv()).unwrap();
+		assert_eq!(puzzle2.fen, puzzle.fen);
+		assert_eq!(puzzle2.moves, puzzle.moves);
+		assert!(Puzzle::from_csv(Puzzle::CSV_HEADER).is_none());
+	}
+
+	#[test]
+	fn test_attempt() {
+		let line = "00sHx,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2e6 d7d8 f7f8,1760,80,83,72,mate mateIn2 middlegame short,https://lichess.org/yyznGmXs/black#34,Italian_Game";
+		let puzzle = Puzzle::from_csv(line).unwrap();
+		let mut attempt = PuzzleAttempt::new(puzzle.clone()).unwrap();
+		assert_eq!(attempt.solver(), Color::White);
+		assert_eq!(attempt.played_moves().len(), 1);
+		let moves = attempt.position().gen_legal();
+		let wrong = *moves.iter().find(|mov| mov.uci_notation() == "f7f8").unwrap();
+		assert_eq!(attempt.play(wrong), PuzzleStep::Wrong);
+		assert_eq!(attempt.mistakes, 1);
+		let hint = attempt.hint().unwrap();
+		assert_eq!(hint.uci_notation(), "a2e6");
+		assert_eq!(attempt.play(hint), PuzzleStep::Correct);
+		assert_eq!(attempt.hint(), None);
+		assert_eq!(attempt.play_reply().unwrap().uci_notation(), "d7d8");
+		assert_eq!(attempt.play_reply(), None);
+		let mate = *attempt.position().gen_legal().iter().find(|mov| mov.uci_notation() == "f7f8").unwrap();
+		assert_eq!(attempt.play(mate), PuzzleStep::Solved);
+		assert!(attempt.is_solved());
+
+		// another mate than the one of the solution
+		let mut attempt = PuzzleAttempt::new(Puzzle {
+			moves: vec!["a8a6".to_string(), "b1b8".to_string()], ..Puzzle::from_csv("x,q5k1/5ppp/8/8/8/8/8/1R2R1K1 b - - 0 1,,1500").unwrap()
+		}).unwrap();
+		let other = *attempt.position().gen_legal().iter().find(|mov| mov.uci_notation() == "e1e8").unwrap();
+		assert_eq!(attempt.play(other), PuzzleStep::Solved);
+		assert_eq!(attempt.played_moves().last(), Some(&other));
+
+		let invalid = Puzzle { moves: vec!["e2e4".to_string()], ..puzzle };
+		assert!(PuzzleAttempt::new(invalid).is_err());
+	}
+
+	#[test]
+	fn test_stats() {
+		let puzzle = |id: &str, rating| Puzzle::from_csv(&format!("{},8/8/8/8/8/8/8/K6k w - - 0 1,a1a2,{}", id, rating)).unwrap();
+		let puzzles = [puzzle("a", 1200), puzzle("b", 1550), puzzle("c", 2000)];
+		let mut stats = PuzzleStats::default();
+		assert_eq!(stats.pick(&puzzles).unwrap().id, "b");
+		stats.record(&puzzles[1], true);
+		assert!(stats.rating > 1500.0);
+		assert_eq!((stats.attempted, stats.solved, stats.streak), (1, 1, 1));
+		// only the first attempt counts
+		stats.record(&puzzles[1], false);
+		assert_eq!((stats.attempted, stats.streak), (1, 1));
+		stats.record(&puzzles[0], false);
+		assert_eq!((stats.attempted, stats.solved, stats.streak, stats.best_streak), (2, 1, 0, 1));
+		assert_eq!(stats.pick(&puzzles).unwrap().id, "c");
+		stats.record(&puzzles[2], true);
+		assert!(stats.pick(&puzzles).is_none());
+
+		let json = serde_json::to_string(&stats).unwrap();
+		let loaded: PuzzleStats = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded.seen, stats.seen);
+		assert_eq!(serde_json::from_str::<PuzzleStats>("{}").unwrap().rating, 1500.0);
+	}
+
+	#[test]
+	fn test_find_mate() {
+		// 2... g5?? allows Qh5#
+		let game = Pgn::parse("1. e4 f6 2. d4 g5 3. Qh5# 1-0").ok().unwrap();
+		let finder = PuzzleFinder { scan_depth: 3, verify_depth: 3, ..Default::default() };
+		let puzzles = finder.find_in_game(&game, "test");
+		assert_eq!(puzzles.len(), 1);
+		assert_eq!(puzzles[0].moves, ["g7g5", "d1h5"]);
+		assert!(puzzles[0].themes.iter().any(|t| t == "mateIn1"));
+	}
+
+	#[test]
+	fn test_find_depth_1() {
+		// 2... Qh4?? hangs the queen; the replies along the solution are searched at depth 1 too
+		let game = Pgn::parse("1. e4 e5 2. Nf3 Qh4 3. Nxh4 *").ok().unwrap();
+		let finder = PuzzleFinder { scan_depth: 1, verify_depth: 1, ..Default::default() };
+		let puzzles = finder.find_in_game(&game, "test");
+		assert_eq!(puzzles.len(), 1);
+		assert_eq!(puzzles[0].moves[..2], ["d8h4", "f3h4"]);
+	}
+}
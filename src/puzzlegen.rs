@@ -0,0 +1,82 @@
+use std::{io::Write, path::{Path, PathBuf}};
+
+use chesslib::{lichess::LichessGame, pgn::Pgn, puzzle::{Puzzle, PuzzleFinder}};
+
+const USAGE: &str = "usage: puzzlegen [--depth N] [--verify-depth N] [-o OUTPUT] [PGN or JSON files...]
+Scans games for tactical moments and writes them as puzzles in the Lichess CSV format.
+Files ending in .json or .ndjson are read as lichess JSON game exports.
+If no files are given, all games logged by the bot in games/ are scanned.";
+
+// PGN games, or lichess JSON exports converted to PGN
+fn load_games(path: &Path) -> Result<Vec<Pgn>, String> {
+	if path.extension().is_some_and(|ext| ext == "json" || ext == "ndjson") {
+		let text = std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+		LichessGame::parse_ndjson(&text)?.iter().map(|game| game.to_pgn()).collect()
+	} else {
+		Pgn::load(path).map_err(|err| err.to_string())
+	}
+}
+
+fn main() {
+	if let Err(err) = || -> Result<(), String> {
+		let mut finder = PuzzleFinder::default();
+		let mut output = PathBuf::from("puzzles.csv");
+		let mut inputs = vec![];
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			match arg.as_str() {
+				"--depth" => finder.scan_depth = value("--depth")?.parse()
+					.map_err(|_| format!("--depth is not a positive integer"))?,
+				"--verify-depth" => finder.verify_depth = value("--verify-depth")?.parse()
+					.map_err(|_| format!("--verify-depth is not a positive integer"))?,
+				"-o" => output = PathBuf::from(value("-o")?),
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(());
+				},
+				_ => inputs.push(PathBuf::from(arg)),
+			}
+		}
+		if finder.scan_depth < 1 || finder.verify_depth < finder.scan_depth {
+			return Err(format!("depths must satisfy 1 <= depth <= verify depth"));
+		}
+		if inputs.is_empty() {
+			let dir = std::fs::read_dir("games").map_err(|err| format!("could not read games/: {}", err))?;
+			for entry in dir {
+				let path = entry.map_err(|err| format!("could not read games/: {}", err))?.path();
+				if path.extension().is_some_and(|ext| ext == "pgn") {
+					inputs.push(path);
+				}
+			}
+			inputs.sort();
+		}
+
+		let mut puzzles: Vec<Puzzle> = vec![];
+		for path in inputs {
+			let games = load_games(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+			let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+			for (i, game) in games.iter().enumerate() {
+				let game_id = if games.len() == 1 { stem.clone() } else { format!("{}-{}", stem, i + 1) };
+				let found = finder.find_in_game(game, &game_id);
+				println!("{}: {} puzzle(s)", game_id, found.len());
+				for puzzle in &found {
+					println!("  {}", puzzle);
+				}
+				puzzles.extend(found);
+			}
+		}
+
+		let mut file = std::fs::File::create(&output)
+			.map_err(|err| format!("could not create {}: {}", output.display(), err))?;
+		writeln!(file, "{}", Puzzle::CSV_HEADER)
+			.and_then(|_| puzzles.iter().try_for_each(|p| writeln!(file, "{}", p.to_csv())))
+			.map_err(|err| format!("could not write {}: {}", output.display(), err))?;
+		println!("wrote {} puzzle(s) to {}", puzzles.len(), output.display());
+		Ok(())
+	}() {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	}
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Instant};
+
+use chesslib::movetest::TestFile;
+
+const USAGE: &str = "usage: runtests [--tag TAG]... [--perft-depth N] [-v] [FILES...]
+Runs the move generation tests of the given JSON files (by default, all of tests/). With --tag,
+only the cases with one of the tags are run. Perft counts are checked up to --perft-depth
+(default: all of them). With -v, every failure is printed.";
+
+#[derive(Default)]
+struct Stats {
+	cases: usize,
+	failed: usize,
+	moves: usize,
+	perft_nodes: u64,
+}
+impl Stats {
+	fn add(&mut self, other: &Stats) {
+		self.cases += other.cases;
+		self.failed += other.failed;
+		self.moves += other.moves;
+		self.perft_nodes += other.perft_nodes;
+	}
+}
+
+fn main() {
+	match (|| -> Result<bool, String> {
+		let mut tags = vec![];
+		let mut max_perft_depth = usize::MAX;
+		let mut verbose = false;
+		let mut paths = vec![];
+
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+			match arg.as_str() {
+				"--tag" => tags.push(value("--tag")?),
+				"--perft-depth" => max_perft_depth = value("--perft-depth")?.parse()
+					.map_err(|_| format!("--perft-depth is not a non-negative integer"))?,
+				"-v" | "--verbose" => verbose = true,
+				"-h" | "--help" => {
+					println!("{}", USAGE);
+					return Ok(true);
+				},
+				_ => paths.push(PathBuf::from(arg)),
+			}
+		}
+		if paths.is_empty() {
+			paths = std::fs::read_dir("tests").map_err(|err| format!("could not list tests/: {}", err))?
+				.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+				.filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+				.collect();
+			paths.sort();
+		}
+
+		let mut total = Stats::default();
+		let mut by_tag: BTreeMap<String, Stats> = BTreeMap::new();
+		for path in &paths {
+			let file = TestFile::load(path)?;
+			let t0 = Instant::now();
+			let mut stats = Stats::default();
+			for (i, case) in file.test_cases.iter().enumerate() {
+				if !case.has_any_tag(&file, &tags) {
+					continue;
+				}
+				let report = case.run(max_perft_depth);
+				let case_stats = Stats {
+					cases: 1,
+					failed: !report.failures.is_empty() as usize,
+					moves: report.moves,
+					perft_nodes: report.perft_nodes,
+				};
+				if verbose {
+					for failure in &report.failures {
+						println!("{} #{}: {}", path.display(), i, failure);
+					}
+				}
+				for tag in case.tags.iter().chain(&file.tags) {
+					by_tag.entry(tag.clone()).or_default().add(&case_stats);
+				}
+				stats.add(&case_stats);
+			}
+			if stats.cases > 0 {
+				println!("{:<24} {:>4} cases, {:>3} failed, {:>5} moves, {:>9} perft nodes, {:>6} ms",
+					path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+					stats.cases, stats.failed, stats.moves, stats.perft_nodes, t0.elapsed().as_millis());
+			}
+			total.add(&stats);
+		}
+
+		if !by_tag.is_empty() {
+			println!("\ntag          cases failed");
+			for (tag, stats) in &by_tag {
+				println!("{:<12} {:>5} {:>6}", tag, stats.cases, stats.failed);
+			}
+		}
+		println!("\n{} cases, {} passed, {} failed", total.cases, total.cases - total.failed, total.failed);
+		Ok(total.failed == 0)
+	})() {
+		Ok(true) => {},
+		Ok(false) => std::process::exit(1),
+		Err(err) => {
+			eprintln!("error: {}", err);
+			std::process::exit(2);
+		},
+	}
+}
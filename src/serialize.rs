@@ -0,0 +1,59 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{game::Position, state::{Board, Square}};
+
+// Serde impls for the core types that have a standard text form: squares are
+// written by name ("e4"), boards as the first field of a FEN and positions as a
+// full FEN. Pieces, piece types, colors and moves derive theirs in state.rs.
+
+macro_rules! serde_as_string {
+	($type:ty, $what:literal, $to_string:expr, $parse:expr) => {
+		impl Serialize for $type {
+			fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+				serializer.serialize_str(&$to_string(self))
+			}
+		}
+		impl<'de> Deserialize<'de> for $type {
+			fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+				let s = String::deserialize(deserializer)?;
+				$parse(&s).ok_or_else(|| D::Error::custom(format!("invalid {}: {}", $what, s)))
+			}
+		}
+	};
+}
+
+serde_as_string!(Square, "square", Square::to_string, Square::parse);
+serde_as_string!(Board, "board", Board::to_fen, Board::from_fen);
+serde_as_string!(Position, "FEN", Position::to_fen, |fen| Position::from_fen(fen).ok());
+
+#[cfg(test)]
+mod test_serialize {
+	use crate::{game::Position, state::{Board, Color, Move, Piece, PieceType, SpecialMove, Square}};
+
+	#[test]
+	fn test_round_trip() {
+		assert_eq!(serde_json::to_string(&Square::E4).unwrap(), "\"e4\"");
+		assert_eq!(serde_json::from_str::<Square>("\"h8\"").unwrap(), Square::H8);
+		assert!(serde_json::from_str::<Square>("\"i9\"").is_err());
+
+		let piece = Piece::new(Color::Black, PieceType::Knight);
+		assert_eq!(serde_json::to_string(&piece).unwrap(), r#"{"color":"black","ptype":"knight"}"#);
+		assert_eq!(serde_json::from_str::<Piece>(r#"{"color":"black","ptype":"knight"}"#).unwrap(), piece);
+
+		let mov = Move { ptype: PieceType::Pawn, from: Square::E7, to: Square::E8, special: SpecialMove::PromoteQ };
+		let json = serde_json::to_string(&mov).unwrap();
+		assert_eq!(json, r#"{"ptype":"pawn","from":"e7","to":"e8","special":"promote_q"}"#);
+		assert!(serde_json::from_str::<Move>(&json).unwrap() == mov);
+
+		let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+		let pos = Position::from_fen(fen).unwrap();
+		assert_eq!(serde_json::to_string(&pos).unwrap(), format!("\"{}\"", fen));
+		assert_eq!(serde_json::from_str::<Position>(&format!("\"{}\"", fen)).unwrap().to_fen(), fen);
+		assert!(serde_json::from_str::<Position>("\"8/8/8 w - - 0 1\"").is_err());
+
+		let board = pos.get_board();
+		let json = serde_json::to_string(board).unwrap();
+		assert_eq!(json, "\"r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R\"");
+		assert_eq!(serde_json::from_str::<Board>(&json).unwrap().to_fen(), board.to_fen());
+	}
+}
@@ -1,6 +1,7 @@
-use std::fmt::{self, Write};
+use core::fmt::{self, Write};
+use alloc::{format, string::String};
 
-use crate::bitboard::Bb;
+use crate::bitboard::{between, cast_cardinals, cast_diagonals, Bb};
 
 fn parse_file(c: u8) -> Option<u8> {
 	if b'a' <= c && c <= b'h' {
@@ -17,13 +18,43 @@ fn parse_rank(c: u8) -> Option<u8> {
 	}
 }
 
+// file and rank indices, from a to h and from 1 to 8
+pub fn files() -> core::ops::Range<u8> {
+	0..8
+}
+pub fn ranks() -> core::ops::Range<u8> {
+	0..8
+}
+
+// squares are indexed file by file: a1 is 0, a2 is 1... h8 is 63
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Square { pub(crate) idx: u8 }
 impl Square {
-	pub fn at(file: u8, rank: u8) -> Square {
+	// the coordinates must be in 0..8, which is only checked in debug builds
+	pub const fn at(file: u8, rank: u8) -> Square {
 		debug_assert!(file < 8 && rank < 8);
 		Square { idx: file << 3 | rank }
 	}
+	pub const fn try_at(file: u8, rank: u8) -> Option<Square> {
+		if file < 8 && rank < 8 { Some(Square::at(file, rank)) } else { None }
+	}
+	// the index must be in 0..64, which is only checked in debug builds
+	pub const fn from_index_unchecked(idx: u8) -> Square {
+		debug_assert!(idx < 64);
+		Square { idx }
+	}
+	pub const fn from_index(idx: u8) -> Option<Square> {
+		if idx < 64 { Some(Square { idx }) } else { None }
+	}
+	pub const fn index(self) -> u8 {
+		self.idx
+	}
+	// parses a square name at compile time, see the sq! macro
+	pub const fn from_name(name: &str) -> Square {
+		let b = name.as_bytes();
+		assert!(b.len() == 2 && b[0] >= b'a' && b[0] <= b'h' && b[1] >= b'1' && b[1] <= b'8', "invalid square name");
+		Square::at(b[0] - b'a', b[1] - b'1')
+	}
 	pub const fn file(self) -> u8 {
 		self.idx >> 3
 	}
@@ -38,20 +69,61 @@ impl Square {
 			None
 		}
 	}
-	pub(crate) fn shift(self, dfile: i8, drank: i8) -> Square {
+	// all 64 squares in index order (a1, a2... h8)
+	pub fn iter_all() -> impl Iterator<Item=Square> {
+		(0..64).map(|idx| Square { idx })
+	}
+	// the square `dfile` files and `drank` ranks away, which must be on the board
+	pub fn shift(self, dfile: i8, drank: i8) -> Square {
 		let file = (self.file() as i8 + dfile) as u8;
 		let rank = (self.rank() as i8 + drank) as u8;
 		debug_assert!(file < 8 && rank < 8);
 		Square::at(file, rank)
 	}
+	pub fn try_shift(self, dfile: i8, drank: i8) -> Option<Square> {
+		Square::try_at(self.file().checked_add_signed(dfile)?, self.rank().checked_add_signed(drank)?)
+	}
+}
+// named constants for all squares: Square::A1 to Square::H8
+macro_rules! file_constants {
+	($file:literal: $r1:ident $r2:ident $r3:ident $r4:ident $r5:ident $r6:ident $r7:ident $r8:ident) => {
+		pub const $r1: Square = Square::at($file, 0);
+		pub const $r2: Square = Square::at($file, 1);
+		pub const $r3: Square = Square::at($file, 2);
+		pub const $r4: Square = Square::at($file, 3);
+		pub const $r5: Square = Square::at($file, 4);
+		pub const $r6: Square = Square::at($file, 5);
+		pub const $r7: Square = Square::at($file, 6);
+		pub const $r8: Square = Square::at($file, 7);
+	};
 }
-impl<T> std::ops::Index<Square> for [T; 64] {
+impl Square {
+	file_constants!(0: A1 A2 A3 A4 A5 A6 A7 A8);
+	file_constants!(1: B1 B2 B3 B4 B5 B6 B7 B8);
+	file_constants!(2: C1 C2 C3 C4 C5 C6 C7 C8);
+	file_constants!(3: D1 D2 D3 D4 D5 D6 D7 D8);
+	file_constants!(4: E1 E2 E3 E4 E5 E6 E7 E8);
+	file_constants!(5: F1 F2 F3 F4 F5 F6 F7 F8);
+	file_constants!(6: G1 G2 G3 G4 G5 G6 G7 G8);
+	file_constants!(7: H1 H2 H3 H4 H5 H6 H7 H8);
+}
+
+// Square constant from its name, checked at compile time: sq!("e4")
+#[macro_export]
+macro_rules! sq {
+	($name:literal) => {{
+		const SQU: $crate::state::Square = $crate::state::Square::from_name($name);
+		SQU
+	}};
+}
+
+impl<T> core::ops::Index<Square> for [T; 64] {
 	type Output = T;
 	fn index(&self, index: Square) -> &Self::Output {
 		return &self[index.idx as usize];
 	}
 }
-impl<T> std::ops::IndexMut<Square> for [T; 64] {
+impl<T> core::ops::IndexMut<Square> for [T; 64] {
 	fn index_mut(&mut self, index: Square) -> &mut Self::Output {
 		return &mut self[index.idx as usize];
 	}
@@ -59,14 +131,21 @@ impl<T> std::ops::IndexMut<Square> for [T; 64] {
 impl fmt::Display for Square {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}{}",
-			('a' as u8 + self.file()) as char,
-			('1' as u8 + self.rank()) as char
+			(b'a' + self.file()) as char,
+			(b'1' + self.rank()) as char
 		)
 	}
 }
+impl fmt::Debug for Square {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum PieceType {
 	Pawn,
 	Knight,
@@ -81,7 +160,7 @@ impl PieceType {
 	}
 	fn from_ordinal(n: u8) -> PieceType {
 		debug_assert!(n < 6);
-		unsafe { std::mem::transmute(n) }
+		unsafe { core::mem::transmute(n) }
 	}
 	pub fn all() -> impl Iterator<Item=PieceType> {
 		(0..6u8).map(PieceType::from_ordinal)
@@ -90,6 +169,8 @@ impl PieceType {
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Color {
 	White,
 	Black,
@@ -106,9 +187,9 @@ impl Color {
 	}
 	pub(crate) fn from_ordinal(n: u8) -> Color {
 		debug_assert!(n < 2);
-		unsafe { std::mem::transmute(n) }
+		unsafe { core::mem::transmute(n) }
 	}
-	fn all() -> impl Iterator<Item=Color> {
+	pub fn all() -> impl Iterator<Item=Color> {
 		(0..2u8).map(Color::from_ordinal)
 	}
 	pub fn rel_rank(self, rank: u8) -> u8 {
@@ -127,13 +208,13 @@ impl Color {
 		-self.up()
 	}
 }
-impl<T> std::ops::Index<Color> for [T; 2] {
+impl<T> core::ops::Index<Color> for [T; 2] {
 	type Output = T;
 	fn index(&self, index: Color) -> &Self::Output {
 		return &self[index as usize];
 	}
 }
-impl<T> std::ops::IndexMut<Color> for [T; 2] {
+impl<T> core::ops::IndexMut<Color> for [T; 2] {
 	fn index_mut(&mut self, index: Color) -> &mut Self::Output {
 		return &mut self[index as usize];
 	}
@@ -148,6 +229,7 @@ impl fmt::Display for Color {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Piece {
 	pub color: Color,
 	pub ptype: PieceType,
@@ -156,7 +238,7 @@ impl Piece {
 	pub fn new(color: Color, ptype: PieceType) -> Piece {
 		Piece { color, ptype }
 	}
-	fn ordinal(self) -> usize {
+	pub(crate) fn ordinal(self) -> usize {
 		(self.color as usize) * 6 + self.ptype as usize
 	}
 	const FEN_NOTATION: &'static[u8] = b"PNBRQKpnbrqk";
@@ -168,19 +250,22 @@ impl Piece {
 			Piece::new(Color::from_ordinal(ord as u8 / 6), PieceType::from_ordinal(ord as u8 % 6)))
 	}
 }
-impl<T> std::ops::Index<Piece> for [T; 12] {
+impl<T> core::ops::Index<Piece> for [T; 12] {
 	type Output = T;
 	fn index(&self, index: Piece) -> &Self::Output {
 		return &self[index.ordinal()];
 	}
 }
-impl<T> std::ops::IndexMut<Piece> for [T; 12] {
+impl<T> core::ops::IndexMut<Piece> for [T; 12] {
 	fn index_mut(&mut self, index: Piece) -> &mut Self::Output {
 		return &mut self[index.ordinal()];
 	}
 }
 
+#[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum SpecialMove {
 	None,
 	EnPassant,
@@ -192,6 +277,11 @@ pub enum SpecialMove {
 	CastleK,
 }
 impl SpecialMove {
+	#[cfg(feature = "std")]
+	fn from_ordinal(n: u8) -> SpecialMove {
+		debug_assert!(n < 8);
+		unsafe { core::mem::transmute(n) }
+	}
 	pub fn get_promotion(self) -> Option<PieceType> {
 		match self {
 			SpecialMove::PromoteN => Some(PieceType::Knight),
@@ -204,6 +294,7 @@ impl SpecialMove {
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Move {
 	pub ptype: PieceType,
 	pub from: Square,
@@ -211,6 +302,89 @@ pub struct Move {
 	pub special: SpecialMove,
 }
 
+// more than the 218 moves possible in a legal position, pseudolegal moves included
+pub const MAX_MOVES: usize = 256;
+
+// list of moves stored inline, so that move generation does not allocate;
+// it dereferences to a slice of moves
+#[derive(Clone)]
+pub struct MoveList {
+	moves: [Move; MAX_MOVES],
+	len: usize,
+}
+impl MoveList {
+	pub fn new() -> MoveList {
+		const EMPTY: Move = Move { ptype: PieceType::Pawn, from: Square::A1, to: Square::A1, special: SpecialMove::None };
+		MoveList { moves: [EMPTY; MAX_MOVES], len: 0 }
+	}
+	pub fn push(&mut self, mov: Move) {
+		self.moves[self.len] = mov;
+		self.len += 1;
+	}
+	pub fn pop(&mut self) -> Option<Move> {
+		if self.len == 0 {
+			return None;
+		}
+		self.len -= 1;
+		Some(self.moves[self.len])
+	}
+	// keeps the moves for which `keep` is true, in the same order
+	pub fn retain(&mut self, mut keep: impl FnMut(&Move) -> bool) {
+		let mut kept = 0;
+		for i in 0..self.len {
+			if keep(&self.moves[i]) {
+				self.moves[kept] = self.moves[i];
+				kept += 1;
+			}
+		}
+		self.len = kept;
+	}
+}
+impl Default for MoveList {
+	fn default() -> Self {
+		MoveList::new()
+	}
+}
+impl core::ops::Deref for MoveList {
+	type Target = [Move];
+	fn deref(&self) -> &[Move] {
+		&self.moves[..self.len]
+	}
+}
+impl core::ops::DerefMut for MoveList {
+	fn deref_mut(&mut self) -> &mut [Move] {
+		&mut self.moves[..self.len]
+	}
+}
+impl FromIterator<Move> for MoveList {
+	fn from_iter<I: IntoIterator<Item=Move>>(iter: I) -> Self {
+		let mut list = MoveList::new();
+		list.extend(iter);
+		list
+	}
+}
+impl Extend<Move> for MoveList {
+	fn extend<I: IntoIterator<Item=Move>>(&mut self, iter: I) {
+		for mov in iter {
+			self.push(mov);
+		}
+	}
+}
+impl IntoIterator for MoveList {
+	type Item = Move;
+	type IntoIter = core::iter::Take<core::array::IntoIter<Move, MAX_MOVES>>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.moves.into_iter().take(self.len)
+	}
+}
+impl<'a> IntoIterator for &'a MoveList {
+	type Item = &'a Move;
+	type IntoIter = core::slice::Iter<'a, Move>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
 pub enum ParseMoveError {
 	InvalidSyntax,
 	AmbiguousMove,
@@ -229,6 +403,20 @@ fn or_invalid<T>(opt: Option<T>) -> Result<T, ParseMoveError> {
 	opt.ok_or(ParseMoveError::InvalidSyntax)
 }
 impl Move {
+	// 18-bit encoding, for tables that store moves in atomic words
+	#[cfg(feature = "std")]
+	pub(crate) fn pack(self) -> u32 {
+		(self.ptype as u32) << 15 | (self.from.index() as u32) << 9 | (self.to.index() as u32) << 3 | self.special as u32
+	}
+	#[cfg(feature = "std")]
+	pub(crate) fn unpack(bits: u32) -> Move {
+		Move {
+			ptype: PieceType::from_ordinal((bits >> 15 & 7) as u8),
+			from: Square::from_index_unchecked((bits >> 9 & 63) as u8),
+			to: Square::from_index_unchecked((bits >> 3 & 63) as u8),
+			special: SpecialMove::from_ordinal((bits & 7) as u8),
+		}
+	}
 	pub fn parse_uci<'moves>(s: &str, legal_moves: &'moves [Move]) -> Result<&'moves Move, ParseMoveError> {
 		let mut chars = s.chars().peekable();
 		let from_file = or_invalid(parse_file(or_invalid(chars.next())? as u8))?;
@@ -373,53 +561,95 @@ impl fmt::Display for Move {
 	}
 }
 
-#[derive(Default, Clone)]
-pub struct Board([Bb; 12]); // bitboard for each piece
+// the occupancy bitboards and the mailbox are redundant with the piece bitboards,
+// and kept in sync by add and remove
+#[derive(Clone)]
+pub struct Board {
+	pieces: [Bb; 12], // bitboard for each piece
+	colors: [Bb; 2],
+	occupied: Bb,
+	mailbox: [Option<Piece>; 64],
+}
+impl Default for Board {
+	fn default() -> Self {
+		Board { pieces: [Bb::EMPTY; 12], colors: [Bb::EMPTY; 2], occupied: Bb::EMPTY, mailbox: [None; 64] }
+	}
+}
 impl Board {
 	pub fn find_piece(&self, piece: Piece) -> Bb {
-		self.0[piece]
+		self.pieces[piece]
 	}
 	pub fn find_color(&self, color: Color) -> Bb {
-		let mut sum = Bb::EMPTY;
-		for ptype in PieceType::all() {
-			sum |= self.0[Piece::new(color, ptype)];
-		}
-		sum
+		self.colors[color]
 	}
 	pub fn count_pieces(&self, color: Color, ptype: PieceType) -> u32 {
 		self.find_piece(Piece::new(color, ptype)).count()
 	}
 	pub fn all_pieces(&self) -> Bb {
-		self.find_color(Color::White) | self.find_color(Color::Black)
+		self.occupied
 	}
-	pub fn add(&mut self, squ: Square, piece: Piece) {
-		self.0[piece] |= Bb::one(squ);
+	pub fn piece_at(&self, squ: Square) -> Option<Piece> {
+		self.mailbox[squ]
 	}
-	pub fn remove(&mut self, squ: Square, piece: Piece) {
-		self.0[piece] &= !Bb::one(squ);
+	// the pieces on the board with their squares, in square index order
+	pub fn iter(&self) -> impl Iterator<Item=(Square, Piece)> + '_ {
+		self.occupied.iter().map(|squ| (squ, self.mailbox[squ].unwrap()))
 	}
 
-	pub fn get_pieces(&self) -> [Option<Piece>; 64] {
-		let mut board = [None; 64];
-		for color in Color::all() {
-			for ptype in PieceType::all() {
-				for squ in self.0[Piece::new(color, ptype)].iter() {
-					debug_assert!(board[squ].is_none(), "multiple piece types on same square");
-					board[squ] = Some(Piece::new(color, ptype));
-				}
+	// pieces of either color standing alone between `target` and a bishop, rook or
+	// queen of `color` aiming at it: the sliders are found with x-ray attacks from
+	// `target`, as if the board was empty
+	pub fn slider_blockers(&self, target: Square, color: Color) -> Bb {
+		let find = |ptype| self.find_piece(Piece::new(color, ptype));
+		let queens = find(PieceType::Queen);
+		let snipers = (cast_diagonals(target, Bb::EMPTY) & (find(PieceType::Bishop) | queens))
+			| (cast_cardinals(target, Bb::EMPTY) & (find(PieceType::Rook) | queens));
+		let pieces = self.all_pieces();
+		let mut blockers = Bb::EMPTY;
+		for sniper in snipers.iter() {
+			let between = between(target, sniper) & pieces;
+			if !between.none() && !between.more_than_one() {
+				blockers |= between;
 			}
 		}
-		board
+		blockers
+	}
+	// pieces of `color` that can only move along the line between their king and an enemy slider
+	pub fn pinned_pieces(&self, color: Color) -> Bb {
+		match self.find_piece(Piece::new(color, PieceType::King)).iter().next() {
+			Some(king_pos) => self.slider_blockers(king_pos, color.opponent()) & self.find_color(color),
+			None => Bb::EMPTY,
+		}
+	}
+	// the square must be empty
+	pub fn add(&mut self, squ: Square, piece: Piece) {
+		debug_assert!(self.mailbox[squ].is_none(), "multiple pieces on same square");
+		let bb = Bb::one(squ);
+		self.pieces[piece] |= bb;
+		self.colors[piece.color] |= bb;
+		self.occupied |= bb;
+		self.mailbox[squ] = Some(piece);
+	}
+	// the piece must be on the square
+	pub fn remove(&mut self, squ: Square, piece: Piece) {
+		debug_assert!(self.mailbox[squ] == Some(piece), "removed piece not found");
+		let bb = !Bb::one(squ);
+		self.pieces[piece] &= bb;
+		self.colors[piece.color] &= bb;
+		self.occupied &= bb;
+		self.mailbox[squ] = None;
+	}
+
+	pub fn get_pieces(&self) -> [Option<Piece>; 64] {
+		self.mailbox
 	}
 
 	pub fn to_fen(&self) -> String {
-		let pieces = self.get_pieces();
 		let mut res = String::new();
 		for rank in (0..8).rev() {
 			let mut blanks = 0;
 			for file in 0..8 {
-				let squ = Square::at(file, rank);
-				if let Some(piece) = pieces[squ] {
+				if let Some(piece) = self.piece_at(Square::at(file, rank)) {
 					if blanks > 0 {
 						res.push((b'0' + blanks as u8) as char);
 						blanks = 0
@@ -471,14 +701,21 @@ impl Board {
 		Some(board)
 	}
 }
+impl FromIterator<(Square, Piece)> for Board {
+	fn from_iter<I: IntoIterator<Item=(Square, Piece)>>(iter: I) -> Board {
+		let mut board = Board::default();
+		for (squ, piece) in iter {
+			board.add(squ, piece);
+		}
+		board
+	}
+}
 impl fmt::Display for Board {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let pieces = self.get_pieces();
 		for rank in (0..8).rev() {
 			f.write_char('|')?;
 			for file in 0..8 {
-				let squ = Square::at(file, rank);
-				if let Some(piece) = pieces[squ] {
+				if let Some(piece) = self.piece_at(Square::at(file, rank)) {
 					f.write_char(piece.to_fen() as char)?;
 				} else {
 					f.write_char(' ')?;
@@ -492,3 +729,63 @@ impl fmt::Display for Board {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod test_square {
+	use super::{files, ranks, Square};
+
+	#[test]
+	fn test_iterators() {
+		let all: Vec<Square> = Square::iter_all().collect();
+		assert_eq!(all.len(), 64);
+		assert!(all[0] == Square::A1 && all[1] == Square::A2 && all[63] == Square::H8);
+		let by_coords: Vec<Square> = files().flat_map(|file| ranks().map(move |rank| Square::at(file, rank))).collect();
+		assert!(all == by_coords);
+	}
+
+	#[test]
+	fn test_constants() {
+		assert!(Square::A1 == Square::at(0, 0));
+		assert!(Square::E4 == Square::parse("e4").unwrap());
+		assert!(Square::H8 == Square::at(7, 7));
+		assert!(sq!("c6") == Square::C6);
+		assert_eq!(format!("{}", sq!("g2")), "g2");
+		assert_eq!(format!("{:?}", Some(Square::B3)), "Some(b3)");
+	}
+
+	#[test]
+	fn test_checked() {
+		assert_eq!(Square::try_at(3, 4), Some(Square::D5));
+		assert_eq!(Square::try_at(8, 0), None);
+		assert_eq!(Square::try_at(0, 8), None);
+		for squ in Square::iter_all() {
+			assert_eq!(Square::from_index(squ.index()), Some(squ));
+			assert_eq!(Square::from_index_unchecked(squ.index()), squ);
+		}
+		assert_eq!(Square::from_index(64), None);
+		assert_eq!(Square::G7.try_shift(1, 1), Some(Square::H8));
+		assert_eq!(Square::G7.try_shift(2, 0), None);
+		assert_eq!(Square::B2.try_shift(-2, 0), None);
+		assert_eq!(Square::B2.try_shift(-1, -1), Some(Square::A1));
+		assert_eq!(Square::C3.shift(2, -1), Square::E2);
+	}
+}
+
+#[cfg(test)]
+mod test_move_list {
+	use super::{Move, MoveList, PieceType, SpecialMove, Square};
+
+	#[test]
+	fn test_move_list() {
+		let mov = |from, to| Move { ptype: PieceType::Rook, from, to, special: SpecialMove::None };
+		let mut list: MoveList = Square::iter_all().skip(1).map(|to| mov(Square::A1, to)).collect();
+		assert_eq!(list.len(), 63);
+		assert!(list[0] == mov(Square::A1, Square::A2));
+		list.retain(|m| m.to.file() == 0);
+		assert_eq!(list.len(), 7);
+		assert!(list.iter().all(|m| m.to.file() == 0) && list.contains(&mov(Square::A1, Square::A8)));
+		assert!(list.pop() == Some(mov(Square::A1, Square::A8)));
+		assert_eq!(list.into_iter().count(), 6);
+		assert!(MoveList::new().is_empty());
+	}
+}
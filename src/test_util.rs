@@ -0,0 +1,35 @@
+use std::{io::{Read, Write}, net::TcpListener, thread::JoinHandle};
+
+// helpers shared by the tests of the modules talking to lichess
+
+// answers each connection with the next of the (status, body) responses,
+// and returns the requests it received
+pub fn mock_server(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<String>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let base = format!("http://{}/", listener.local_addr().unwrap());
+	let server = std::thread::spawn(move || {
+		let mut requests = vec![];
+		for (status, body) in responses {
+			let (mut conn, _) = listener.accept().unwrap();
+			let mut req = vec![];
+			let mut buf = [0; 4096];
+			// headers, then as much body as announced
+			loop {
+				let n = conn.read(&mut buf).unwrap();
+				req.extend_from_slice(&buf[..n]);
+				let text = String::from_utf8_lossy(&req).into_owned();
+				if let Some(end) = text.find("\r\n\r\n") {
+					let len = text.lines().find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|len| len.trim().parse().unwrap()));
+					if n == 0 || req.len() >= end + 4 + len.unwrap_or(0) {
+						break;
+					}
+				}
+			}
+			write!(conn, "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+				status, body.len(), body).unwrap();
+			requests.push(String::from_utf8(req).unwrap());
+		}
+		requests
+	});
+	(base, server)
+}
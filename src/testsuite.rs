@@ -0,0 +1,205 @@
+use std::{fmt, path::Path};
+
+use crate::{game::Position, state::Move};
+
+// EPD records, the format of test suites such as Win At Chess or the Strategic
+// Test Suite: the first four fields of a FEN, then operations made of an opcode
+// and its operands, each ended by a semicolon, as in
+//   r1b1kb1r/3q1ppp/pBp1pn2/8/Np3P2/5B2/PPP3PP/R2Q1RK1 w kq - bm Bxc6; id "WAC.081";
+// Operands in double quotes may contain spaces and semicolons.
+
+#[derive(Clone)]
+pub struct EpdRecord {
+	pub position: Position,
+	pub operations: Vec<(String, Vec<String>)>, // opcode and operands, in order
+}
+
+impl EpdRecord {
+	pub fn parse(line: &str) -> Result<EpdRecord, String> {
+		let line = line.trim();
+		let mut rest = line;
+		let mut fields = vec![];
+		for _ in 0..4 {
+			let (field, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+			if field.is_empty() {
+				return Err(format!("missing position fields: {}", line));
+			}
+			fields.push(field);
+			rest = after.trim_start();
+		}
+		let operations = parse_operations(rest).map_err(|err| format!("{}: {}", err, line))?;
+
+		// the clocks of the FEN are given by the hmvc and fmvn operations, if at all
+		let operand = |opcode: &str| operations.iter().find(|(op, _)| op == opcode).and_then(|(_, operands)| operands.first());
+		let fen = format!("{} {} {}", fields.join(" "),
+			operand("hmvc").map_or("0", |s| s.as_str()), operand("fmvn").map_or("1", |s| s.as_str()));
+		let position = Position::from_fen(&fen).map_err(|err| format!("invalid position {}: {}", fen, err))?;
+		Ok(EpdRecord { position, operations })
+	}
+
+	// records of a file, one per line, skipping blank lines
+	pub fn load(path: impl AsRef<Path>) -> Result<Vec<EpdRecord>, String> {
+		let path = path.as_ref();
+		let text = std::fs::read_to_string(path)
+			.map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+		text.lines().enumerate()
+			.filter(|(_, line)| !line.trim().is_empty())
+			.map(|(i, line)| EpdRecord::parse(line).map_err(|err| format!("{}:{}: {}", path.display(), i + 1, err)))
+			.collect()
+	}
+
+	// the operands of the first operation with this opcode
+	pub fn get(&self, opcode: &str) -> Option<&[String]> {
+		self.operations.iter().find(|(op, _)| op == opcode).map(|(_, operands)| operands.as_slice())
+	}
+	pub fn id(&self) -> Option<&str> {
+		self.get("id")?.first().map(|id| id.as_str())
+	}
+
+	// the moves of an operation, in SAN, such as bm (best moves) and am (moves to avoid)
+	pub fn moves(&self, opcode: &str) -> Result<Vec<Move>, String> {
+		let legal_moves = self.position.gen_legal();
+		self.get(opcode).unwrap_or_default().iter()
+			.map(|san| Move::parse_algebraic(san, &legal_moves).copied()
+				.map_err(|err| format!("{} {}: {}", opcode, san, err)))
+			.collect()
+	}
+
+	// the points of the moves listed in the c0 comment of STS records, as in
+	// c0 "Rd1=10, Qe2=4, Rb1=3", with the best move worth 10
+	pub fn move_points(&self) -> Result<Vec<(Move, u32)>, String> {
+		let Some(comment) = self.get("c0").and_then(|operands| operands.first()) else {
+			return Ok(vec![]);
+		};
+		let legal_moves = self.position.gen_legal();
+		comment.split(',').map(|entry| {
+			let invalid = || format!("invalid move points: {}", entry.trim());
+			let (san, points) = entry.trim().split_once('=').ok_or_else(invalid)?;
+			let mov = Move::parse_algebraic(san, &legal_moves).map_err(|err| format!("c0 {}: {}", san, err))?;
+			Ok((*mov, points.parse().map_err(|_| invalid())?))
+		}).collect()
+	}
+
+	// how well a move does: the points of STS records, or otherwise 1 for a best
+	// move that is not to be avoided (records with only am accept any other move)
+	pub fn score(&self, mov: &Move) -> Result<u32, String> {
+		let points = self.move_points()?;
+		if !points.is_empty() {
+			return Ok(points.iter().find(|(m, _)| m == mov).map_or(0, |(_, points)| *points));
+		}
+		let best = self.moves("bm")?;
+		let avoid = self.moves("am")?;
+		Ok(((best.is_empty() || best.contains(mov)) && !avoid.contains(mov)) as u32)
+	}
+	// the score of the best moves
+	pub fn max_score(&self) -> Result<u32, String> {
+		Ok(self.move_points()?.iter().map(|(_, points)| *points).max().unwrap_or(1))
+	}
+}
+
+impl fmt::Display for EpdRecord {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// the position without its clocks, which only appear as operations
+		let fen = self.position.to_fen();
+		let fields: Vec<&str> = fen.split(' ').take(4).collect();
+		write!(f, "{}", fields.join(" "))?;
+		for (opcode, operands) in &self.operations {
+			write!(f, " {}", opcode)?;
+			for operand in operands {
+				if operand.is_empty() || operand.contains(|c: char| c.is_whitespace() || c == ';' || c == '"') {
+					write!(f, " \"{}\"", operand)?;
+				} else {
+					write!(f, " {}", operand)?;
+				}
+			}
+			write!(f, ";")?;
+		}
+		Ok(())
+	}
+}
+
+fn parse_operations(s: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+	let mut operations = vec![];
+	let mut tokens = vec![];
+	let mut chars = s.chars().peekable();
+	while let Some(&c) = chars.peek() {
+		if c.is_whitespace() {
+			chars.next();
+		} else if c == ';' {
+			chars.next();
+			if tokens.is_empty() {
+				return Err("empty operation".to_string());
+			}
+			let opcode = tokens.remove(0);
+			operations.push((opcode, std::mem::take(&mut tokens)));
+		} else if c == '"' {
+			chars.next();
+			let mut token = String::new();
+			loop {
+				match chars.next() {
+					Some('"') => break,
+					Some(c) => token.push(c),
+					None => return Err("unterminated string".to_string()),
+				}
+			}
+			tokens.push(token);
+		} else {
+			let mut token = String::new();
+			while let Some(&c) = chars.peek() {
+				if c.is_whitespace() || c == ';' || c == '"' {
+					break;
+				}
+				token.push(c);
+				chars.next();
+			}
+			tokens.push(token);
+		}
+	}
+	if !tokens.is_empty() {
+		return Err(format!("operation without a semicolon: {}", tokens.join(" ")));
+	}
+	Ok(operations)
+}
+
+#[cfg(test)]
+mod test_epd {
+	use super::EpdRecord;
+
+	#[test]
+	fn test_parse() {
+		let record = EpdRecord::parse("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";").unwrap();
+		assert_eq!(record.id(), Some("WAC.001"));
+		assert_eq!(record.position.to_fen(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+		let best = record.moves("bm").unwrap();
+		assert_eq!(best.len(), 1);
+		assert_eq!(record.position.algebraic_notation(&best[0]), "Qg6");
+		assert_eq!(record.score(&best[0]), Ok(1));
+		let other = record.position.gen_legal().into_iter().find(|mov| *mov != best[0]).unwrap();
+		assert_eq!(record.score(&other), Ok(0));
+		assert_eq!(record.to_string(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id WAC.001;");
+
+		// clocks, several best moves, and quoted operands with separators
+		let record = EpdRecord::parse("4k3/8/8/8/8/8/8/R3K2R w KQ - bm O-O Ra8+; hmvc 12; fmvn 40; c1 \"a; b\";").unwrap();
+		assert_eq!(record.position.to_fen(), "4k3/8/8/8/8/8/8/R3K2R w KQ - 12 40");
+		assert_eq!(record.moves("bm").unwrap().len(), 2);
+		assert_eq!(record.get("c1").unwrap(), ["a; b"]);
+		assert_eq!(record.to_string(), "4k3/8/8/8/8/8/8/R3K2R w KQ - bm O-O Ra8+; hmvc 12; fmvn 40; c1 \"a; b\";");
+
+		assert!(EpdRecord::parse("8/8/8/8 w - - bm e4;").is_err());
+		assert!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - bm Kd2").is_err());
+		assert!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"x;").is_err());
+		assert!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - bm Ke4;").unwrap().moves("bm").is_err());
+	}
+
+	#[test]
+	fn test_sts_points() {
+		let record = EpdRecord::parse("1kr5/3n4/q3p2p/p2n2p1/PppB1P2/5BP1/1P2Q2P/3R2K1 w - - bm f5; id \"STS(v1.0) Undermine.001\"; c0 \"f5=10, Be5+=2, Bf2=3, Bg4=2\";").unwrap();
+		let points = record.move_points().unwrap();
+		assert_eq!(points.len(), 4);
+		assert_eq!(record.max_score(), Ok(10));
+		assert_eq!(record.score(&points[0].0), Ok(10));
+		assert_eq!(record.score(&points[2].0), Ok(3));
+		let other = record.position.gen_legal().into_iter().find(|mov| points.iter().all(|(m, _)| m != mov)).unwrap();
+		assert_eq!(record.score(&other), Ok(0));
+	}
+}
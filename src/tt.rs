@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::state::Move;
+
+// how a stored score relates to the true score of the position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+	Exact, // PV node: every move was searched inside the window
+	Lower, // cut node: a move failed high
+	Upper, // all node: no move raised alpha
+}
+impl Bound {
+	// exact scores are worth the most when choosing what to overwrite
+	fn value(self) -> i32 {
+		match self {
+			Bound::Exact => 2,
+			Bound::Lower => 1,
+			Bound::Upper => 0,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TtEntry {
+	pub key: u64,
+	pub best_move: Option<Move>,
+	pub score: i16,
+	pub depth: u8,
+	pub bound: Bound,
+	age: u8,
+}
+impl TtEntry {
+	// layout of the data word: move (18 bits), has move (1), score (16),
+	// depth (8), bound (2), age (8); the top bit marks occupied slots
+	fn pack(&self) -> u64 {
+		let mov = self.best_move.map_or(0, |mov| 1 << 18 | mov.pack() as u64);
+		mov | (self.score as u16 as u64) << 19 | (self.depth as u64) << 35
+			| (self.bound as u64) << 43 | (self.age as u64) << 45 | 1 << 63
+	}
+	fn unpack(key: u64, data: u64) -> TtEntry {
+		TtEntry {
+			key,
+			best_move: if data >> 18 & 1 != 0 { Some(Move::unpack(data as u32 & 0x3ffff)) } else { None },
+			score: (data >> 19) as u16 as i16,
+			depth: (data >> 35) as u8,
+			bound: [Bound::Exact, Bound::Lower, Bound::Upper][(data >> 43 & 3) as usize],
+			age: (data >> 45) as u8,
+		}
+	}
+}
+
+// An entry is stored as two words, the data and the key xored with the data,
+// so that an entry torn by concurrent writes does not match its key anymore:
+// threads share the table without locking.
+#[derive(Default)]
+struct Slot {
+	check: AtomicU64,
+	data: AtomicU64,
+}
+impl Slot {
+	fn load(&self) -> Option<TtEntry> {
+		let data = self.data.load(Ordering::Relaxed);
+		if data == 0 {
+			return None;
+		}
+		Some(TtEntry::unpack(self.check.load(Ordering::Relaxed) ^ data, data))
+	}
+	fn save(&self, entry: &TtEntry) {
+		let data = entry.pack();
+		self.check.store(entry.key ^ data, Ordering::Relaxed);
+		self.data.store(data, Ordering::Relaxed);
+	}
+	fn reset(&self) {
+		self.check.store(0, Ordering::Relaxed);
+		self.data.store(0, Ordering::Relaxed);
+	}
+}
+
+const BUCKET_SIZE: usize = 2;
+type Bucket = [Slot; BUCKET_SIZE];
+
+// A transposition table made of two-entry buckets. When a bucket is full,
+// the entry from the oldest search, or else the shallowest one, is replaced,
+// with exact scores favored over bounds.
+pub struct TranspositionTable {
+	buckets: Vec<Bucket>,
+	age: AtomicU8,
+}
+impl TranspositionTable {
+	pub fn new(size_mb: usize) -> TranspositionTable {
+		let count = (size_mb << 20) / std::mem::size_of::<Bucket>();
+		TranspositionTable {
+			buckets: (0..count.max(1)).map(|_| Bucket::default()).collect(),
+			age: AtomicU8::new(0),
+		}
+	}
+
+	pub fn clear(&self) {
+		self.buckets.iter().flatten().for_each(Slot::reset);
+		self.age.store(0, Ordering::Relaxed);
+	}
+	// marks the entries of previous searches as stale, to be overwritten first
+	pub fn new_search(&self) {
+		self.age.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn bucket_index(&self, key: u64) -> usize {
+		((key as u128 * self.buckets.len() as u128) >> 64) as usize
+	}
+
+	pub fn probe(&self, key: u64) -> Option<TtEntry> {
+		self.buckets[self.bucket_index(key)].iter().filter_map(Slot::load)
+			.find(|entry| entry.key == key)
+	}
+
+	fn worth(&self, entry: Option<TtEntry>) -> i32 {
+		let Some(entry) = entry else { return i32::MIN };
+		let age = self.age.load(Ordering::Relaxed).wrapping_sub(entry.age) as i32;
+		entry.depth as i32 * 4 + entry.bound.value() - age * 64
+	}
+
+	// other threads may write to the bucket meanwhile, in which case one of the writes is lost
+	pub fn store(&self, key: u64, depth: u8, score: i16, bound: Bound, best_move: Option<Move>) {
+		let age = self.age.load(Ordering::Relaxed);
+		let bucket = &self.buckets[self.bucket_index(key)];
+		let entries = [bucket[0].load(), bucket[1].load()];
+		let slot = if let Some(slot) = entries.iter().position(|e| e.is_some_and(|e| e.key == key)) {
+			let old = entries[slot].unwrap();
+			// keep a deeper result for the same position, unless it is stale
+			if old.age == age && old.depth > depth && bound != Bound::Exact {
+				return;
+			}
+			slot
+		} else {
+			// empty slots are worth the least
+			(0..BUCKET_SIZE).min_by_key(|&slot| self.worth(entries[slot])).unwrap()
+		};
+		let old_move = entries[slot].filter(|e| e.key == key).and_then(|e| e.best_move);
+		bucket[slot].save(&TtEntry {
+			key,
+			best_move: best_move.or(old_move),
+			score,
+			depth,
+			bound,
+			age,
+		});
+	}
+
+	// occupancy in permill, sampled from the first buckets, counting only
+	// entries from the current search (as in the UCI "info hashfull" field)
+	pub fn hashfull(&self) -> u32 {
+		let sample = &self.buckets[..self.buckets.len().min(500)];
+		let age = self.age.load(Ordering::Relaxed);
+		let used = sample.iter().flatten().filter_map(Slot::load).filter(|e| e.age == age).count();
+		(used * 1000 / (sample.len() * BUCKET_SIZE)) as u32
+	}
+}
+
+#[cfg(test)]
+mod test_tt {
+	use super::{Bound, TranspositionTable};
+	use crate::game::Position;
+
+	#[test]
+	fn test_replacement() {
+		let tt = TranspositionTable::new(0); // a single bucket
+		tt.store(1, 5, 10, Bound::Exact, None);
+		tt.store(2, 2, 20, Bound::Upper, None);
+		// the bucket is full: the shallow entry goes
+		tt.store(3, 3, 30, Bound::Lower, None);
+		assert!(tt.probe(1).is_some());
+		assert!(tt.probe(2).is_none());
+		assert_eq!(tt.probe(3).unwrap().score, 30);
+		// a shallower bound does not overwrite the same position
+		tt.store(1, 1, 40, Bound::Lower, None);
+		assert_eq!(tt.probe(1).unwrap().depth, 5);
+		// entries of older searches are replaced first, whatever their depth
+		tt.new_search();
+		tt.store(3, 1, 50, Bound::Upper, None);
+		tt.store(4, 1, 60, Bound::Upper, None);
+		assert!(tt.probe(1).is_none());
+		assert!(tt.probe(3).is_some() && tt.probe(4).is_some());
+	}
+
+	#[test]
+	fn test_hashfull() {
+		let tt = TranspositionTable::new(1);
+		assert_eq!(tt.hashfull(), 0);
+		for key in 0..100_000u64 {
+			tt.store(key.wrapping_mul(0x9e3779b97f4a7c15), 1, 0, Bound::Exact, None);
+		}
+		assert!(tt.hashfull() > 500);
+		tt.new_search();
+		assert_eq!(tt.hashfull(), 0);
+		tt.clear();
+		assert!(tt.probe(0x9e3779b97f4a7c15).is_none());
+	}
+
+	#[test]
+	fn test_moves() {
+		let tt = TranspositionTable::new(1);
+		let pos = Position::from_fen("r3k3/1P6/8/8/8/8/8/R3K2R w KQq - 0 1").unwrap();
+		for (i, mov) in pos.gen_legal().iter().enumerate() {
+			tt.store(i as u64, 3, -(i as i16), Bound::Lower, Some(*mov));
+			let entry = tt.probe(i as u64).unwrap();
+			assert!(entry.best_move == Some(*mov));
+			assert_eq!((entry.score, entry.depth, entry.bound), (-(i as i16), 3, Bound::Lower));
+		}
+	}
+
+	#[test]
+	fn test_concurrent_stores() {
+		let tt = TranspositionTable::new(0);
+		// the threads store different scores for the same keys:
+		// an entry may come from either thread, but never from both
+		std::thread::scope(|scope| {
+			for thread in 0..4u64 {
+				let tt = &tt;
+				scope.spawn(move || {
+					for i in 0..10_000u64 {
+						let key = i % 4;
+						tt.store(key, 1, (key * 1000 + thread) as i16, Bound::Exact, None);
+						if let Some(entry) = tt.probe(key) {
+							assert_eq!(entry.score as u64 / 1000, key);
+						}
+					}
+				});
+			}
+		});
+	}
+}
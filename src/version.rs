@@ -0,0 +1,8 @@
+use crate::ai::eval_signature;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("CHESS_GIT_HASH");
+
+pub fn build_info() -> String {
+	format!("pyxyne_chess {} ({}, eval {:08x})", VERSION, GIT_HASH, eval_signature())
+}
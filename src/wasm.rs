@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+	ai::{SearchLimits, SimpleAi},
+	game::Position,
+	pgn::PgnResult,
+	state::{Color, Move, MoveList},
+};
+
+// JS bindings for a browser GUI, built with wasm-pack from the `wasm` feature.
+// Moves cross the boundary as strings: UCI ("e2e4", "e7e8q") by default, SAN
+// where the names say so. Errors are thrown as JS `Error`s.
+
+#[wasm_bindgen(js_name = Position)]
+pub struct JsPosition {
+	pos: Position,
+	legal_moves: MoveList,
+}
+
+impl JsPosition {
+	fn wrap(pos: Position) -> JsPosition {
+		let legal_moves = pos.gen_legal();
+		JsPosition { pos, legal_moves }
+	}
+
+	// accepts both notations, UCI first since SAN has no move of the same form
+	fn parse_move(&self, s: &str) -> Result<Move, String> {
+		Move::parse_uci(s, &self.legal_moves)
+			.or_else(|_| Move::parse_algebraic(s, &self.legal_moves))
+			.copied()
+			.map_err(|err| format!("{}: {}", err, s))
+	}
+}
+
+#[wasm_bindgen(js_class = Position)]
+impl JsPosition {
+	// the initial position if no FEN is given
+	#[wasm_bindgen(constructor)]
+	pub fn new(fen: Option<String>) -> Result<JsPosition, JsError> {
+		let fen = fen.as_deref().unwrap_or(Position::FEN_INITIAL);
+		let pos = Position::from_fen(fen).map_err(|err| JsError::new(&err.to_string()))?;
+		pos.validate().map_err(|err| JsError::new(&err.to_string()))?;
+		Ok(JsPosition::wrap(pos))
+	}
+
+	pub fn fen(&self) -> String {
+		self.pos.to_fen()
+	}
+
+	// "white" or "black"
+	#[wasm_bindgen(js_name = sideToMove)]
+	pub fn side_to_move(&self) -> String {
+		match self.pos.side_to_move() {
+			Color::White => "white",
+			Color::Black => "black",
+		}.to_string()
+	}
+
+	#[wasm_bindgen(js_name = legalMoves)]
+	pub fn legal_moves(&self) -> Vec<String> {
+		self.legal_moves.iter().map(Move::uci_notation).collect()
+	}
+
+	#[wasm_bindgen(js_name = isCheck)]
+	pub fn is_check(&self) -> bool {
+		self.pos.is_in_check(self.pos.side_to_move())
+	}
+
+	// "1-0", "0-1" or "1/2-1/2" once the game is over, claimable draws aside
+	pub fn result(&self) -> Option<String> {
+		self.pos.game_result().map(|res| PgnResult::from(res).to_pgn().to_string())
+	}
+
+	// plays a move given in UCI or SAN, and returns its SAN
+	pub fn play(&mut self, mov: &str) -> Result<String, JsError> {
+		let mov = self.parse_move(mov).map_err(|err| JsError::new(&err))?;
+		let san = self.pos.algebraic_notation(&mov);
+		self.pos.apply_move(&mov);
+		self.legal_moves = self.pos.gen_legal();
+		Ok(san)
+	}
+
+	#[wasm_bindgen(js_name = uciToSan)]
+	pub fn uci_to_san(&self, uci: &str) -> Result<String, JsError> {
+		let mov = Move::parse_uci(uci, &self.legal_moves).map_err(|err| JsError::new(&format!("{}: {}", err, uci)))?;
+		Ok(self.pos.algebraic_notation(mov))
+	}
+
+	#[wasm_bindgen(js_name = sanToUci)]
+	pub fn san_to_uci(&self, san: &str) -> Result<String, JsError> {
+		let mov = Move::parse_algebraic(san, &self.legal_moves).map_err(|err| JsError::new(&format!("{}: {}", err, san)))?;
+		Ok(mov.uci_notation())
+	}
+}
+
+// result of Engine.search, with the moves in UCI apart from `san`
+#[wasm_bindgen(getter_with_clone)]
+pub struct SearchResult {
+	#[wasm_bindgen(js_name = bestMove)]
+	pub best_move: String,
+	pub san: String,
+	pub score: i16, // centipawns, from the perspective of the side to move
+	pub mate: Option<i16>, // moves to a forced mate, negative if the side to move gets mated
+	pub depth: u32,
+	pub pv: Vec<String>,
+}
+
+// SimpleAi on the calling thread; a GUI should run it in a web worker
+#[wasm_bindgen]
+pub struct Engine {
+	ai: SimpleAi,
+}
+
+#[wasm_bindgen]
+impl Engine {
+	#[wasm_bindgen(constructor)]
+	pub fn new(max_depth: u32) -> Engine {
+		Engine { ai: SimpleAi::new(max_depth) }
+	}
+
+	// searches up to the maximum depth, or for about `time_ms` milliseconds if given;
+	// returns undefined if the game is over
+	pub fn search(&self, pos: &JsPosition, time_ms: Option<u32>) -> Option<SearchResult> {
+		if pos.pos.game_result().is_some() {
+			return None;
+		}
+		let limits = SearchLimits { time: time_ms.map(|ms| Duration::from_millis(ms as u64)), ..Default::default() };
+		let res = self.ai.search_reporting(&pos.pos, &pos.legal_moves, limits, |_| {});
+		Some(SearchResult {
+			best_move: res.best_move.uci_notation(),
+			san: pos.pos.algebraic_notation(&res.best_move),
+			score: res.score,
+			mate: res.mate,
+			depth: res.depth,
+			pv: res.pv.iter().map(Move::uci_notation).collect(),
+		})
+	}
+}
@@ -0,0 +1,161 @@
+// Conversion between centipawn scores and expected game outcomes, using a
+// logistic model: a score of `scale` centipawns is worth 10:1 odds.
+
+#[derive(Clone, Copy, Debug)]
+pub struct WdlModel {
+	pub scale: f64,
+	pub draw_margin: f64, // in centipawns, widens the draw band around equality
+}
+impl Default for WdlModel {
+	fn default() -> Self {
+		WdlModel { scale: 400.0, draw_margin: 60.0 }
+	}
+}
+impl WdlModel {
+	pub fn new(scale: f64) -> WdlModel {
+		WdlModel { scale, ..Default::default() }
+	}
+
+	fn sigmoid(&self, cp: f64) -> f64 {
+		1.0 / (1.0 + 10f64.powf(-cp / self.scale))
+	}
+
+	// expected score (win = 1, draw = 0.5, loss = 0) for the side the score is relative to
+	pub fn expected_score(&self, cp: i32) -> f64 {
+		self.sigmoid(cp as f64)
+	}
+
+	// inverse of expected_score, saturating for certain outcomes
+	pub fn score_from_expected(&self, expected: f64) -> i32 {
+		const MAX_SCORE: f64 = 10000.0;
+		if expected <= 0.0 {
+			return -MAX_SCORE as i32;
+		} else if expected >= 1.0 {
+			return MAX_SCORE as i32;
+		}
+		let cp = self.scale * (expected / (1.0 - expected)).log10();
+		cp.clamp(-MAX_SCORE, MAX_SCORE).round() as i32
+	}
+
+	// win / draw / loss probabilities, summing to 1
+	pub fn wdl(&self, cp: i32) -> (f64, f64, f64) {
+		let win = self.sigmoid(cp as f64 - self.draw_margin);
+		let loss = self.sigmoid(-cp as f64 - self.draw_margin);
+		(win, 1.0 - win - loss, loss)
+	}
+
+	// mean squared error between expected scores and actual results, as
+	// minimized by Texel tuning; `results` are from the same side as the scores
+	pub fn mean_squared_error(&self, samples: impl IntoIterator<Item=(i32, f64)>) -> f64 {
+		let mut sum = 0.0;
+		let mut count = 0;
+		for (cp, result) in samples {
+			let err = result - self.expected_score(cp);
+			sum += err * err;
+			count += 1;
+		}
+		if count == 0 { 0.0 } else { sum / count as f64 }
+	}
+}
+
+// sequential probability ratio test between two Elo differences, H0 (`elo0`)
+// and H1 (`elo1`), with false positive rate `alpha` and false negative rate `beta`
+#[derive(Clone, Copy, Debug)]
+pub struct Sprt {
+	pub elo0: f64,
+	pub elo1: f64,
+	pub alpha: f64,
+	pub beta: f64,
+}
+impl Sprt {
+	pub fn new(elo0: f64, elo1: f64) -> Sprt {
+		Sprt { elo0, elo1, alpha: 0.05, beta: 0.05 }
+	}
+
+	// the test stops when the log-likelihood ratio leaves this interval
+	pub fn bounds(&self) -> (f64, f64) {
+		((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+	}
+
+	// log-likelihood ratio of H1 against H0 given the results of one side,
+	// approximating the score distribution by a normal one
+	pub fn llr(&self, wins: usize, draws: usize, losses: usize) -> f64 {
+		let games = (wins + draws + losses) as f64;
+		let mean = (wins as f64 + draws as f64 / 2.0) / games;
+		let variance = (wins as f64 * (1.0 - mean).powi(2) + draws as f64 * (0.5 - mean).powi(2)
+			+ losses as f64 * mean.powi(2)) / games;
+		if games == 0.0 || variance == 0.0 {
+			// all results are the same so far, nothing to measure
+			return 0.0;
+		}
+		let score = |elo: f64| 1.0 / (1.0 + 10f64.powf(-elo / 400.0));
+		let (s0, s1) = (score(self.elo0), score(self.elo1));
+		games * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+	}
+
+	// Some(true) once H1 is accepted, Some(false) once H0 is
+	pub fn decision(&self, llr: f64) -> Option<bool> {
+		let (lower, upper) = self.bounds();
+		if llr >= upper {
+			Some(true)
+		} else if llr <= lower {
+			Some(false)
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod test_wdl {
+	use super::{Sprt, WdlModel};
+
+	#[test]
+	fn test_expected_score() {
+		let model = WdlModel::new(400.0);
+		assert!((model.expected_score(0) - 0.5).abs() < 1e-9);
+		assert!((model.expected_score(400) - 10.0 / 11.0).abs() < 1e-9);
+		assert!((model.expected_score(-250) + model.expected_score(250) - 1.0).abs() < 1e-9);
+		for cp in [-900, -120, 0, 35, 600] {
+			assert_eq!(model.score_from_expected(model.expected_score(cp)), cp);
+		}
+		assert_eq!(model.score_from_expected(1.0), 10000);
+	}
+
+	#[test]
+	fn test_wdl() {
+		let model = WdlModel::default();
+		let (win, draw, loss) = model.wdl(0);
+		assert!((win - loss).abs() < 1e-9);
+		assert!(draw > 0.0 && (win + draw + loss - 1.0).abs() < 1e-9);
+		let (win, _, loss) = model.wdl(300);
+		assert!(win > 0.75 && loss < 0.15);
+	}
+
+	#[test]
+	fn test_mean_squared_error() {
+		let model = WdlModel::new(400.0);
+		let samples = [(0, 0.5), (400, 1.0), (-400, 0.0)];
+		let err = model.mean_squared_error(samples);
+		assert!((err - 2.0 / 3.0 * (1.0 / 11.0f64).powi(2)).abs() < 1e-9);
+		assert!(model.mean_squared_error([]) == 0.0);
+		// a better-fitting scale has a lower error
+		assert!(WdlModel::new(200.0).mean_squared_error(samples) < err);
+	}
+
+	#[test]
+	fn test_sprt() {
+		let sprt = Sprt::new(0.0, 10.0);
+		let (lower, upper) = sprt.bounds();
+		assert!((lower + 2.944).abs() < 1e-3 && (upper - 2.944).abs() < 1e-3);
+		assert_eq!(sprt.llr(0, 0, 0), 0.0);
+		assert_eq!(sprt.llr(0, 12, 0), 0.0);
+		// results matching one hypothesis favor it more as games accumulate
+		assert!(sprt.llr(60, 100, 40) > 0.0);
+		assert!(sprt.llr(600, 1000, 400) > sprt.llr(60, 100, 40));
+		assert!(sprt.llr(40, 100, 60) < 0.0);
+		assert_eq!(sprt.decision(sprt.llr(600, 1000, 400)), Some(true));
+		assert_eq!(sprt.decision(sprt.llr(400, 1000, 600)), Some(false));
+		assert_eq!(sprt.decision(0.5), None);
+	}
+}